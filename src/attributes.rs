@@ -0,0 +1,256 @@
+use std::{ops::Index, slice};
+
+/// The attributes of an element in the order they were written. Names are
+/// stored lowercased, apart from mixed case SVG and MathML names such as
+/// `viewBox`, and looked up ignoring ASCII case, so `get("ID")` finds an `id`
+/// attribute.
+#[derive(Clone, Debug, Default)]
+pub struct Attributes {
+    entries: Vec<(String, String)>,
+}
+
+/// Iterates over the names and values of [`Attributes`] in source order
+#[derive(Clone, Debug)]
+pub struct Iter<'a> {
+    entries: slice::Iter<'a, (String, String)>,
+}
+
+impl Attributes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.position(name)
+            .map(|index| self.entries[index].1.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.position(name).is_some()
+    }
+
+    /// Sets an attribute, keeping its place if it already exists and adding
+    /// it at the end otherwise. Returns the value it replaced.
+    pub fn insert(&mut self, name: &str, value: &str) -> Option<String> {
+        match self.position(name) {
+            Some(index) => Some(std::mem::replace(
+                &mut self.entries[index].1,
+                value.to_string(),
+            )),
+            None => {
+                self.entries
+                    .push((name.to_ascii_lowercase(), value.to_string()));
+                None
+            }
+        }
+    }
+
+    /// Gives an attribute the spelling of `name`, which only differs from
+    /// the stored name in ASCII case, keeping its value and place
+    pub(crate) fn adjust_case(&mut self, name: &str) {
+        if let Some(index) = self.position(name) {
+            self.entries[index].0 = name.to_string();
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let index = self.position(name)?;
+        Some(self.entries.remove(index).1)
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            entries: self.entries.iter(),
+        }
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.iter().map(|(name, _)| name)
+    }
+
+    /// Whether a boolean attribute such as `disabled` is present. Its value
+    /// is irrelevant, so `disabled="false"` still counts as set.
+    pub fn get_bool(&self, name: &str) -> bool {
+        self.contains(name)
+    }
+
+    /// Parses a value with the rules for non-negative integers, which allow
+    /// leading whitespace and ignore anything after the digits, so
+    /// `colspan=" 2px"` is 2
+    pub fn get_usize(&self, name: &str) -> Option<usize> {
+        let value = self
+            .get(name)?
+            .trim_start_matches(|c: char| c.is_ascii_whitespace());
+        let value = value.strip_prefix('+').unwrap_or(value);
+        let digits = value
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(value.len());
+        value[..digits].parse().ok()
+    }
+
+    /// A URL valued attribute such as `href` with the surrounding whitespace
+    /// browsers ignore removed. Empty values give `None`.
+    pub fn get_url(&self, name: &str) -> Option<&str> {
+        let url = self
+            .get(name)?
+            .trim_matches(|c: char| c.is_ascii_whitespace());
+        (!url.is_empty()).then_some(url)
+    }
+
+    /// The `data-*` attributes in source order, named as in the DOM's
+    /// `dataset`: `data-user-id` becomes `userId`
+    pub fn dataset(&self) -> Vec<(String, &str)> {
+        self.iter()
+            .filter_map(|(name, value)| Some((data_key(name.strip_prefix("data-")?), value)))
+            .collect()
+    }
+
+    /// Looks up a `data-*` attribute by its `dataset` name, so
+    /// `get_data("userId")` reads `data-user-id`
+    pub fn get_data(&self, key: &str) -> Option<&str> {
+        let mut name = String::from("data-");
+        for char in key.chars() {
+            if char.is_ascii_uppercase() {
+                name.push('-');
+            }
+            name.push(char.to_ascii_lowercase());
+        }
+        self.get(&name)
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|(key, _)| key.eq_ignore_ascii_case(name))
+    }
+}
+
+/// Turns the part of a `data-*` name after the prefix into camel case
+fn data_key(name: &str) -> String {
+    let mut key = String::with_capacity(name.len());
+    let mut chars = name.chars().peekable();
+    while let Some(char) = chars.next() {
+        match chars.peek() {
+            Some(next) if char == '-' && next.is_ascii_lowercase() => {
+                key.push(next.to_ascii_uppercase());
+                chars.next();
+            }
+            _ => key.push(char),
+        }
+    }
+    key
+}
+
+/// Two sets of attributes are equal when they have the same names and
+/// values, whatever order they were written in
+impl PartialEq for Attributes {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .all(|(name, value)| other.get(name) == Some(value))
+    }
+}
+
+impl Eq for Attributes {}
+
+impl Index<&str> for Attributes {
+    type Output = str;
+
+    /// Panics if the attribute is missing
+    fn index(&self, name: &str) -> &str {
+        match self.get(name) {
+            Some(value) => value,
+            None => panic!("no attribute named {name}"),
+        }
+    }
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a str, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries
+            .next()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+}
+
+impl<'a> IntoIterator for &'a Attributes {
+    type Item = (&'a str, &'a str);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+/// Collects name and value pairs, where a later duplicate replaces the value
+/// of the earlier one but keeps its position
+impl<N: AsRef<str>, V: AsRef<str>> FromIterator<(N, V)> for Attributes {
+    fn from_iter<I: IntoIterator<Item = (N, V)>>(iter: I) -> Self {
+        let mut attributes = Attributes::new();
+        for (name, value) in iter {
+            attributes.insert(name.as_ref(), value.as_ref());
+        }
+        attributes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_order_and_lookup() {
+        let mut attributes: Attributes = [("href", "/a"), ("CLASS", "x"), ("id", "b")]
+            .into_iter()
+            .collect();
+        assert_eq!(attributes.get("Class"), Some("x"));
+        assert_eq!(&attributes["ID"], "b");
+        assert_eq!(attributes.insert("class", "y"), Some("x".to_string()));
+        attributes.insert("title", "t");
+        assert_eq!(attributes.remove("href"), Some("/a".to_string()));
+        let names: Vec<&str> = attributes.names().collect();
+        assert_eq!(names, vec!["class", "id", "title"]);
+
+        let reordered: Attributes = [("title", "t"), ("id", "b"), ("class", "y")]
+            .into_iter()
+            .collect();
+        assert_eq!(attributes, reordered);
+    }
+
+    #[test]
+    fn check_typed_getters() {
+        let attributes: Attributes = [
+            ("colspan", " +2px"),
+            ("rowspan", "-1"),
+            ("hidden", ""),
+            ("href", "\n /next \t"),
+            ("src", "  "),
+            ("data-user-id", "7"),
+            ("data-x", "y"),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(attributes.get_usize("colspan"), Some(2));
+        assert_eq!(attributes.get_usize("rowspan"), None);
+        assert!(attributes.get_bool("hidden"));
+        assert!(!attributes.get_bool("disabled"));
+        assert_eq!(attributes.get_url("href"), Some("/next"));
+        assert_eq!(attributes.get_url("src"), None);
+        assert_eq!(
+            attributes.dataset(),
+            vec![("userId".to_string(), "7"), ("x".to_string(), "y")]
+        );
+        assert_eq!(attributes.get_data("userId"), Some("7"));
+    }
+}
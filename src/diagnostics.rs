@@ -0,0 +1,146 @@
+use std::fmt;
+
+use crate::span::Span;
+
+/// How serious a problem with the markup is. Errors are input browsers have
+/// to repair; warnings are omissions the specification allows for but which
+/// usually point to a mistake.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// The kinds of parse error which are reported. Tokeniser errors use the
+/// WHATWG parse error codes; the specification does not name tree
+/// construction errors, so those follow the codes used by html5lib.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    AbruptClosingOfEmptyComment,
+    CdataInHtmlContent,
+    DuplicateAttribute,
+    EndTagWithAttributes,
+    EndTagWithTrailingSolidus,
+    EofBeforeTagName,
+    EofInCdata,
+    EofInComment,
+    EofInDoctype,
+    EofInTag,
+    IncorrectlyClosedComment,
+    IncorrectlyOpenedComment,
+    InvalidFirstCharacterOfTagName,
+    MissingAttributeValue,
+    MissingEndTagName,
+    MissingWhitespaceBetweenAttributes,
+    NonVoidHtmlElementStartTagWithTrailingSolidus,
+    UnexpectedCharacterInAttributeName,
+    UnexpectedCharacterInUnquotedAttributeValue,
+    UnexpectedEqualsSignBeforeAttributeName,
+    UnexpectedNullCharacter,
+    UnexpectedQuestionMarkInsteadOfTagName,
+    UnexpectedSolidusInTag,
+    MissingDoctype,
+    UnexpectedEndTag,
+    EndTagTooEarly,
+    ExpectedClosingTagButGotEof,
+}
+
+/// A single problem found while parsing
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub code: ErrorCode,
+    pub severity: Severity,
+    pub span: Span,
+    /// The tag or attribute name involved, where there is one
+    pub name: Option<String>,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::AbruptClosingOfEmptyComment => "abrupt-closing-of-empty-comment",
+            ErrorCode::CdataInHtmlContent => "cdata-in-html-content",
+            ErrorCode::DuplicateAttribute => "duplicate-attribute",
+            ErrorCode::EndTagWithAttributes => "end-tag-with-attributes",
+            ErrorCode::EndTagWithTrailingSolidus => "end-tag-with-trailing-solidus",
+            ErrorCode::EofBeforeTagName => "eof-before-tag-name",
+            ErrorCode::EofInCdata => "eof-in-cdata",
+            ErrorCode::EofInComment => "eof-in-comment",
+            ErrorCode::EofInDoctype => "eof-in-doctype",
+            ErrorCode::EofInTag => "eof-in-tag",
+            ErrorCode::IncorrectlyClosedComment => "incorrectly-closed-comment",
+            ErrorCode::IncorrectlyOpenedComment => "incorrectly-opened-comment",
+            ErrorCode::InvalidFirstCharacterOfTagName => "invalid-first-character-of-tag-name",
+            ErrorCode::MissingAttributeValue => "missing-attribute-value",
+            ErrorCode::MissingEndTagName => "missing-end-tag-name",
+            ErrorCode::MissingWhitespaceBetweenAttributes => {
+                "missing-whitespace-between-attributes"
+            }
+            ErrorCode::NonVoidHtmlElementStartTagWithTrailingSolidus => {
+                "non-void-html-element-start-tag-with-trailing-solidus"
+            }
+            ErrorCode::UnexpectedCharacterInAttributeName => {
+                "unexpected-character-in-attribute-name"
+            }
+            ErrorCode::UnexpectedCharacterInUnquotedAttributeValue => {
+                "unexpected-character-in-unquoted-attribute-value"
+            }
+            ErrorCode::UnexpectedEqualsSignBeforeAttributeName => {
+                "unexpected-equals-sign-before-attribute-name"
+            }
+            ErrorCode::UnexpectedNullCharacter => "unexpected-null-character",
+            ErrorCode::UnexpectedQuestionMarkInsteadOfTagName => {
+                "unexpected-question-mark-instead-of-tag-name"
+            }
+            ErrorCode::UnexpectedSolidusInTag => "unexpected-solidus-in-tag",
+            ErrorCode::MissingDoctype => "missing-doctype",
+            ErrorCode::UnexpectedEndTag => "unexpected-end-tag",
+            ErrorCode::EndTagTooEarly => "end-tag-too-early",
+            ErrorCode::ExpectedClosingTagButGotEof => "expected-closing-tag-but-got-eof",
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        match self {
+            ErrorCode::MissingDoctype
+            | ErrorCode::NonVoidHtmlElementStartTagWithTrailingSolidus
+            | ErrorCode::ExpectedClosingTagButGotEof => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl Diagnostic {
+    pub(crate) fn new(code: ErrorCode, span: Span, name: Option<&str>) -> Self {
+        Diagnostic {
+            code,
+            severity: code.severity(),
+            span,
+            name: name.map(str::to_string),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(
+            f,
+            "{}:{}: {severity}: {}",
+            self.span.start.line, self.span.start.column, self.code
+        )?;
+        if let Some(name) = &self.name {
+            write!(f, " ({name})")?;
+        }
+        Ok(())
+    }
+}
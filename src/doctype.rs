@@ -0,0 +1,272 @@
+use std::fmt;
+
+/// A parsed `<!DOCTYPE>`. Names are lowercased; identifiers keep their case.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Doctype {
+    pub name: Option<String>,
+    pub public_id: Option<String>,
+    pub system_id: Option<String>,
+    /// Set when the doctype was malformed, which always puts a document in quirks mode
+    pub force_quirks: bool,
+}
+
+/// The rendering mode a doctype selects, which decides how browsers lay out
+/// a page and so how old markup was meant to look
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum QuirksMode {
+    #[default]
+    NoQuirks,
+    LimitedQuirks,
+    Quirks,
+}
+
+const QUIRKS_PUBLIC_PREFIXES: &[&str] = &[
+    "+//silmaril//dtd html pro v0r11 19970101//",
+    "-//as//dtd html 3.0 aswedit + extensions//",
+    "-//advasoft ltd//dtd html 3.0 aswedit + extensions//",
+    "-//ietf//dtd html 2.0 level 1//",
+    "-//ietf//dtd html 2.0 level 2//",
+    "-//ietf//dtd html 2.0 strict level 1//",
+    "-//ietf//dtd html 2.0 strict level 2//",
+    "-//ietf//dtd html 2.0 strict//",
+    "-//ietf//dtd html 2.0//",
+    "-//ietf//dtd html 2.1e//",
+    "-//ietf//dtd html 3.0//",
+    "-//ietf//dtd html 3.2 final//",
+    "-//ietf//dtd html 3.2//",
+    "-//ietf//dtd html 3//",
+    "-//ietf//dtd html level 0//",
+    "-//ietf//dtd html level 1//",
+    "-//ietf//dtd html level 2//",
+    "-//ietf//dtd html level 3//",
+    "-//ietf//dtd html strict level 0//",
+    "-//ietf//dtd html strict level 1//",
+    "-//ietf//dtd html strict level 2//",
+    "-//ietf//dtd html strict level 3//",
+    "-//ietf//dtd html strict//",
+    "-//ietf//dtd html//",
+    "-//metrius//dtd metrius presentational//",
+    "-//microsoft//dtd internet explorer 2.0 html strict//",
+    "-//microsoft//dtd internet explorer 2.0 html//",
+    "-//microsoft//dtd internet explorer 2.0 tables//",
+    "-//microsoft//dtd internet explorer 3.0 html strict//",
+    "-//microsoft//dtd internet explorer 3.0 html//",
+    "-//microsoft//dtd internet explorer 3.0 tables//",
+    "-//netscape comm. corp.//dtd html//",
+    "-//netscape comm. corp.//dtd strict html//",
+    "-//o'reilly and associates//dtd html 2.0//",
+    "-//o'reilly and associates//dtd html extended 1.0//",
+    "-//o'reilly and associates//dtd html extended relaxed 1.0//",
+    "-//sq//dtd html 2.0 hotmetal + extensions//",
+    "-//softquad software//dtd hotmetal pro 6.0::19990601::extensions to html 4.0//",
+    "-//softquad//dtd hotmetal pro 4.0::19971010::extensions to html 4.0//",
+    "-//spyglass//dtd html 2.0 extended//",
+    "-//sun microsystems corp.//dtd hotjava html//",
+    "-//sun microsystems corp.//dtd hotjava strict html//",
+    "-//w3c//dtd html 3 1995-03-24//",
+    "-//w3c//dtd html 3.2 draft//",
+    "-//w3c//dtd html 3.2 final//",
+    "-//w3c//dtd html 3.2//",
+    "-//w3c//dtd html 3.2s draft//",
+    "-//w3c//dtd html 4.0 frameset//",
+    "-//w3c//dtd html 4.0 transitional//",
+    "-//w3c//dtd html experimental 19960712//",
+    "-//w3c//dtd html experimental 970421//",
+    "-//w3c//dtd w3 html//",
+    "-//w3o//dtd w3 html 3.0//",
+    "-//webtechs//dtd mozilla html 2.0//",
+    "-//webtechs//dtd mozilla html//",
+];
+
+const QUIRKS_PUBLIC_IDS: &[&str] = &[
+    "-//w3o//dtd w3 html strict 3.0//en//",
+    "-/w3c/dtd html 4.0 transitional/en",
+    "html",
+];
+
+/// HTML 4.01 doctypes, which are quirky without a system identifier and only
+/// limited-quirky with one
+const HTML_401_PUBLIC_PREFIXES: &[&str] = &[
+    "-//w3c//dtd html 4.01 frameset//",
+    "-//w3c//dtd html 4.01 transitional//",
+];
+
+const LIMITED_QUIRKS_PUBLIC_PREFIXES: &[&str] = &[
+    "-//w3c//dtd xhtml 1.0 frameset//",
+    "-//w3c//dtd xhtml 1.0 transitional//",
+];
+
+impl Doctype {
+    /// Parses the source of a doctype token, from `<!doctype` up to and
+    /// including the closing `>` if there is one
+    pub(crate) fn parse(source: &str) -> Doctype {
+        let mut doctype = Doctype::default();
+        let contents = match source.strip_suffix('>') {
+            Some(contents) => contents,
+            None => {
+                doctype.force_quirks = true;
+                source
+            }
+        };
+        let rest = contents
+            .get(9..)
+            .unwrap_or_default()
+            .trim_start_matches(is_whitespace);
+        let name_length = rest.find(is_whitespace).unwrap_or(rest.len());
+        if name_length == 0 {
+            doctype.force_quirks = true;
+            return doctype;
+        }
+        doctype.name = Some(rest[..name_length].to_ascii_lowercase());
+
+        let rest = rest[name_length..].trim_start_matches(is_whitespace);
+        if rest.is_empty() {
+            return doctype;
+        }
+        let keyword = rest.get(..6).unwrap_or_default();
+        let rest = rest
+            .get(6..)
+            .unwrap_or_default()
+            .trim_start_matches(is_whitespace);
+        if keyword.eq_ignore_ascii_case("public") {
+            let Some((public_id, rest)) = quoted(rest) else {
+                doctype.force_quirks = true;
+                return doctype;
+            };
+            doctype.public_id = Some(public_id.to_string());
+            let rest = rest.trim_start_matches(is_whitespace);
+            if rest.is_empty() {
+                return doctype;
+            }
+            match quoted(rest) {
+                Some((system_id, _)) => doctype.system_id = Some(system_id.to_string()),
+                None => doctype.force_quirks = true,
+            }
+        } else if keyword.eq_ignore_ascii_case("system") {
+            match quoted(rest) {
+                Some((system_id, _)) => doctype.system_id = Some(system_id.to_string()),
+                None => doctype.force_quirks = true,
+            }
+        } else {
+            doctype.force_quirks = true;
+        }
+        doctype
+    }
+
+    /// The mode this doctype puts a document in, following the rules of the
+    /// "initial" insertion mode
+    pub fn quirks_mode(&self) -> QuirksMode {
+        let public_id = self.public_id.as_deref().map(str::to_ascii_lowercase);
+        let public_id = public_id.as_deref();
+        let system_id = self.system_id.as_deref().map(str::to_ascii_lowercase);
+        let public_starts_with = |prefixes: &[&str]| {
+            public_id.is_some_and(|id| prefixes.iter().any(|prefix| id.starts_with(prefix)))
+        };
+
+        if self.force_quirks
+            || self.name.as_deref() != Some("html")
+            || public_id.is_some_and(|id| QUIRKS_PUBLIC_IDS.contains(&id))
+            || system_id.as_deref()
+                == Some("http://www.ibm.com/data/dtd/v11/ibmxhtml1-transitional.dtd")
+            || public_starts_with(QUIRKS_PUBLIC_PREFIXES)
+            || (system_id.is_none() && public_starts_with(HTML_401_PUBLIC_PREFIXES))
+        {
+            QuirksMode::Quirks
+        } else if public_starts_with(LIMITED_QUIRKS_PUBLIC_PREFIXES)
+            || (system_id.is_some() && public_starts_with(HTML_401_PUBLIC_PREFIXES))
+        {
+            QuirksMode::LimitedQuirks
+        } else {
+            QuirksMode::NoQuirks
+        }
+    }
+}
+
+impl fmt::Display for Doctype {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<!DOCTYPE {}", self.name.as_deref().unwrap_or_default())?;
+        match (&self.public_id, &self.system_id) {
+            (Some(public_id), Some(system_id)) => {
+                write!(f, " PUBLIC \"{public_id}\" \"{system_id}\"")?
+            }
+            (Some(public_id), None) => write!(f, " PUBLIC \"{public_id}\"")?,
+            (None, Some(system_id)) => write!(f, " SYSTEM \"{system_id}\"")?,
+            (None, None) => {}
+        }
+        write!(f, ">")
+    }
+}
+
+fn is_whitespace(char: char) -> bool {
+    matches!(char, '\t' | '\n' | '\x0C' | '\r' | ' ')
+}
+
+/// Splits a leading single or double quoted string from the rest of the input
+fn quoted(input: &str) -> Option<(&str, &str)> {
+    let quote = input
+        .chars()
+        .next()
+        .filter(|&char| char == '"' || char == '\'')?;
+    let end = input[1..].find(quote)? + 1;
+    Some((&input[1..end], &input[end + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_parse() {
+        assert_eq!(
+            Doctype::parse("<!DOCTYPE html>"),
+            Doctype {
+                name: Some("html".to_string()),
+                ..Default::default()
+            }
+        );
+        let strict = Doctype::parse(
+            r#"<!doctype HTML PUBLIC "-//W3C//DTD HTML 4.01//EN" 'http://www.w3.org/TR/html4/strict.dtd'>"#,
+        );
+        assert_eq!(
+            strict.public_id.as_deref(),
+            Some("-//W3C//DTD HTML 4.01//EN")
+        );
+        assert_eq!(
+            strict.system_id.as_deref(),
+            Some("http://www.w3.org/TR/html4/strict.dtd")
+        );
+        assert!(!strict.force_quirks);
+        assert_eq!(
+            strict.to_string(),
+            r#"<!DOCTYPE html PUBLIC "-//W3C//DTD HTML 4.01//EN" "http://www.w3.org/TR/html4/strict.dtd">"#
+        );
+        assert!(Doctype::parse("<!DOCTYPE>").force_quirks);
+        assert!(Doctype::parse("<!DOCTYPE html PUBLIC>").force_quirks);
+        assert!(Doctype::parse("<!DOCTYPE html").force_quirks);
+    }
+
+    #[test]
+    fn check_quirks_mode() {
+        let mode = |source: &str| Doctype::parse(source).quirks_mode();
+        assert_eq!(mode("<!DOCTYPE html>"), QuirksMode::NoQuirks);
+        assert_eq!(
+            mode(r#"<!DOCTYPE html SYSTEM "about:legacy-compat">"#),
+            QuirksMode::NoQuirks
+        );
+        assert_eq!(mode("<!DOCTYPE svg>"), QuirksMode::Quirks);
+        assert_eq!(
+            mode(r#"<!DOCTYPE HTML PUBLIC "-//W3C//DTD HTML 4.01 Transitional//EN">"#),
+            QuirksMode::Quirks
+        );
+        assert_eq!(
+            mode(
+                r#"<!DOCTYPE HTML PUBLIC "-//W3C//DTD HTML 4.01 Transitional//EN" "http://www.w3.org/TR/html4/loose.dtd">"#
+            ),
+            QuirksMode::LimitedQuirks
+        );
+        assert_eq!(
+            mode(r#"<!DOCTYPE html PUBLIC "-//W3C//DTD XHTML 1.0 Transitional//EN" "x">"#),
+            QuirksMode::LimitedQuirks
+        );
+    }
+}
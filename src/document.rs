@@ -0,0 +1,959 @@
+use std::{cell::RefCell, io::Read, iter, ops::Index, rc::Rc};
+
+use crate::{
+    attributes::Attributes,
+    diagnostics::Diagnostic,
+    doctype::{Doctype, QuirksMode},
+    encoding::{self, DetectedEncoding},
+    form::{self, Form},
+    html_elements::HtmlElement,
+    links::{self, Link},
+    markdown::{self, MarkdownOptions},
+    metadata::{self, Metadata},
+    node_tree::{Namespace, Node, NodeRef, NodeType, TreeNode},
+    selector::{Selector, SelectorError},
+    serializer::{self, SerializeOptions},
+    span::{SourceLocation, Span},
+    table::{self, Table},
+    text::{self, TextOptions},
+    tokeniser::{TokenStream, Tokeniser},
+    tree_builder,
+    whitespace::{self, WhitespacePolicy},
+};
+
+/// The index of a node within a [`Document`]. Ids are only meaningful for the
+/// document which handed them out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(usize);
+
+/// A single node stored in a [`Document`], linked to its neighbours by id
+#[derive(Clone, Debug)]
+pub struct DocumentNode {
+    pub(crate) node_type: NodeType,
+    pub(crate) properties: Attributes,
+    pub(crate) namespace: Namespace,
+    pub(crate) location: Option<SourceLocation>,
+    parent: Option<NodeId>,
+    first_child: Option<NodeId>,
+    last_child: Option<NodeId>,
+    previous_sibling: Option<NodeId>,
+    next_sibling: Option<NodeId>,
+}
+
+/// A parsed document with every node held in one contiguous arena. Nodes
+/// refer to each other by [`NodeId`] rather than by pointer, so the whole
+/// document can be traversed without borrowing and moved between threads.
+///
+/// A `Document` has no public methods which change it; edit the copy made by
+/// [`to_node_tree`](Self::to_node_tree) instead. Holding no `Rc` or `RefCell`
+/// makes it `Send + Sync`, so wrap it in an `Arc` to query one parse from
+/// many threads at once.
+#[derive(Clone, Debug)]
+pub struct Document {
+    nodes: Vec<DocumentNode>,
+    pub(crate) quirks_mode: QuirksMode,
+}
+
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Document>();
+    assert_send_sync::<NodeId>();
+};
+
+/// A node paired with the document it lives in, for the shared query code
+#[derive(Clone, Copy)]
+pub(crate) struct DocumentNodeRef<'a> {
+    document: &'a Document,
+    id: NodeId,
+}
+
+impl TreeNode for DocumentNodeRef<'_> {
+    fn with_node_type<R>(&self, f: impl FnOnce(&NodeType) -> R) -> R {
+        f(&self.document[self.id].node_type)
+    }
+
+    fn parent(&self) -> Option<Self> {
+        self.document[self.id]
+            .parent
+            .map(|id| self.document.node_ref(id))
+    }
+
+    fn children(&self) -> Vec<Self> {
+        self.document.child_refs(self.id)
+    }
+
+    fn previous_siblings(&self) -> impl Iterator<Item = Self> {
+        let document = self.document;
+        iter::successors(document[self.id].previous_sibling, move |&id| {
+            document[id].previous_sibling
+        })
+        .map(move |id| document.node_ref(id))
+    }
+
+    fn next_siblings(&self) -> impl Iterator<Item = Self> {
+        let document = self.document;
+        iter::successors(document[self.id].next_sibling, move |&id| {
+            document[id].next_sibling
+        })
+        .map(move |id| document.node_ref(id))
+    }
+
+    fn attribute(&self, name: &str) -> Option<String> {
+        self.document[self.id]
+            .properties
+            .get(name)
+            .map(str::to_string)
+    }
+
+    fn with_attributes<R>(&self, f: impl FnOnce(&Attributes) -> R) -> R {
+        f(&self.document[self.id].properties)
+    }
+
+    fn same_node(&self, other: &Self) -> bool {
+        std::ptr::eq(self.document, other.document) && self.id == other.id
+    }
+}
+
+impl DocumentNode {
+    pub fn node_type(&self) -> &NodeType {
+        &self.node_type
+    }
+
+    pub fn properties(&self) -> &Attributes {
+        &self.properties
+    }
+
+    /// The namespace the node was created in, HTML for anything not an element
+    pub fn namespace(&self) -> Namespace {
+        self.namespace
+    }
+
+    /// Where the node was parsed from, if it came from source
+    pub fn location(&self) -> Option<&SourceLocation> {
+        self.location.as_ref()
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn first_child(&self) -> Option<NodeId> {
+        self.first_child
+    }
+
+    pub fn last_child(&self) -> Option<NodeId> {
+        self.last_child
+    }
+
+    pub fn previous_sibling(&self) -> Option<NodeId> {
+        self.previous_sibling
+    }
+
+    pub fn next_sibling(&self) -> Option<NodeId> {
+        self.next_sibling
+    }
+
+    /// Returns the element kind, or `None` for documents, text and comments
+    pub fn element(&self) -> Option<&HtmlElement> {
+        match &self.node_type {
+            NodeType::Element(element) => Some(element),
+            _ => None,
+        }
+    }
+}
+
+impl Index<NodeId> for Document {
+    type Output = DocumentNode;
+
+    fn index(&self, id: NodeId) -> &DocumentNode {
+        &self.nodes[id.0]
+    }
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Controls how source is parsed by [`Document::parse_with`] and
+/// [`Document::from_token_stream_with`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ParseOptions {
+    pub whitespace: WhitespacePolicy,
+}
+
+impl Document {
+    /// Creates a document holding only its root node
+    pub fn new() -> Self {
+        let mut document = Document {
+            nodes: vec![],
+            quirks_mode: QuirksMode::NoQuirks,
+        };
+        document.create_node(NodeType::Document, Attributes::new());
+        document
+    }
+
+    /// Tokenises and builds a document from HTML source
+    pub fn parse(html: &str) -> Self {
+        Self::parse_with(html, &ParseOptions::default())
+    }
+
+    /// Parses like [`Document::parse`] with control over how whitespace in
+    /// text is kept
+    pub fn parse_with(html: &str, options: &ParseOptions) -> Self {
+        let mut document = tree_builder::build(Tokeniser::with_input(html));
+        whitespace::apply(&mut document, options.whitespace);
+        document
+    }
+
+    /// Parses like [`Document::parse`] but also returns every parse error
+    /// found, in source order
+    pub fn parse_with_diagnostics(html: &str) -> (Self, Vec<Diagnostic>) {
+        let mut tokeniser = Tokeniser::with_input(html);
+        let (mut document, mut diagnostics) =
+            tree_builder::build_with_diagnostics(tokeniser.by_ref());
+        whitespace::apply(&mut document, WhitespacePolicy::default());
+        diagnostics.extend(tokeniser.take_diagnostics());
+        diagnostics.sort_by_key(|diagnostic| diagnostic.span.start.offset);
+        (document, diagnostics)
+    }
+
+    /// Sniffs the encoding of raw bytes, decodes them and builds a document.
+    /// `transport_hint` is the charset from a Content-Type header, if known.
+    pub fn parse_bytes(bytes: &[u8], transport_hint: Option<&str>) -> (Self, DetectedEncoding) {
+        let (html, detected) = encoding::decode(bytes, transport_hint);
+        (Self::parse(&html), detected)
+    }
+
+    /// Builds a document from a token stream, following the HTML tree
+    /// construction rules for implied and mis-nested tags. The tokens were
+    /// read without tree construction, so a `<![CDATA[` inside SVG or MathML
+    /// is a bogus comment rather than text.
+    pub fn from_token_stream(token_stream: TokenStream) -> Self {
+        Self::from_token_stream_with(token_stream, &ParseOptions::default())
+    }
+
+    /// Builds a document like [`Document::from_token_stream`] with control
+    /// over how whitespace in text is kept
+    pub fn from_token_stream_with(token_stream: TokenStream, options: &ParseOptions) -> Self {
+        let mut document = tree_builder::build(token_stream);
+        whitespace::apply(&mut document, options.whitespace);
+        document
+    }
+
+    /// Tokenises and builds a document from UTF-8 read incrementally, so the
+    /// source never has to be held in memory as a whole
+    pub fn from_reader<R: Read>(reader: R) -> std::io::Result<Self> {
+        let mut tokens = Tokeniser::from_reader(reader);
+        let mut document = tree_builder::build(tokens.by_ref());
+        whitespace::apply(&mut document, WhitespacePolicy::default());
+        match tokens.take_error() {
+            Some(error) => Err(error),
+            None => Ok(document),
+        }
+    }
+
+    /// The id of the document node everything else descends from
+    pub fn root(&self) -> NodeId {
+        NodeId(0)
+    }
+
+    /// The document's doctype, if it had one
+    pub fn doctype(&self) -> Option<&Doctype> {
+        self.children(self.root())
+            .find_map(|child| match &self[child].node_type {
+                NodeType::Doctype(doctype) => Some(doctype),
+                _ => None,
+            })
+    }
+
+    /// The rendering mode picked from the doctype. Documents without one are
+    /// in quirks mode.
+    pub fn quirks_mode(&self) -> QuirksMode {
+        self.quirks_mode
+    }
+
+    /// The number of nodes in the arena, counting the root and any nodes the
+    /// parser detached, so it can exceed the number reachable from the root
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Whether the root has no children, as for [`Document::new`]
+    pub fn is_empty(&self) -> bool {
+        self[self.root()].first_child.is_none()
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<&DocumentNode> {
+        self.nodes.get(id.0)
+    }
+
+    /// Returns the children of a node in order
+    pub fn children(&self, id: NodeId) -> Children<'_> {
+        Children {
+            document: self,
+            next: self[id].first_child,
+        }
+    }
+
+    /// Returns the ancestors of a node, nearest first, ending with the root
+    pub fn ancestors(&self, id: NodeId) -> Ancestors<'_> {
+        Ancestors {
+            document: self,
+            next: self[id].parent,
+        }
+    }
+
+    /// Returns every node below this one in document order, not including itself
+    pub fn descendants(&self, id: NodeId) -> Descendants<'_> {
+        Descendants {
+            document: self,
+            root: id,
+            next: self[id].first_child,
+        }
+    }
+
+    pub fn get_element_by_id(&self, id: &str) -> Option<NodeId> {
+        self.descendants(self.root()).find(|&node| {
+            self[node].element().is_some()
+                && self[node].properties.get("id").is_some_and(|v| v == id)
+        })
+    }
+
+    pub fn get_elements_by_tag(&self, tag: &HtmlElement) -> Vec<NodeId> {
+        self.descendants(self.root())
+            .filter(|&node| self[node].element() == Some(tag))
+            .collect()
+    }
+
+    pub fn get_elements_by_class(&self, class: &str) -> Vec<NodeId> {
+        self.descendants(self.root())
+            .filter(|&node| {
+                self[node].element().is_some()
+                    && self.get_class_list(node).iter().any(|c| c == class)
+            })
+            .collect()
+    }
+
+    pub fn get_class_list(&self, id: NodeId) -> Vec<String> {
+        self[id]
+            .properties
+            .get("class")
+            .map(|classes| classes.split_whitespace().map(|v| v.to_string()).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn inner_text(&self, id: NodeId) -> String {
+        if let NodeType::Text(text) = &self[id].node_type {
+            return text.clone();
+        }
+        self.descendants(id)
+            .filter_map(|node| match &self[node].node_type {
+                NodeType::Text(text) => Some(text.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The span of source a node was parsed from, running from its start tag
+    /// to its end tag or, if that was implied, to the end of its last descendant
+    pub fn source_span(&self, id: NodeId) -> Option<Span> {
+        let location = self[id].location.as_ref()?;
+        let end = self
+            .descendants(id)
+            .filter_map(|node| self[node].location.as_ref())
+            .map(SourceLocation::end)
+            .chain([location.end()])
+            .max_by_key(|position| position.offset)?;
+        Some(Span {
+            start: location.span.start,
+            end,
+        })
+    }
+
+    /// The text of `source`, the HTML the document was parsed from, which a
+    /// node was parsed from
+    pub fn source<'a>(&self, id: NodeId, source: &'a str) -> Option<&'a str> {
+        self.source_span(id)?.slice(source)
+    }
+
+    pub fn inner_html(&self, id: NodeId) -> String {
+        self.inner_html_with(id, &SerializeOptions::default())
+    }
+
+    pub fn inner_html_with(&self, id: NodeId, options: &SerializeOptions) -> String {
+        let children = self.child_refs(id);
+        let parent = self[id].parent.map(|parent| self.node_ref(parent));
+        serializer::inner_html(&self[id].node_type, &children, parent, options)
+    }
+
+    pub fn outer_html(&self, id: NodeId) -> String {
+        self.outer_html_with(id, &SerializeOptions::default())
+    }
+
+    pub fn outer_html_with(&self, id: NodeId, options: &SerializeOptions) -> String {
+        let children = self.child_refs(id);
+        let parent = self[id].parent.map(|parent| self.node_ref(parent));
+        serializer::outer_html(
+            &self[id].node_type,
+            &self[id].properties,
+            &children,
+            parent,
+            options,
+        )
+    }
+
+    /// Converts a node and its descendants to Markdown
+    pub fn to_markdown(&self, id: NodeId, options: &MarkdownOptions) -> String {
+        let children = self.child_refs(id);
+        markdown::to_markdown(
+            &self[id].node_type,
+            &self[id].properties,
+            &children,
+            options,
+        )
+    }
+
+    /// Renders a node and its descendants as plain text laid out like a
+    /// browser would
+    pub fn to_text(&self, id: NodeId, options: &TextOptions) -> String {
+        let children = self.child_refs(id);
+        text::to_text(
+            &self[id].node_type,
+            &self[id].properties,
+            &children,
+            options,
+        )
+    }
+
+    /// Collects every URL in the document, from links, images, forms,
+    /// embedded content and CSS `url()`s in `style` attributes, resolved
+    /// against `document_url` and the document's `<base href>`
+    pub fn links(&self, document_url: Option<&str>) -> Vec<Link> {
+        let root = self.root();
+        let children = self.child_refs(root);
+        links::extract_links(
+            &self[root].node_type,
+            &self[root].properties,
+            &children,
+            document_url,
+        )
+    }
+
+    /// Lays out a `<table>` element as a grid. Returns `None` for any other
+    /// node.
+    pub fn table(&self, id: NodeId) -> Option<Table> {
+        let children = self.child_refs(id);
+        table::build_table(&self[id].node_type, &children)
+    }
+
+    /// Every table in the document, in document order
+    pub fn tables(&self) -> Vec<Table> {
+        self.get_elements_by_tag(&HtmlElement::Table)
+            .into_iter()
+            .filter_map(|id| self.table(id))
+            .collect()
+    }
+
+    /// Builds the form for a `<form>` element, resolving its action against
+    /// `document_url` and the document's `<base href>`. Returns `None` for
+    /// any other node.
+    pub fn form(&self, id: NodeId, document_url: Option<&str>) -> Option<Form> {
+        form::build_form(&self.node_ref(id), document_url)
+    }
+
+    /// Every form in the document, in document order
+    pub fn forms(&self, document_url: Option<&str>) -> Vec<Form> {
+        self.get_elements_by_tag(&HtmlElement::Form)
+            .into_iter()
+            .filter_map(|id| self.form(id, document_url))
+            .collect()
+    }
+
+    /// Reads the page's title, meta tags, OpenGraph and Twitter card
+    /// properties and head links, resolving URLs against `document_url` and
+    /// the document's `<base href>`
+    pub fn metadata(&self, document_url: Option<&str>) -> Metadata {
+        let children = self.child_refs(self.root());
+        metadata::extract_metadata(&children, document_url)
+    }
+
+    /// Returns every element matching the CSS selector, in document order
+    pub fn select(&self, selector: &str) -> Result<Vec<NodeId>, SelectorError> {
+        Ok(self.select_within(self.root(), &Selector::parse(selector)?))
+    }
+
+    /// Returns the first element matching the CSS selector
+    pub fn select_first(&self, selector: &str) -> Result<Option<NodeId>, SelectorError> {
+        let selector = Selector::parse(selector)?;
+        let mut matches = vec![];
+        selector.collect(&self.node_ref(self.root()), None, true, &mut matches);
+        Ok(matches.first().map(|node| node.id))
+    }
+
+    /// Returns the descendants of `id` matching an already parsed selector,
+    /// with `:scope` referring to `id`
+    pub fn select_within(&self, id: NodeId, selector: &Selector) -> Vec<NodeId> {
+        let node = self.node_ref(id);
+        let scope = (id != self.root()).then_some(node);
+        let mut matches = vec![];
+        selector.collect(&node, scope.as_ref(), false, &mut matches);
+        matches.into_iter().map(|node| node.id).collect()
+    }
+
+    /// Checks whether a node matches the selector
+    pub fn matches(&self, id: NodeId, selector: &Selector) -> bool {
+        selector.matches_in_scope(&self.node_ref(id), None)
+    }
+
+    fn node_ref(&self, id: NodeId) -> DocumentNodeRef<'_> {
+        DocumentNodeRef { document: self, id }
+    }
+
+    /// The children of a node, paired with the document for the shared
+    /// serializing and extraction code
+    fn child_refs(&self, id: NodeId) -> Vec<DocumentNodeRef<'_>> {
+        self.children(id)
+            .map(|child| self.node_ref(child))
+            .collect()
+    }
+
+    /// Copies a node tree into a new document. Anything other than a
+    /// document node becomes the only child of the new root.
+    pub fn from_node(node: &Node) -> Self {
+        let mut document = Document::new();
+        let root = document.root();
+        match node.node_type {
+            NodeType::Document => {
+                for child in &node.children {
+                    document.copy_node(&child.borrow(), root);
+                }
+            }
+            _ => document.copy_node(node, root),
+        }
+        document
+    }
+
+    fn copy_node(&mut self, node: &Node, parent: NodeId) {
+        let id = self.create_node(node.node_type.clone(), node.properties.clone());
+        self.nodes[id.0].namespace = node.namespace;
+        self.nodes[id.0].location = node.location.clone();
+        self.append(parent, id);
+        for child in &node.children {
+            self.copy_node(&child.borrow(), id);
+        }
+    }
+
+    /// Copies the document into a tree of [`NodeRef`]s
+    pub fn to_node_tree(&self) -> NodeRef {
+        self.to_node_ref(self.root(), None)
+    }
+
+    fn to_node_ref(&self, id: NodeId, parent: Option<&NodeRef>) -> NodeRef {
+        let node = Rc::new(RefCell::new(Node {
+            node_type: self[id].node_type.clone(),
+            parent_element: parent.map(Rc::downgrade),
+            children: vec![],
+            properties: self[id].properties.clone(),
+            namespace: self[id].namespace,
+            location: self[id].location.clone(),
+        }));
+        let children = self
+            .children(id)
+            .map(|child| self.to_node_ref(child, Some(&node)))
+            .collect();
+        node.borrow_mut().children = children;
+        node
+    }
+
+    pub(crate) fn create_node(&mut self, node_type: NodeType, properties: Attributes) -> NodeId {
+        self.nodes.push(DocumentNode {
+            node_type,
+            properties,
+            namespace: Namespace::Html,
+            location: None,
+            parent: None,
+            first_child: None,
+            last_child: None,
+            previous_sibling: None,
+            next_sibling: None,
+        });
+        NodeId(self.nodes.len() - 1)
+    }
+
+    pub(crate) fn node_mut(&mut self, id: NodeId) -> &mut DocumentNode {
+        &mut self.nodes[id.0]
+    }
+
+    /// Unlinks a node from its parent and siblings, keeping its own children
+    pub(crate) fn detach(&mut self, id: NodeId) {
+        let DocumentNode {
+            parent,
+            previous_sibling,
+            next_sibling,
+            ..
+        } = self[id];
+        let Some(parent) = parent else {
+            return;
+        };
+        match previous_sibling {
+            Some(previous) => self.nodes[previous.0].next_sibling = next_sibling,
+            None => self.nodes[parent.0].first_child = next_sibling,
+        }
+        match next_sibling {
+            Some(next) => self.nodes[next.0].previous_sibling = previous_sibling,
+            None => self.nodes[parent.0].last_child = previous_sibling,
+        }
+        let node = &mut self.nodes[id.0];
+        node.parent = None;
+        node.previous_sibling = None;
+        node.next_sibling = None;
+    }
+
+    /// Moves a node to the end of `parent`'s children
+    pub(crate) fn append(&mut self, parent: NodeId, id: NodeId) {
+        self.detach(id);
+        let previous = self[parent].last_child;
+        match previous {
+            Some(previous) => self.nodes[previous.0].next_sibling = Some(id),
+            None => self.nodes[parent.0].first_child = Some(id),
+        }
+        self.nodes[parent.0].last_child = Some(id);
+        let node = &mut self.nodes[id.0];
+        node.parent = Some(parent);
+        node.previous_sibling = previous;
+    }
+
+    /// Moves a node so that it sits immediately before `before`. Leaves the
+    /// node where it was if `before` has no parent to insert into.
+    pub(crate) fn insert_before(&mut self, before: NodeId, id: NodeId) {
+        let Some(parent) = self[before].parent else {
+            return;
+        };
+        if id == before {
+            return;
+        }
+        self.detach(id);
+        let previous = self[before].previous_sibling;
+        match previous {
+            Some(previous) => self.nodes[previous.0].next_sibling = Some(id),
+            None => self.nodes[parent.0].first_child = Some(id),
+        }
+        self.nodes[before.0].previous_sibling = Some(id);
+        let node = &mut self.nodes[id.0];
+        node.parent = Some(parent);
+        node.previous_sibling = previous;
+        node.next_sibling = Some(before);
+    }
+}
+
+/// Iterator over the children of a node, created by [`Document::children`]
+pub struct Children<'a> {
+    document: &'a Document,
+    next: Option<NodeId>,
+}
+
+impl Iterator for Children<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let current = self.next?;
+        self.next = self.document[current].next_sibling;
+        Some(current)
+    }
+}
+
+/// Iterator over the ancestors of a node, created by [`Document::ancestors`]
+pub struct Ancestors<'a> {
+    document: &'a Document,
+    next: Option<NodeId>,
+}
+
+impl Iterator for Ancestors<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let current = self.next?;
+        self.next = self.document[current].parent;
+        Some(current)
+    }
+}
+
+/// Pre-order iterator over a subtree, created by [`Document::descendants`]
+pub struct Descendants<'a> {
+    document: &'a Document,
+    root: NodeId,
+    next: Option<NodeId>,
+}
+
+impl Iterator for Descendants<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let current = self.next?;
+        let node = &self.document[current];
+        self.next = node.first_child.or_else(|| {
+            // Climb until there is a next sibling, stopping at the subtree root
+            let mut ancestor = current;
+            loop {
+                if ancestor == self.root {
+                    return None;
+                }
+                let node = &self.document[ancestor];
+                if let Some(next) = node.next_sibling {
+                    return Some(next);
+                }
+                ancestor = node.parent?;
+            }
+        });
+        Some(current)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokeniser::get_tokens;
+
+    const TEST: &str = r#"<div id="outer"><p class="a b">One</p><p class="b">Two <b>bold</b></p></div><ul><li>x</li></ul>"#;
+
+    #[test]
+    fn check_source_spans() {
+        let html = "<div id=\"outer\">\n  <p class=\"a\">One\n<p>Two</p>\n</div>";
+        let document = Document::parse(html);
+        let outer = document.get_element_by_id("outer").unwrap();
+        let paragraphs = document.select("p").unwrap();
+
+        assert_eq!(document.source(outer, html), Some(html));
+        assert_eq!(
+            document.source(paragraphs[0], html),
+            Some("<p class=\"a\">One\n")
+        );
+        assert_eq!(document.source(paragraphs[1], html), Some("<p>Two</p>"));
+
+        let location = document[paragraphs[0]].location().unwrap();
+        assert_eq!(location.span.start.line, 2);
+        assert_eq!(location.span.start.column, 3);
+        assert!(location.end_tag.is_none());
+        let class = location.attributes["class"];
+        assert_eq!(class.name.slice(html), Some("class"));
+        assert_eq!(class.value.unwrap().slice(html), Some("a"));
+
+        let body = document.get_elements_by_tag(&HtmlElement::Body)[0];
+        assert!(document[body].location().is_none());
+        assert_eq!(document.source(body, html), None);
+    }
+
+    #[test]
+    fn check_parse_with_diagnostics() {
+        let html = "<div id=a id=b><p>One</span></div><br/><section><b>x<div/></section><img alt";
+        let (document, diagnostics) = Document::parse_with_diagnostics(html);
+        let found: Vec<(&str, Option<&str>)> = diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.code.as_str(), diagnostic.name.as_deref()))
+            .collect();
+
+        assert_eq!(
+            found,
+            vec![
+                ("missing-doctype", None),
+                ("duplicate-attribute", Some("id")),
+                ("unexpected-end-tag", Some("span")),
+                (
+                    "non-void-html-element-start-tag-with-trailing-solidus",
+                    Some("div")
+                ),
+                ("end-tag-too-early", Some("b")),
+                ("end-tag-too-early", Some("div")),
+                ("eof-in-tag", None),
+            ]
+        );
+        let duplicate = &diagnostics[1];
+        assert_eq!(duplicate.span.slice(html), Some("id"));
+        assert_eq!(duplicate.span.start.column, 11);
+        assert_eq!(
+            duplicate.to_string(),
+            "1:11: error: duplicate-attribute (id)"
+        );
+        assert_eq!(
+            diagnostics[0].severity,
+            crate::diagnostics::Severity::Warning
+        );
+        let paragraph = document.select_first("p").unwrap().unwrap();
+        assert_eq!(document.inner_text(paragraph), "One");
+
+        let (_, diagnostics) = Document::parse_with_diagnostics("<!DOCTYPE html><p>fine");
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn check_parse_bytes() {
+        let bytes = b"<meta charset=windows-1251><p id=greeting>\xCF\xF0\xE8\xE2\xE5\xF2</p>";
+        let (document, detected) = Document::parse_bytes(bytes, None);
+        let greeting = document.get_element_by_id("greeting").unwrap();
+
+        assert_eq!(
+            document.inner_text(greeting),
+            "\u{41F}\u{440}\u{438}\u{432}\u{435}\u{442}"
+        );
+        assert_eq!(detected.encoding, encoding::Encoding::Windows1251);
+        assert_eq!(detected.source, encoding::EncodingSource::MetaPrescan);
+    }
+
+    #[test]
+    fn check_from_reader() {
+        let document = Document::from_reader(std::io::Cursor::new(TEST)).unwrap();
+        assert_eq!(
+            document.outer_html(document.root()),
+            Document::parse(TEST).outer_html(document.root())
+        );
+    }
+
+    #[test]
+    fn check_navigation() {
+        let document = Document::parse(TEST);
+        let outer = document.get_element_by_id("outer").unwrap();
+        let paragraphs: Vec<NodeId> = document.children(outer).collect();
+
+        assert_eq!(paragraphs.len(), 2);
+        assert_eq!(document[paragraphs[0]].next_sibling(), Some(paragraphs[1]));
+        assert_eq!(
+            document[paragraphs[1]].previous_sibling(),
+            Some(paragraphs[0])
+        );
+        assert_eq!(document[paragraphs[0]].parent(), Some(outer));
+        assert_eq!(document[outer].last_child(), Some(paragraphs[1]));
+
+        let names: Vec<&str> = document
+            .ancestors(paragraphs[0])
+            .filter_map(|id| document[id].element().map(|e| e.tag_name()))
+            .collect();
+        assert_eq!(names, vec!["div", "body", "html"]);
+        assert_eq!(
+            document.ancestors(paragraphs[0]).last(),
+            Some(document.root())
+        );
+
+        assert!(!document.is_empty());
+        assert!(Document::new().is_empty());
+        assert_eq!(Document::new().len(), 1);
+    }
+
+    #[test]
+    fn check_descendants_stay_in_subtree() {
+        let document = Document::parse(TEST);
+        let outer = document.get_element_by_id("outer").unwrap();
+
+        assert_eq!(document.descendants(outer).count(), 6);
+        assert_eq!(document.inner_text(outer), "OneTwo bold");
+    }
+
+    #[test]
+    fn check_queries() {
+        let document = Document::parse(TEST);
+
+        assert_eq!(document.get_elements_by_class("b").len(), 2);
+        assert_eq!(document.get_elements_by_tag(&HtmlElement::Li).len(), 1);
+        assert!(document.get_element_by_id("missing").is_none());
+    }
+
+    #[test]
+    fn check_serialisation() {
+        let document = Document::parse(TEST);
+        let list = document.get_elements_by_tag(&HtmlElement::Ul)[0];
+
+        assert_eq!(document.outer_html(list), "<ul><li>x</li></ul>");
+        assert_eq!(document.inner_html(list), "<li>x</li>");
+
+        let document = Document::parse("<a title=t HREF=/x id=i hidden>link</a>");
+        let link = document.select_first("a").unwrap().unwrap();
+        assert_eq!(
+            document.outer_html(link),
+            r#"<a title="t" href="/x" id="i" hidden="">link</a>"#
+        );
+        assert_eq!(document[link].properties().get("Href"), Some("/x"));
+    }
+
+    #[test]
+    fn check_conversion_to_node_tree() {
+        let document = Document::parse(TEST);
+        let tree = document.to_node_tree();
+        let outer = tree.borrow().get_element_by_id("outer").unwrap();
+
+        assert_eq!(
+            outer.borrow().outer_html(),
+            document.outer_html(document.get_element_by_id("outer").unwrap())
+        );
+        assert_eq!(outer.borrow().ancestors().len(), 3);
+    }
+
+    #[test]
+    fn check_select() {
+        let document = Document::parse(TEST);
+        let bold = document.select("#outer p.b > b").unwrap();
+
+        assert_eq!(bold.len(), 1);
+        assert_eq!(document.inner_text(bold[0]), "bold");
+        let first = document.select_first("p:first-child").unwrap().unwrap();
+        assert_eq!(document.inner_text(first), "One");
+        assert!(document.select("div >").is_err());
+
+        let outer = document.get_element_by_id("outer").unwrap();
+        let selector = Selector::parse(":scope > p").unwrap();
+        assert_eq!(document.select_within(outer, &selector).len(), 2);
+        assert!(document.matches(outer, &Selector::parse("div#outer").unwrap()));
+    }
+
+    #[test]
+    fn check_from_node() {
+        let tree = Node::from_token_stream(get_tokens(TEST));
+        let document = Document::from_node(&tree.borrow());
+        assert_eq!(document.len(), Document::parse(TEST).len());
+
+        let list = tree.borrow().get_elements_by_tag(&HtmlElement::Ul)[0].clone();
+        let document = Document::from_node(&list.borrow());
+        assert_eq!(document.inner_html(document.root()), "<ul><li>x</li></ul>");
+    }
+
+    #[test]
+    fn check_shared_across_threads() {
+        let document = std::sync::Arc::new(Document::parse(TEST));
+        let handles: Vec<_> = ["p", "li", "b"]
+            .into_iter()
+            .map(|selector| {
+                let document = std::sync::Arc::clone(&document);
+                std::thread::spawn(move || document.select(selector).unwrap().len())
+            })
+            .collect();
+        let counts: Vec<usize> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(counts, vec![2, 1, 1]);
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        std::thread::spawn(move || sender.send(Document::parse(TEST)).unwrap());
+        let received = receiver.recv().unwrap();
+        assert!(received.get_element_by_id("outer").is_some());
+    }
+
+    #[test]
+    fn check_detach_and_insert_before() {
+        let mut document = Document::parse(TEST);
+        let outer = document.get_element_by_id("outer").unwrap();
+        let first = document[outer].first_child().unwrap();
+        let second = document[outer].last_child().unwrap();
+
+        document.insert_before(first, second);
+        assert_eq!(
+            document.children(outer).collect::<Vec<_>>(),
+            vec![second, first]
+        );
+        document.detach(second);
+        assert_eq!(document.children(outer).collect::<Vec<_>>(), vec![first]);
+
+        // Nothing happens without a parent to insert into
+        document.insert_before(second, first);
+        assert_eq!(document[first].parent(), Some(outer));
+        assert_eq!(document[first].previous_sibling(), None);
+        assert_eq!(document[second].parent(), None);
+    }
+}
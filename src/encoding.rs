@@ -0,0 +1,771 @@
+mod tables;
+
+use std::fmt;
+
+/// The character encodings bytes can be decoded from. These are the
+/// encodings of the WHATWG Encoding Standard apart from the multi-byte
+/// Chinese and Korean ones and the EUC-JP and ISO-2022-JP Japanese ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    Utf8,
+    Utf16Be,
+    Utf16Le,
+    ShiftJis,
+    Ibm866,
+    Iso8859_2,
+    Iso8859_3,
+    Iso8859_4,
+    Iso8859_5,
+    Iso8859_6,
+    Iso8859_7,
+    Iso8859_8,
+    Iso8859_8I,
+    Iso8859_10,
+    Iso8859_13,
+    Iso8859_14,
+    Iso8859_15,
+    Iso8859_16,
+    Koi8R,
+    Koi8U,
+    Macintosh,
+    Windows874,
+    Windows1250,
+    Windows1251,
+    Windows1252,
+    Windows1253,
+    Windows1254,
+    Windows1255,
+    Windows1256,
+    Windows1257,
+    Windows1258,
+    XUserDefined,
+}
+
+/// Why an encoding was chosen, in the order the sniffing algorithm tries them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EncodingSource {
+    /// The input started with a UTF-8 or UTF-16 byte order mark
+    ByteOrderMark,
+    /// The caller passed a label, usually the charset of a Content-Type header
+    TransportHint,
+    /// A `<meta charset>` or `http-equiv` declaration in the first 1024 bytes
+    MetaPrescan,
+    /// Nothing declared an encoding but the input is valid UTF-8
+    Autodetected,
+    /// Nothing declared an encoding so windows-1252 was assumed
+    Default,
+}
+
+/// The encoding picked for some bytes and the reason it was picked
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DetectedEncoding {
+    pub encoding: Encoding,
+    pub source: EncodingSource,
+}
+
+/// How far into the input to look for a `<meta>` declaration
+const PRESCAN_LENGTH: usize = 1024;
+
+const LABELS: &[(&str, Encoding)] = &[
+    ("unicode-1-1-utf-8", Encoding::Utf8),
+    ("unicode11utf8", Encoding::Utf8),
+    ("unicode20utf8", Encoding::Utf8),
+    ("utf-8", Encoding::Utf8),
+    ("utf8", Encoding::Utf8),
+    ("x-unicode20utf8", Encoding::Utf8),
+    ("unicodefffe", Encoding::Utf16Be),
+    ("utf-16be", Encoding::Utf16Be),
+    ("csunicode", Encoding::Utf16Le),
+    ("iso-10646-ucs-2", Encoding::Utf16Le),
+    ("ucs-2", Encoding::Utf16Le),
+    ("unicode", Encoding::Utf16Le),
+    ("unicodefeff", Encoding::Utf16Le),
+    ("utf-16", Encoding::Utf16Le),
+    ("utf-16le", Encoding::Utf16Le),
+    ("csshiftjis", Encoding::ShiftJis),
+    ("ms932", Encoding::ShiftJis),
+    ("ms_kanji", Encoding::ShiftJis),
+    ("shift-jis", Encoding::ShiftJis),
+    ("shift_jis", Encoding::ShiftJis),
+    ("sjis", Encoding::ShiftJis),
+    ("windows-31j", Encoding::ShiftJis),
+    ("x-sjis", Encoding::ShiftJis),
+    ("866", Encoding::Ibm866),
+    ("cp866", Encoding::Ibm866),
+    ("csibm866", Encoding::Ibm866),
+    ("ibm866", Encoding::Ibm866),
+    ("csisolatin2", Encoding::Iso8859_2),
+    ("iso-8859-2", Encoding::Iso8859_2),
+    ("iso-ir-101", Encoding::Iso8859_2),
+    ("iso8859-2", Encoding::Iso8859_2),
+    ("iso88592", Encoding::Iso8859_2),
+    ("iso_8859-2", Encoding::Iso8859_2),
+    ("iso_8859-2:1987", Encoding::Iso8859_2),
+    ("l2", Encoding::Iso8859_2),
+    ("latin2", Encoding::Iso8859_2),
+    ("csisolatin3", Encoding::Iso8859_3),
+    ("iso-8859-3", Encoding::Iso8859_3),
+    ("iso-ir-109", Encoding::Iso8859_3),
+    ("iso8859-3", Encoding::Iso8859_3),
+    ("iso88593", Encoding::Iso8859_3),
+    ("iso_8859-3", Encoding::Iso8859_3),
+    ("iso_8859-3:1988", Encoding::Iso8859_3),
+    ("l3", Encoding::Iso8859_3),
+    ("latin3", Encoding::Iso8859_3),
+    ("csisolatin4", Encoding::Iso8859_4),
+    ("iso-8859-4", Encoding::Iso8859_4),
+    ("iso-ir-110", Encoding::Iso8859_4),
+    ("iso8859-4", Encoding::Iso8859_4),
+    ("iso88594", Encoding::Iso8859_4),
+    ("iso_8859-4", Encoding::Iso8859_4),
+    ("iso_8859-4:1988", Encoding::Iso8859_4),
+    ("l4", Encoding::Iso8859_4),
+    ("latin4", Encoding::Iso8859_4),
+    ("csisolatincyrillic", Encoding::Iso8859_5),
+    ("cyrillic", Encoding::Iso8859_5),
+    ("iso-8859-5", Encoding::Iso8859_5),
+    ("iso-ir-144", Encoding::Iso8859_5),
+    ("iso8859-5", Encoding::Iso8859_5),
+    ("iso88595", Encoding::Iso8859_5),
+    ("iso_8859-5", Encoding::Iso8859_5),
+    ("iso_8859-5:1988", Encoding::Iso8859_5),
+    ("arabic", Encoding::Iso8859_6),
+    ("asmo-708", Encoding::Iso8859_6),
+    ("csiso88596e", Encoding::Iso8859_6),
+    ("csiso88596i", Encoding::Iso8859_6),
+    ("csisolatinarabic", Encoding::Iso8859_6),
+    ("ecma-114", Encoding::Iso8859_6),
+    ("iso-8859-6", Encoding::Iso8859_6),
+    ("iso-8859-6-e", Encoding::Iso8859_6),
+    ("iso-8859-6-i", Encoding::Iso8859_6),
+    ("iso-ir-127", Encoding::Iso8859_6),
+    ("iso8859-6", Encoding::Iso8859_6),
+    ("iso88596", Encoding::Iso8859_6),
+    ("iso_8859-6", Encoding::Iso8859_6),
+    ("iso_8859-6:1987", Encoding::Iso8859_6),
+    ("csisolatingreek", Encoding::Iso8859_7),
+    ("ecma-118", Encoding::Iso8859_7),
+    ("elot_928", Encoding::Iso8859_7),
+    ("greek", Encoding::Iso8859_7),
+    ("greek8", Encoding::Iso8859_7),
+    ("iso-8859-7", Encoding::Iso8859_7),
+    ("iso-ir-126", Encoding::Iso8859_7),
+    ("iso8859-7", Encoding::Iso8859_7),
+    ("iso88597", Encoding::Iso8859_7),
+    ("iso_8859-7", Encoding::Iso8859_7),
+    ("iso_8859-7:1987", Encoding::Iso8859_7),
+    ("sun_eu_greek", Encoding::Iso8859_7),
+    ("csiso88598e", Encoding::Iso8859_8),
+    ("csisolatinhebrew", Encoding::Iso8859_8),
+    ("hebrew", Encoding::Iso8859_8),
+    ("iso-8859-8", Encoding::Iso8859_8),
+    ("iso-8859-8-e", Encoding::Iso8859_8),
+    ("iso-ir-138", Encoding::Iso8859_8),
+    ("iso8859-8", Encoding::Iso8859_8),
+    ("iso88598", Encoding::Iso8859_8),
+    ("iso_8859-8", Encoding::Iso8859_8),
+    ("iso_8859-8:1988", Encoding::Iso8859_8),
+    ("visual", Encoding::Iso8859_8),
+    ("csiso88598i", Encoding::Iso8859_8I),
+    ("iso-8859-8-i", Encoding::Iso8859_8I),
+    ("logical", Encoding::Iso8859_8I),
+    ("csisolatin6", Encoding::Iso8859_10),
+    ("iso-8859-10", Encoding::Iso8859_10),
+    ("iso-ir-157", Encoding::Iso8859_10),
+    ("iso8859-10", Encoding::Iso8859_10),
+    ("iso885910", Encoding::Iso8859_10),
+    ("l6", Encoding::Iso8859_10),
+    ("latin6", Encoding::Iso8859_10),
+    ("iso-8859-13", Encoding::Iso8859_13),
+    ("iso8859-13", Encoding::Iso8859_13),
+    ("iso885913", Encoding::Iso8859_13),
+    ("iso-8859-14", Encoding::Iso8859_14),
+    ("iso8859-14", Encoding::Iso8859_14),
+    ("iso885914", Encoding::Iso8859_14),
+    ("csisolatin9", Encoding::Iso8859_15),
+    ("iso-8859-15", Encoding::Iso8859_15),
+    ("iso8859-15", Encoding::Iso8859_15),
+    ("iso885915", Encoding::Iso8859_15),
+    ("iso_8859-15", Encoding::Iso8859_15),
+    ("l9", Encoding::Iso8859_15),
+    ("iso-8859-16", Encoding::Iso8859_16),
+    ("cskoi8r", Encoding::Koi8R),
+    ("koi", Encoding::Koi8R),
+    ("koi8", Encoding::Koi8R),
+    ("koi8-r", Encoding::Koi8R),
+    ("koi8_r", Encoding::Koi8R),
+    ("koi8-ru", Encoding::Koi8U),
+    ("koi8-u", Encoding::Koi8U),
+    ("csmacintosh", Encoding::Macintosh),
+    ("mac", Encoding::Macintosh),
+    ("macintosh", Encoding::Macintosh),
+    ("x-mac-roman", Encoding::Macintosh),
+    ("dos-874", Encoding::Windows874),
+    ("iso-8859-11", Encoding::Windows874),
+    ("iso8859-11", Encoding::Windows874),
+    ("iso885911", Encoding::Windows874),
+    ("tis-620", Encoding::Windows874),
+    ("windows-874", Encoding::Windows874),
+    ("cp1250", Encoding::Windows1250),
+    ("windows-1250", Encoding::Windows1250),
+    ("x-cp1250", Encoding::Windows1250),
+    ("cp1251", Encoding::Windows1251),
+    ("windows-1251", Encoding::Windows1251),
+    ("x-cp1251", Encoding::Windows1251),
+    ("ansi_x3.4-1968", Encoding::Windows1252),
+    ("ascii", Encoding::Windows1252),
+    ("cp1252", Encoding::Windows1252),
+    ("cp819", Encoding::Windows1252),
+    ("csisolatin1", Encoding::Windows1252),
+    ("ibm819", Encoding::Windows1252),
+    ("iso-8859-1", Encoding::Windows1252),
+    ("iso-ir-100", Encoding::Windows1252),
+    ("iso8859-1", Encoding::Windows1252),
+    ("iso88591", Encoding::Windows1252),
+    ("iso_8859-1", Encoding::Windows1252),
+    ("iso_8859-1:1987", Encoding::Windows1252),
+    ("l1", Encoding::Windows1252),
+    ("latin1", Encoding::Windows1252),
+    ("us-ascii", Encoding::Windows1252),
+    ("windows-1252", Encoding::Windows1252),
+    ("x-cp1252", Encoding::Windows1252),
+    ("cp1253", Encoding::Windows1253),
+    ("windows-1253", Encoding::Windows1253),
+    ("x-cp1253", Encoding::Windows1253),
+    ("cp1254", Encoding::Windows1254),
+    ("csisolatin5", Encoding::Windows1254),
+    ("iso-8859-9", Encoding::Windows1254),
+    ("iso-ir-148", Encoding::Windows1254),
+    ("iso8859-9", Encoding::Windows1254),
+    ("iso88599", Encoding::Windows1254),
+    ("iso_8859-9", Encoding::Windows1254),
+    ("iso_8859-9:1989", Encoding::Windows1254),
+    ("l5", Encoding::Windows1254),
+    ("latin5", Encoding::Windows1254),
+    ("windows-1254", Encoding::Windows1254),
+    ("x-cp1254", Encoding::Windows1254),
+    ("cp1255", Encoding::Windows1255),
+    ("windows-1255", Encoding::Windows1255),
+    ("x-cp1255", Encoding::Windows1255),
+    ("cp1256", Encoding::Windows1256),
+    ("windows-1256", Encoding::Windows1256),
+    ("x-cp1256", Encoding::Windows1256),
+    ("cp1257", Encoding::Windows1257),
+    ("windows-1257", Encoding::Windows1257),
+    ("x-cp1257", Encoding::Windows1257),
+    ("cp1258", Encoding::Windows1258),
+    ("windows-1258", Encoding::Windows1258),
+    ("x-cp1258", Encoding::Windows1258),
+    ("x-user-defined", Encoding::XUserDefined),
+];
+
+impl Encoding {
+    /// Looks up an encoding by one of its WHATWG labels, ignoring case and
+    /// surrounding whitespace
+    pub fn for_label(label: &str) -> Option<Encoding> {
+        let label = label.trim_matches(is_ascii_whitespace_char);
+        LABELS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(label))
+            .map(|(_, encoding)| *encoding)
+    }
+
+    /// The canonical WHATWG name of the encoding
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Utf16Be => "UTF-16BE",
+            Encoding::Utf16Le => "UTF-16LE",
+            Encoding::ShiftJis => "Shift_JIS",
+            Encoding::Ibm866 => "IBM866",
+            Encoding::Iso8859_2 => "ISO-8859-2",
+            Encoding::Iso8859_3 => "ISO-8859-3",
+            Encoding::Iso8859_4 => "ISO-8859-4",
+            Encoding::Iso8859_5 => "ISO-8859-5",
+            Encoding::Iso8859_6 => "ISO-8859-6",
+            Encoding::Iso8859_7 => "ISO-8859-7",
+            Encoding::Iso8859_8 => "ISO-8859-8",
+            Encoding::Iso8859_8I => "ISO-8859-8-I",
+            Encoding::Iso8859_10 => "ISO-8859-10",
+            Encoding::Iso8859_13 => "ISO-8859-13",
+            Encoding::Iso8859_14 => "ISO-8859-14",
+            Encoding::Iso8859_15 => "ISO-8859-15",
+            Encoding::Iso8859_16 => "ISO-8859-16",
+            Encoding::Koi8R => "KOI8-R",
+            Encoding::Koi8U => "KOI8-U",
+            Encoding::Macintosh => "macintosh",
+            Encoding::Windows874 => "windows-874",
+            Encoding::Windows1250 => "windows-1250",
+            Encoding::Windows1251 => "windows-1251",
+            Encoding::Windows1252 => "windows-1252",
+            Encoding::Windows1253 => "windows-1253",
+            Encoding::Windows1254 => "windows-1254",
+            Encoding::Windows1255 => "windows-1255",
+            Encoding::Windows1256 => "windows-1256",
+            Encoding::Windows1257 => "windows-1257",
+            Encoding::Windows1258 => "windows-1258",
+            Encoding::XUserDefined => "x-user-defined",
+        }
+    }
+
+    /// Decodes the whole input, replacing malformed sequences with U+FFFD.
+    /// A byte order mark matching the encoding is dropped.
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => {
+                let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+                String::from_utf8_lossy(bytes).into_owned()
+            }
+            Encoding::Utf16Be => {
+                decode_utf16(bytes.strip_prefix(b"\xFE\xFF").unwrap_or(bytes), true)
+            }
+            Encoding::Utf16Le => {
+                decode_utf16(bytes.strip_prefix(b"\xFF\xFE").unwrap_or(bytes), false)
+            }
+            Encoding::ShiftJis => decode_shift_jis(bytes),
+            Encoding::XUserDefined => bytes
+                .iter()
+                .map(|&byte| match byte {
+                    0x00..=0x7F => byte as char,
+                    _ => char::from_u32(0xF780 + byte as u32 - 0x80).unwrap_or('\u{FFFD}'),
+                })
+                .collect(),
+            _ => {
+                // Safe to unwrap as every other encoding is single byte
+                let table = self.single_byte_table().unwrap();
+                bytes
+                    .iter()
+                    .map(|&byte| match byte {
+                        0x00..=0x7F => byte as char,
+                        _ => {
+                            char::from_u32(table[byte as usize - 0x80] as u32).unwrap_or('\u{FFFD}')
+                        }
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    fn single_byte_table(&self) -> Option<&'static [u16; 128]> {
+        Some(match self {
+            Encoding::Ibm866 => &tables::IBM866,
+            Encoding::Iso8859_2 => &tables::ISO_8859_2,
+            Encoding::Iso8859_3 => &tables::ISO_8859_3,
+            Encoding::Iso8859_4 => &tables::ISO_8859_4,
+            Encoding::Iso8859_5 => &tables::ISO_8859_5,
+            Encoding::Iso8859_6 => &tables::ISO_8859_6,
+            Encoding::Iso8859_7 => &tables::ISO_8859_7,
+            Encoding::Iso8859_8 | Encoding::Iso8859_8I => &tables::ISO_8859_8,
+            Encoding::Iso8859_10 => &tables::ISO_8859_10,
+            Encoding::Iso8859_13 => &tables::ISO_8859_13,
+            Encoding::Iso8859_14 => &tables::ISO_8859_14,
+            Encoding::Iso8859_15 => &tables::ISO_8859_15,
+            Encoding::Iso8859_16 => &tables::ISO_8859_16,
+            Encoding::Koi8R => &tables::KOI8_R,
+            Encoding::Koi8U => &tables::KOI8_U,
+            Encoding::Macintosh => &tables::MACINTOSH,
+            Encoding::Windows874 => &tables::WINDOWS_874,
+            Encoding::Windows1250 => &tables::WINDOWS_1250,
+            Encoding::Windows1251 => &tables::WINDOWS_1251,
+            Encoding::Windows1252 => &tables::WINDOWS_1252,
+            Encoding::Windows1253 => &tables::WINDOWS_1253,
+            Encoding::Windows1254 => &tables::WINDOWS_1254,
+            Encoding::Windows1255 => &tables::WINDOWS_1255,
+            Encoding::Windows1256 => &tables::WINDOWS_1256,
+            Encoding::Windows1257 => &tables::WINDOWS_1257,
+            Encoding::Windows1258 => &tables::WINDOWS_1258,
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Picks an encoding for `bytes` following the WHATWG encoding sniffing
+/// algorithm. `transport_hint` is a label from outside the document, such as
+/// the charset parameter of a Content-Type header; unknown labels are ignored.
+pub fn sniff(bytes: &[u8], transport_hint: Option<&str>) -> DetectedEncoding {
+    let detected = |encoding, source| DetectedEncoding { encoding, source };
+    if let Some(encoding) = bom_encoding(bytes) {
+        return detected(encoding, EncodingSource::ByteOrderMark);
+    }
+    if let Some(encoding) = transport_hint.and_then(Encoding::for_label) {
+        return detected(encoding, EncodingSource::TransportHint);
+    }
+    if let Some(encoding) = prescan(&bytes[..bytes.len().min(PRESCAN_LENGTH)]) {
+        return detected(encoding, EncodingSource::MetaPrescan);
+    }
+    if !bytes.is_ascii() && std::str::from_utf8(bytes).is_ok() {
+        return detected(Encoding::Utf8, EncodingSource::Autodetected);
+    }
+    detected(Encoding::Windows1252, EncodingSource::Default)
+}
+
+/// Sniffs the encoding of `bytes` and decodes them with it
+pub fn decode(bytes: &[u8], transport_hint: Option<&str>) -> (String, DetectedEncoding) {
+    let detected = sniff(bytes, transport_hint);
+    (detected.encoding.decode(bytes), detected)
+}
+
+fn bom_encoding(bytes: &[u8]) -> Option<Encoding> {
+    if bytes.starts_with(b"\xEF\xBB\xBF") {
+        Some(Encoding::Utf8)
+    } else if bytes.starts_with(b"\xFE\xFF") {
+        Some(Encoding::Utf16Be)
+    } else if bytes.starts_with(b"\xFF\xFE") {
+        Some(Encoding::Utf16Le)
+    } else {
+        None
+    }
+}
+
+fn decode_utf16(bytes: &[u8], big_endian: bool) -> String {
+    let units = bytes.chunks_exact(2).map(|pair| {
+        if big_endian {
+            u16::from_be_bytes([pair[0], pair[1]])
+        } else {
+            u16::from_le_bytes([pair[0], pair[1]])
+        }
+    });
+    let mut decoded: String = char::decode_utf16(units)
+        .map(|unit| unit.unwrap_or('\u{FFFD}'))
+        .collect();
+    if bytes.len() % 2 == 1 {
+        decoded.push('\u{FFFD}');
+    }
+    decoded
+}
+
+fn decode_shift_jis(bytes: &[u8]) -> String {
+    let mut decoded = String::with_capacity(bytes.len());
+    let mut position = 0;
+    while position < bytes.len() {
+        let lead = bytes[position];
+        position += 1;
+        match lead {
+            0x00..=0x80 => decoded.push(lead as char),
+            0xA1..=0xDF => decoded.push(char::from_u32(0xFF61 + lead as u32 - 0xA1).unwrap()),
+            0x81..=0x9F | 0xE0..=0xFC => {
+                let Some(&trail) = bytes.get(position) else {
+                    decoded.push('\u{FFFD}');
+                    break;
+                };
+                let lead_offset = if lead < 0xA0 { 0x81 } else { 0xC1 };
+                let trail_offset = if trail < 0x7F { 0x40 } else { 0x41 };
+                let code_point = match trail {
+                    0x40..=0x7E | 0x80..=0xFC => {
+                        let pointer =
+                            (lead - lead_offset) as usize * 188 + (trail - trail_offset) as usize;
+                        match pointer {
+                            8836..=10715 => Some(0xE000 + pointer as u32 - 8836),
+                            _ => tables::JIS0208
+                                .get(pointer)
+                                .filter(|&&code_point| code_point != 0)
+                                .map(|&code_point| code_point as u32),
+                        }
+                    }
+                    _ => None,
+                };
+                match code_point.and_then(char::from_u32) {
+                    Some(char) => {
+                        decoded.push(char);
+                        position += 1;
+                    }
+                    // An ASCII trail byte is left to be decoded on its own
+                    None if trail.is_ascii() => decoded.push('\u{FFFD}'),
+                    None => {
+                        decoded.push('\u{FFFD}');
+                        position += 1;
+                    }
+                }
+            }
+            _ => decoded.push('\u{FFFD}'),
+        }
+    }
+    decoded
+}
+
+fn is_ascii_whitespace_char(char: char) -> bool {
+    matches!(char, '\t' | '\n' | '\x0C' | '\r' | ' ')
+}
+
+fn is_whitespace(byte: u8) -> bool {
+    matches!(byte, b'\t' | b'\n' | b'\x0C' | b'\r' | b' ')
+}
+
+/// The prescan from the WHATWG "prescan a byte stream to determine its
+/// encoding" algorithm, looking for the first `<meta>` which declares one
+fn prescan(bytes: &[u8]) -> Option<Encoding> {
+    let mut position = 0;
+    while position < bytes.len() {
+        let rest = &bytes[position..];
+        if rest.starts_with(b"<!--") {
+            // The closing `-->` may share its dashes with the opening `<!--`
+            position += 2 + find(&rest[2..], b"-->")? + 3;
+        } else if starts_with_ignore_case(rest, b"<meta")
+            && rest
+                .get(5)
+                .is_some_and(|&byte| is_whitespace(byte) || byte == b'/')
+        {
+            position += 6;
+            if let Some(encoding) = meta_encoding(bytes, &mut position) {
+                return Some(encoding);
+            }
+        } else if rest.starts_with(b"<")
+            && (rest.get(1).is_some_and(u8::is_ascii_alphabetic)
+                || (rest.get(1) == Some(&b'/') && rest.get(2).is_some_and(u8::is_ascii_alphabetic)))
+        {
+            position += rest
+                .iter()
+                .position(|&byte| is_whitespace(byte) || byte == b'>')?;
+            while get_attribute(bytes, &mut position).is_some() {}
+        } else if rest.starts_with(b"<!") || rest.starts_with(b"</") || rest.starts_with(b"<?") {
+            position += rest.iter().position(|&byte| byte == b'>')? + 1;
+        } else {
+            position += 1;
+        }
+    }
+    None
+}
+
+/// Reads the attributes of a `<meta>` tag, returning the encoding it declares
+fn meta_encoding(bytes: &[u8], position: &mut usize) -> Option<Encoding> {
+    let mut seen: Vec<Vec<u8>> = vec![];
+    let mut got_pragma = false;
+    let mut need_pragma = None;
+    let mut charset = None;
+    while let Some((name, value)) = get_attribute(bytes, position) {
+        if seen.contains(&name) {
+            continue;
+        }
+        match name.as_slice() {
+            b"http-equiv" if value == b"content-type" => got_pragma = true,
+            b"content" if charset.is_none() => {
+                if let Some(label) = charset_from_content(&value) {
+                    charset = Some(Encoding::for_label(&String::from_utf8_lossy(&label)));
+                    need_pragma = Some(true);
+                }
+            }
+            b"charset" => {
+                charset = Some(Encoding::for_label(&String::from_utf8_lossy(&value)));
+                need_pragma = Some(false);
+            }
+            _ => {}
+        }
+        seen.push(name);
+    }
+    if need_pragma.is_none() || (need_pragma == Some(true) && !got_pragma) {
+        return None;
+    }
+    match charset?? {
+        Encoding::Utf16Be | Encoding::Utf16Le => Some(Encoding::Utf8),
+        Encoding::XUserDefined => Some(Encoding::Windows1252),
+        encoding => Some(encoding),
+    }
+}
+
+/// The WHATWG "get an attribute" algorithm, returning the lowercased name and
+/// value or `None` once the end of the tag is reached
+fn get_attribute(bytes: &[u8], position: &mut usize) -> Option<(Vec<u8>, Vec<u8>)> {
+    while bytes
+        .get(*position)
+        .is_some_and(|&byte| is_whitespace(byte) || byte == b'/')
+    {
+        *position += 1;
+    }
+    if *bytes.get(*position)? == b'>' {
+        return None;
+    }
+    let mut name = vec![];
+    loop {
+        let byte = *bytes.get(*position)?;
+        match byte {
+            b'=' if !name.is_empty() => {
+                *position += 1;
+                break;
+            }
+            _ if is_whitespace(byte) => {
+                while bytes
+                    .get(*position)
+                    .is_some_and(|&byte| is_whitespace(byte))
+                {
+                    *position += 1;
+                }
+                if *bytes.get(*position)? != b'=' {
+                    return Some((name, vec![]));
+                }
+                *position += 1;
+                break;
+            }
+            b'/' | b'>' => return Some((name, vec![])),
+            _ => {
+                name.push(byte.to_ascii_lowercase());
+                *position += 1;
+            }
+        }
+    }
+    while bytes
+        .get(*position)
+        .is_some_and(|&byte| is_whitespace(byte))
+    {
+        *position += 1;
+    }
+    let mut value = vec![];
+    let first = *bytes.get(*position)?;
+    if first == b'"' || first == b'\'' {
+        *position += 1;
+        loop {
+            let byte = *bytes.get(*position)?;
+            *position += 1;
+            if byte == first {
+                return Some((name, value));
+            }
+            value.push(byte.to_ascii_lowercase());
+        }
+    }
+    if first == b'>' {
+        return Some((name, value));
+    }
+    loop {
+        let byte = *bytes.get(*position)?;
+        if is_whitespace(byte) || byte == b'>' {
+            return Some((name, value));
+        }
+        value.push(byte.to_ascii_lowercase());
+        *position += 1;
+    }
+}
+
+/// The WHATWG "extract a character encoding from a meta element" algorithm,
+/// applied to an already lowercased `content` value
+fn charset_from_content(content: &[u8]) -> Option<Vec<u8>> {
+    let mut position = 0;
+    loop {
+        position += find(&content[position..], b"charset")? + 7;
+        while content
+            .get(position)
+            .is_some_and(|&byte| is_whitespace(byte))
+        {
+            position += 1;
+        }
+        if content.get(position) == Some(&b'=') {
+            position += 1;
+            break;
+        }
+    }
+    while content
+        .get(position)
+        .is_some_and(|&byte| is_whitespace(byte))
+    {
+        position += 1;
+    }
+    let rest = &content[position..];
+    match rest.first()? {
+        &quote @ (b'"' | b'\'') => {
+            let end = rest[1..].iter().position(|&byte| byte == quote)?;
+            Some(rest[1..end + 1].to_vec())
+        }
+        _ => {
+            let end = rest
+                .iter()
+                .position(|&byte| is_whitespace(byte) || byte == b';')
+                .unwrap_or(rest.len());
+            (end > 0).then(|| rest[..end].to_vec())
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn starts_with_ignore_case(bytes: &[u8], prefix: &[u8]) -> bool {
+    bytes.len() >= prefix.len() && bytes[..prefix.len()].eq_ignore_ascii_case(prefix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_byte_order_mark_wins() {
+        let bytes = b"\xFF\xFEh\x00i\x00";
+        let (text, detected) = decode(bytes, Some("windows-1252"));
+        assert_eq!(text, "hi");
+        assert_eq!(detected.encoding, Encoding::Utf16Le);
+        assert_eq!(detected.source, EncodingSource::ByteOrderMark);
+    }
+
+    #[test]
+    fn check_transport_hint_before_meta() {
+        let bytes = b"<meta charset=\"shift_jis\"><p>caf\xE9</p>";
+        let (text, detected) = decode(bytes, Some(" ISO-8859-1 "));
+        assert_eq!(text, "<meta charset=\"shift_jis\"><p>caf\u{e9}</p>");
+        assert_eq!(detected.encoding, Encoding::Windows1252);
+        assert_eq!(detected.source, EncodingSource::TransportHint);
+
+        let (_, detected) = decode(bytes, Some("not-an-encoding"));
+        assert_eq!(detected.encoding, Encoding::ShiftJis);
+        assert_eq!(detected.source, EncodingSource::MetaPrescan);
+    }
+
+    #[test]
+    fn check_meta_prescan() {
+        let http_equiv = b"<!-- <meta charset=koi8-r> --><head><title>x</title>\
+            <meta http-equiv=\"Content-Type\" content=\"text/html; charset=ISO-8859-2\">";
+        assert_eq!(sniff(http_equiv, None).encoding, Encoding::Iso8859_2);
+
+        let missing_pragma = b"<meta content=\"text/html; charset=ISO-8859-2\">";
+        assert_eq!(sniff(missing_pragma, None).source, EncodingSource::Default);
+
+        let utf16 = b"<meta charset='utf-16'>";
+        assert_eq!(sniff(utf16, None).encoding, Encoding::Utf8);
+
+        let mut late = vec![b' '; PRESCAN_LENGTH];
+        late.extend_from_slice(b"<meta charset=\"koi8-r\">");
+        assert_eq!(sniff(&late, None).encoding, Encoding::Windows1252);
+    }
+
+    #[test]
+    fn check_fallbacks() {
+        assert_eq!(
+            sniff("<p>caf\u{e9}</p>".as_bytes(), None),
+            DetectedEncoding {
+                encoding: Encoding::Utf8,
+                source: EncodingSource::Autodetected
+            }
+        );
+        assert_eq!(
+            sniff(b"<p>caf\xE9</p>", None).source,
+            EncodingSource::Default
+        );
+    }
+
+    #[test]
+    fn check_decoders() {
+        assert_eq!(
+            Encoding::Windows1252.decode(b"\x80\x81\x93"),
+            "\u{20AC}\u{81}\u{201C}"
+        );
+        assert_eq!(Encoding::Iso8859_3.decode(b"\xA5\xA1"), "\u{FFFD}\u{126}");
+        assert_eq!(
+            Encoding::Koi8R.decode(b"\xF0\xD2\xC9"),
+            "\u{41F}\u{440}\u{438}"
+        );
+        assert_eq!(
+            Encoding::ShiftJis.decode(b"\x93\xfa\x96\x7b\xb1 \x82\x20\xF0\x40"),
+            "\u{65E5}\u{672C}\u{FF71} \u{FFFD} \u{E000}"
+        );
+        assert_eq!(
+            Encoding::Utf16Be.decode(b"\xFE\xFF\xD8\x3D\xDE\x00\x00"),
+            "\u{1F600}\u{FFFD}"
+        );
+        assert_eq!(Encoding::Utf8.decode(b"\xEF\xBB\xBFa\xFF"), "a\u{FFFD}");
+    }
+}
@@ -0,0 +1,197 @@
+mod table;
+
+use table::NAMED_REFERENCES;
+
+/// Longest name in the named character reference table, including the `;`
+const LONGEST_REFERENCE: usize = 32;
+
+/// Replacements for numeric references in the C1 control range, which
+/// browsers interpret as windows-1252 code points
+const C1_REPLACEMENTS: [(u32, char); 27] = [
+    (0x80, '\u{20AC}'),
+    (0x82, '\u{201A}'),
+    (0x83, '\u{0192}'),
+    (0x84, '\u{201E}'),
+    (0x85, '\u{2026}'),
+    (0x86, '\u{2020}'),
+    (0x87, '\u{2021}'),
+    (0x88, '\u{02C6}'),
+    (0x89, '\u{2030}'),
+    (0x8A, '\u{0160}'),
+    (0x8B, '\u{2039}'),
+    (0x8C, '\u{0152}'),
+    (0x8E, '\u{017D}'),
+    (0x91, '\u{2018}'),
+    (0x92, '\u{2019}'),
+    (0x93, '\u{201C}'),
+    (0x94, '\u{201D}'),
+    (0x95, '\u{2022}'),
+    (0x96, '\u{2013}'),
+    (0x97, '\u{2014}'),
+    (0x98, '\u{02DC}'),
+    (0x99, '\u{2122}'),
+    (0x9A, '\u{0161}'),
+    (0x9B, '\u{203A}'),
+    (0x9C, '\u{0153}'),
+    (0x9E, '\u{017E}'),
+    (0x9F, '\u{0178}'),
+];
+
+/// Looks up a named character reference such as `amp;` or the legacy `amp`
+pub fn lookup(name: &str) -> Option<&'static str> {
+    NAMED_REFERENCES
+        .binary_search_by(|(key, _)| key.as_bytes().cmp(name.as_bytes()))
+        .ok()
+        .map(|index| NAMED_REFERENCES[index].1)
+}
+
+/// Decodes character references in text content
+pub fn decode_text(text: &str) -> String {
+    decode(text, false)
+}
+
+/// Decodes character references in an attribute value. Legacy references
+/// without a semicolon are left alone when followed by `=` or an alphanumeric
+/// character, so query strings like `?a=1&copy=2` survive intact.
+pub fn decode_attribute(value: &str) -> String {
+    decode(value, true)
+}
+
+fn decode(input: &str, in_attribute: bool) -> String {
+    if !input.contains('&') {
+        return input.to_string();
+    }
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(index) = rest.find('&') {
+        output.push_str(&rest[..index]);
+        rest = &rest[index + 1..];
+        let consumed = if let Some(digits) = rest.strip_prefix('#') {
+            decode_numeric(digits).map(|(c, length)| {
+                output.push(c);
+                length + 1
+            })
+        } else {
+            decode_named(rest, in_attribute).map(|(value, length)| {
+                output.push_str(value);
+                length
+            })
+        };
+        match consumed {
+            Some(length) => rest = &rest[length..],
+            None => output.push('&'),
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+/// Finds the longest named reference at the start of `input`, returning the
+/// replacement and the number of bytes it consumed
+fn decode_named(input: &str, in_attribute: bool) -> Option<(&'static str, usize)> {
+    let mut end = input
+        .char_indices()
+        .find(|(_, c)| !c.is_ascii_alphanumeric())
+        .map(|(index, _)| index)
+        .unwrap_or(input.len())
+        .min(LONGEST_REFERENCE);
+    if input[end..].starts_with(';') {
+        end += 1;
+    }
+
+    let (length, value) = (1..=end)
+        .rev()
+        .find_map(|length| lookup(&input[..length]).map(|value| (length, value)))?;
+
+    let terminated = input[..length].ends_with(';');
+    let next = input[length..].chars().next();
+    if in_attribute && !terminated && next.is_some_and(|c| c == '=' || c.is_ascii_alphanumeric()) {
+        return None;
+    }
+    Some((value, length))
+}
+
+/// Decodes the digits of a `&#...;` reference, `input` starting after the `#`
+fn decode_numeric(input: &str) -> Option<(char, usize)> {
+    let (radix, prefix) = match input.chars().next() {
+        Some('x' | 'X') => (16, 1),
+        _ => (10, 0),
+    };
+    let digits = input[prefix..]
+        .chars()
+        .take_while(|c| c.is_digit(radix))
+        .count();
+    if digits == 0 {
+        return None;
+    }
+    let mut length = prefix + digits;
+    // Saturate rather than overflow so huge references become U+FFFD
+    let code = input[prefix..length].chars().fold(0u32, |code, c| {
+        code.saturating_mul(radix)
+            .saturating_add(c.to_digit(radix).unwrap_or(0))
+    });
+    if input[length..].starts_with(';') {
+        length += 1;
+    }
+
+    let c = match code {
+        0 => '\u{FFFD}',
+        0x80..=0x9F => C1_REPLACEMENTS
+            .iter()
+            .find(|(from, _)| *from == code)
+            .map(|(_, to)| *to)
+            .unwrap_or_else(|| char::from_u32(code).unwrap_or('\u{FFFD}')),
+        code => char::from_u32(code).unwrap_or('\u{FFFD}'),
+    };
+    Some((c, length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_table_is_sorted() {
+        assert!(
+            NAMED_REFERENCES
+                .windows(2)
+                .all(|pair| pair[0].0.as_bytes() < pair[1].0.as_bytes())
+        );
+        assert_eq!(NAMED_REFERENCES.len(), 2231);
+    }
+
+    #[test]
+    fn check_named_references() {
+        assert_eq!(decode_text("fish &amp; chips"), "fish & chips");
+        assert_eq!(decode_text("caf&eacute;"), "café");
+        assert_eq!(decode_text("a&nbsp;b"), "a\u{A0}b");
+        assert_eq!(decode_text("&NotEqualTilde;"), "\u{2242}\u{338}");
+        assert_eq!(decode_text("&unknown; &"), "&unknown; &");
+    }
+
+    #[test]
+    fn check_legacy_references_without_semicolon() {
+        assert_eq!(decode_text("&copy 2024"), "© 2024");
+        assert_eq!(decode_text("&notit;"), "¬it;");
+        assert_eq!(decode_text("&ampx"), "&x");
+    }
+
+    #[test]
+    fn check_attribute_rules() {
+        assert_eq!(decode_attribute("?a=1&copy=2"), "?a=1&copy=2");
+        assert_eq!(decode_attribute("?a=1&copyx"), "?a=1&copyx");
+        assert_eq!(decode_attribute("?a=1&copy;=2"), "?a=1©=2");
+        assert_eq!(decode_attribute("&amp; &copy"), "& ©");
+    }
+
+    #[test]
+    fn check_numeric_references() {
+        assert_eq!(decode_text("&#x27;quoted&#39;"), "'quoted'");
+        assert_eq!(decode_text("&#X41;&#66"), "AB");
+        assert_eq!(decode_text("&#0;"), "\u{FFFD}");
+        assert_eq!(decode_text("&#x80;"), "\u{20AC}");
+        assert_eq!(decode_text("&#xD800;"), "\u{FFFD}");
+        assert_eq!(decode_text("&#99999999999;"), "\u{FFFD}");
+        assert_eq!(decode_text("&#;&#x;"), "&#;&#x;");
+    }
+}
@@ -0,0 +1,956 @@
+use crate::{
+    attributes::Attributes, html_elements::HtmlElement, links, node_tree::TreeNode, url, whitespace,
+};
+
+/// A `<form>` and the controls associated with it, holding their current
+/// values so they can be changed before the form is encoded
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Form {
+    pub attributes: Attributes,
+    /// The `action` resolved against the document's base URL. An empty or
+    /// missing action is the document's URL.
+    pub action: String,
+    pub method: Method,
+    pub enctype: Enctype,
+    controls: Vec<Control>,
+    // What a submit button's `formaction` is resolved against
+    base: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Method {
+    #[default]
+    Get,
+    Post,
+    Dialog,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Enctype {
+    #[default]
+    UrlEncoded,
+    Multipart,
+    TextPlain,
+}
+
+/// A submittable element: an `<input>`, `<button>`, `<select>` or
+/// `<textarea>`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Control {
+    pub element: HtmlElement,
+    pub attributes: Attributes,
+    pub name: String,
+    /// The `type` of an `<input>` or `<button>` as the browser understands
+    /// it, so unknown input types are `text`. `select` or `textarea` for
+    /// those elements.
+    pub control_type: String,
+    /// The value, sanitised as the input type requires, or for a
+    /// `<textarea>` its text. Unused for `<select>`.
+    pub value: String,
+    /// Whether a checkbox or radio button is checked
+    pub checked: bool,
+    /// Whether the control is disabled itself or by a `<fieldset>`
+    pub disabled: bool,
+    /// The options of a `<select>`, including those in `<optgroup>`s
+    pub options: Vec<SelectOption>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SelectOption {
+    pub value: String,
+    pub text: String,
+    pub selected: bool,
+    pub disabled: bool,
+}
+
+/// A form submission: where to send it and what
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FormRequest {
+    pub method: Method,
+    /// The action, with the form data as its query for `GET`
+    pub url: String,
+    pub content_type: Option<String>,
+    pub body: Option<String>,
+}
+
+struct Entry {
+    name: String,
+    value: String,
+    file: bool,
+}
+
+const INPUT_TYPES: &[&str] = &[
+    "hidden",
+    "text",
+    "search",
+    "tel",
+    "url",
+    "email",
+    "password",
+    "date",
+    "month",
+    "week",
+    "time",
+    "datetime-local",
+    "number",
+    "range",
+    "color",
+    "checkbox",
+    "radio",
+    "file",
+    "submit",
+    "image",
+    "reset",
+    "button",
+];
+
+/// Builds the form for a `<form>` node. Its controls are found in the whole
+/// tree, as a control's `form` attribute can place it anywhere.
+pub(crate) fn build_form<N: TreeNode>(form: &N, document_url: Option<&str>) -> Option<Form> {
+    let attributes = match form.element() {
+        Some(HtmlElement::Form) => form.with_attributes(Attributes::clone),
+        _ => return None,
+    };
+    let mut root = form.clone();
+    while let Some(parent) = root.parent() {
+        root = parent;
+    }
+    let base = links::base_url(
+        links::find_base_href(std::slice::from_ref(&root)).as_deref(),
+        document_url,
+    );
+    let mut elements = vec![];
+    descendants(&root, &mut elements);
+    let controls = elements
+        .iter()
+        .filter(|node| {
+            matches!(
+                node.element(),
+                Some(
+                    HtmlElement::Input
+                        | HtmlElement::Button
+                        | HtmlElement::Select
+                        | HtmlElement::Textarea
+                )
+            )
+        })
+        .filter(|&node| owner(node, &elements).is_some_and(|owner| owner.same_node(form)))
+        .map(build_control)
+        .collect();
+    Some(Form {
+        action: resolve_action(attributes.get_url("action"), base.as_deref(), document_url),
+        method: parse_method(attributes.get("method")).unwrap_or_default(),
+        enctype: parse_enctype(attributes.get("enctype")).unwrap_or_default(),
+        attributes,
+        controls,
+        base,
+    })
+}
+
+impl Form {
+    /// The form's controls in tree order
+    pub fn controls(&self) -> &[Control] {
+        &self.controls
+    }
+
+    pub fn controls_mut(&mut self) -> &mut [Control] {
+        &mut self.controls
+    }
+
+    /// Sets the value of the named controls the way a user would: typing
+    /// into a text control, checking the checkbox or radio button with that
+    /// value, or selecting the enabled option with that value. Typed values
+    /// are sanitised for the input type, so `abc` empties a number input.
+    /// Returns whether any control took the value, which file inputs never
+    /// do as there is no file to choose.
+    pub fn set(&mut self, name: &str, value: &str) -> bool {
+        let mut found = false;
+        for control in self
+            .controls
+            .iter_mut()
+            .filter(|control| control.name == name)
+        {
+            match control.control_type.as_str() {
+                "checkbox" | "radio" => {
+                    if control.checkable_value() == value {
+                        control.checked = true;
+                        found = true;
+                    } else if control.control_type == "radio" {
+                        control.checked = false;
+                    }
+                }
+                "select" => {
+                    let multiple = control.attributes.get_bool("multiple");
+                    let selectable =
+                        |option: &SelectOption| option.value == value && !option.disabled;
+                    if control.options.iter().any(selectable) {
+                        for option in &mut control.options {
+                            if selectable(option) {
+                                option.selected = true;
+                            } else if !multiple {
+                                option.selected = false;
+                            }
+                        }
+                        found = true;
+                    }
+                }
+                "submit" | "image" | "reset" | "button" | "file" => {}
+                _ => {
+                    control.value = sanitize_value(
+                        &control.control_type,
+                        value.to_string(),
+                        &control.attributes,
+                    );
+                    found = true;
+                }
+            }
+        }
+        found
+    }
+
+    /// The form data set which would be submitted without a submit button:
+    /// the name and value of each enabled control which has a name, with
+    /// unchecked checkboxes and radio buttons and unselected options left
+    /// out. File inputs give their file name, which is empty.
+    pub fn data(&self) -> Vec<(String, String)> {
+        self.entries(None)
+            .into_iter()
+            .map(|entry| (entry.name, entry.value))
+            .collect()
+    }
+
+    /// The form data as an `application/x-www-form-urlencoded` string
+    pub fn urlencoded(&self) -> String {
+        urlencode(&self.entries(None))
+    }
+
+    /// The form data as a `multipart/form-data` body separated by
+    /// `boundary`, which must not occur in the data
+    pub fn multipart(&self, boundary: &str) -> String {
+        multipart(&self.entries(None), boundary)
+    }
+
+    /// Builds the request submitting the form, optionally by the control at
+    /// `submitter` in [`Form::controls`]. A submit button adds its own name
+    /// and value, and its `formaction`, `formmethod` and `formenctype`
+    /// override the form's.
+    pub fn request(&self, submitter: Option<usize>) -> FormRequest {
+        let submitter = submitter
+            .and_then(|index| self.controls.get(index))
+            .filter(|control| control.is_submit_button());
+        let attribute = |name: &str| submitter.and_then(|control| control.attributes.get(name));
+        let method = parse_method(attribute("formmethod")).unwrap_or(self.method);
+        let enctype = parse_enctype(attribute("formenctype")).unwrap_or(self.enctype);
+        let action = match submitter.and_then(|control| control.attributes.get_url("formaction")) {
+            Some(formaction) => resolve_action(Some(formaction), self.base.as_deref(), None),
+            None => self.action.clone(),
+        };
+        let entries = self.entries(submitter);
+        match method {
+            Method::Get => {
+                let (url, fragment) = match action.split_once('#') {
+                    Some((url, fragment)) => (url, Some(fragment)),
+                    None => (action.as_str(), None),
+                };
+                let mut url = format!(
+                    "{}?{}",
+                    url.split('?').next().unwrap_or_default(),
+                    urlencode(&entries)
+                );
+                if let Some(fragment) = fragment {
+                    url.push('#');
+                    url.push_str(fragment);
+                }
+                FormRequest {
+                    method,
+                    url,
+                    content_type: None,
+                    body: None,
+                }
+            }
+            Method::Post => {
+                let (content_type, body) = match enctype {
+                    Enctype::UrlEncoded => (
+                        "application/x-www-form-urlencoded".to_string(),
+                        urlencode(&entries),
+                    ),
+                    Enctype::Multipart => {
+                        let boundary = boundary(&entries);
+                        let body = multipart(&entries, &boundary);
+                        (format!("multipart/form-data; boundary={boundary}"), body)
+                    }
+                    Enctype::TextPlain => {
+                        let body = entries
+                            .iter()
+                            .map(|entry| format!("{}={}\r\n", entry.name, entry.value))
+                            .collect();
+                        ("text/plain".to_string(), body)
+                    }
+                };
+                FormRequest {
+                    method,
+                    url: action,
+                    content_type: Some(content_type),
+                    body: Some(body),
+                }
+            }
+            Method::Dialog => FormRequest {
+                method,
+                url: action,
+                content_type: None,
+                body: None,
+            },
+        }
+    }
+
+    /// Constructs the entry list, with line breaks in names and values
+    /// normalised to CRLF
+    fn entries(&self, submitter: Option<&Control>) -> Vec<Entry> {
+        let mut entries = vec![];
+        for control in &self.controls {
+            let is_submitter = submitter.is_some_and(|submitter| std::ptr::eq(submitter, control));
+            if control.disabled || control.is_button() && !is_submitter {
+                continue;
+            }
+            if control.control_type == "image" {
+                let prefix = match control.name.is_empty() {
+                    true => String::new(),
+                    false => format!("{}.", control.name),
+                };
+                for axis in ["x", "y"] {
+                    entries.push(Entry {
+                        name: format!("{prefix}{axis}"),
+                        value: "0".to_string(),
+                        file: false,
+                    });
+                }
+                continue;
+            }
+            if control.name.is_empty() {
+                continue;
+            }
+            let mut push = |value: &str, file: bool| {
+                entries.push(Entry {
+                    name: normalize_newlines(&control.name),
+                    value: normalize_newlines(value),
+                    file,
+                })
+            };
+            match control.control_type.as_str() {
+                "select" => {
+                    for option in control
+                        .options
+                        .iter()
+                        .filter(|option| option.selected && !option.disabled)
+                    {
+                        push(&option.value, false);
+                    }
+                }
+                "checkbox" | "radio" => {
+                    if control.checked {
+                        push(control.checkable_value(), false);
+                    }
+                }
+                "file" => push("", true),
+                "hidden" if control.name.eq_ignore_ascii_case("_charset_") => push("UTF-8", false),
+                _ => push(&control.value, false),
+            }
+        }
+        entries
+    }
+}
+
+impl Control {
+    fn is_button(&self) -> bool {
+        matches!(
+            self.control_type.as_str(),
+            "submit" | "image" | "reset" | "button"
+        )
+    }
+
+    fn is_submit_button(&self) -> bool {
+        matches!(self.control_type.as_str(), "submit" | "image")
+    }
+
+    /// The value a checkbox or radio button submits when checked
+    fn checkable_value(&self) -> &str {
+        match self.attributes.contains("value") {
+            true => &self.value,
+            false => "on",
+        }
+    }
+}
+
+fn build_control<N: TreeNode>(node: &N) -> Control {
+    let element = node.element().unwrap_or(HtmlElement::Input);
+    let attributes = node.with_attributes(Attributes::clone);
+    let control_type = match element {
+        HtmlElement::Input => {
+            let input_type = attributes
+                .get("type")
+                .unwrap_or_default()
+                .to_ascii_lowercase();
+            match INPUT_TYPES.contains(&input_type.as_str()) {
+                true => input_type,
+                false => "text".to_string(),
+            }
+        }
+        HtmlElement::Button => match attributes
+            .get("type")
+            .map(str::to_ascii_lowercase)
+            .as_deref()
+        {
+            Some("reset") => "reset".to_string(),
+            Some("button") => "button".to_string(),
+            _ => "submit".to_string(),
+        },
+        HtmlElement::Select => "select".to_string(),
+        _ => "textarea".to_string(),
+    };
+    let value = match element {
+        HtmlElement::Textarea => node.text_content(),
+        _ => attributes.get("value").unwrap_or_default().to_string(),
+    };
+    let value = sanitize_value(&control_type, value, &attributes);
+    let mut options = vec![];
+    if element == HtmlElement::Select {
+        collect_options(node, false, &mut options);
+        // A drop-down shows its first enabled option when none is selected
+        let drop_down =
+            !attributes.get_bool("multiple") && attributes.get_usize("size").unwrap_or(1) <= 1;
+        if drop_down {
+            if let Some(last) = options.iter().rposition(|option| option.selected) {
+                for option in &mut options[..last] {
+                    option.selected = false;
+                }
+            } else if let Some(first) = options.iter_mut().find(|option| !option.disabled) {
+                first.selected = true;
+            }
+        }
+    }
+    Control {
+        name: attributes.get("name").unwrap_or_default().to_string(),
+        checked: attributes.get_bool("checked"),
+        disabled: is_disabled(node),
+        element,
+        attributes,
+        control_type,
+        value,
+        options,
+    }
+}
+
+/// Applies the value sanitization algorithm of an input type: invalid
+/// numbers, dates and times become empty, a colour defaults to black and a
+/// range is moved onto its bounds and step
+fn sanitize_value(control_type: &str, mut value: String, attributes: &Attributes) -> String {
+    let valid = |valid: bool, value: String| match valid {
+        true => value,
+        false => String::new(),
+    };
+    if matches!(
+        control_type,
+        "text" | "search" | "tel" | "password" | "url" | "email"
+    ) {
+        value.retain(|c| c != '\n' && c != '\r');
+    }
+    match control_type {
+        "url" => value
+            .trim_matches(|c: char| c.is_ascii_whitespace())
+            .to_string(),
+        "email" if attributes.get_bool("multiple") => value
+            .split(',')
+            .map(|address| address.trim_matches(|c: char| c.is_ascii_whitespace()))
+            .collect::<Vec<_>>()
+            .join(","),
+        "email" => value
+            .trim_matches(|c: char| c.is_ascii_whitespace())
+            .to_string(),
+        "number" => valid(parse_number(&value).is_some(), value),
+        "range" => sanitize_range(&value, attributes),
+        "color" => match value.len() == 7
+            && value.starts_with('#')
+            && value[1..].bytes().all(|b| b.is_ascii_hexdigit())
+        {
+            true => value.to_ascii_lowercase(),
+            false => "#000000".to_string(),
+        },
+        "date" => valid(parse_date(&value).is_some(), value),
+        "month" => valid(parse_month(&value).is_some(), value),
+        "week" => valid(is_week(&value), value),
+        "time" => valid(normalize_time(&value).is_some(), value),
+        "datetime-local" => normalize_date_time(&value).unwrap_or_default(),
+        _ => value,
+    }
+}
+
+/// Clamps a range's value to its bounds and rounds it to the nearest step,
+/// starting from the midpoint when the value is missing or invalid
+fn sanitize_range(value: &str, attributes: &Attributes) -> String {
+    let number = |name: &str| attributes.get(name).and_then(parse_number);
+    let min = number("min").unwrap_or(0.0);
+    let max = number("max").unwrap_or(100.0).max(min);
+    let mut value = parse_number(value)
+        .unwrap_or(min + (max - min) / 2.0)
+        .clamp(min, max);
+    let step = match attributes.get("step") {
+        Some(step) if step.eq_ignore_ascii_case("any") => None,
+        step => Some(
+            step.and_then(parse_number)
+                .filter(|&step| step > 0.0)
+                .unwrap_or(1.0),
+        ),
+    };
+    if let Some(step) = step {
+        // Halfway values round up, and the last step below the maximum is
+        // used when rounding overshoots it
+        value = min + ((value - min) / step + 0.5).floor() * step;
+        if value > max {
+            value -= step;
+        }
+    }
+    // Enough precision to hide the error in steps such as 0.1
+    let value = format!("{value:.12}");
+    match value.trim_end_matches('0').trim_end_matches('.') {
+        "-0" => "0".to_string(),
+        value => value.to_string(),
+    }
+}
+
+/// Parses a valid floating-point number: an optional `-`, digits with an
+/// optional fraction and an optional exponent
+fn parse_number(value: &str) -> Option<f64> {
+    let all_digits =
+        |digits: &str| !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit());
+    let unsigned = value.strip_prefix('-').unwrap_or(value);
+    let (mantissa, exponent) = match unsigned.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+        None => (unsigned, None),
+    };
+    let valid_mantissa = match mantissa.split_once('.') {
+        Some((integer, fraction)) => {
+            (integer.is_empty() || all_digits(integer)) && all_digits(fraction)
+        }
+        None => all_digits(mantissa),
+    };
+    let valid_exponent = exponent
+        .is_none_or(|exponent| all_digits(exponent.strip_prefix(['-', '+']).unwrap_or(exponent)));
+    if !valid_mantissa || !valid_exponent {
+        return None;
+    }
+    value.parse().ok().filter(|number: &f64| number.is_finite())
+}
+
+/// Parses exactly `len` ASCII digits
+fn parse_digits(value: &str, len: usize) -> Option<u32> {
+    match value.len() == len && value.bytes().all(|b| b.is_ascii_digit()) {
+        true => value.parse().ok(),
+        false => None,
+    }
+}
+
+/// Parses a year of at least four digits, which must be after year zero
+fn parse_year(value: &str) -> Option<u32> {
+    match value.len() >= 4 && value.bytes().all(|b| b.is_ascii_digit()) {
+        true => value.parse().ok().filter(|&year| year > 0),
+        false => None,
+    }
+}
+
+fn is_leap_year(year: u32) -> bool {
+    year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400))
+}
+
+/// Parses a month string such as `2024-02`
+fn parse_month(value: &str) -> Option<(u32, u32)> {
+    let (year, month) = value.split_once('-')?;
+    let year = parse_year(year)?;
+    let month = parse_digits(month, 2)?;
+    (1..=12).contains(&month).then_some((year, month))
+}
+
+/// Parses a date string such as `2024-02-29`
+fn parse_date(value: &str) -> Option<(u32, u32, u32)> {
+    let (month, day) = value.rsplit_once('-')?;
+    let (year, month) = parse_month(month)?;
+    let day = parse_digits(day, 2)?;
+    let days = match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+    (1..=days).contains(&day).then_some((year, month, day))
+}
+
+/// Whether a value is a week string such as `2020-W53`. A year has 53 weeks
+/// when it starts on a Thursday, or on a Wednesday in a leap year.
+fn is_week(value: &str) -> bool {
+    let Some((year, week)) = value.split_once("-W") else {
+        return false;
+    };
+    let (Some(year), Some(week)) = (parse_year(year), parse_digits(week, 2)) else {
+        return false;
+    };
+    let before = u64::from(year - 1);
+    // Days of the week counted from Sunday
+    let first_day = (1 + 5 * (before % 4) + 4 * (before % 100) + 6 * (before % 400)) % 7;
+    let weeks = match first_day == 4 || first_day == 3 && is_leap_year(year) {
+        true => 53,
+        false => 52,
+    };
+    (1..=weeks).contains(&week)
+}
+
+/// Parses a time string such as `09:30:05.25`, returning its shortest
+/// form, which leaves out zero seconds and trailing zeros in the fraction
+fn normalize_time(value: &str) -> Option<String> {
+    let (hour, rest) = value.split_once(':')?;
+    let (minute, second) = match rest.split_once(':') {
+        Some((minute, second)) => (minute, Some(second)),
+        None => (rest, None),
+    };
+    let hour = parse_digits(hour, 2).filter(|&hour| hour < 24)?;
+    let minute = parse_digits(minute, 2).filter(|&minute| minute < 60)?;
+    let mut time = format!("{hour:02}:{minute:02}");
+    if let Some(second) = second {
+        let (second, fraction) = match second.split_once('.') {
+            Some((second, fraction)) => {
+                let digits = (1..=3).contains(&fraction.len())
+                    && fraction.bytes().all(|b| b.is_ascii_digit());
+                (second, digits.then_some(fraction)?)
+            }
+            None => (second, ""),
+        };
+        let second = parse_digits(second, 2).filter(|&second| second < 60)?;
+        let fraction = fraction.trim_end_matches('0');
+        if second > 0 || !fraction.is_empty() {
+            time.push_str(&format!(":{second:02}"));
+        }
+        if !fraction.is_empty() {
+            time.push('.');
+            time.push_str(fraction);
+        }
+    }
+    Some(time)
+}
+
+/// Normalises a local date and time string, using a `T` between the date
+/// and the shortest form of the time
+fn normalize_date_time(value: &str) -> Option<String> {
+    let (date, time) = value.split_once(['T', ' '])?;
+    parse_date(date)?;
+    Some(format!("{date}T{}", normalize_time(time)?))
+}
+
+fn collect_options<N: TreeNode>(node: &N, disabled: bool, options: &mut Vec<SelectOption>) {
+    for child in node.children() {
+        let own_disabled = child.attribute("disabled").is_some();
+        match child.element() {
+            Some(HtmlElement::Option) => {
+                let text = whitespace::normalize(&child.text_content());
+                options.push(SelectOption {
+                    value: child.attribute("value").unwrap_or_else(|| text.clone()),
+                    text,
+                    selected: child.attribute("selected").is_some(),
+                    disabled: disabled || own_disabled,
+                });
+            }
+            Some(HtmlElement::Optgroup) => collect_options(&child, own_disabled, options),
+            _ => {}
+        }
+    }
+}
+
+/// Whether a control is disabled by its own attribute, by a disabled
+/// `<fieldset>` outside that fieldset's first `<legend>`, or by being in a
+/// `<datalist>`
+fn is_disabled<N: TreeNode>(node: &N) -> bool {
+    if node.attribute("disabled").is_some() {
+        return true;
+    }
+    let mut child = node.clone();
+    while let Some(parent) = child.parent() {
+        match parent.element() {
+            Some(HtmlElement::Datalist) => return true,
+            Some(HtmlElement::Fieldset) if parent.attribute("disabled").is_some() => {
+                let first_legend = parent
+                    .children()
+                    .into_iter()
+                    .find(|child| child.element() == Some(HtmlElement::Legend));
+                if !first_legend.is_some_and(|legend| legend.same_node(&child)) {
+                    return true;
+                }
+            }
+            _ => {}
+        }
+        child = parent;
+    }
+    false
+}
+
+/// The form a control belongs to: the form its `form` attribute names, or
+/// its nearest form ancestor
+fn owner<N: TreeNode>(node: &N, elements: &[N]) -> Option<N> {
+    if let Some(id) = node.attribute("form") {
+        return elements
+            .iter()
+            .find(|element| element.attribute("id").as_deref() == Some(id.as_str()))
+            .filter(|&owner| owner.element() == Some(HtmlElement::Form))
+            .cloned();
+    }
+    let mut current = node.parent();
+    while let Some(parent) = current {
+        if parent.element() == Some(HtmlElement::Form) {
+            return Some(parent);
+        }
+        current = parent.parent();
+    }
+    None
+}
+
+fn descendants<N: TreeNode>(node: &N, elements: &mut Vec<N>) {
+    for child in node.children() {
+        if child.is_element() {
+            elements.push(child.clone());
+        }
+        descendants(&child, elements);
+    }
+}
+
+fn resolve_action(action: Option<&str>, base: Option<&str>, document_url: Option<&str>) -> String {
+    match action {
+        Some(action) => base
+            .and_then(|base| url::resolve(base, action))
+            .unwrap_or_else(|| action.to_string()),
+        None => document_url.unwrap_or_default().to_string(),
+    }
+}
+
+fn parse_method(method: Option<&str>) -> Option<Method> {
+    match method?.to_ascii_lowercase().as_str() {
+        "get" => Some(Method::Get),
+        "post" => Some(Method::Post),
+        "dialog" => Some(Method::Dialog),
+        _ => None,
+    }
+}
+
+fn parse_enctype(enctype: Option<&str>) -> Option<Enctype> {
+    match enctype?.to_ascii_lowercase().as_str() {
+        "application/x-www-form-urlencoded" => Some(Enctype::UrlEncoded),
+        "multipart/form-data" => Some(Enctype::Multipart),
+        "text/plain" => Some(Enctype::TextPlain),
+        _ => None,
+    }
+}
+
+fn normalize_newlines(value: &str) -> String {
+    value
+        .replace("\r\n", "\n")
+        .replace('\r', "\n")
+        .replace('\n', "\r\n")
+}
+
+fn urlencode(entries: &[Entry]) -> String {
+    let encode = |value: &str| {
+        let mut encoded = String::with_capacity(value.len());
+        for byte in value.bytes() {
+            match byte {
+                b' ' => encoded.push('+'),
+                b'*' | b'-' | b'.' | b'_' => encoded.push(byte as char),
+                _ if byte.is_ascii_alphanumeric() => encoded.push(byte as char),
+                _ => encoded.push_str(&format!("%{byte:02X}")),
+            }
+        }
+        encoded
+    };
+    entries
+        .iter()
+        .map(|entry| format!("{}={}", encode(&entry.name), encode(&entry.value)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// A boundary which occurs in no entry's name or value, numbering the
+/// default one until it is unique
+fn boundary(entries: &[Entry]) -> String {
+    let occurs = |boundary: &str| {
+        entries
+            .iter()
+            .any(|entry| entry.name.contains(boundary) || entry.value.contains(boundary))
+    };
+    let mut boundary = "----FormBoundary".to_string();
+    let mut count = 0;
+    while occurs(&boundary) {
+        count += 1;
+        boundary = format!("----FormBoundary{count}");
+    }
+    boundary
+}
+
+fn multipart(entries: &[Entry], boundary: &str) -> String {
+    let escape = |name: &str| {
+        name.replace('\n', "%0A")
+            .replace('\r', "%0D")
+            .replace('"', "%22")
+    };
+    let mut body = String::new();
+    for entry in entries {
+        body.push_str(&format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"{}\"",
+            escape(&entry.name)
+        ));
+        if entry.file {
+            body.push_str(&format!(
+                "; filename=\"{}\"\r\nContent-Type: application/octet-stream",
+                escape(&entry.value)
+            ));
+            body.push_str("\r\n\r\n\r\n");
+        } else {
+            body.push_str(&format!("\r\n\r\n{}\r\n", entry.value));
+        }
+    }
+    body.push_str(&format!("--{boundary}--\r\n"));
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::Document;
+
+    const HTML: &str = r#"<base href="/app/">
+        <form id="search" action="find?old=1#results" method="get">
+            <input name="q" value="a b">
+            <input type="checkbox" name="safe" checked>
+            <input type="checkbox" name="images" value="yes">
+            <input type="radio" name="size" value="s">
+            <input type="radio" name="size" value="l" checked>
+            <select name="lang"><option>en</option><option value="fr" disabled>French</option></select>
+            <select name="tags" multiple><optgroup label="g" disabled><option selected>x</option></optgroup><option selected>y</option></select>
+            <textarea name="notes">one
+two</textarea>
+            <input name="off" disabled value="x">
+            <fieldset disabled><legend><input name="legend" value="kept"></legend><input name="fenced"></fieldset>
+            <button name="go" value="1">Go</button>
+        </form>
+        <input name="outside" value="o" form="search">
+        <form method="POST" enctype="multipart/form-data"><input type="file" name="upload"><input name="n&quot;" value="v"></form>"#;
+
+    #[test]
+    fn check_form_data_set() {
+        let document = Document::parse(HTML);
+        let forms = document.forms(Some("https://example.com/index.html"));
+        let form = &forms[0];
+        assert_eq!(form.action, "https://example.com/app/find?old=1#results");
+        assert_eq!(form.controls().len(), 13);
+        let data = form.data();
+        let data: Vec<(&str, &str)> = data.iter().map(|(n, v)| (n.as_str(), v.as_str())).collect();
+        assert_eq!(
+            data,
+            vec![
+                ("q", "a b"),
+                ("safe", "on"),
+                ("size", "l"),
+                ("lang", "en"),
+                ("tags", "y"),
+                ("notes", "one\r\ntwo"),
+                ("legend", "kept"),
+                ("outside", "o"),
+            ]
+        );
+    }
+
+    #[test]
+    fn check_setting_values_and_encoding() {
+        let document = Document::parse(HTML);
+        let mut form = document.forms(Some("https://example.com/")).remove(0);
+        assert!(form.set("q", "rust & html"));
+        assert!(form.set("images", "yes"));
+        assert!(form.set("size", "s"));
+        assert!(!form.set("lang", "fr"));
+        assert!(!form.set("missing", "x"));
+        assert_eq!(
+            form.urlencoded(),
+            "q=rust+%26+html&safe=on&images=yes&size=s&lang=en&tags=y&notes=one%0D%0Atwo&legend=kept&outside=o"
+        );
+        let submitter = form
+            .controls()
+            .iter()
+            .position(|control| control.name == "go");
+        let request = form.request(submitter);
+        assert_eq!(request.method, Method::Get);
+        assert!(
+            request
+                .url
+                .starts_with("https://example.com/app/find?q=rust")
+        );
+        assert!(request.url.contains("legend=kept&go=1&outside=o#results"));
+
+        let upload = &document.forms(None)[1];
+        assert_eq!(upload.action, "");
+        assert_eq!(
+            upload.multipart("b"),
+            "--b\r\nContent-Disposition: form-data; name=\"upload\"; filename=\"\"\r\nContent-Type: application/octet-stream\r\n\r\n\r\n--b\r\nContent-Disposition: form-data; name=\"n%22\"\r\n\r\nv\r\n--b--\r\n"
+        );
+        let request = upload.request(None);
+        assert_eq!(request.method, Method::Post);
+        assert_eq!(
+            request.content_type.as_deref(),
+            Some("multipart/form-data; boundary=----FormBoundary")
+        );
+        assert_eq!(request.body, Some(upload.multipart("----FormBoundary")));
+
+        let mut upload = upload.clone();
+        assert!(upload.set("n\"", "--x\r\n------FormBoundary--"));
+        assert_eq!(
+            upload.request(None).content_type.as_deref(),
+            Some("multipart/form-data; boundary=----FormBoundary1")
+        );
+    }
+
+    #[test]
+    fn check_value_sanitization() {
+        let html = r##"<form>
+            <input type="range" name="r">
+            <input type="range" name="stepped" min="0" max="10" step="3" value="10">
+            <input type="range" name="fine" min="0" max="1" step="0.1" value="0.28">
+            <input type="number" name="n" value="1e3">
+            <input type="color" name="c" value="#ABCDEF">
+            <input type="url" name="u" value=" /x ">
+            <input type="email" name="e" multiple value="a@b.c , d@e.f">
+            <input type="date" name="d" value="2023-02-29">
+            <input type="week" name="w" value="2020-W53">
+            <input type="datetime-local" name="dt" value="2024-02-29 09:30:00.500">
+            <input type="file" name="f">
+        </form>"##;
+        let document = Document::parse(html);
+        let mut form = document.forms(None).remove(0);
+        let value = |form: &Form, name: &str| {
+            let control = form.controls().iter().find(|control| control.name == name);
+            control.unwrap().value.clone()
+        };
+        assert_eq!(value(&form, "r"), "50");
+        assert_eq!(value(&form, "stepped"), "9");
+        assert_eq!(value(&form, "fine"), "0.3");
+        assert_eq!(value(&form, "n"), "1e3");
+        assert_eq!(value(&form, "c"), "#abcdef");
+        assert_eq!(value(&form, "u"), "/x");
+        assert_eq!(value(&form, "e"), "a@b.c,d@e.f");
+        assert_eq!(value(&form, "d"), "");
+        assert_eq!(value(&form, "w"), "2020-W53");
+        assert_eq!(value(&form, "dt"), "2024-02-29T09:30:00.5");
+
+        assert!(form.set("r", "-4"));
+        assert_eq!(value(&form, "r"), "0");
+        assert!(form.set("n", "12abc"));
+        assert_eq!(value(&form, "n"), "");
+        assert!(form.set("c", "red"));
+        assert_eq!(value(&form, "c"), "#000000");
+        assert!(form.set("w", "2021-W53"));
+        assert_eq!(value(&form, "w"), "");
+        assert!(!form.set("f", "/etc/passwd"));
+        assert_eq!(value(&form, "f"), "");
+    }
+}
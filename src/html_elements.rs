@@ -153,6 +153,9 @@ pub enum HtmlElement {
     Noframes,
     Strike,
     Tt,
+    Xmp,
+    Plaintext,
+    Noembed,
 
     // Custom or unrecognized elements
     Unknown(String),
@@ -313,6 +316,9 @@ impl HtmlElement {
             "noframes" => Self::Noframes,
             "strike" => Self::Strike,
             "tt" => Self::Tt,
+            "xmp" => Self::Xmp,
+            "plaintext" => Self::Plaintext,
+            "noembed" => Self::Noembed,
 
             // Unknown element
             _ => Self::Unknown(tag.to_string()),
@@ -473,6 +479,9 @@ impl HtmlElement {
             Self::Noframes => "noframes",
             Self::Strike => "strike",
             Self::Tt => "tt",
+            Self::Xmp => "xmp",
+            Self::Plaintext => "plaintext",
+            Self::Noembed => "noembed",
 
             // Unknown element
             Self::Unknown(tag) => tag,
@@ -488,6 +497,7 @@ impl HtmlElement {
                 | Self::Br
                 | Self::Col
                 | Self::Embed
+                | Self::Frame
                 | Self::Hr
                 | Self::Img
                 | Self::Input
@@ -516,9 +526,108 @@ impl HtmlElement {
                 | Self::Noframes
                 | Self::Strike
                 | Self::Tt
+                | Self::Xmp
+                | Self::Plaintext
+                | Self::Noembed
+        )
+    }
+
+    /// Checks if the element's contents are raw text which is never parsed
+    /// for tags or character references
+    pub fn is_raw_text_element(&self) -> bool {
+        matches!(
+            self,
+            Self::Script
+                | Self::Style
+                | Self::Xmp
+                | Self::Iframe
+                | Self::Noembed
+                | Self::Noframes
+                | Self::Noscript
+                | Self::Plaintext
+        )
+    }
+
+    /// Checks if the element's contents are text in which character
+    /// references are decoded but tags are not recognised (RCDATA)
+    pub fn is_escapable_raw_text_element(&self) -> bool {
+        matches!(self, Self::Textarea | Self::Title)
+    }
+
+    /// Checks if the element is laid out inline by default, so whitespace
+    /// around it is rendered
+    pub fn is_inline(&self) -> bool {
+        matches!(
+            self,
+            Self::A
+                | Self::Abbr
+                | Self::B
+                | Self::Bdi
+                | Self::Bdo
+                | Self::Br
+                | Self::Cite
+                | Self::Code
+                | Self::Data
+                | Self::Dfn
+                | Self::Em
+                | Self::I
+                | Self::Kbd
+                | Self::Mark
+                | Self::Q
+                | Self::Rp
+                | Self::Rt
+                | Self::Ruby
+                | Self::S
+                | Self::Samp
+                | Self::Small
+                | Self::Span
+                | Self::Strong
+                | Self::Sub
+                | Self::Sup
+                | Self::Time
+                | Self::U
+                | Self::Var
+                | Self::Wbr
+                | Self::Picture
+                | Self::Img
+                | Self::Svg
+                | Self::Math
+                | Self::Audio
+                | Self::Video
+                | Self::Map
+                | Self::Area
+                | Self::Iframe
+                | Self::Embed
+                | Self::Object
+                | Self::Canvas
+                | Self::Del
+                | Self::Ins
+                | Self::Label
+                | Self::Input
+                | Self::Button
+                | Self::Select
+                | Self::Textarea
+                | Self::Output
+                | Self::Progress
+                | Self::Meter
+                | Self::Slot
+                | Self::Acronym
+                | Self::Big
+                | Self::Font
+                | Self::Strike
+                | Self::Tt
+                | Self::Unknown(_)
         )
     }
 
+    /// Checks if whitespace in the element's text is significant, as in
+    /// `<pre>` and the raw text elements
+    pub fn preserves_whitespace(&self) -> bool {
+        matches!(self, Self::Pre | Self::Textarea)
+            || self.is_raw_text_element()
+            || self.tag_name() == "listing"
+    }
+
     /// Checks if the element is a sectioning element
     pub fn is_sectioning(&self) -> bool {
         matches!(
@@ -527,6 +636,15 @@ impl HtmlElement {
         )
     }
 
+    /// Checks if the element's contents are never rendered, as with
+    /// `<script>` and everything in `<head>`
+    pub fn is_hidden(&self) -> bool {
+        matches!(
+            self,
+            Self::Head | Self::Script | Self::Style | Self::Template | Self::Noscript | Self::Title
+        )
+    }
+
     /// Checks if the element is a heading element
     pub fn is_heading(&self) -> bool {
         matches!(
@@ -609,6 +727,7 @@ mod tests {
         assert!(HtmlElement::Br.is_void_element());
         assert!(HtmlElement::Hr.is_void_element());
         assert!(HtmlElement::Img.is_void_element());
+        assert!(HtmlElement::Frame.is_void_element());
         assert!(!HtmlElement::Div.is_void_element());
         assert!(!HtmlElement::P.is_void_element());
     }
@@ -621,6 +740,16 @@ mod tests {
         assert!(!HtmlElement::Span.is_obsolete());
     }
 
+    #[test]
+    fn test_raw_text_elements() {
+        assert!(HtmlElement::Script.is_raw_text_element());
+        assert!(HtmlElement::from_tag_name("xmp").is_raw_text_element());
+        assert!(!HtmlElement::Title.is_raw_text_element());
+        assert!(HtmlElement::Title.is_escapable_raw_text_element());
+        assert!(HtmlElement::Textarea.is_escapable_raw_text_element());
+        assert!(!HtmlElement::Div.is_escapable_raw_text_element());
+    }
+
     #[test]
     fn test_sectioning_elements() {
         assert!(HtmlElement::Article.is_sectioning());
@@ -1,3 +1,23 @@
+pub mod attributes;
+pub mod diagnostics;
+pub mod doctype;
+pub mod document;
+pub mod encoding;
+pub mod entities;
+pub mod form;
 pub mod html_elements;
+pub mod links;
+pub mod markdown;
+pub mod metadata;
+pub mod mutation;
 pub mod node_tree;
+pub mod selector;
+pub mod serializer;
+pub mod span;
+pub mod table;
+pub mod text;
 pub mod tokeniser;
+mod tree_builder;
+pub mod url;
+pub mod whitespace;
+pub mod xpath;
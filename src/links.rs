@@ -0,0 +1,309 @@
+use crate::{
+    attributes::Attributes,
+    html_elements::HtmlElement,
+    node_tree::{NodeType, TreeNode},
+    url, whitespace,
+};
+
+/// A URL found in an attribute of an element
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Link {
+    /// The URL resolved against the document's base URL, or as written if
+    /// there is no absolute base to resolve it against
+    pub url: String,
+    /// The URL as written, without surrounding whitespace
+    pub raw: String,
+    pub element: HtmlElement,
+    /// The attribute the URL was found in, `style` for a CSS `url()`
+    pub attribute: String,
+    /// The tokens of the element's `rel` attribute, lowercased
+    pub rel: Vec<String>,
+    /// The text of a link, or the alt text of an image or image map area
+    pub text: Option<String>,
+}
+
+/// Collects the links in a node and its descendants, given its parts, in
+/// document order. URLs are resolved against the first `<base href>`, which
+/// is itself resolved against `document_url`.
+pub(crate) fn extract_links<N: TreeNode>(
+    node_type: &NodeType,
+    attributes: &Attributes,
+    children: &[N],
+    document_url: Option<&str>,
+) -> Vec<Link> {
+    let base_href = match node_type {
+        NodeType::Element(HtmlElement::Base) => attributes.get_url("href").map(str::to_string),
+        _ => find_base_href(children),
+    };
+    let mut extractor = Extractor {
+        base: base_url(base_href.as_deref(), document_url),
+        links: vec![],
+    };
+    extractor.node(node_type, attributes, children);
+    extractor.links
+}
+
+/// The URL relative URLs are resolved against: a `<base href>` resolved
+/// against the document's URL, or the document's URL itself
+pub(crate) fn base_url(base_href: Option<&str>, document_url: Option<&str>) -> Option<String> {
+    base_href
+        .and_then(|href| url::resolve(document_url.unwrap_or_default(), href))
+        .or_else(|| document_url.map(str::to_string))
+}
+
+struct Extractor {
+    base: Option<String>,
+    links: Vec<Link>,
+}
+
+impl Extractor {
+    fn node<N: TreeNode>(&mut self, node_type: &NodeType, attributes: &Attributes, children: &[N]) {
+        if let NodeType::Element(element) = node_type {
+            self.element(element, attributes, children);
+        }
+        for child in children {
+            child.with_node_type(|node_type| {
+                child.with_attributes(|attributes| {
+                    self.node(node_type, attributes, &child.children())
+                })
+            });
+        }
+    }
+
+    fn element<N: TreeNode>(
+        &mut self,
+        element: &HtmlElement,
+        attributes: &Attributes,
+        children: &[N],
+    ) {
+        let text = match element {
+            HtmlElement::A => Some(whitespace::normalize(
+                &children
+                    .iter()
+                    .map(TreeNode::text_content)
+                    .collect::<String>(),
+            )),
+            HtmlElement::Img | HtmlElement::Area => {
+                attributes.get("alt").map(whitespace::normalize)
+            }
+            _ => None,
+        }
+        .filter(|text| !text.is_empty());
+        let rel: Vec<String> = attributes
+            .get("rel")
+            .unwrap_or_default()
+            .split_ascii_whitespace()
+            .map(str::to_ascii_lowercase)
+            .collect();
+        let mut found = vec![];
+        for &attribute in url_attributes(element) {
+            match attribute {
+                "srcset" => {
+                    if let Some(srcset) = attributes.get("srcset") {
+                        found.extend(
+                            srcset_urls(srcset)
+                                .into_iter()
+                                .map(|raw| (attribute, raw.to_string())),
+                        );
+                    }
+                }
+                _ => {
+                    if let Some(raw) = attributes.get_url(attribute) {
+                        found.push((attribute, raw.to_string()));
+                    }
+                }
+            }
+        }
+        if let Some(style) = attributes.get("style") {
+            found.extend(css_urls(style).into_iter().map(|raw| ("style", raw)));
+        }
+        for (attribute, raw) in found {
+            self.links.push(Link {
+                url: self.resolve(&raw),
+                raw,
+                element: element.clone(),
+                attribute: attribute.to_string(),
+                rel: rel.clone(),
+                text: text.clone(),
+            });
+        }
+    }
+
+    fn resolve(&self, raw: &str) -> String {
+        self.base
+            .as_deref()
+            .and_then(|base| url::resolve(base, raw))
+            .unwrap_or_else(|| raw.to_string())
+    }
+}
+
+/// The attributes of an element which hold URLs, other than `style`
+fn url_attributes(element: &HtmlElement) -> &'static [&'static str] {
+    match element {
+        HtmlElement::A | HtmlElement::Area | HtmlElement::Link => &["href"],
+        HtmlElement::Img | HtmlElement::Source => &["src", "srcset"],
+        HtmlElement::Iframe
+        | HtmlElement::Frame
+        | HtmlElement::Embed
+        | HtmlElement::Script
+        | HtmlElement::Audio
+        | HtmlElement::Track => &["src"],
+        HtmlElement::Video => &["src", "poster"],
+        HtmlElement::Input => &["src", "formaction"],
+        HtmlElement::Button => &["formaction"],
+        HtmlElement::Form => &["action"],
+        _ => &[],
+    }
+}
+
+/// The `href` of the first `<base>` which has one
+pub(crate) fn find_base_href<N: TreeNode>(children: &[N]) -> Option<String> {
+    children.iter().find_map(|child| {
+        let is_base = child
+            .with_node_type(|node_type| matches!(node_type, NodeType::Element(HtmlElement::Base)));
+        let href =
+            child.with_attributes(|attributes| attributes.get_url("href").map(str::to_string));
+        match href {
+            Some(href) if is_base => Some(href),
+            _ => find_base_href(&child.children()),
+        }
+    })
+}
+
+/// The URLs of the image candidates in a `srcset`, skipping their width and
+/// density descriptors
+fn srcset_urls(srcset: &str) -> Vec<&str> {
+    let mut urls = vec![];
+    let mut rest = srcset;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == ',');
+        if rest.is_empty() {
+            return urls;
+        }
+        let end = rest
+            .find(|c: char| c.is_ascii_whitespace())
+            .unwrap_or(rest.len());
+        let candidate = &rest[..end];
+        rest = &rest[end..];
+        // A URL ending in a comma has no descriptors
+        let url = candidate.trim_end_matches(',');
+        if url.len() == candidate.len() {
+            let mut depth = 0usize;
+            let end = rest
+                .find(|c: char| {
+                    match c {
+                        '(' => depth += 1,
+                        ')' => depth = depth.saturating_sub(1),
+                        _ => {}
+                    }
+                    c == ',' && depth == 0
+                })
+                .unwrap_or(rest.len());
+            rest = &rest[end..];
+        }
+        if !url.is_empty() {
+            urls.push(url);
+        }
+    }
+}
+
+/// The arguments of the `url()` functions in a CSS declaration list, with
+/// their quotes and escapes removed
+fn css_urls(css: &str) -> Vec<String> {
+    let mut urls = vec![];
+    let lowercase = css.to_ascii_lowercase();
+    let mut offset = 0;
+    while let Some(index) = lowercase[offset..].find("url(") {
+        let start = offset + index + 4;
+        let rest = css[start..].trim_start_matches(|c: char| c.is_ascii_whitespace());
+        let (url, consumed) = match rest.chars().next() {
+            Some(quote @ ('"' | '\'')) => css_string(&rest[1..], quote),
+            _ => {
+                let end = rest.find(')').unwrap_or(rest.len());
+                (css_unescape(rest[..end].trim_end()), end)
+            }
+        };
+        offset = css.len() - rest.len() + consumed;
+        if !url.is_empty() {
+            urls.push(url);
+        }
+    }
+    urls
+}
+
+/// Reads a quoted CSS string up to its closing quote, returning its value
+/// and the number of bytes used including both quotes
+fn css_string(css: &str, quote: char) -> (String, usize) {
+    let mut value = String::new();
+    let mut chars = css.char_indices();
+    while let Some((index, char)) = chars.next() {
+        match char {
+            '\\' => {
+                if let Some((_, escaped)) = chars.next() {
+                    value.push(escaped);
+                }
+            }
+            _ if char == quote => return (value, index + 2),
+            _ => value.push(char),
+        }
+    }
+    (value, css.len() + 1)
+}
+
+fn css_unescape(css: &str) -> String {
+    let mut value = String::new();
+    let mut chars = css.chars();
+    while let Some(char) = chars.next() {
+        match char {
+            '\\' => value.extend(chars.next()),
+            _ => value.push(char),
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::Document;
+
+    #[test]
+    fn check_link_extraction() {
+        let html = r#"<head><base href="/docs/"><link rel="Stylesheet" href="site.css"></head>
+            <body><a href=" ../about#team " rel="nofollow noopener">About <b>us</b></a>
+            <img src="a.png" srcset="a-1x.png 1x, a-2x.png 2x,b.png," alt="Logo">
+            <form action="https://other.example/submit"></form>
+            <video poster="p.jpg"></video>
+            <div style="background: URL( 'bg.png' ) , url(x\(y.png)"></div>"#;
+        let document = Document::parse(html);
+        let links = document.links(Some("https://example.com/index.html"));
+        let found: Vec<(&str, &str)> = links
+            .iter()
+            .map(|link| (link.attribute.as_str(), link.url.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("href", "https://example.com/docs/site.css"),
+                ("href", "https://example.com/about#team"),
+                ("src", "https://example.com/docs/a.png"),
+                ("srcset", "https://example.com/docs/a-1x.png"),
+                ("srcset", "https://example.com/docs/a-2x.png"),
+                ("srcset", "https://example.com/docs/b.png"),
+                ("action", "https://other.example/submit"),
+                ("poster", "https://example.com/docs/p.jpg"),
+                ("style", "https://example.com/docs/bg.png"),
+                ("style", "https://example.com/docs/x(y.png"),
+            ]
+        );
+        assert_eq!(links[0].rel, vec!["stylesheet"]);
+        assert_eq!(links[1].raw, "../about#team");
+        assert_eq!(links[1].element, HtmlElement::A);
+        assert_eq!(links[1].rel, vec!["nofollow", "noopener"]);
+        assert_eq!(links[1].text.as_deref(), Some("About us"));
+        assert_eq!(links[2].text.as_deref(), Some("Logo"));
+
+        let unresolved = document.links(None);
+        assert_eq!(unresolved[1].url, "../about#team");
+    }
+}
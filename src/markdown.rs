@@ -0,0 +1,610 @@
+use crate::{
+    attributes::Attributes,
+    html_elements::HtmlElement,
+    node_tree::{NodeType, TreeNode},
+    serializer::{self, SerializeOptions},
+};
+
+/// Controls how HTML is turned into Markdown
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MarkdownOptions {
+    pub link_style: LinkStyle,
+    pub unknown_elements: UnknownElements,
+}
+
+/// How the destinations of links and images are written
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LinkStyle {
+    /// `[text](url "title")`
+    #[default]
+    Inline,
+    /// `[text][1]`, with `[1]: url "title"` collected at the end
+    Reference,
+}
+
+/// What to do with elements the crate does not recognise, such as custom
+/// elements
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UnknownElements {
+    /// Converts their contents as though the element was not there
+    #[default]
+    Contents,
+    /// Passes them through as raw HTML, which CommonMark allows
+    Html,
+    /// Leaves them and their contents out
+    Skip,
+}
+
+/// Converts a node, given its parts, to CommonMark with the GitHub table
+/// and strikethrough extensions
+pub(crate) fn to_markdown<N: TreeNode>(
+    node_type: &NodeType,
+    attributes: &Attributes,
+    children: &[N],
+    options: &MarkdownOptions,
+) -> String {
+    let mut converter = Converter {
+        options,
+        references: vec![],
+    };
+    let mut blocks = vec![];
+    converter.block(node_type, attributes, children, &mut blocks);
+    if !converter.references.is_empty() {
+        let definitions: Vec<String> = converter
+            .references
+            .iter()
+            .enumerate()
+            .map(|(index, (url, title))| {
+                format!("[{}]: {}", index + 1, destination(url, title.as_deref()))
+            })
+            .collect();
+        blocks.push(definitions.join("\n"));
+    }
+    blocks.join("\n\n")
+}
+
+struct Converter<'a> {
+    options: &'a MarkdownOptions,
+    // Destinations and titles of reference style links, numbered from 1
+    references: Vec<(String, Option<String>)>,
+}
+
+/// Inline content being gathered into a paragraph, heading or table cell
+#[derive(Default)]
+struct Inline {
+    text: String,
+}
+
+impl Inline {
+    /// Adds text with its whitespace collapsed and Markdown syntax escaped
+    fn push_text(&mut self, text: &str) {
+        for char in text.chars() {
+            if char.is_ascii_whitespace() {
+                if !self.text.is_empty() && !self.text.ends_with([' ', '\n']) {
+                    self.text.push(' ');
+                }
+                continue;
+            }
+            if matches!(char, '\\' | '`' | '*' | '_' | '[' | ']' | '<') {
+                self.text.push('\\');
+            }
+            self.text.push(char);
+        }
+    }
+
+    fn push_markup(&mut self, markup: &str) {
+        self.text.push_str(markup);
+    }
+
+    fn push_hard_break(&mut self) {
+        self.text.truncate(self.text.trim_end_matches(' ').len());
+        if !self.text.is_empty() {
+            self.text.push_str("\\\n");
+        }
+    }
+
+    /// The gathered text, trimmed and escaped where it would otherwise start
+    /// a different kind of block
+    fn finish(self) -> String {
+        let mut text = self.text.trim_matches(|c: char| c.is_ascii_whitespace());
+        if let Some(stripped) = text.strip_suffix('\\')
+            && !stripped.ends_with('\\')
+        {
+            text = stripped.trim_end();
+        }
+        let digits = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(0);
+        if text.starts_with(['#', '>', '-', '+', '=', '~']) {
+            format!("\\{text}")
+        } else if digits > 0 && text[digits..].starts_with(['.', ')']) {
+            format!("{}\\{}", &text[..digits], &text[digits..])
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+impl Converter<'_> {
+    /// Converts a sequence of nodes, gathering runs of inline content into
+    /// paragraphs
+    fn blocks<N: TreeNode>(&mut self, nodes: &[N], blocks: &mut Vec<String>) {
+        let mut inline = Inline::default();
+        for node in nodes {
+            if is_block(node) {
+                push_paragraph(std::mem::take(&mut inline), blocks);
+                node.with_node_type(|node_type| {
+                    node.with_attributes(|attributes| {
+                        self.block(node_type, attributes, &node.children(), blocks)
+                    })
+                });
+            } else {
+                self.inline(node, &mut inline);
+            }
+        }
+        push_paragraph(inline, blocks);
+    }
+
+    fn block<N: TreeNode>(
+        &mut self,
+        node_type: &NodeType,
+        attributes: &Attributes,
+        children: &[N],
+        blocks: &mut Vec<String>,
+    ) {
+        let element = match node_type {
+            NodeType::Element(element) => element,
+            NodeType::Document => return self.blocks(children, blocks),
+            NodeType::Text(_) | NodeType::Comment(_) | NodeType::Doctype(_) => {
+                let mut inline = Inline::default();
+                if let NodeType::Text(text) = node_type {
+                    inline.push_text(text);
+                }
+                return push_paragraph(inline, blocks);
+            }
+        };
+        match element {
+            _ if element.is_hidden() => {}
+            _ if element.is_heading() => {
+                let text = self.inline_children(children).finish();
+                if !text.is_empty() {
+                    let level = element.tag_name()[1..].parse().unwrap_or(1);
+                    blocks.push(format!("{} {text}", "#".repeat(level)));
+                }
+            }
+            HtmlElement::Hr => blocks.push("---".to_string()),
+            HtmlElement::Pre => blocks.push(code_block(children)),
+            HtmlElement::Blockquote => {
+                let mut quoted = vec![];
+                self.blocks(children, &mut quoted);
+                if !quoted.is_empty() {
+                    blocks.push(prefix_lines(&quoted.join("\n\n"), "> ", ">"));
+                }
+            }
+            HtmlElement::Ul | HtmlElement::Ol => {
+                let start = match element {
+                    HtmlElement::Ol => Some(attributes.get_usize("start").unwrap_or(1)),
+                    _ => None,
+                };
+                let list = self.list(children, start);
+                if !list.is_empty() {
+                    blocks.push(list);
+                }
+            }
+            HtmlElement::Li => {
+                let item = self.list_item(children, "- ");
+                blocks.push(item);
+            }
+            HtmlElement::Table => {
+                let table = self.table(children, blocks);
+                if !table.is_empty() {
+                    blocks.push(table);
+                }
+            }
+            HtmlElement::Unknown(_) => match self.options.unknown_elements {
+                UnknownElements::Contents => self.blocks(children, blocks),
+                UnknownElements::Html => blocks.push(serializer::outer_html(
+                    node_type,
+                    attributes,
+                    children,
+                    None,
+                    &SerializeOptions::default(),
+                )),
+                UnknownElements::Skip => {}
+            },
+            _ => self.blocks(children, blocks),
+        }
+    }
+
+    fn inline<N: TreeNode>(&mut self, node: &N, inline: &mut Inline) {
+        let element = node.with_node_type(|node_type| match node_type {
+            NodeType::Element(element) => Some(element.clone()),
+            NodeType::Text(text) => {
+                inline.push_text(text);
+                None
+            }
+            _ => None,
+        });
+        let Some(element) = element else {
+            return;
+        };
+        let children = node.children();
+        match element {
+            _ if element.is_hidden() => {}
+            HtmlElement::Br => inline.push_hard_break(),
+            HtmlElement::Em | HtmlElement::I => self.emphasis(&children, "*", inline),
+            HtmlElement::Strong | HtmlElement::B => self.emphasis(&children, "**", inline),
+            HtmlElement::Del | HtmlElement::S | HtmlElement::Strike => {
+                self.emphasis(&children, "~~", inline)
+            }
+            HtmlElement::Code | HtmlElement::Kbd | HtmlElement::Samp | HtmlElement::Tt => {
+                let code = node.text_content();
+                if !code.is_empty() {
+                    inline.push_markup(&code_span(&code));
+                }
+            }
+            HtmlElement::A => {
+                let text = self.inline_children(&children).finish();
+                match node.attribute("href") {
+                    Some(href) => {
+                        let href = href.trim_matches(|c: char| c.is_ascii_whitespace());
+                        let title = node.attribute("title");
+                        let link = self.link(&text, href, title);
+                        inline.push_markup(&link);
+                    }
+                    None => inline.push_markup(&text),
+                }
+            }
+            HtmlElement::Img => {
+                if let Some(src) = node.attribute("src") {
+                    let mut alt = Inline::default();
+                    alt.push_text(&node.attribute("alt").unwrap_or_default());
+                    let src = src.trim_matches(|c: char| c.is_ascii_whitespace());
+                    let image = self.link(&alt.finish(), src, node.attribute("title"));
+                    inline.push_markup(&format!("!{image}"));
+                }
+            }
+            HtmlElement::Q => {
+                inline.push_markup("\"");
+                self.inline_nodes(&children, inline);
+                inline.push_markup("\"");
+            }
+            HtmlElement::Unknown(_) => match self.options.unknown_elements {
+                UnknownElements::Contents => self.inline_nodes(&children, inline),
+                UnknownElements::Html => {
+                    let html = node.with_node_type(|node_type| {
+                        node.with_attributes(|attributes| {
+                            serializer::outer_html(
+                                node_type,
+                                attributes,
+                                &children,
+                                None,
+                                &SerializeOptions::default(),
+                            )
+                        })
+                    });
+                    inline.push_markup(&html);
+                }
+                UnknownElements::Skip => {}
+            },
+            _ => self.inline_nodes(&children, inline),
+        }
+    }
+
+    fn inline_nodes<N: TreeNode>(&mut self, nodes: &[N], inline: &mut Inline) {
+        for node in nodes {
+            self.inline(node, inline);
+        }
+    }
+
+    fn inline_children<N: TreeNode>(&mut self, nodes: &[N]) -> Inline {
+        let mut inline = Inline::default();
+        self.inline_nodes(nodes, &mut inline);
+        inline
+    }
+
+    /// Wraps content in delimiters, keeping surrounding whitespace outside
+    /// them since `** bold **` is not emphasis
+    fn emphasis<N: TreeNode>(&mut self, nodes: &[N], delimiter: &str, inline: &mut Inline) {
+        let inner = self.inline_children(nodes).text;
+        let trimmed = inner.trim_matches(|c: char| c.is_ascii_whitespace());
+        if trimmed.is_empty() {
+            inline.push_text(&inner);
+            return;
+        }
+        if inner.starts_with(|c: char| c.is_ascii_whitespace()) {
+            inline.push_text(" ");
+        }
+        inline.push_markup(&format!("{delimiter}{trimmed}{delimiter}"));
+        if inner.ends_with(|c: char| c.is_ascii_whitespace()) {
+            inline.push_text(" ");
+        }
+    }
+
+    fn link(&mut self, text: &str, url: &str, title: Option<String>) -> String {
+        match self.options.link_style {
+            LinkStyle::Inline => format!("[{text}]({})", destination(url, title.as_deref())),
+            LinkStyle::Reference => {
+                let reference = (url.to_string(), title);
+                let index = match self.references.iter().position(|r| *r == reference) {
+                    Some(index) => index,
+                    None => {
+                        self.references.push(reference);
+                        self.references.len() - 1
+                    }
+                };
+                format!("[{text}][{}]", index + 1)
+            }
+        }
+    }
+
+    /// A list, with nested blocks indented to line up with the item's text.
+    /// `start` is the first number of an ordered list.
+    fn list<N: TreeNode>(&mut self, nodes: &[N], start: Option<usize>) -> String {
+        let mut items = vec![];
+        let mut loose = false;
+        for node in nodes {
+            let is_item = node.with_node_type(|node_type| {
+                matches!(node_type, NodeType::Element(HtmlElement::Li))
+            });
+            if !is_item {
+                continue;
+            }
+            let children = node.children();
+            loose |= children.iter().any(|child| {
+                child.with_node_type(|node_type| {
+                    matches!(node_type, NodeType::Element(HtmlElement::P))
+                })
+            });
+            let marker = match start {
+                Some(start) => format!("{}. ", start + items.len()),
+                None => "- ".to_string(),
+            };
+            items.push(self.list_item(&children, &marker));
+        }
+        items.join(if loose { "\n\n" } else { "\n" })
+    }
+
+    fn list_item<N: TreeNode>(&mut self, nodes: &[N], marker: &str) -> String {
+        let mut blocks = vec![];
+        self.blocks(nodes, &mut blocks);
+        let loose = nodes.iter().any(|node| {
+            node.with_node_type(|node_type| matches!(node_type, NodeType::Element(HtmlElement::P)))
+        });
+        let content = blocks.join(if loose { "\n\n" } else { "\n" });
+        let indent = " ".repeat(marker.len());
+        let mut item = marker.to_string();
+        for (index, line) in content.lines().enumerate() {
+            if index > 0 {
+                item.push('\n');
+                if !line.is_empty() {
+                    item.push_str(&indent);
+                }
+            }
+            item.push_str(line);
+        }
+        item.truncate(item.trim_end().len());
+        item
+    }
+
+    /// A GitHub table. The first row is the header, as GitHub tables need
+    /// one, and a caption becomes a paragraph before the table.
+    fn table<N: TreeNode>(&mut self, nodes: &[N], blocks: &mut Vec<String>) -> String {
+        let mut rows: Vec<Vec<String>> = vec![];
+        self.table_rows(nodes, &mut rows, blocks);
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+        if columns == 0 {
+            return String::new();
+        }
+        let mut lines = vec![];
+        for (index, row) in rows.iter().enumerate() {
+            let mut cells = row.clone();
+            cells.resize(columns, String::new());
+            lines.push(format!("| {} |", cells.join(" | ")));
+            if index == 0 {
+                lines.push(format!("|{}", " --- |".repeat(columns)));
+            }
+        }
+        lines.join("\n")
+    }
+
+    /// Collects the cells of each row, looking inside row groups
+    fn table_rows<N: TreeNode>(
+        &mut self,
+        nodes: &[N],
+        rows: &mut Vec<Vec<String>>,
+        blocks: &mut Vec<String>,
+    ) {
+        for node in nodes {
+            let element = node.with_node_type(|node_type| match node_type {
+                NodeType::Element(element) if element.is_table_element() => Some(element.clone()),
+                _ => None,
+            });
+            match element {
+                Some(HtmlElement::Caption) => {
+                    let caption = self.inline_children(&node.children());
+                    push_paragraph(caption, blocks);
+                }
+                Some(HtmlElement::Thead | HtmlElement::Tbody | HtmlElement::Tfoot) => {
+                    self.table_rows(&node.children(), rows, blocks)
+                }
+                Some(HtmlElement::Tr) => {
+                    let cells = node
+                        .children()
+                        .iter()
+                        .filter(|cell| {
+                            cell.with_node_type(|node_type| {
+                                matches!(
+                                    node_type,
+                                    NodeType::Element(HtmlElement::Td | HtmlElement::Th)
+                                )
+                            })
+                        })
+                        .map(|cell| {
+                            let text = self.inline_children(&cell.children()).finish();
+                            text.replace('|', "\\|").replace("\\\n", " ")
+                        })
+                        .collect();
+                    rows.push(cells);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Whether a node has to be converted as a block of its own. Elements which
+/// are normally inline count as blocks when they contain blocks.
+fn is_block<N: TreeNode>(node: &N) -> bool {
+    match node.element() {
+        Some(element) if !element.is_inline() => true,
+        Some(_) => node.children().iter().any(is_block),
+        None => false,
+    }
+}
+
+fn push_paragraph(inline: Inline, blocks: &mut Vec<String>) {
+    let text = inline.finish();
+    if !text.is_empty() {
+        blocks.push(text);
+    }
+}
+
+/// Wraps code in enough backticks that none inside can close it
+fn code_span(code: &str) -> String {
+    let fence = "`".repeat(longest_run(code, '`') + 1);
+    if code.starts_with('`') || code.ends_with('`') {
+        format!("{fence} {code} {fence}")
+    } else {
+        format!("{fence}{code}{fence}")
+    }
+}
+
+/// A fenced code block, taking its language from a `language-*` or `lang-*`
+/// class on a `<code>` inside the `<pre>`
+fn code_block<N: TreeNode>(nodes: &[N]) -> String {
+    let language = nodes
+        .iter()
+        .find_map(|node| {
+            let is_code = node.with_node_type(|node_type| {
+                matches!(node_type, NodeType::Element(HtmlElement::Code))
+            });
+            let class = node.attribute("class").filter(|_| is_code)?;
+            class.split_ascii_whitespace().find_map(|class| {
+                class
+                    .strip_prefix("language-")
+                    .or_else(|| class.strip_prefix("lang-"))
+                    .map(str::to_string)
+            })
+        })
+        .unwrap_or_default();
+    let code: String = nodes.iter().map(TreeNode::text_content).collect();
+    let code = code.trim_end_matches('\n');
+    let fence = "`".repeat(longest_run(code, '`').max(2) + 1);
+    format!("{fence}{language}\n{code}\n{fence}")
+}
+
+fn longest_run(text: &str, char: char) -> usize {
+    text.split(|c| c != char).map(str::len).max().unwrap_or(0)
+}
+
+/// A link destination, in angle brackets if it contains spaces, with its
+/// optional title
+fn destination(url: &str, title: Option<&str>) -> String {
+    let url = if url.contains([' ', '(', ')']) || url.is_empty() {
+        format!("<{}>", url.replace('<', "%3C").replace('>', "%3E"))
+    } else {
+        url.to_string()
+    };
+    match title {
+        Some(title) => format!("{url} \"{}\"", title.replace('"', "\\\"")),
+        None => url,
+    }
+}
+
+fn prefix_lines(text: &str, prefix: &str, empty_prefix: &str) -> String {
+    text.lines()
+        .map(|line| {
+            if line.is_empty() {
+                empty_prefix.to_string()
+            } else {
+                format!("{prefix}{line}")
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{document::Document, node_tree::Node};
+
+    fn convert(html: &str, options: &MarkdownOptions) -> String {
+        let document = Document::parse(html);
+        document.to_markdown(document.root(), options)
+    }
+
+    #[test]
+    fn check_blocks_and_inline() {
+        let html = "<h1>Title</h1><p>Some <em>emphasis</em>, <strong> strong </strong> and <code>a`b</code>.<br>Next * line</p><hr><blockquote><p>Quoted</p><p>Twice</p></blockquote><pre><code class=\"language-rust\">fn main() {}\n</code></pre><p>1. not a list</p>";
+        assert_eq!(
+            convert(html, &MarkdownOptions::default()),
+            "# Title\n\nSome *emphasis*, **strong** and ``a`b``.\\\nNext \\* line\n\n---\n\n> Quoted\n>\n> Twice\n\n```rust\nfn main() {}\n```\n\n1\\. not a list"
+        );
+    }
+
+    #[test]
+    fn check_lists() {
+        let html = "<ul><li>One</li><li>Two<ol start=3><li>Three</li><li>Four</li></ol></li></ul>";
+        assert_eq!(
+            convert(html, &MarkdownOptions::default()),
+            "- One\n- Two\n  3. Three\n  4. Four"
+        );
+    }
+
+    #[test]
+    fn check_links_and_images() {
+        let html = "<p><a href=\"/a\" title=\"A\">first</a>, <img src=\"/i.png\" alt=\"pic\"> and <a href=\"/a\" title=\"A\">again</a></p>";
+        assert_eq!(
+            convert(html, &MarkdownOptions::default()),
+            "[first](/a \"A\"), ![pic](/i.png) and [again](/a \"A\")"
+        );
+        let options = MarkdownOptions {
+            link_style: LinkStyle::Reference,
+            ..Default::default()
+        };
+        assert_eq!(
+            convert(html, &options),
+            "[first][1], ![pic][2] and [again][1]\n\n[1]: /a \"A\"\n[2]: /i.png"
+        );
+    }
+
+    #[test]
+    fn check_tables() {
+        let html = "<table><caption>Scores</caption><thead><tr><th>Name</th><th>Score</th></tr></thead><tbody><tr><td>a|b</td><td>1</td></tr><tr><td>c</td></tr></tbody></table>";
+        assert_eq!(
+            convert(html, &MarkdownOptions::default()),
+            "Scores\n\n| Name | Score |\n| --- | --- |\n| a\\|b | 1 |\n| c |  |"
+        );
+    }
+
+    #[test]
+    fn check_unknown_elements() {
+        let html = "<p>a <x-tag>b</x-tag> c</p>";
+        let with = |unknown_elements| MarkdownOptions {
+            unknown_elements,
+            ..Default::default()
+        };
+        assert_eq!(convert(html, &with(UnknownElements::Contents)), "a b c");
+        assert_eq!(
+            convert(html, &with(UnknownElements::Html)),
+            "a <x-tag>b</x-tag> c"
+        );
+        assert_eq!(convert(html, &with(UnknownElements::Skip)), "a c");
+
+        let tree = Node::from_token_stream(crate::tokeniser::get_tokens(html));
+        assert_eq!(
+            tree.borrow().to_markdown(&MarkdownOptions::default()),
+            "a b c"
+        );
+    }
+}
@@ -0,0 +1,350 @@
+use crate::{
+    attributes::Attributes, html_elements::HtmlElement, links, node_tree::TreeNode, url, whitespace,
+};
+
+/// What a page says about itself in its `<head>`. URLs from `<link>`
+/// elements and OpenGraph are resolved against the document's base URL.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metadata {
+    /// The first `<title>`, with whitespace collapsed
+    pub title: Option<String>,
+    /// The `lang` of the `<html>` element
+    pub lang: Option<String>,
+    pub charset: Option<String>,
+    /// The `description` meta tag
+    pub description: Option<String>,
+    pub canonical: Option<String>,
+    pub amphtml: Option<String>,
+    pub icons: Vec<Icon>,
+    pub alternates: Vec<Alternate>,
+    /// Every `<meta name>` as a lowercased name and its content, in source
+    /// order
+    pub meta: Vec<(String, String)>,
+    /// Every `<meta property>`, as used by OpenGraph and RDFa
+    pub properties: Vec<(String, String)>,
+    /// Every `<meta http-equiv>`, with the header name lowercased
+    pub http_equiv: Vec<(String, String)>,
+    pub open_graph: OpenGraph,
+    pub twitter: TwitterCard,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Icon {
+    pub href: String,
+    /// The `rel`, such as `icon` or `apple-touch-icon`
+    pub rel: String,
+    pub sizes: Option<String>,
+    pub media_type: Option<String>,
+}
+
+/// A `<link rel="alternate">`: a translation, a feed or another format
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Alternate {
+    pub href: String,
+    pub hreflang: Option<String>,
+    pub media_type: Option<String>,
+    pub title: Option<String>,
+}
+
+/// The OpenGraph `og:` properties
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OpenGraph {
+    pub title: Option<String>,
+    /// `og:type`
+    pub kind: Option<String>,
+    pub url: Option<String>,
+    pub description: Option<String>,
+    pub site_name: Option<String>,
+    pub locale: Option<String>,
+    pub images: Vec<OpenGraphMedia>,
+    pub videos: Vec<OpenGraphMedia>,
+    pub audio: Vec<OpenGraphMedia>,
+}
+
+/// An `og:image`, `og:video` or `og:audio` with its structured properties.
+/// Each `og:image` starts a new image and properties like `og:image:width`
+/// describe the latest one.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OpenGraphMedia {
+    pub url: String,
+    pub secure_url: Option<String>,
+    pub media_type: Option<String>,
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub alt: Option<String>,
+}
+
+/// The Twitter card `twitter:` properties
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TwitterCard {
+    pub card: Option<String>,
+    pub site: Option<String>,
+    pub creator: Option<String>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub image: Option<String>,
+    pub image_alt: Option<String>,
+}
+
+/// Reads the metadata of a node and its descendants, given its children
+pub(crate) fn extract_metadata<N: TreeNode>(
+    children: &[N],
+    document_url: Option<&str>,
+) -> Metadata {
+    let base = links::base_url(links::find_base_href(children).as_deref(), document_url);
+    let resolve = |href: &str| {
+        base.as_deref()
+            .and_then(|base| url::resolve(base, href))
+            .unwrap_or_else(|| href.to_string())
+    };
+    let mut elements = vec![];
+    collect_elements(children, &mut elements);
+    let mut metadata = Metadata::default();
+    for (node, element, attributes) in &elements {
+        match element {
+            HtmlElement::Html if metadata.lang.is_none() => {
+                metadata.lang = attributes.get("lang").map(str::to_string);
+            }
+            HtmlElement::Title if metadata.title.is_none() => {
+                metadata.title = Some(whitespace::normalize(&node.text_content()));
+            }
+            HtmlElement::Meta => metadata.add_meta(attributes, &resolve),
+            HtmlElement::Link => {
+                let Some(href) = attributes.get_url("href") else {
+                    continue;
+                };
+                let href = resolve(href);
+                let rel = attributes
+                    .get("rel")
+                    .unwrap_or_default()
+                    .to_ascii_lowercase();
+                for token in rel.split_ascii_whitespace() {
+                    match token {
+                        "canonical" if metadata.canonical.is_none() => {
+                            metadata.canonical = Some(href.clone())
+                        }
+                        "amphtml" if metadata.amphtml.is_none() => {
+                            metadata.amphtml = Some(href.clone())
+                        }
+                        "alternate" => metadata.alternates.push(Alternate {
+                            href: href.clone(),
+                            hreflang: attributes.get("hreflang").map(str::to_string),
+                            media_type: attributes.get("type").map(str::to_string),
+                            title: attributes.get("title").map(str::to_string),
+                        }),
+                        _ if token == "icon" || token.starts_with("apple-touch-icon") => {
+                            metadata.icons.push(Icon {
+                                href: href.clone(),
+                                rel: token.to_string(),
+                                sizes: attributes.get("sizes").map(str::to_string),
+                                media_type: attributes.get("type").map(str::to_string),
+                            })
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    metadata
+}
+
+impl Metadata {
+    fn add_meta(&mut self, attributes: &Attributes, resolve: &impl Fn(&str) -> String) {
+        if let Some(charset) = attributes.get("charset") {
+            self.charset
+                .get_or_insert_with(|| charset.trim().to_string());
+        }
+        let Some(content) = attributes.get("content") else {
+            return;
+        };
+        if let Some(header) = attributes.get("http-equiv") {
+            self.http_equiv
+                .push((header.to_ascii_lowercase(), content.to_string()));
+        }
+        if let Some(name) = attributes.get("name") {
+            let name = name.trim().to_ascii_lowercase();
+            if name == "description" {
+                self.description.get_or_insert_with(|| content.to_string());
+            }
+            // Twitter cards are often written with `property` and sometimes
+            // OpenGraph with `name`
+            self.add_property(&name, content, resolve);
+            self.meta.push((name, content.to_string()));
+        }
+        if let Some(property) = attributes.get("property") {
+            let property = property.trim().to_ascii_lowercase();
+            if attributes
+                .get("name")
+                .is_none_or(|name| !name.eq_ignore_ascii_case(&property))
+            {
+                self.add_property(&property, content, resolve);
+            }
+            self.properties.push((property, content.to_string()));
+        }
+    }
+
+    fn add_property(&mut self, property: &str, content: &str, resolve: &impl Fn(&str) -> String) {
+        let set = |field: &mut Option<String>| {
+            field.get_or_insert_with(|| content.to_string());
+        };
+        if let Some(name) = property.strip_prefix("og:") {
+            let open_graph = &mut self.open_graph;
+            let (media, rest) = match name.split_once(':') {
+                Some((media, rest)) => (media, Some(rest)),
+                None => (name, None),
+            };
+            let list = match media {
+                "image" => &mut open_graph.images,
+                "video" => &mut open_graph.videos,
+                "audio" => &mut open_graph.audio,
+                "title" => return set(&mut open_graph.title),
+                "type" => return set(&mut open_graph.kind),
+                "url" => {
+                    open_graph.url.get_or_insert_with(|| resolve(content));
+                    return;
+                }
+                "description" => return set(&mut open_graph.description),
+                "site_name" => return set(&mut open_graph.site_name),
+                "locale" => return set(&mut open_graph.locale),
+                _ => return,
+            };
+            match rest {
+                None | Some("url") => list.push(OpenGraphMedia {
+                    url: resolve(content),
+                    ..Default::default()
+                }),
+                Some(rest) => {
+                    let Some(current) = list.last_mut() else {
+                        return;
+                    };
+                    match rest {
+                        "secure_url" => current.secure_url = Some(resolve(content)),
+                        "type" => current.media_type = Some(content.to_string()),
+                        "width" => current.width = content.trim().parse().ok(),
+                        "height" => current.height = content.trim().parse().ok(),
+                        "alt" => current.alt = Some(content.to_string()),
+                        _ => {}
+                    }
+                }
+            }
+        } else if let Some(name) = property.strip_prefix("twitter:") {
+            let twitter = &mut self.twitter;
+            match name {
+                "card" => set(&mut twitter.card),
+                "site" => set(&mut twitter.site),
+                "creator" => set(&mut twitter.creator),
+                "title" => set(&mut twitter.title),
+                "description" => set(&mut twitter.description),
+                "image" | "image:src" => {
+                    twitter.image.get_or_insert_with(|| resolve(content));
+                }
+                "image:alt" => set(&mut twitter.image_alt),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn collect_elements<N: TreeNode>(children: &[N], elements: &mut Vec<(N, HtmlElement, Attributes)>) {
+    for child in children {
+        if let Some(element) = child.element() {
+            let attributes = child.with_attributes(Attributes::clone);
+            elements.push((child.clone(), element, attributes));
+        }
+        collect_elements(&child.children(), elements);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::Document;
+
+    const HTML: &str = r#"<!DOCTYPE html><html lang="en-GB"><head>
+        <meta charset="utf-8">
+        <title>
+            Example   Page
+        </title>
+        <base href="https://cdn.example.com/">
+        <meta name="Description" content="A page">
+        <meta http-equiv="Refresh" content="30">
+        <link rel="canonical" href="/page">
+        <link rel="shortcut icon" href="favicon.ico">
+        <link rel="apple-touch-icon" sizes="180x180" href="touch.png">
+        <link rel="alternate" hreflang="fr" href="/fr/page">
+        <link rel="amphtml" href="/amp/page">
+        <meta property="og:title" content="OG title">
+        <meta property="og:type" content="article">
+        <meta property="og:image" content="one.png">
+        <meta property="og:image:width" content="1200">
+        <meta property="og:image:alt" content="First">
+        <meta property="og:image" content="https://other.example/two.png">
+        <meta property="og:image:height" content="630">
+        <meta name="twitter:card" content="summary_large_image">
+        <meta property="twitter:site" content="@example">
+    </head><body><title>Not this</title></body></html>"#;
+
+    #[test]
+    fn check_metadata() {
+        let document = Document::parse(HTML);
+        let metadata = document.metadata(Some("https://example.com/"));
+        assert_eq!(metadata.title.as_deref(), Some("Example Page"));
+        assert_eq!(metadata.lang.as_deref(), Some("en-GB"));
+        assert_eq!(metadata.charset.as_deref(), Some("utf-8"));
+        assert_eq!(metadata.description.as_deref(), Some("A page"));
+        assert_eq!(
+            metadata.http_equiv,
+            vec![("refresh".to_string(), "30".to_string())]
+        );
+        assert_eq!(
+            metadata.canonical.as_deref(),
+            Some("https://cdn.example.com/page")
+        );
+        assert_eq!(
+            metadata.amphtml.as_deref(),
+            Some("https://cdn.example.com/amp/page")
+        );
+        let icons: Vec<(&str, &str)> = metadata
+            .icons
+            .iter()
+            .map(|icon| (icon.rel.as_str(), icon.href.as_str()))
+            .collect();
+        assert_eq!(
+            icons,
+            vec![
+                ("icon", "https://cdn.example.com/favicon.ico"),
+                ("apple-touch-icon", "https://cdn.example.com/touch.png"),
+            ]
+        );
+        assert_eq!(metadata.icons[1].sizes.as_deref(), Some("180x180"));
+        assert_eq!(metadata.alternates[0].hreflang.as_deref(), Some("fr"));
+
+        let open_graph = &metadata.open_graph;
+        assert_eq!(open_graph.title.as_deref(), Some("OG title"));
+        assert_eq!(open_graph.kind.as_deref(), Some("article"));
+        assert_eq!(
+            open_graph.images,
+            vec![
+                OpenGraphMedia {
+                    url: "https://cdn.example.com/one.png".to_string(),
+                    width: Some(1200),
+                    alt: Some("First".to_string()),
+                    ..Default::default()
+                },
+                OpenGraphMedia {
+                    url: "https://other.example/two.png".to_string(),
+                    height: Some(630),
+                    ..Default::default()
+                },
+            ]
+        );
+        assert_eq!(
+            metadata.twitter.card.as_deref(),
+            Some("summary_large_image")
+        );
+        assert_eq!(metadata.twitter.site.as_deref(), Some("@example"));
+        assert_eq!(metadata.properties.len(), 8);
+    }
+}
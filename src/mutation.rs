@@ -0,0 +1,474 @@
+use std::{cell::RefCell, fmt, iter, rc::Rc};
+
+use crate::{
+    attributes::Attributes,
+    document::Document,
+    entities::decode_text,
+    html_elements::HtmlElement,
+    node_tree::{Namespace, Node, NodeRef, NodeType},
+    selector::{Selector, SelectorError},
+    tokeniser::Tokeniser,
+    tree_builder,
+};
+
+/// The reason a change to the tree was refused
+#[derive(Clone, Debug, PartialEq)]
+pub enum MutationError {
+    /// The change would put a node inside itself, give a text or comment
+    /// node children, or move a document node under something else
+    HierarchyRequest,
+    /// The reference node is not a child of the node being changed
+    NotFound,
+}
+
+impl fmt::Display for MutationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MutationError::HierarchyRequest => write!(f, "node cannot be inserted here"),
+            MutationError::NotFound => write!(f, "reference node is not a child"),
+        }
+    }
+}
+
+impl std::error::Error for MutationError {}
+
+/// Structural changes to a tree, and lookups which can return the node
+/// itself. These live on [`NodeRef`] rather than [`Node`] because they need
+/// the shared handle of a node, which a detached [`Node`] cannot find.
+pub trait NodeRefExt {
+    /// Moves `child` to the end of this node's children
+    fn append_child(&self, child: &NodeRef) -> Result<(), MutationError>;
+    /// Moves `child` so it sits immediately before `reference`, one of this node's children
+    fn insert_before(&self, child: &NodeRef, reference: &NodeRef) -> Result<(), MutationError>;
+    /// Unlinks this node from its parent, leaving it and its subtree intact
+    fn detach(&self);
+    /// Unlinks this node from its parent and discards its subtree
+    fn remove(&self);
+    /// Puts `replacement` where this node was and detaches this node
+    fn replace_with(&self, replacement: &NodeRef) -> Result<(), MutationError>;
+    /// Puts `wrapper` where this node was and moves this node inside it
+    fn wrap(&self, wrapper: &NodeRef) -> Result<(), MutationError>;
+    /// Replaces this node with its own children
+    fn unwrap(&self);
+    /// Replaces an element's children with a single text node, or the data
+    /// of a text or comment node. Documents and doctypes are left unchanged.
+    fn set_inner_text(&self, text: &str);
+    /// Replaces this node's children with the result of parsing `html` as a
+    /// fragment inside this element, so `<tr>` works in a `<tbody>`
+    fn set_inner_html(&self, html: &str);
+    /// Returns the nearest element, starting with this one and moving up
+    /// through its ancestors, which matches the CSS selector
+    fn closest(&self, selector: &str) -> Result<Option<NodeRef>, SelectorError>;
+    /// Like [`NodeRefExt::closest`] but with a selector which has already been parsed
+    fn closest_with(&self, selector: &Selector) -> Option<NodeRef>;
+    /// Returns the nearest node, starting with this one and moving up through
+    /// its ancestors, for which the predicate holds
+    fn closest_by(&self, predicate: impl Fn(&Node) -> bool) -> Option<NodeRef>;
+}
+
+impl Node {
+    /// Creates a detached element with no attributes
+    pub fn new_element(element: HtmlElement) -> NodeRef {
+        Self::new_node(NodeType::Element(element))
+    }
+
+    /// Creates a detached text node
+    pub fn new_text(text: &str) -> NodeRef {
+        Self::new_node(NodeType::Text(text.to_string()))
+    }
+
+    /// Creates a detached comment holding `data`, without `<!--` and `-->`
+    pub fn new_comment(comment: &str) -> NodeRef {
+        Self::new_node(NodeType::Comment(comment.to_string()))
+    }
+
+    fn new_node(node_type: NodeType) -> NodeRef {
+        Rc::new(RefCell::new(Node {
+            node_type,
+            parent_element: None,
+            children: vec![],
+            properties: Attributes::new(),
+            namespace: Namespace::Html,
+            location: None,
+        }))
+    }
+
+    pub fn set_attribute(&mut self, name: &str, value: &str) {
+        self.properties.insert(name, value);
+    }
+
+    pub fn remove_attribute(&mut self, name: &str) -> Option<String> {
+        self.properties.remove(name)
+    }
+
+    pub fn add_class(&mut self, class: &str) {
+        let mut classes = self.get_class_list();
+        if !classes.iter().any(|c| c == class) {
+            classes.push(class.to_string());
+            self.properties.insert("class", &classes.join(" "));
+        }
+    }
+
+    /// Removes a class, dropping the `class` attribute once none are left
+    pub fn remove_class(&mut self, class: &str) {
+        let classes: Vec<String> = self
+            .get_class_list()
+            .into_iter()
+            .filter(|c| c != class)
+            .collect();
+        if classes.is_empty() {
+            self.properties.remove("class");
+        } else {
+            self.properties.insert("class", &classes.join(" "));
+        }
+    }
+}
+
+impl NodeRefExt for NodeRef {
+    fn closest(&self, selector: &str) -> Result<Option<NodeRef>, SelectorError> {
+        Ok(self.closest_with(&Selector::parse(selector)?))
+    }
+
+    fn closest_with(&self, selector: &Selector) -> Option<NodeRef> {
+        iter::once(self.clone())
+            .chain(self.borrow().ancestors())
+            .find(|node| selector.matches(node))
+    }
+
+    fn closest_by(&self, predicate: impl Fn(&Node) -> bool) -> Option<NodeRef> {
+        iter::once(self.clone())
+            .chain(self.borrow().ancestors())
+            .find(|node| predicate(&node.borrow()))
+    }
+
+    fn append_child(&self, child: &NodeRef) -> Result<(), MutationError> {
+        check_insertion(self, child)?;
+        child.detach();
+        link(self, child, None);
+        Ok(())
+    }
+
+    fn insert_before(&self, child: &NodeRef, reference: &NodeRef) -> Result<(), MutationError> {
+        if !is_child_of(reference, self) {
+            return Err(MutationError::NotFound);
+        }
+        check_insertion(self, child)?;
+        if Rc::ptr_eq(child, reference) {
+            return Ok(());
+        }
+        child.detach();
+        let index = child_index(self, reference);
+        link(self, child, index);
+        Ok(())
+    }
+
+    fn detach(&self) {
+        let parent = self.borrow().parent();
+        if let Some(parent) = parent {
+            parent
+                .borrow_mut()
+                .children
+                .retain(|child| !Rc::ptr_eq(child, self));
+        }
+        self.borrow_mut().parent_element = None;
+    }
+
+    fn remove(&self) {
+        self.detach();
+        let children = std::mem::take(&mut self.borrow_mut().children);
+        for child in children {
+            child.remove();
+        }
+    }
+
+    fn replace_with(&self, replacement: &NodeRef) -> Result<(), MutationError> {
+        let Some(parent) = self.borrow().parent() else {
+            return Err(MutationError::NotFound);
+        };
+        if Rc::ptr_eq(self, replacement) {
+            return Ok(());
+        }
+        parent.insert_before(replacement, self)?;
+        self.detach();
+        Ok(())
+    }
+
+    fn wrap(&self, wrapper: &NodeRef) -> Result<(), MutationError> {
+        if Rc::ptr_eq(self, wrapper) || is_inclusive_ancestor(self, wrapper) {
+            return Err(MutationError::HierarchyRequest);
+        }
+        self.replace_with(wrapper)?;
+        wrapper.append_child(self)
+    }
+
+    fn unwrap(&self) {
+        let Some(parent) = self.borrow().parent() else {
+            return;
+        };
+        let children = self.borrow().children.clone();
+        for child in children {
+            // Children of a node already in the tree can always move up a level
+            let _ = parent.insert_before(&child, self);
+        }
+        self.detach();
+    }
+
+    fn set_inner_text(&self, text: &str) {
+        match &mut self.borrow_mut().node_type {
+            NodeType::Text(data) | NodeType::Comment(data) => {
+                *data = text.to_string();
+                return;
+            }
+            NodeType::Element(_) => {}
+            NodeType::Document | NodeType::Doctype(_) => return,
+        }
+        clear_children(self);
+        if !text.is_empty() {
+            link(self, &Node::new_text(text), None);
+        }
+    }
+
+    fn set_inner_html(&self, html: &str) {
+        clear_children(self);
+        let (node_type, namespace) = {
+            let node = self.borrow();
+            (node.node_type.clone(), node.namespace)
+        };
+        let children = match node_type {
+            NodeType::Element(_) if html.is_empty() => vec![],
+            // Raw text is never markup, and only escapable raw text has
+            // character references
+            NodeType::Element(element) if element.is_raw_text_element() => {
+                vec![Node::new_text(html)]
+            }
+            NodeType::Element(element) if element.is_escapable_raw_text_element() => {
+                vec![Node::new_text(&decode_text(html))]
+            }
+            NodeType::Element(element) => {
+                let document =
+                    tree_builder::build_fragment(Tokeniser::with_input(html), &element, namespace);
+                // The fragment is built inside a stand-in `<html>` root
+                let root = document.to_node_tree();
+                let html = root.borrow().children.first().cloned();
+                html.map(|html| html.borrow().children.clone())
+                    .unwrap_or_default()
+            }
+            NodeType::Document => Document::parse(html)
+                .to_node_tree()
+                .borrow()
+                .children
+                .clone(),
+            NodeType::Text(_) | NodeType::Comment(_) | NodeType::Doctype(_) => vec![],
+        };
+        for child in children {
+            link(self, &child, None);
+        }
+    }
+}
+
+fn link(parent: &NodeRef, child: &NodeRef, index: Option<usize>) {
+    child.borrow_mut().parent_element = Some(Rc::downgrade(parent));
+    let mut parent = parent.borrow_mut();
+    match index {
+        Some(index) => parent.children.insert(index, child.clone()),
+        None => parent.children.push(child.clone()),
+    }
+}
+
+fn clear_children(node: &NodeRef) {
+    let children = std::mem::take(&mut node.borrow_mut().children);
+    for child in children {
+        child.borrow_mut().parent_element = None;
+    }
+}
+
+fn child_index(parent: &NodeRef, child: &NodeRef) -> Option<usize> {
+    parent
+        .borrow()
+        .children
+        .iter()
+        .position(|c| Rc::ptr_eq(c, child))
+}
+
+fn is_child_of(child: &NodeRef, parent: &NodeRef) -> bool {
+    child
+        .borrow()
+        .parent()
+        .is_some_and(|p| Rc::ptr_eq(&p, parent))
+}
+
+/// Whether `ancestor` is `node` or one of its ancestors
+fn is_inclusive_ancestor(ancestor: &NodeRef, node: &NodeRef) -> bool {
+    Rc::ptr_eq(ancestor, node)
+        || node
+            .borrow()
+            .ancestors()
+            .iter()
+            .any(|a| Rc::ptr_eq(a, ancestor))
+}
+
+fn check_insertion(parent: &NodeRef, child: &NodeRef) -> Result<(), MutationError> {
+    let parent_can_have_children = matches!(
+        parent.borrow().node_type,
+        NodeType::Document | NodeType::Element(_)
+    );
+    if !parent_can_have_children
+        || child.borrow().node_type == NodeType::Document
+        || is_inclusive_ancestor(child, parent)
+    {
+        return Err(MutationError::HierarchyRequest);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokeniser::get_tokens;
+
+    const TEST: &str = r#"<div id="main"><p id="first" class="intro">One</p><p id="second">Two</p></div><div id="ad">Buy now</div>"#;
+
+    fn parse() -> (NodeRef, NodeRef) {
+        let document = Node::from_token_stream(get_tokens(TEST));
+        let main = document.borrow().get_element_by_id("main").unwrap();
+        (document, main)
+    }
+
+    #[test]
+    fn check_append_and_insert_before() {
+        let (document, main) = parse();
+        let second = document.borrow().get_element_by_id("second").unwrap();
+        let first = document.borrow().get_element_by_id("first").unwrap();
+
+        main.append_child(&first).unwrap();
+        assert_eq!(main.borrow().inner_text(), "TwoOne");
+        main.insert_before(&first, &second).unwrap();
+        assert_eq!(main.borrow().inner_text(), "OneTwo");
+        assert!(Rc::ptr_eq(&first.borrow().parent().unwrap(), &main));
+        assert_eq!(main.borrow().children.len(), 2);
+
+        let text = Node::new_text("!");
+        assert_eq!(
+            text.insert_before(&first, &second),
+            Err(MutationError::NotFound)
+        );
+        assert_eq!(
+            first.append_child(&main),
+            Err(MutationError::HierarchyRequest)
+        );
+        assert_eq!(
+            text.append_child(&first),
+            Err(MutationError::HierarchyRequest)
+        );
+    }
+
+    #[test]
+    fn check_remove_and_replace() {
+        let (document, main) = parse();
+        let ad = document.borrow().get_element_by_id("ad").unwrap();
+        ad.remove();
+        assert!(document.borrow().get_element_by_id("ad").is_none());
+        assert!(ad.borrow().parent().is_none());
+        assert!(ad.borrow().children.is_empty());
+
+        let first = document.borrow().get_element_by_id("first").unwrap();
+        let heading = Node::new_element(HtmlElement::H1);
+        heading.set_inner_text("Title");
+        first.replace_with(&heading).unwrap();
+        assert_eq!(
+            main.borrow().inner_html(),
+            r#"<h1>Title</h1><p id="second">Two</p>"#
+        );
+        assert!(first.borrow().parent().is_none());
+    }
+
+    #[test]
+    fn check_set_inner_text() {
+        let paragraph = Node::new_element(HtmlElement::P);
+        paragraph.set_inner_text("a < b");
+        assert_eq!(paragraph.borrow().outer_html(), "<p>a &lt; b</p>");
+
+        let text = paragraph.borrow().children[0].clone();
+        text.set_inner_text("bye");
+        assert!(text.borrow().children.is_empty());
+        assert_eq!(paragraph.borrow().inner_html(), "bye");
+
+        let comment = Node::new_comment(" note ");
+        comment.set_inner_text("changed");
+        assert!(comment.borrow().children.is_empty());
+        assert_eq!(comment.borrow().outer_html(), "<!--changed-->");
+    }
+
+    #[test]
+    fn check_wrap_and_unwrap() {
+        let (document, main) = parse();
+        let second = document.borrow().get_element_by_id("second").unwrap();
+        let section = Node::new_element(HtmlElement::Section);
+        second.wrap(&section).unwrap();
+
+        assert_eq!(second.borrow().ancestors().len(), 5);
+        assert_eq!(main.borrow().children.len(), 2);
+        assert!(Rc::ptr_eq(&section.borrow().parent().unwrap(), &main));
+
+        main.unwrap();
+        let body = document.borrow().get_elements_by_tag(&HtmlElement::Body)[0].clone();
+        assert_eq!(body.borrow().children.len(), 3);
+        assert!(Rc::ptr_eq(&section.borrow().parent().unwrap(), &body));
+    }
+
+    #[test]
+    fn check_attributes_and_classes() {
+        let (document, _) = parse();
+        let first = document.borrow().get_element_by_id("first").unwrap();
+        let mut node = first.borrow_mut();
+
+        node.set_attribute("Data-Seen", "yes");
+        assert_eq!(node.properties.get("data-seen").unwrap(), "yes");
+        assert_eq!(node.remove_attribute("DATA-SEEN").as_deref(), Some("yes"));
+        node.add_class("lead");
+        node.add_class("intro");
+        assert_eq!(node.get_class_list(), vec!["intro", "lead"]);
+        node.remove_class("intro");
+        node.remove_class("lead");
+        assert!(!node.properties.contains("class"));
+    }
+
+    #[test]
+    fn check_set_inner_html() {
+        let (_, main) = parse();
+        main.set_inner_html("<a href=\"/x\">link</a> and <b>bold");
+
+        assert_eq!(
+            main.borrow().inner_html(),
+            "<a href=\"/x\">link</a> and <b>bold</b>"
+        );
+        let link = main.borrow().children[0].clone();
+        assert!(Rc::ptr_eq(&link.borrow().parent().unwrap(), &main));
+
+        main.set_inner_html("<!-- note -->\n <p>x");
+        assert_eq!(main.borrow().inner_html(), "<!-- note -->\n <p>x</p>");
+    }
+
+    #[test]
+    fn check_set_inner_html_in_tables() {
+        let table = Node::new_element(HtmlElement::Table);
+        table.set_inner_html("<tr><td>a</td></tr>");
+        assert_eq!(
+            table.borrow().inner_html(),
+            "<tbody><tr><td>a</td></tr></tbody>"
+        );
+
+        let body = Node::new_element(HtmlElement::Tbody);
+        body.set_inner_html("<tr><td>a<td>b</tr>");
+        assert_eq!(body.borrow().inner_html(), "<tr><td>a</td><td>b</td></tr>");
+
+        let row = Node::new_element(HtmlElement::Tr);
+        row.set_inner_html("<td>a</td>text");
+        assert_eq!(row.borrow().inner_html(), "<td>a</td>text");
+
+        let title = Node::new_element(HtmlElement::Title);
+        title.set_inner_html("<b>a &amp; b</b>");
+        assert_eq!(title.borrow().children.len(), 1);
+        assert_eq!(title.borrow().inner_text(), "<b>a & b</b>");
+    }
+}
@@ -1,15 +1,25 @@
 use std::{
     cell::RefCell,
-    collections::HashMap,
     rc::{Rc, Weak},
 };
 
 use crate::{
+    attributes::Attributes,
+    doctype::Doctype,
+    document::{Document, ParseOptions},
+    form::{self, Form},
     html_elements::HtmlElement,
-    tokeniser::{TokenStream, TokenType},
+    links::{self, Link},
+    markdown::{self, MarkdownOptions},
+    metadata::{self, Metadata},
+    serializer::{self, SerializeOptions},
+    span::{Position, SourceLocation, Span},
+    table::{self, Table},
+    text::{self, TextOptions},
+    tokeniser::TokenStream,
 };
 
-type NodeRef = Rc<RefCell<Node>>;
+pub type NodeRef = Rc<RefCell<Node>>;
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum NodeType {
@@ -17,42 +27,118 @@ pub enum NodeType {
     Element(HtmlElement),
     Text(String),
     Comment(String),
+    Doctype(Doctype),
+}
+
+/// The namespace an element was created in. Elements inside `<svg>` and
+/// `<math>` are foreign, which changes how their contents are parsed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Namespace {
+    #[default]
+    Html,
+    Svg,
+    MathMl,
+}
+
+/// Read-only access to a node in either tree representation, so that queries
+/// like selectors can be written once for both [`NodeRef`] and `Document`
+pub(crate) trait TreeNode: Clone {
+    fn with_node_type<R>(&self, f: impl FnOnce(&NodeType) -> R) -> R;
+    fn parent(&self) -> Option<Self>;
+    fn children(&self) -> Vec<Self>;
+    /// The siblings before this node, nearest first
+    fn previous_siblings(&self) -> impl Iterator<Item = Self>;
+    /// The siblings after this node, nearest first
+    fn next_siblings(&self) -> impl Iterator<Item = Self>;
+    /// Looks up an attribute, ignoring ASCII case in the name
+    fn attribute(&self, name: &str) -> Option<String>;
+    fn with_attributes<R>(&self, f: impl FnOnce(&Attributes) -> R) -> R;
+    fn same_node(&self, other: &Self) -> bool;
+
+    fn is_element(&self) -> bool {
+        self.with_node_type(|node_type| matches!(node_type, NodeType::Element(_)))
+    }
+
+    /// The element kind, or `None` for documents, text and comments
+    fn element(&self) -> Option<HtmlElement> {
+        self.with_node_type(|node_type| match node_type {
+            NodeType::Element(element) => Some(element.clone()),
+            _ => None,
+        })
+    }
+
+    /// The text of this node and every text node inside it, like the DOM's
+    /// `textContent`
+    fn text_content(&self) -> String {
+        let mut text = String::new();
+        self.with_node_type(|node_type| {
+            if let NodeType::Text(value) = node_type {
+                text.push_str(value);
+            }
+        });
+        for child in self.children() {
+            text.push_str(&child.text_content());
+        }
+        text
+    }
+}
+
+impl TreeNode for NodeRef {
+    fn with_node_type<R>(&self, f: impl FnOnce(&NodeType) -> R) -> R {
+        f(&self.borrow().node_type)
+    }
+
+    fn parent(&self) -> Option<Self> {
+        self.borrow().parent()
+    }
+
+    fn children(&self) -> Vec<Self> {
+        self.borrow().children.clone()
+    }
+
+    fn previous_siblings(&self) -> impl Iterator<Item = Self> {
+        let index = self.borrow().index_in_parent().unwrap_or(0);
+        self.parent().into_iter().flat_map(move |parent| {
+            (0..index)
+                .rev()
+                .map(move |index| parent.borrow().children[index].clone())
+        })
+    }
+
+    fn next_siblings(&self) -> impl Iterator<Item = Self> {
+        let index = self.borrow().index_in_parent().unwrap_or(0);
+        self.parent().into_iter().flat_map(move |parent| {
+            let len = parent.borrow().children.len();
+            (index + 1..len).map(move |index| parent.borrow().children[index].clone())
+        })
+    }
+
+    fn attribute(&self, name: &str) -> Option<String> {
+        self.borrow().properties.get(name).map(str::to_string)
+    }
+
+    fn with_attributes<R>(&self, f: impl FnOnce(&Attributes) -> R) -> R {
+        f(&self.borrow().properties)
+    }
+
+    fn same_node(&self, other: &Self) -> bool {
+        Rc::ptr_eq(self, other)
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct Node {
-    node_type: NodeType,
-    _parent_element: Option<Weak<RefCell<Node>>>,
-    children: Vec<NodeRef>,
-    properties: HashMap<String, String>,
+    pub(crate) node_type: NodeType,
+    pub(crate) parent_element: Option<Weak<RefCell<Node>>>,
+    pub(crate) children: Vec<NodeRef>,
+    pub(crate) properties: Attributes,
+    pub(crate) namespace: Namespace,
+    pub(crate) location: Option<SourceLocation>,
 }
 
 impl From<&Node> for String {
     fn from(node: &Node) -> Self {
-        match &node.node_type {
-            NodeType::Text(text) => text.clone(),
-            NodeType::Element(element) => {
-                if node.properties.is_empty() {
-                    return format!("<{}>", element.tag_name());
-                }
-                let properties = node
-                    .properties
-                    .iter()
-                    .map(|(k, v)| {
-                        if v.is_empty() {
-                            k.clone()
-                        } else {
-                            format!("{}=\"{}\"", k, v)
-                        }
-                    })
-                    .collect::<Vec<String>>()
-                    .join(" ");
-                let string_repr = format!("<{} {}>", element.tag_name(), properties);
-                string_repr
-            }
-            NodeType::Document => "Document".to_string(),
-            NodeType::Comment(comment) => comment.to_string(),
-        }
+        node.outer_html()
     }
 }
 
@@ -61,12 +147,23 @@ impl Node {
         self.children.clone()
     }
 
+    /// The element's attributes in source order
+    pub fn properties(&self) -> &Attributes {
+        &self.properties
+    }
+
+    /// The namespace the node was created in, HTML for anything not an element
+    pub fn namespace(&self) -> Namespace {
+        self.namespace
+    }
+
     pub fn walk_tree(&self) {
         match &self.node_type {
             NodeType::Document => println!("Document"),
             NodeType::Element(element) => println!("Element: {:?}", element),
             NodeType::Text(text) => println!("Text: {}", text),
             NodeType::Comment(comment) => println!("Comment: {}", comment),
+            NodeType::Doctype(doctype) => println!("Doctype: {}", doctype),
         }
 
         for child in &self.children {
@@ -74,47 +171,112 @@ impl Node {
         }
     }
 
-    pub fn get_elements_by_class(&self, class: &str) -> Vec<Node> {
+    pub fn get_elements_by_class(&self, class: &str) -> Vec<NodeRef> {
         let mut elements = Vec::new();
-        if let NodeType::Element(_element) = &self.node_type
-            && self.get_class_list().contains(&class.to_string())
-        {
-            elements.push(self.clone());
-        }
-
-        for child in &self.children {
-            elements.extend(child.borrow().get_elements_by_class(class));
-        }
+        self.find_elements(
+            &|node| node.get_class_list().iter().any(|c| c == class),
+            false,
+            &mut elements,
+        );
         elements
     }
 
     pub fn outer_html(&self) -> String {
-        let mut html = String::new();
-        // Add opening tag
-        html.push_str(String::from(self).as_str());
-        // Add children recursively
+        self.outer_html_with(&SerializeOptions::default())
+    }
+
+    pub fn outer_html_with(&self, options: &SerializeOptions) -> String {
+        serializer::outer_html(
+            &self.node_type,
+            &self.properties,
+            &self.children,
+            self.parent(),
+            options,
+        )
+    }
+
+    /// Where the node was parsed from, if it came from source
+    pub fn location(&self) -> Option<&SourceLocation> {
+        self.location.as_ref()
+    }
+
+    /// The span of source the node was parsed from, running from its start
+    /// tag to its end tag or, if that was implied, to the end of its last
+    /// descendant
+    pub fn source_span(&self) -> Option<Span> {
+        let location = self.location.as_ref()?;
+        let mut end = location.end();
+        self.extend_source_end(&mut end);
+        Some(Span {
+            start: location.span.start,
+            end,
+        })
+    }
+
+    fn extend_source_end(&self, end: &mut Position) {
         for child in &self.children {
-            html.push_str(child.borrow().outer_html().as_str());
-        }
-        // Add closing tag if not void
-        if let NodeType::Element(e) = &self.node_type {
-            if e.is_void_element() {
-                return html;
+            let child = child.borrow();
+            if let Some(location) = &child.location
+                && location.end().offset > end.offset
+            {
+                *end = location.end();
             }
-            html.push_str("</");
-            html.push_str(e.tag_name());
-            html.push('>');
+            child.extend_source_end(end);
         }
-        html
+    }
+
+    /// The text of `source`, the HTML the tree was parsed from, which the node
+    /// was parsed from
+    pub fn source<'a>(&self, source: &'a str) -> Option<&'a str> {
+        self.source_span()?.slice(source)
     }
 
     pub fn inner_html(&self) -> String {
-        let mut html = String::new();
-        // Add children recursively
-        for child in &self.children {
-            html.push_str(child.borrow().outer_html().as_str());
-        }
-        html
+        self.inner_html_with(&SerializeOptions::default())
+    }
+
+    pub fn inner_html_with(&self, options: &SerializeOptions) -> String {
+        serializer::inner_html(&self.node_type, &self.children, self.parent(), options)
+    }
+
+    /// Converts the node and its descendants to Markdown
+    pub fn to_markdown(&self, options: &MarkdownOptions) -> String {
+        markdown::to_markdown(&self.node_type, &self.properties, &self.children, options)
+    }
+
+    /// Renders the node as plain text laid out like a browser would, unlike
+    /// [`Node::inner_text`] which joins the text nodes as they are
+    pub fn to_text(&self, options: &TextOptions) -> String {
+        text::to_text(&self.node_type, &self.properties, &self.children, options)
+    }
+
+    /// Collects the URLs in the node and its descendants, resolved against
+    /// `document_url` and any `<base href>` among them
+    pub fn links(&self, document_url: Option<&str>) -> Vec<Link> {
+        links::extract_links(
+            &self.node_type,
+            &self.properties,
+            &self.children,
+            document_url,
+        )
+    }
+
+    /// Lays out a `<table>` element as a grid. Returns `None` for any other
+    /// node.
+    pub fn table(&self) -> Option<Table> {
+        table::build_table(&self.node_type, &self.children)
+    }
+
+    /// Builds the form for a `<form>` element, finding its controls in the
+    /// whole tree. Returns `None` for any other node.
+    pub fn form(&self, document_url: Option<&str>) -> Option<Form> {
+        form::build_form(&self.self_ref()?, document_url)
+    }
+
+    /// Reads the title, meta tags, OpenGraph and Twitter card properties and
+    /// head links of the node's descendants
+    pub fn metadata(&self, document_url: Option<&str>) -> Metadata {
+        metadata::extract_metadata(&self.children, document_url)
     }
 
     pub fn inner_text(&self) -> String {
@@ -130,18 +292,13 @@ impl Node {
         inner_text
     }
 
-    pub fn get_elements_by_tag(&self, tag: &HtmlElement) -> Vec<Node> {
+    pub fn get_elements_by_tag(&self, tag: &HtmlElement) -> Vec<NodeRef> {
         let mut elements = Vec::new();
-        if let NodeType::Element(element) = &self.node_type
-            && element == tag
-        {
-            elements.push(self.clone());
-        }
-
-        for child in &self.children {
-            elements.extend(child.borrow().get_elements_by_tag(tag));
-        }
-
+        self.find_elements(
+            &|node| node.node_type == NodeType::Element(tag.clone()),
+            false,
+            &mut elements,
+        );
         elements
     }
 
@@ -157,79 +314,151 @@ impl Node {
         classes
     }
 
-    pub fn get_element_by_id(&self, id: &str) -> Option<Node> {
-        if let NodeType::Element(_element) = &self.node_type
-            && self.properties.get("id") == Some(&id.to_string())
+    pub fn get_element_by_id(&self, id: &str) -> Option<NodeRef> {
+        let mut elements = Vec::new();
+        self.find_elements(
+            &|node| node.properties.get("id").is_some_and(|value| value == id),
+            true,
+            &mut elements,
+        );
+        elements.pop()
+    }
+
+    /// Collects handles to this element and its descendant elements matching
+    /// the predicate, in document order
+    fn find_elements(
+        &self,
+        predicate: &dyn Fn(&Node) -> bool,
+        first_only: bool,
+        elements: &mut Vec<NodeRef>,
+    ) {
+        if matches!(self.node_type, NodeType::Element(_))
+            && predicate(self)
+            && let Some(node) = self.self_ref()
         {
-            return Some(self.clone());
+            elements.push(node);
         }
+        self.find_descendant_elements(predicate, first_only, elements);
+    }
 
+    /// Walks the descendants holding each child's handle, so no node has to
+    /// be looked up in its parent
+    fn find_descendant_elements(
+        &self,
+        predicate: &dyn Fn(&Node) -> bool,
+        first_only: bool,
+        elements: &mut Vec<NodeRef>,
+    ) {
         for child in &self.children {
-            if let Some(node) = child.borrow().get_element_by_id(id) {
-                return Some(node);
+            if first_only && !elements.is_empty() {
+                return;
+            }
+            let node = child.borrow();
+            if matches!(node.node_type, NodeType::Element(_)) && predicate(&node) {
+                elements.push(child.clone());
             }
+            node.find_descendant_elements(predicate, first_only, elements);
         }
+    }
 
-        None
+    /// Returns the node this one is a child of
+    pub fn parent(&self) -> Option<NodeRef> {
+        self.parent_element.as_ref()?.upgrade()
     }
 
-    pub fn from_token_stream(token_stream: TokenStream) -> NodeRef {
-        let root = Rc::new(RefCell::new(Node {
-            node_type: NodeType::Document,
-            _parent_element: None,
-            children: vec![],
-            properties: HashMap::new(),
-        }));
-
-        let mut open_tags: Vec<NodeRef> = vec![];
-
-        for token in token_stream.into_iter() {
-            let parent_element = open_tags.last().unwrap_or(&root);
-            match token.get_token_type() {
-                TokenType::OpeningTag => {
-                    let new_node = Rc::new(RefCell::new(Node {
-                        node_type: NodeType::Element(token.get_html_element().unwrap()),
-                        _parent_element: Some(Rc::downgrade(parent_element)),
-                        children: vec![],
-                        properties: token.get_properties(),
-                    }));
-                    parent_element.borrow_mut().children.push(new_node.clone());
-                    open_tags.push(new_node);
-                }
-                TokenType::ClosingTag => {
-                    open_tags.pop();
-                }
-                TokenType::VoidTag => {
-                    let new_node = Rc::new(RefCell::new(Node {
-                        node_type: NodeType::Element(token.get_html_element().unwrap()),
-                        _parent_element: Some(Rc::downgrade(parent_element)),
-                        children: vec![],
-                        properties: token.get_properties(),
-                    }));
-                    parent_element.borrow_mut().children.push(new_node.clone());
-                }
-                TokenType::Text => {
-                    let new_node = Rc::new(RefCell::new(Node {
-                        node_type: NodeType::Text(token.get_text()),
-                        _parent_element: Some(Rc::downgrade(parent_element)),
-                        children: vec![],
-                        properties: HashMap::new(),
-                    }));
-                    parent_element.borrow_mut().children.push(new_node.clone());
-                }
-                TokenType::Comment => {
-                    let new_node = Rc::new(RefCell::new(Node {
-                        node_type: NodeType::Comment(token.get_text()),
-                        _parent_element: Some(Rc::downgrade(parent_element)),
-                        children: vec![],
-                        properties: token.get_properties(),
-                    }));
-                    parent_element.borrow_mut().children.push(new_node.clone());
-                }
-                TokenType::Unknown => {} // TODO
-            }
+    /// Returns every ancestor of this node, nearest first, ending with the document
+    pub fn ancestors(&self) -> Vec<NodeRef> {
+        let mut ancestors = vec![];
+        let mut current = self.parent();
+        while let Some(node) = current {
+            current = node.borrow().parent();
+            ancestors.push(node);
         }
-        root
+        ancestors
+    }
+
+    /// Returns the position of this node among its parent's children
+    pub fn index_in_parent(&self) -> Option<usize> {
+        let parent = self.parent()?;
+        parent
+            .borrow()
+            .children
+            .iter()
+            .position(|child| std::ptr::eq(child.as_ptr(), self))
+    }
+
+    /// Returns the node immediately after this one, whatever its type
+    pub fn next_sibling(&self) -> Option<NodeRef> {
+        self.following_siblings().into_iter().next()
+    }
+
+    /// Returns the node immediately before this one, whatever its type
+    pub fn previous_sibling(&self) -> Option<NodeRef> {
+        self.preceding_siblings().pop()
+    }
+
+    /// Returns the first element after this one, skipping text and comments
+    pub fn next_element_sibling(&self) -> Option<NodeRef> {
+        self.following_siblings()
+            .into_iter()
+            .find(|node| matches!(node.borrow().node_type, NodeType::Element(_)))
+    }
+
+    /// Returns the last element before this one, skipping text and comments
+    pub fn previous_element_sibling(&self) -> Option<NodeRef> {
+        self.preceding_siblings()
+            .into_iter()
+            .rev()
+            .find(|node| matches!(node.borrow().node_type, NodeType::Element(_)))
+    }
+
+    /// Returns every sibling after this node, in document order
+    pub fn following_siblings(&self) -> Vec<NodeRef> {
+        match (self.parent(), self.index_in_parent()) {
+            (Some(parent), Some(index)) => parent.borrow().children[index + 1..].to_vec(),
+            _ => vec![],
+        }
+    }
+
+    /// Returns every sibling before this node, in document order
+    pub fn preceding_siblings(&self) -> Vec<NodeRef> {
+        match (self.parent(), self.index_in_parent()) {
+            (Some(parent), Some(index)) => parent.borrow().children[..index].to_vec(),
+            _ => vec![],
+        }
+    }
+
+    /// Returns the nearest node, starting with this one and moving up through
+    /// its ancestors, for which the predicate holds. A detached node has no
+    /// handle to return, so this gives `None` for one;
+    /// [`NodeRefExt::closest_by`](crate::mutation::NodeRefExt::closest_by)
+    /// checks the node itself too.
+    pub fn closest_by<F>(&self, predicate: F) -> Option<NodeRef>
+    where
+        F: Fn(&Node) -> bool,
+    {
+        self.self_ref()
+            .into_iter()
+            .chain(self.ancestors())
+            .find(|node| predicate(&node.borrow()))
+    }
+
+    /// Finds the shared handle to this node by looking it up in its parent
+    pub(crate) fn self_ref(&self) -> Option<NodeRef> {
+        let index = self.index_in_parent()?;
+        Some(self.parent()?.borrow().children[index].clone())
+    }
+
+    /// Builds a document from a token stream, following the HTML tree
+    /// construction rules for implied and mis-nested tags
+    pub fn from_token_stream(token_stream: TokenStream) -> NodeRef {
+        Document::from_token_stream(token_stream).to_node_tree()
+    }
+
+    /// Builds a tree like [`Node::from_token_stream`] with control over how
+    /// whitespace in text is kept
+    pub fn from_token_stream_with(token_stream: TokenStream, options: &ParseOptions) -> NodeRef {
+        Document::from_token_stream_with(token_stream, options).to_node_tree()
     }
 }
 
@@ -237,7 +466,7 @@ mod tests {
     #[cfg(test)]
     use super::*;
     #[cfg(test)]
-    use crate::tokeniser::get_tokens;
+    use crate::{mutation::NodeRefExt, tokeniser::get_tokens, whitespace::WhitespacePolicy};
 
     #[cfg(test)]
     const TEST: &str = r##"<html><head><title id="hmm">Test</title><br /></head><body><p id="some-paragraph">Hello, world!</p><div id='classy' class='bg-red p-10 primary'>This is a div with a few classes</div>
@@ -267,19 +496,34 @@ mod tests {
     #[test]
     fn check_children_and_inner_html() {
         let tokens = get_tokens(TEST);
-        let document = Node::from_token_stream(tokens);
+        let options = ParseOptions {
+            whitespace: WhitespacePolicy::TrimInsignificant,
+        };
+        let document = Node::from_token_stream_with(tokens, &options);
         let element = document.borrow().get_element_by_id("some-list").unwrap();
-        let children = element.get_children();
+        let children = element.borrow().get_children();
 
         assert_eq!(children[0].borrow().inner_html(), "Item 1");
     }
 
+    #[test]
+    fn check_whitespace_is_kept_by_default() {
+        let html = "<ul>\n  <li>a</li>\n</ul>";
+        let document = Node::from_token_stream(get_tokens(html));
+        let list = document.borrow().get_elements_by_tag(&HtmlElement::Ul)[0].clone();
+        assert_eq!(list.borrow().outer_html(), html);
+        assert_eq!(list.borrow().get_children().len(), 3);
+    }
+
     #[test]
     fn check_children() {
         let tokens = get_tokens(TEST);
-        let document = Node::from_token_stream(tokens);
+        let options = ParseOptions {
+            whitespace: WhitespacePolicy::TrimInsignificant,
+        };
+        let document = Node::from_token_stream_with(tokens, &options);
         let element = document.borrow().get_element_by_id("some-list").unwrap();
-        let children = element.get_children();
+        let children = element.borrow().get_children();
 
         assert_eq!(children.len(), 3);
     }
@@ -293,7 +537,10 @@ mod tests {
             .get_element_by_id("some-paragraph")
             .unwrap();
 
-        assert_eq!(element.node_type, NodeType::Element(HtmlElement::P))
+        assert_eq!(
+            element.borrow().node_type,
+            NodeType::Element(HtmlElement::P)
+        )
     }
 
     #[test]
@@ -304,6 +551,7 @@ mod tests {
             .borrow()
             .get_element_by_id("classy")
             .unwrap()
+            .borrow()
             .get_class_list();
 
         assert_eq!(
@@ -340,7 +588,10 @@ mod tests {
         let document = Node::from_token_stream(tokens);
         let element = document.borrow().get_element_by_id("hmm").unwrap();
 
-        assert_eq!(element.outer_html(), *"<title id=\"hmm\">Test</title>");
+        assert_eq!(
+            element.borrow().outer_html(),
+            *"<title id=\"hmm\">Test</title>"
+        );
     }
 
     #[test]
@@ -349,7 +600,7 @@ mod tests {
         let document = Node::from_token_stream(tokens);
         let element = document.borrow().get_element_by_id("hmm").unwrap();
 
-        assert_eq!(element.inner_html(), *"Test");
+        assert_eq!(element.borrow().inner_html(), *"Test");
     }
 
     #[test]
@@ -358,7 +609,7 @@ mod tests {
         let document = Node::from_token_stream(tokens);
         let element = document.borrow().get_element_by_id("nested").unwrap();
 
-        assert_eq!(element.inner_html(), *"<p>Some text inside</p>");
+        assert_eq!(element.borrow().inner_html(), *"<p>Some text inside</p>");
     }
 
     #[test]
@@ -368,7 +619,7 @@ mod tests {
         let element = document.borrow().get_element_by_id("nested").unwrap();
 
         assert_eq!(
-            element.outer_html(),
+            element.borrow().outer_html(),
             *"<div id=\"nested\"><p>Some text inside</p></div>"
         );
     }
@@ -379,7 +630,10 @@ mod tests {
         let document = Node::from_token_stream(tokens);
         let footers = document.borrow().get_elements_by_tag(&HtmlElement::Footer);
 
-        assert_eq!(footers[0].outer_html(), *"<footer>No props footer</footer>");
+        assert_eq!(
+            footers[0].borrow().outer_html(),
+            *"<footer>No props footer</footer>"
+        );
     }
 
     #[test]
@@ -388,7 +642,7 @@ mod tests {
         let document = Node::from_token_stream(tokens);
         let footers = document.borrow().get_elements_by_tag(&HtmlElement::Footer);
 
-        assert_eq!(footers[0].inner_html(), *"No props footer");
+        assert_eq!(footers[0].borrow().inner_html(), *"No props footer");
     }
 
     #[test]
@@ -397,7 +651,7 @@ mod tests {
         let document = Node::from_token_stream(tokens);
         let brs = document.borrow().get_elements_by_tag(&HtmlElement::Br);
 
-        assert_eq!(brs[0].outer_html(), *"<br>");
+        assert_eq!(brs[0].borrow().outer_html(), *"<br>");
     }
 
     #[test]
@@ -406,7 +660,7 @@ mod tests {
         let document = Node::from_token_stream(tokens);
         let hrs = document.borrow().get_elements_by_tag(&HtmlElement::Hr);
 
-        assert_eq!(hrs[0].outer_html(), *"<hr class=\"thicc\">");
+        assert_eq!(hrs[0].borrow().outer_html(), *"<hr class=\"thicc\">");
     }
 
     #[test]
@@ -416,7 +670,7 @@ mod tests {
         let divs = document.borrow().get_elements_by_tag(&HtmlElement::Div);
 
         assert_eq!(
-            divs[1].properties.get("class").unwrap(),
+            divs[1].borrow().properties.get("class").unwrap(),
             &"malformed".to_string()
         );
     }
@@ -428,7 +682,7 @@ mod tests {
         let divs = document.borrow().get_elements_by_tag(&HtmlElement::Div);
 
         assert_eq!(
-            divs[2].properties.get("class").unwrap(),
+            divs[2].borrow().properties.get("class").unwrap(),
             &"malformed".to_string()
         );
     }
@@ -440,7 +694,7 @@ mod tests {
         let divs = document.borrow().get_elements_by_tag(&HtmlElement::Div);
 
         assert_eq!(
-            divs[3].properties.get("class").unwrap(),
+            divs[3].borrow().properties.get("class").unwrap(),
             &"malformed".to_string()
         );
     }
@@ -452,7 +706,7 @@ mod tests {
         let divs = document.borrow().get_elements_by_tag(&HtmlElement::Div);
 
         assert_eq!(
-            divs[4].properties.get("class").unwrap(),
+            divs[4].borrow().properties.get("class").unwrap(),
             &"malformed".to_string()
         );
     }
@@ -464,8 +718,165 @@ mod tests {
         let divs = document.borrow().get_elements_by_tag(&HtmlElement::Div);
 
         assert_eq!(
-            divs[0].inner_text(),
+            divs[0].borrow().inner_text(),
             "The quick brown fox jumped over the lazy dog".to_string()
         );
     }
+
+    #[test]
+    fn check_query_results_share_the_document() {
+        let tokens = get_tokens(TEST);
+        let document = Node::from_token_stream(tokens);
+        let element = document.borrow().get_element_by_id("classy").unwrap();
+
+        assert!(element.borrow().parent().is_some());
+        element.borrow_mut().properties.insert("data-seen", "yes");
+        let again = &document.borrow().get_elements_by_class("primary")[0];
+        assert!(Rc::ptr_eq(&element, again));
+        assert_eq!(again.borrow().properties.get("data-seen").unwrap(), "yes");
+    }
+
+    #[test]
+    fn check_parent_and_ancestors() {
+        let tokens = get_tokens(INNER_TEXT_EXAMPLE);
+        let document = Node::from_token_stream(tokens);
+        let bold = &document.borrow().get_elements_by_tag(&HtmlElement::B)[0];
+        let parent = bold.borrow().parent().unwrap();
+
+        assert_eq!(
+            parent.borrow().node_type,
+            NodeType::Element(HtmlElement::Span)
+        );
+        let ancestors: Vec<NodeType> = bold
+            .borrow()
+            .ancestors()
+            .iter()
+            .map(|node| node.borrow().node_type.clone())
+            .collect();
+        assert_eq!(
+            ancestors,
+            vec![
+                NodeType::Element(HtmlElement::Span),
+                NodeType::Element(HtmlElement::A),
+                NodeType::Element(HtmlElement::Div),
+                NodeType::Element(HtmlElement::Body),
+                NodeType::Element(HtmlElement::Html),
+                NodeType::Document,
+            ]
+        );
+    }
+
+    #[test]
+    fn check_siblings() {
+        let html = "<form><label>Name</label><input id=\"name\"><!-- note -->text<br><br></form>";
+        let tokens = get_tokens(html);
+        let document = Node::from_token_stream(tokens);
+        let input = document.borrow().get_element_by_id("name").unwrap();
+
+        assert_eq!(input.borrow().index_in_parent(), Some(1));
+        let label = input.borrow().previous_element_sibling().unwrap();
+        assert_eq!(label.borrow().inner_text(), "Name");
+        assert_eq!(
+            input.borrow().next_sibling().unwrap().borrow().node_type,
+            NodeType::Comment(" note ".to_string())
+        );
+        assert_eq!(
+            input
+                .borrow()
+                .next_element_sibling()
+                .unwrap()
+                .borrow()
+                .node_type,
+            NodeType::Element(HtmlElement::Br)
+        );
+        assert_eq!(input.borrow().following_siblings().len(), 4);
+        assert_eq!(input.borrow().preceding_siblings().len(), 1);
+        assert!(label.borrow().previous_sibling().is_none());
+        assert!(document.borrow().next_sibling().is_none());
+    }
+
+    #[test]
+    fn check_closest() {
+        let html = "<table><tr class=\"row\"><td><a id=\"link\">x</a></td></tr></table>";
+        let tokens = get_tokens(html);
+        let document = Node::from_token_stream(tokens);
+        let link = document.borrow().get_element_by_id("link").unwrap();
+
+        let row = link.borrow().closest("tr.row").unwrap().unwrap();
+        assert_eq!(row.borrow().node_type, NodeType::Element(HtmlElement::Tr));
+        let itself = link.borrow().closest("a").unwrap().unwrap();
+        assert_eq!(itself.borrow().properties.get("id").unwrap(), "link");
+        assert!(link.borrow().closest("ul").unwrap().is_none());
+        assert!(Rc::ptr_eq(&link.closest("a").unwrap().unwrap(), &link));
+        assert!(Rc::ptr_eq(
+            &link.closest_with(&"tr".parse().unwrap()).unwrap(),
+            &row
+        ));
+
+        // A detached node can only be found through its handle
+        let detached = Node::new_element(HtmlElement::Div);
+        assert!(detached.borrow().closest("div").unwrap().is_none());
+        let found = detached.closest("div").unwrap().unwrap();
+        assert!(Rc::ptr_eq(&found, &detached));
+        assert!(
+            detached
+                .closest_by(|node| node.parent().is_none())
+                .is_some()
+        );
+
+        let table = link
+            .borrow()
+            .closest_by(|node| node.node_type == NodeType::Element(HtmlElement::Table))
+            .unwrap();
+        assert_eq!(
+            table.borrow().get_elements_by_tag(&HtmlElement::Td).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn check_source() {
+        let html = "<ul>\n<li id=\"first\">One<li>Two <b>bold</b></ul>";
+        let document = Node::from_token_stream(get_tokens(html));
+        let first = document.borrow().get_element_by_id("first").unwrap();
+        let items = document.borrow().get_elements_by_tag(&HtmlElement::Li);
+
+        assert_eq!(first.borrow().source(html), Some("<li id=\"first\">One"));
+        assert_eq!(items[1].borrow().source(html), Some("<li>Two <b>bold</b>"));
+        let span = first.borrow().location().unwrap().span;
+        assert_eq!(
+            (span.start.offset, span.start.line, span.start.column),
+            (5, 2, 1)
+        );
+        assert!(Node::new_text("x").borrow().source(html).is_none());
+    }
+
+    #[test]
+    fn check_outer_html_escapes_references() {
+        let html = r#"<p title="&quot;Fish&quot; &amp; chips">1 &lt; 2 &amp;&amp; caf&eacute;</p>"#;
+        let tokens = get_tokens(html);
+        let document = Node::from_token_stream(tokens);
+        let paragraph = &document.borrow().get_elements_by_tag(&HtmlElement::P)[0];
+
+        assert_eq!(paragraph.borrow().inner_text(), "1 < 2 && café");
+        assert_eq!(
+            paragraph.borrow().outer_html(),
+            r#"<p title="&quot;Fish&quot; &amp; chips">1 &lt; 2 &amp;&amp; café</p>"#
+        );
+    }
+
+    #[test]
+    fn check_script_contents_are_kept() {
+        let html = r#"<html><head><script type="application/ld+json">{"name": "a & b"}</script></head><body><script>window.__STATE__ = {"x": "<p>"};</script></body></html>"#;
+        let tokens = get_tokens(html);
+        let document = Node::from_token_stream(tokens);
+        let scripts = document.borrow().get_elements_by_tag(&HtmlElement::Script);
+
+        assert_eq!(scripts.len(), 2);
+        assert_eq!(scripts[0].borrow().inner_text(), r#"{"name": "a & b"}"#);
+        assert_eq!(
+            scripts[1].borrow().outer_html(),
+            r#"<script>window.__STATE__ = {"x": "<p>"};</script>"#
+        );
+    }
 }
@@ -0,0 +1,1032 @@
+use std::{fmt, str::FromStr};
+
+use crate::node_tree::{Node, NodeRef, NodeType, TreeNode};
+
+/// A parsed CSS selector list which can be matched against nodes repeatedly
+/// without being re-parsed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Selector {
+    selectors: Vec<ComplexSelector>,
+}
+
+/// The reason a selector failed to parse, along with the byte offset in the
+/// source string at which the problem was found.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SelectorError {
+    pub position: usize,
+    pub kind: SelectorErrorKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SelectorErrorKind {
+    EmptySelector,
+    UnexpectedEnd,
+    UnexpectedCharacter(char),
+    DanglingCombinator,
+    UnterminatedString,
+    UnterminatedAttribute,
+    UnknownPseudoClass(String),
+    InvalidNth(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct ComplexSelector {
+    compounds: Vec<CompoundSelector>,
+    // combinators[i] sits between compounds[i] and compounds[i + 1]
+    combinators: Vec<Combinator>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Combinator {
+    Descendant,
+    Child,
+    NextSibling,
+    SubsequentSibling,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+struct CompoundSelector {
+    // None means the universal selector
+    tag: Option<String>,
+    simple: Vec<SimpleSelector>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum SimpleSelector {
+    Id(String),
+    Class(String),
+    Attribute(AttributeSelector),
+    Pseudo(PseudoClass),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct AttributeSelector {
+    name: String,
+    operation: Option<(AttrOperator, String)>,
+    case_insensitive: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum AttrOperator {
+    Equals,
+    Includes,
+    DashMatch,
+    Prefix,
+    Suffix,
+    Substring,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum PseudoClass {
+    Root,
+    Scope,
+    Empty,
+    FirstChild,
+    LastChild,
+    OnlyChild,
+    FirstOfType,
+    LastOfType,
+    OnlyOfType,
+    NthChild(Nth),
+    NthLastChild(Nth),
+    NthOfType(Nth),
+    NthLastOfType(Nth),
+    Not(Selector),
+    Is(Selector),
+    Has(Vec<ComplexSelector>),
+    Checked,
+    Disabled,
+    Enabled,
+}
+
+/// The `an+b` argument of the `:nth-*` family of pseudo-classes
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Nth {
+    a: i64,
+    b: i64,
+}
+
+impl Nth {
+    fn matches(&self, index: i64) -> bool {
+        if self.a == 0 {
+            return index == self.b;
+        }
+        // Out of range arguments can match nothing rather than overflow
+        let Some(diff) = index.checked_sub(self.b) else {
+            return false;
+        };
+        diff.checked_rem(self.a) == Some(0) && diff.checked_div(self.a).is_some_and(|n| n >= 0)
+    }
+}
+
+impl fmt::Display for SelectorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            SelectorErrorKind::EmptySelector => write!(f, "empty selector")?,
+            SelectorErrorKind::UnexpectedEnd => write!(f, "unexpected end of selector")?,
+            SelectorErrorKind::UnexpectedCharacter(c) => write!(f, "unexpected character '{c}'")?,
+            SelectorErrorKind::DanglingCombinator => {
+                write!(f, "combinator is not followed by a selector")?
+            }
+            SelectorErrorKind::UnterminatedString => write!(f, "unterminated string")?,
+            SelectorErrorKind::UnterminatedAttribute => {
+                write!(f, "attribute selector is missing ']'")?
+            }
+            SelectorErrorKind::UnknownPseudoClass(name) => {
+                write!(f, "unknown pseudo-class ':{name}'")?
+            }
+            SelectorErrorKind::InvalidNth(arg) => write!(f, "invalid an+b expression '{arg}'")?,
+        }
+        write!(f, " at position {}", self.position)
+    }
+}
+
+impl std::error::Error for SelectorError {}
+
+impl FromStr for Selector {
+    type Err = SelectorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Selector::parse(s)
+    }
+}
+
+impl Selector {
+    /// Parses a comma separated selector list such as `div.item > a[href^="https"]`
+    pub fn parse(selector: &str) -> Result<Self, SelectorError> {
+        let mut parser = Parser {
+            input: selector,
+            position: 0,
+        };
+        let selectors = parser.parse_selector_list(false)?;
+        parser.skip_whitespace();
+        if let Some(c) = parser.peek() {
+            return Err(parser.error(SelectorErrorKind::UnexpectedCharacter(c)));
+        }
+        Ok(Selector { selectors })
+    }
+
+    /// Checks whether the node matches any selector in the list
+    pub fn matches(&self, node: &NodeRef) -> bool {
+        self.matches_in_scope(node, None)
+    }
+
+    pub(crate) fn matches_in_scope<N: TreeNode>(&self, node: &N, scope: Option<&N>) -> bool {
+        self.selectors
+            .iter()
+            .any(|complex| complex.matches(node, scope))
+    }
+
+    /// Collects the descendants of `node` which match, in document order
+    pub(crate) fn collect<N: TreeNode>(
+        &self,
+        node: &N,
+        scope: Option<&N>,
+        first_only: bool,
+        out: &mut Vec<N>,
+    ) {
+        for child in node.children() {
+            if first_only && !out.is_empty() {
+                return;
+            }
+            if self.matches_in_scope(&child, scope) {
+                out.push(child.clone());
+            }
+            self.collect(&child, scope, first_only, out);
+        }
+    }
+}
+
+impl Node {
+    /// Returns every descendant element matching the CSS selector, in document order
+    pub fn select(&self, selector: &str) -> Result<Vec<NodeRef>, SelectorError> {
+        Ok(self.select_with(&Selector::parse(selector)?))
+    }
+
+    /// Returns the first descendant element matching the CSS selector
+    pub fn select_first(&self, selector: &str) -> Result<Option<NodeRef>, SelectorError> {
+        Ok(self.select_first_with(&Selector::parse(selector)?))
+    }
+
+    /// Like [`Node::select`] but with a selector which has already been parsed
+    pub fn select_with(&self, selector: &Selector) -> Vec<NodeRef> {
+        let mut matches = vec![];
+        let scope = self.self_ref();
+        for child in &self.children {
+            if selector.matches_in_scope(child, scope.as_ref()) {
+                matches.push(child.clone());
+            }
+            selector.collect(child, scope.as_ref(), false, &mut matches);
+        }
+        matches
+    }
+
+    /// Like [`Node::select_first`] but with a selector which has already been parsed
+    pub fn select_first_with(&self, selector: &Selector) -> Option<NodeRef> {
+        let mut matches = vec![];
+        let scope = self.self_ref();
+        for child in &self.children {
+            if selector.matches_in_scope(child, scope.as_ref()) {
+                return Some(child.clone());
+            }
+            selector.collect(child, scope.as_ref(), true, &mut matches);
+            if let Some(found) = matches.first() {
+                return Some(found.clone());
+            }
+        }
+        None
+    }
+
+    /// Returns the nearest element, starting with this one and moving up
+    /// through its ancestors, which matches the CSS selector. A detached node
+    /// has no handle to return, so this gives `None` for one;
+    /// [`NodeRefExt::closest`](crate::mutation::NodeRefExt::closest) checks
+    /// the node itself too.
+    pub fn closest(&self, selector: &str) -> Result<Option<NodeRef>, SelectorError> {
+        Ok(self.closest_with(&Selector::parse(selector)?))
+    }
+
+    /// Like [`Node::closest`] but with a selector which has already been parsed
+    pub fn closest_with(&self, selector: &Selector) -> Option<NodeRef> {
+        self.self_ref()
+            .into_iter()
+            .chain(self.ancestors())
+            .find(|node| selector.matches(node))
+    }
+}
+
+impl ComplexSelector {
+    fn matches<N: TreeNode>(&self, node: &N, scope: Option<&N>) -> bool {
+        self.matches_from(self.compounds.len() - 1, node, scope)
+    }
+
+    fn matches_from<N: TreeNode>(&self, index: usize, node: &N, scope: Option<&N>) -> bool {
+        if !self.compounds[index].matches(node, scope) {
+            return false;
+        }
+        if index == 0 {
+            return true;
+        }
+        match self.combinators[index - 1] {
+            Combinator::Child => parent_element(node)
+                .is_some_and(|parent| self.matches_from(index - 1, &parent, scope)),
+            Combinator::Descendant => {
+                let mut current = parent_element(node);
+                while let Some(ancestor) = current {
+                    if self.matches_from(index - 1, &ancestor, scope) {
+                        return true;
+                    }
+                    current = parent_element(&ancestor);
+                }
+                false
+            }
+            Combinator::NextSibling => previous_elements(node)
+                .next()
+                .is_some_and(|sibling| self.matches_from(index - 1, &sibling, scope)),
+            Combinator::SubsequentSibling => {
+                previous_elements(node).any(|sibling| self.matches_from(index - 1, &sibling, scope))
+            }
+        }
+    }
+}
+
+impl CompoundSelector {
+    fn matches<N: TreeNode>(&self, node: &N, scope: Option<&N>) -> bool {
+        let tag_matches = node.with_node_type(|node_type| match node_type {
+            NodeType::Element(element) => self
+                .tag
+                .as_ref()
+                .is_none_or(|tag| element.tag_name().eq_ignore_ascii_case(tag)),
+            _ => false,
+        });
+        tag_matches && self.simple.iter().all(|simple| simple.matches(node, scope))
+    }
+}
+
+impl SimpleSelector {
+    fn matches<N: TreeNode>(&self, node: &N, scope: Option<&N>) -> bool {
+        match self {
+            SimpleSelector::Id(id) => node.attribute("id").as_deref() == Some(id.as_str()),
+            SimpleSelector::Class(class) => node
+                .attribute("class")
+                .is_some_and(|classes| classes.split_whitespace().any(|c| c == class)),
+            SimpleSelector::Attribute(selector) => selector.matches(node),
+            SimpleSelector::Pseudo(pseudo) => pseudo.matches(node, scope),
+        }
+    }
+}
+
+impl AttributeSelector {
+    fn matches<N: TreeNode>(&self, node: &N) -> bool {
+        let Some(value) = node.attribute(&self.name) else {
+            return false;
+        };
+        let Some((operator, expected)) = &self.operation else {
+            return true;
+        };
+        let (value, expected) = if self.case_insensitive {
+            (value.to_lowercase(), expected.to_lowercase())
+        } else {
+            (value, expected.clone())
+        };
+        match operator {
+            AttrOperator::Equals => value == expected,
+            AttrOperator::Includes => value.split_whitespace().any(|v| v == expected),
+            AttrOperator::DashMatch => {
+                value == expected || value.starts_with(&format!("{expected}-"))
+            }
+            AttrOperator::Prefix => !expected.is_empty() && value.starts_with(&expected),
+            AttrOperator::Suffix => !expected.is_empty() && value.ends_with(&expected),
+            AttrOperator::Substring => !expected.is_empty() && value.contains(&expected),
+        }
+    }
+}
+
+impl PseudoClass {
+    fn matches<N: TreeNode>(&self, node: &N, scope: Option<&N>) -> bool {
+        match self {
+            PseudoClass::Root => node.parent().is_some_and(|parent| {
+                parent.with_node_type(|node_type| *node_type == NodeType::Document)
+            }),
+            PseudoClass::Scope => match scope {
+                Some(scope) => scope.same_node(node),
+                None => PseudoClass::Root.matches(node, None),
+            },
+            PseudoClass::Empty => node.children().iter().all(|child| {
+                child.with_node_type(|node_type| match node_type {
+                    NodeType::Comment(_) => true,
+                    NodeType::Text(text) => text.is_empty(),
+                    _ => false,
+                })
+            }),
+            PseudoClass::FirstChild => previous_elements(node).next().is_none(),
+            PseudoClass::LastChild => next_elements(node).next().is_none(),
+            PseudoClass::OnlyChild => {
+                previous_elements(node).next().is_none() && next_elements(node).next().is_none()
+            }
+            PseudoClass::FirstOfType => same_type(node, previous_elements(node)).next().is_none(),
+            PseudoClass::LastOfType => same_type(node, next_elements(node)).next().is_none(),
+            PseudoClass::OnlyOfType => {
+                same_type(node, previous_elements(node)).next().is_none()
+                    && same_type(node, next_elements(node)).next().is_none()
+            }
+            PseudoClass::NthChild(nth) => nth.matches(previous_elements(node).count() as i64 + 1),
+            PseudoClass::NthLastChild(nth) => nth.matches(next_elements(node).count() as i64 + 1),
+            PseudoClass::NthOfType(nth) => {
+                nth.matches(same_type(node, previous_elements(node)).count() as i64 + 1)
+            }
+            PseudoClass::NthLastOfType(nth) => {
+                nth.matches(same_type(node, next_elements(node)).count() as i64 + 1)
+            }
+            PseudoClass::Not(selector) => !selector.matches_in_scope(node, scope),
+            PseudoClass::Is(selector) => selector.matches_in_scope(node, scope),
+            PseudoClass::Has(relative) => relative.iter().any(|complex| has_match(complex, node)),
+            PseudoClass::Checked => {
+                node.attribute("checked").is_some() || node.attribute("selected").is_some()
+            }
+            PseudoClass::Disabled => node.attribute("disabled").is_some(),
+            PseudoClass::Enabled => {
+                node.with_node_type(
+                    |node_type| matches!(node_type, NodeType::Element(e) if e.is_form_element()),
+                ) && node.attribute("disabled").is_none()
+            }
+        }
+    }
+}
+
+/// Relative selectors inside `:has()` are stored with a leading `:scope`
+/// compound, so the anchor element only needs to be passed down as the scope
+fn has_match<N: TreeNode>(complex: &ComplexSelector, anchor: &N) -> bool {
+    let mut candidates = vec![];
+    match complex.combinators[0] {
+        Combinator::Descendant | Combinator::Child => descendants(anchor, &mut candidates),
+        Combinator::NextSibling | Combinator::SubsequentSibling => {
+            for sibling in next_elements(anchor) {
+                candidates.push(sibling.clone());
+                descendants(&sibling, &mut candidates);
+            }
+        }
+    }
+    candidates
+        .iter()
+        .any(|candidate| complex.matches(candidate, Some(anchor)))
+}
+
+fn descendants<N: TreeNode>(node: &N, out: &mut Vec<N>) {
+    for child in node.children() {
+        out.push(child.clone());
+        descendants(&child, out);
+    }
+}
+
+fn parent_element<N: TreeNode>(node: &N) -> Option<N> {
+    node.parent().filter(|parent| parent.is_element())
+}
+
+/// The element siblings before a node, nearest first
+fn previous_elements<N: TreeNode>(node: &N) -> impl Iterator<Item = N> {
+    node.previous_siblings().filter(TreeNode::is_element)
+}
+
+/// The element siblings after a node, nearest first
+fn next_elements<N: TreeNode>(node: &N) -> impl Iterator<Item = N> {
+    node.next_siblings().filter(TreeNode::is_element)
+}
+
+/// The siblings which are the same kind of element as `node`
+fn same_type<N: TreeNode>(node: &N, siblings: impl Iterator<Item = N>) -> impl Iterator<Item = N> {
+    let node_type = node.with_node_type(|node_type| node_type.clone());
+    siblings.filter(move |sibling| sibling.with_node_type(|other| *other == node_type))
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.input[self.position..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += c.len_utf8();
+        Some(c)
+    }
+
+    fn error(&self, kind: SelectorErrorKind) -> SelectorError {
+        SelectorError {
+            position: self.position,
+            kind,
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), SelectorError> {
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => {
+                self.position -= c.len_utf8();
+                Err(self.error(SelectorErrorKind::UnexpectedCharacter(c)))
+            }
+            None => Err(self.error(SelectorErrorKind::UnexpectedEnd)),
+        }
+    }
+
+    fn skip_whitespace(&mut self) -> bool {
+        let start = self.position;
+        while self.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+            self.next();
+        }
+        self.position != start
+    }
+
+    fn parse_selector_list(
+        &mut self,
+        relative: bool,
+    ) -> Result<Vec<ComplexSelector>, SelectorError> {
+        let mut selectors = vec![self.parse_complex(relative)?];
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(',') {
+                return Ok(selectors);
+            }
+            self.next();
+            selectors.push(self.parse_complex(relative)?);
+        }
+    }
+
+    fn parse_combinator(&mut self) -> Option<Combinator> {
+        let combinator = match self.peek()? {
+            '>' => Combinator::Child,
+            '+' => Combinator::NextSibling,
+            '~' => Combinator::SubsequentSibling,
+            _ => return None,
+        };
+        self.next();
+        Some(combinator)
+    }
+
+    fn parse_complex(&mut self, relative: bool) -> Result<ComplexSelector, SelectorError> {
+        self.skip_whitespace();
+        let mut complex = ComplexSelector {
+            compounds: vec![],
+            combinators: vec![],
+        };
+        if relative {
+            complex.compounds.push(CompoundSelector {
+                tag: None,
+                simple: vec![SimpleSelector::Pseudo(PseudoClass::Scope)],
+            });
+            let combinator = self.parse_combinator().unwrap_or(Combinator::Descendant);
+            complex.combinators.push(combinator);
+            self.skip_whitespace();
+        }
+        match self.parse_compound()? {
+            Some(compound) => complex.compounds.push(compound),
+            None if relative => return Err(self.error(SelectorErrorKind::DanglingCombinator)),
+            None => {
+                return Err(match self.peek() {
+                    Some(c) if c != ',' && c != ')' => {
+                        self.error(SelectorErrorKind::UnexpectedCharacter(c))
+                    }
+                    _ => self.error(SelectorErrorKind::EmptySelector),
+                });
+            }
+        }
+
+        loop {
+            let had_whitespace = self.skip_whitespace();
+            let combinator = match self.parse_combinator() {
+                Some(combinator) => {
+                    self.skip_whitespace();
+                    combinator
+                }
+                None if had_whitespace => Combinator::Descendant,
+                None => return Ok(complex),
+            };
+            match self.parse_compound()? {
+                Some(compound) => {
+                    complex.combinators.push(combinator);
+                    complex.compounds.push(compound);
+                }
+                None if combinator == Combinator::Descendant => return Ok(complex),
+                None => return Err(self.error(SelectorErrorKind::DanglingCombinator)),
+            }
+        }
+    }
+
+    fn parse_compound(&mut self) -> Result<Option<CompoundSelector>, SelectorError> {
+        let mut compound = CompoundSelector::default();
+        let mut empty = true;
+
+        if self.peek() == Some('*') {
+            self.next();
+            empty = false;
+        } else if let Some(ident) = self.parse_ident() {
+            compound.tag = Some(ident.to_lowercase());
+            empty = false;
+        }
+
+        loop {
+            let simple = match self.peek() {
+                Some('#') => {
+                    self.next();
+                    let id = self.require_ident()?;
+                    SimpleSelector::Id(id)
+                }
+                Some('.') => {
+                    self.next();
+                    let class = self.require_ident()?;
+                    SimpleSelector::Class(class)
+                }
+                Some('[') => {
+                    self.next();
+                    SimpleSelector::Attribute(self.parse_attribute()?)
+                }
+                Some(':') => {
+                    self.next();
+                    SimpleSelector::Pseudo(self.parse_pseudo()?)
+                }
+                _ => break,
+            };
+            compound.simple.push(simple);
+            empty = false;
+        }
+
+        Ok(if empty { None } else { Some(compound) })
+    }
+
+    fn parse_ident(&mut self) -> Option<String> {
+        let start = self.position;
+        let mut ident = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' || !c.is_ascii() {
+                ident.push(c);
+                self.next();
+            } else if c == '\\' {
+                self.next();
+                match self.next() {
+                    Some(escaped) => ident.push(escaped),
+                    None => break,
+                }
+            } else {
+                break;
+            }
+        }
+        // Identifiers may not start with a digit, or a hyphen followed by a digit
+        let mut leading = ident.chars();
+        let invalid_start = match leading.next() {
+            Some(c) if c.is_ascii_digit() => true,
+            Some('-') => leading.next().is_some_and(|c| c.is_ascii_digit()),
+            _ => false,
+        };
+        if ident.is_empty() || (invalid_start && !self.input[start..].starts_with('\\')) {
+            self.position = start;
+            return None;
+        }
+        Some(ident)
+    }
+
+    fn require_ident(&mut self) -> Result<String, SelectorError> {
+        match self.parse_ident() {
+            Some(ident) => Ok(ident),
+            None => Err(match self.peek() {
+                Some(c) => self.error(SelectorErrorKind::UnexpectedCharacter(c)),
+                None => self.error(SelectorErrorKind::UnexpectedEnd),
+            }),
+        }
+    }
+
+    fn parse_string(&mut self, quote: char) -> Result<String, SelectorError> {
+        let mut value = String::new();
+        loop {
+            match self.next() {
+                Some(c) if c == quote => return Ok(value),
+                Some('\\') => match self.next() {
+                    Some(escaped) => value.push(escaped),
+                    None => return Err(self.error(SelectorErrorKind::UnterminatedString)),
+                },
+                Some(c) => value.push(c),
+                None => return Err(self.error(SelectorErrorKind::UnterminatedString)),
+            }
+        }
+    }
+
+    fn parse_attribute(&mut self) -> Result<AttributeSelector, SelectorError> {
+        self.skip_whitespace();
+        let name = self.require_ident()?;
+        self.skip_whitespace();
+        let mut selector = AttributeSelector {
+            name,
+            operation: None,
+            case_insensitive: false,
+        };
+
+        let operator = match self.peek() {
+            Some(']') => {
+                self.next();
+                return Ok(selector);
+            }
+            Some('=') => {
+                self.next();
+                AttrOperator::Equals
+            }
+            Some(c @ ('~' | '|' | '^' | '$' | '*')) => {
+                self.next();
+                self.expect('=')?;
+                match c {
+                    '~' => AttrOperator::Includes,
+                    '|' => AttrOperator::DashMatch,
+                    '^' => AttrOperator::Prefix,
+                    '$' => AttrOperator::Suffix,
+                    _ => AttrOperator::Substring,
+                }
+            }
+            Some(c) => return Err(self.error(SelectorErrorKind::UnexpectedCharacter(c))),
+            None => return Err(self.error(SelectorErrorKind::UnterminatedAttribute)),
+        };
+
+        self.skip_whitespace();
+        let value = match self.peek() {
+            Some(quote @ ('"' | '\'')) => {
+                self.next();
+                self.parse_string(quote)?
+            }
+            _ => self.require_ident()?,
+        };
+        selector.operation = Some((operator, value));
+
+        self.skip_whitespace();
+        match self.peek() {
+            Some('i' | 'I') => {
+                self.next();
+                selector.case_insensitive = true;
+                self.skip_whitespace();
+            }
+            Some('s' | 'S') => {
+                self.next();
+                self.skip_whitespace();
+            }
+            _ => {}
+        }
+        match self.next() {
+            Some(']') => Ok(selector),
+            _ => Err(self.error(SelectorErrorKind::UnterminatedAttribute)),
+        }
+    }
+
+    fn parse_pseudo(&mut self) -> Result<PseudoClass, SelectorError> {
+        let start = self.position;
+        let name = self.require_ident()?.to_lowercase();
+        let functional = self.peek() == Some('(');
+
+        let pseudo = if functional {
+            self.next();
+            self.skip_whitespace();
+            let pseudo = match name.as_str() {
+                "not" => PseudoClass::Not(Selector {
+                    selectors: self.parse_selector_list(false)?,
+                }),
+                "is" | "where" | "matches" => PseudoClass::Is(Selector {
+                    selectors: self.parse_selector_list(false)?,
+                }),
+                "has" => PseudoClass::Has(self.parse_selector_list(true)?),
+                "nth-child" => PseudoClass::NthChild(self.parse_nth()?),
+                "nth-last-child" => PseudoClass::NthLastChild(self.parse_nth()?),
+                "nth-of-type" => PseudoClass::NthOfType(self.parse_nth()?),
+                "nth-last-of-type" => PseudoClass::NthLastOfType(self.parse_nth()?),
+                _ => {
+                    self.position = start;
+                    return Err(self.error(SelectorErrorKind::UnknownPseudoClass(name)));
+                }
+            };
+            self.skip_whitespace();
+            self.expect(')')?;
+            pseudo
+        } else {
+            match name.as_str() {
+                "root" => PseudoClass::Root,
+                "scope" => PseudoClass::Scope,
+                "empty" => PseudoClass::Empty,
+                "first-child" => PseudoClass::FirstChild,
+                "last-child" => PseudoClass::LastChild,
+                "only-child" => PseudoClass::OnlyChild,
+                "first-of-type" => PseudoClass::FirstOfType,
+                "last-of-type" => PseudoClass::LastOfType,
+                "only-of-type" => PseudoClass::OnlyOfType,
+                "checked" => PseudoClass::Checked,
+                "disabled" => PseudoClass::Disabled,
+                "enabled" => PseudoClass::Enabled,
+                _ => {
+                    self.position = start;
+                    return Err(self.error(SelectorErrorKind::UnknownPseudoClass(name)));
+                }
+            }
+        };
+        Ok(pseudo)
+    }
+
+    fn parse_nth(&mut self) -> Result<Nth, SelectorError> {
+        let start = self.position;
+        let end = self.input[start..]
+            .find(')')
+            .map(|offset| start + offset)
+            .ok_or_else(|| self.error(SelectorErrorKind::UnexpectedEnd))?;
+        let raw = &self.input[start..end];
+        let invalid = || SelectorError {
+            position: start,
+            kind: SelectorErrorKind::InvalidNth(raw.trim().to_string()),
+        };
+
+        let expression: String = raw
+            .chars()
+            .filter(|c| !c.is_ascii_whitespace())
+            .collect::<String>()
+            .to_lowercase();
+        let nth = match expression.as_str() {
+            "odd" => Nth { a: 2, b: 1 },
+            "even" => Nth { a: 2, b: 0 },
+            _ => match expression.find('n') {
+                Some(index) => {
+                    let a = match &expression[..index] {
+                        "" | "+" => 1,
+                        "-" => -1,
+                        a => a.parse().map_err(|_| invalid())?,
+                    };
+                    let b = match &expression[index + 1..] {
+                        "" => 0,
+                        b if b.starts_with('+') || b.starts_with('-') => {
+                            b.parse().map_err(|_| invalid())?
+                        }
+                        _ => return Err(invalid()),
+                    };
+                    Nth { a, b }
+                }
+                None => Nth {
+                    a: 0,
+                    b: expression.parse().map_err(|_| invalid())?,
+                },
+            },
+        };
+        self.position = end;
+        Ok(nth)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{html_elements::HtmlElement, tokeniser::get_tokens};
+
+    const TEST: &str = r##"<html><body>
+    <nav class="top-nav">
+        <a class="nav-link" href="https://random.com/home" data-tags="main top">Home</a>
+        <a class="nav-link active" href="http://random.com/about">About</a>
+        <a class="nav-link" href="/contact" lang="en-GB">Contact</a>
+    </nav>
+    <ul id="list">
+        <li>One</li>
+        <li class="odd">Two</li>
+        <li>Three</li>
+        <li><span>Four</span></li>
+    </ul>
+    <p>First</p>
+    <div id="after"></div>
+    <p>Second</p>
+    </body></html>"##;
+
+    fn texts(nodes: &[NodeRef]) -> Vec<String> {
+        nodes
+            .iter()
+            .map(|node| node.borrow().inner_text())
+            .collect()
+    }
+
+    #[test]
+    fn check_type_class_and_id() {
+        let document = Node::from_token_stream(get_tokens(TEST));
+        let document = document.borrow();
+
+        assert_eq!(document.select("a").unwrap().len(), 3);
+        assert_eq!(texts(&document.select("a.active").unwrap()), vec!["About"]);
+        assert_eq!(document.select("#list li").unwrap().len(), 4);
+        assert_eq!(document.select("ul#list").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn check_attribute_operators() {
+        let document = Node::from_token_stream(get_tokens(TEST));
+        let document = document.borrow();
+
+        assert_eq!(
+            texts(&document.select("[href^=https]").unwrap()),
+            vec!["Home"]
+        );
+        assert_eq!(
+            texts(&document.select("[href$='/about']").unwrap()),
+            vec!["About"]
+        );
+        assert_eq!(
+            texts(&document.select("[data-tags~=top]").unwrap()),
+            vec!["Home"]
+        );
+        assert_eq!(
+            texts(&document.select("[lang|=en]").unwrap()),
+            vec!["Contact"]
+        );
+        assert_eq!(
+            texts(&document.select("[href*=RANDOM i]").unwrap()).len(),
+            2
+        );
+        assert_eq!(document.select("a[lang]").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn check_combinators() {
+        let document = Node::from_token_stream(get_tokens(TEST));
+        let document = document.borrow();
+
+        assert_eq!(document.select("ul > span").unwrap().len(), 0);
+        assert_eq!(document.select("ul span").unwrap().len(), 1);
+        assert_eq!(
+            texts(&document.select("li.odd + li").unwrap()),
+            vec!["Three"]
+        );
+        assert_eq!(
+            texts(&document.select("li.odd ~ li").unwrap()),
+            vec!["Three", "Four"]
+        );
+        assert_eq!(
+            texts(&document.select("#after ~ p").unwrap()),
+            vec!["Second"]
+        );
+    }
+
+    #[test]
+    fn check_selector_list_is_in_document_order() {
+        let document = Node::from_token_stream(get_tokens(TEST));
+        let document = document.borrow();
+
+        assert_eq!(
+            texts(&document.select("p, a.active").unwrap()),
+            vec!["About", "First", "Second"]
+        );
+    }
+
+    #[test]
+    fn check_pseudo_classes() {
+        let document = Node::from_token_stream(get_tokens(TEST));
+        let document = document.borrow();
+
+        assert_eq!(
+            texts(&document.select("li:first-child").unwrap()),
+            vec!["One"]
+        );
+        assert_eq!(
+            texts(&document.select("li:last-child").unwrap()),
+            vec!["Four"]
+        );
+        assert_eq!(
+            texts(&document.select("li:nth-child(2n+1)").unwrap()),
+            vec!["One", "Three"]
+        );
+        assert_eq!(
+            texts(&document.select("li:nth-child(even)").unwrap()),
+            vec!["Two", "Four"]
+        );
+        assert_eq!(
+            texts(&document.select("li:nth-child(-n + 2)").unwrap()),
+            vec!["One", "Two"]
+        );
+        assert_eq!(
+            texts(&document.select("li:nth-last-child(1)").unwrap()),
+            vec!["Four"]
+        );
+        assert_eq!(
+            texts(&document.select("p:first-of-type").unwrap()),
+            vec!["First"]
+        );
+        assert_eq!(document.select("li:not(.odd)").unwrap().len(), 3);
+        assert_eq!(
+            texts(&document.select("li:has(span)").unwrap()),
+            vec!["Four"]
+        );
+        assert_eq!(
+            texts(&document.select("li:has(+ .odd)").unwrap()),
+            vec!["One"]
+        );
+        assert_eq!(document.select("div:empty").unwrap().len(), 1);
+        assert_eq!(document.select("html:root").unwrap().len(), 1);
+        assert!(
+            document
+                .select("li:nth-child(n-9223372036854775808)")
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            document
+                .select("li:nth-child(-n+9223372036854775807)")
+                .unwrap()
+                .len(),
+            4
+        );
+
+        let detached = Node::new_element(HtmlElement::Div);
+        assert!(!Selector::parse(":root").unwrap().matches(&detached));
+    }
+
+    #[test]
+    fn check_select_first() {
+        let document = Node::from_token_stream(get_tokens(TEST));
+        let document = document.borrow();
+
+        let first = document.select_first("nav a").unwrap().unwrap();
+        assert_eq!(first.borrow().inner_text(), "Home");
+        assert!(document.select_first("table").unwrap().is_none());
+    }
+
+    #[test]
+    fn check_reusable_selector() {
+        let document = Node::from_token_stream(get_tokens(TEST));
+        let selector: Selector = "nav > a".parse().unwrap();
+        let nav = document.borrow().select_first("nav").unwrap().unwrap();
+        let list = document.borrow().select_first("ul").unwrap().unwrap();
+
+        assert_eq!(document.borrow().select_with(&selector).len(), 3);
+        assert_eq!(nav.borrow().select_with(&selector).len(), 3);
+        assert_eq!(list.borrow().select_with(&selector).len(), 0);
+    }
+
+    #[test]
+    fn check_parse_errors() {
+        let error = Selector::parse("div >").unwrap_err();
+        assert_eq!(error.kind, SelectorErrorKind::DanglingCombinator);
+
+        let error = Selector::parse("a:hover").unwrap_err();
+        assert_eq!(
+            error.kind,
+            SelectorErrorKind::UnknownPseudoClass("hover".to_string())
+        );
+        assert_eq!(error.position, 2);
+
+        let error = Selector::parse("a[href").unwrap_err();
+        assert_eq!(error.kind, SelectorErrorKind::UnterminatedAttribute);
+
+        let error = Selector::parse("li:nth-child(foo)").unwrap_err();
+        assert_eq!(error.kind, SelectorErrorKind::InvalidNth("foo".to_string()));
+
+        assert_eq!(
+            Selector::parse("").unwrap_err().kind,
+            SelectorErrorKind::EmptySelector
+        );
+        assert_eq!(
+            Selector::parse("a,").unwrap_err().kind,
+            SelectorErrorKind::EmptySelector
+        );
+        assert_eq!(
+            Selector::parse("a }").unwrap_err().to_string(),
+            "unexpected character '}' at position 2"
+        );
+    }
+}
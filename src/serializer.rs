@@ -0,0 +1,449 @@
+use crate::{
+    attributes::Attributes,
+    html_elements::HtmlElement,
+    node_tree::{NodeType, TreeNode},
+};
+
+/// Controls how a tree is turned back into HTML. The default follows the
+/// HTML fragment serialization algorithm, except that doctypes keep their
+/// public and system identifiers so that reparsing gives the same quirks mode.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SerializeOptions {
+    /// The indentation for each level of nesting, or `None` to keep the tree
+    /// on the lines it was written on. Only children which are all blocks,
+    /// comments or whitespace are moved onto lines of their own, so no
+    /// rendered whitespace is added.
+    pub indent: Option<String>,
+    /// Drops comments, collapses runs of whitespace in text and writes empty
+    /// attributes as bare names. Takes precedence over `indent`.
+    pub minify: bool,
+    pub quote_style: QuoteStyle,
+    pub escaping: Escaping,
+    pub void_style: VoidStyle,
+    /// Writes output which is also well-formed XML: void elements are
+    /// self-closed, attributes are always quoted and given values, `&nbsp;`
+    /// becomes `&#160;` and the root element gets the XHTML namespace. The
+    /// contents of raw text elements such as `<script>` are left as they are.
+    pub xhtml: bool,
+}
+
+/// How attribute values are quoted
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum QuoteStyle {
+    #[default]
+    Double,
+    Single,
+    /// Leaves values unquoted where that parses back the same, falling back
+    /// to double quotes
+    Unquoted,
+}
+
+/// Which characters are replaced with character references
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Escaping {
+    /// `&`, no-break spaces, `<` and `>`, plus the quote in attribute values
+    #[default]
+    Standard,
+    /// Only what would otherwise be misparsed: `&` and `<` in text, `&` and
+    /// the quote in attribute values
+    Minimal,
+    /// As `Standard`, with every non-ASCII character written as a numeric
+    /// reference
+    Ascii,
+}
+
+/// How void elements such as `<br>` are written
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VoidStyle {
+    /// `<br>`
+    #[default]
+    Html,
+    /// `<br/>`
+    SelfClosing,
+    /// `<br />`
+    SpacedSelfClosing,
+}
+
+const XHTML_NAMESPACE: &str = "http://www.w3.org/1999/xhtml";
+
+impl SerializeOptions {
+    /// Indents nested blocks by two spaces
+    pub fn pretty() -> Self {
+        SerializeOptions {
+            indent: Some("  ".to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Compact output which renders the same as the tree. Comments and
+    /// repeated whitespace are dropped, so it does not parse back to the
+    /// same tree.
+    pub fn minified() -> Self {
+        SerializeOptions {
+            minify: true,
+            quote_style: QuoteStyle::Unquoted,
+            escaping: Escaping::Minimal,
+            ..Default::default()
+        }
+    }
+
+    pub fn xhtml() -> Self {
+        SerializeOptions {
+            void_style: VoidStyle::SpacedSelfClosing,
+            xhtml: true,
+            ..Default::default()
+        }
+    }
+}
+
+/// Writes a node and its descendants, given the parts of the node and its
+/// parent, so that either tree representation can be serialized
+pub(crate) fn outer_html<N: TreeNode>(
+    node_type: &NodeType,
+    attributes: &Attributes,
+    children: &[N],
+    parent: Option<N>,
+    options: &SerializeOptions,
+) -> String {
+    let mut serializer = Serializer::new(options);
+    let (parent, preformatted) = parent_context(parent);
+    serializer.write(
+        node_type,
+        attributes,
+        children,
+        parent.as_ref(),
+        0,
+        preformatted,
+    );
+    serializer.html
+}
+
+/// Writes the descendants of a node
+pub(crate) fn inner_html<N: TreeNode>(
+    node_type: &NodeType,
+    children: &[N],
+    parent: Option<N>,
+    options: &SerializeOptions,
+) -> String {
+    let mut serializer = Serializer::new(options);
+    let (parent, preformatted) = parent_context(parent);
+    match node_type {
+        NodeType::Element(element) => serializer.write_children(
+            Some(element),
+            children,
+            0,
+            preformatted || element.preserves_whitespace(),
+            true,
+        ),
+        _ => serializer.write_children(parent.as_ref(), children, 0, preformatted, true),
+    }
+    serializer.html
+}
+
+/// The element a node sits in and whether whitespace inside it is kept as is
+fn parent_context<N: TreeNode>(parent: Option<N>) -> (Option<HtmlElement>, bool) {
+    let parent_element = parent.as_ref().and_then(TreeNode::element);
+    let mut preformatted = false;
+    let mut current = parent;
+    while let Some(node) = current {
+        if node
+            .element()
+            .is_some_and(|element| element.preserves_whitespace())
+        {
+            preformatted = true;
+            break;
+        }
+        current = node.parent();
+    }
+    (parent_element, preformatted)
+}
+
+struct Serializer<'a> {
+    options: &'a SerializeOptions,
+    html: String,
+}
+
+impl<'a> Serializer<'a> {
+    fn new(options: &'a SerializeOptions) -> Self {
+        Serializer {
+            options,
+            html: String::new(),
+        }
+    }
+
+    fn write_node<N: TreeNode>(
+        &mut self,
+        node: &N,
+        parent: Option<&HtmlElement>,
+        depth: usize,
+        preformatted: bool,
+    ) {
+        node.with_node_type(|node_type| {
+            node.with_attributes(|attributes| {
+                self.write(
+                    node_type,
+                    attributes,
+                    &node.children(),
+                    parent,
+                    depth,
+                    preformatted,
+                )
+            })
+        })
+    }
+
+    fn write<N: TreeNode>(
+        &mut self,
+        node_type: &NodeType,
+        attributes: &Attributes,
+        children: &[N],
+        parent: Option<&HtmlElement>,
+        depth: usize,
+        preformatted: bool,
+    ) {
+        match node_type {
+            NodeType::Text(text) => self.write_text(text, parent, preformatted),
+            NodeType::Comment(data) => {
+                if !self.options.minify {
+                    self.html.push_str("<!--");
+                    self.html.push_str(data);
+                    self.html.push_str("-->");
+                }
+            }
+            NodeType::Doctype(doctype) => self.html.push_str(&doctype.to_string()),
+            NodeType::Document => self.write_children(None, children, depth, preformatted, true),
+            NodeType::Element(element) => {
+                self.html.push('<');
+                self.html.push_str(element.tag_name());
+                for (name, value) in attributes {
+                    self.write_attribute(name, value);
+                }
+                if self.options.xhtml
+                    && *element == HtmlElement::Html
+                    && !attributes.contains("xmlns")
+                {
+                    self.write_attribute("xmlns", XHTML_NAMESPACE);
+                }
+                if element.is_void_element() {
+                    let void_style = match self.options.void_style {
+                        VoidStyle::Html if self.options.xhtml => VoidStyle::SpacedSelfClosing,
+                        void_style => void_style,
+                    };
+                    self.html.push_str(match void_style {
+                        VoidStyle::Html => ">",
+                        VoidStyle::SelfClosing => "/>",
+                        VoidStyle::SpacedSelfClosing => " />",
+                    });
+                    return;
+                }
+                self.html.push('>');
+                let preformatted = preformatted || element.preserves_whitespace();
+                self.write_children(Some(element), children, depth, preformatted, false);
+                self.html.push_str("</");
+                self.html.push_str(element.tag_name());
+                self.html.push('>');
+            }
+        }
+    }
+
+    /// Writes children inline, or when pretty-printing a block of children,
+    /// each on a line of its own. Top level children have no line break
+    /// before the first or after the last.
+    fn write_children<N: TreeNode>(
+        &mut self,
+        parent: Option<&HtmlElement>,
+        children: &[N],
+        depth: usize,
+        preformatted: bool,
+        top_level: bool,
+    ) {
+        let indent = match &self.options.indent {
+            Some(indent)
+                if !self.options.minify
+                    && !preformatted
+                    && children.iter().all(|child| child.with_node_type(is_block)) =>
+            {
+                indent.clone()
+            }
+            _ => {
+                for child in children {
+                    self.write_node(child, parent, depth, preformatted);
+                }
+                return;
+            }
+        };
+        let child_depth = if top_level { depth } else { depth + 1 };
+        let mut first = true;
+        for child in children {
+            if child.with_node_type(is_whitespace_text) {
+                continue;
+            }
+            if !(top_level && first) {
+                self.html.push('\n');
+            }
+            self.html.push_str(&indent.repeat(child_depth));
+            self.write_node(child, parent, child_depth, preformatted);
+            first = false;
+        }
+        if !top_level && !first {
+            self.html.push('\n');
+            self.html.push_str(&indent.repeat(depth));
+        }
+    }
+
+    fn write_text(&mut self, text: &str, parent: Option<&HtmlElement>, preformatted: bool) {
+        if parent.is_some_and(HtmlElement::is_raw_text_element) {
+            self.html.push_str(text);
+            return;
+        }
+        if !self.options.minify || preformatted {
+            self.escape(text, false, None);
+            return;
+        }
+        // Output only ends in a space after minified text, so this also
+        // joins up whitespace around a comment which was dropped
+        let mut space = self.html.ends_with(' ');
+        let mut collapsed = String::with_capacity(text.len());
+        for char in text.chars() {
+            if !char.is_ascii_whitespace() {
+                collapsed.push(char);
+                space = false;
+            } else if !space {
+                collapsed.push(' ');
+                space = true;
+            }
+        }
+        self.escape(&collapsed, false, None);
+    }
+
+    fn write_attribute(&mut self, name: &str, value: &str) {
+        self.html.push(' ');
+        self.html.push_str(name);
+        if value.is_empty() && self.options.minify && !self.options.xhtml {
+            return;
+        }
+        let quote = match self.options.quote_style {
+            QuoteStyle::Single => Some('\''),
+            QuoteStyle::Unquoted if !self.options.xhtml && can_be_unquoted(value) => None,
+            QuoteStyle::Double | QuoteStyle::Unquoted => Some('"'),
+        };
+        self.html.push('=');
+        self.html.extend(quote);
+        self.escape(value, true, quote);
+        self.html.extend(quote);
+    }
+
+    /// Escapes text, or an attribute value written inside `quote`
+    fn escape(&mut self, text: &str, in_attribute: bool, quote: Option<char>) {
+        let minimal = self.options.escaping == Escaping::Minimal;
+        for char in text.chars() {
+            match char {
+                '&' => self.html.push_str("&amp;"),
+                '<' if !in_attribute || !minimal => self.html.push_str("&lt;"),
+                '>' if !minimal => self.html.push_str("&gt;"),
+                '"' if quote == Some('"') => self.html.push_str("&quot;"),
+                '\'' if quote == Some('\'') => self.html.push_str("&#39;"),
+                '\u{A0}' if !minimal && self.options.escaping != Escaping::Ascii => {
+                    self.html.push_str(if self.options.xhtml {
+                        "&#160;"
+                    } else {
+                        "&nbsp;"
+                    })
+                }
+                _ if !char.is_ascii() && self.options.escaping == Escaping::Ascii => {
+                    self.html.push_str(&format!("&#x{:X};", char as u32))
+                }
+                _ => self.html.push(char),
+            }
+        }
+    }
+}
+
+/// Whether a node can go on a line of its own without changing how the
+/// page renders
+fn is_block(node_type: &NodeType) -> bool {
+    match node_type {
+        NodeType::Element(element) => !element.is_inline(),
+        NodeType::Text(_) => is_whitespace_text(node_type),
+        _ => true,
+    }
+}
+
+fn is_whitespace_text(node_type: &NodeType) -> bool {
+    matches!(node_type, NodeType::Text(text) if text.chars().all(|c| c.is_ascii_whitespace()))
+}
+
+fn can_be_unquoted(value: &str) -> bool {
+    !value.is_empty()
+        && !value
+            .chars()
+            .any(|c| c.is_ascii_whitespace() || matches!(c, '"' | '\'' | '=' | '<' | '>' | '`'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::Document;
+
+    const HTML: &str = "<!DOCTYPE html><html><head><title>T</title></head><body><!-- note --><div id=main class=\"a b\"><p>One &amp; <b>two</b>\u{A0}!</p><br><input disabled value='say \"hi\"'></div><pre>  keep\n  this </pre></body></html>";
+
+    fn serialize(options: &SerializeOptions) -> String {
+        let document = Document::parse(HTML);
+        document.outer_html_with(document.root(), options)
+    }
+
+    #[test]
+    fn check_default_serialization() {
+        assert_eq!(
+            serialize(&SerializeOptions::default()),
+            "<!DOCTYPE html><html><head><title>T</title></head><body><!-- note --><div id=\"main\" class=\"a b\"><p>One &amp; <b>two</b>&nbsp;!</p><br><input disabled=\"\" value=\"say &quot;hi&quot;\"></div><pre>  keep\n  this </pre></body></html>"
+        );
+    }
+
+    #[test]
+    fn check_pretty_printing() {
+        assert_eq!(
+            serialize(&SerializeOptions::pretty()),
+            "<!DOCTYPE html>\n<html>\n  <head>\n    <title>T</title>\n  </head>\n  <body>\n    <!-- note -->\n    <div id=\"main\" class=\"a b\"><p>One &amp; <b>two</b>&nbsp;!</p><br><input disabled=\"\" value=\"say &quot;hi&quot;\"></div>\n    <pre>  keep\n  this </pre>\n  </body>\n</html>"
+        );
+    }
+
+    #[test]
+    fn check_minified_and_xhtml() {
+        assert_eq!(
+            serialize(&SerializeOptions::minified()),
+            "<!DOCTYPE html><html><head><title>T</title></head><body><div id=main class=\"a b\"><p>One &amp; <b>two</b>\u{A0}!</p><br><input disabled value=\"say &quot;hi&quot;\"></div><pre>  keep\n  this </pre></body></html>"
+        );
+        let document = Document::parse("<p>a <!-- c --> b<!-- d -->\n c</p>");
+        let paragraph = document.select_first("p").unwrap().unwrap();
+        assert_eq!(
+            document.outer_html_with(paragraph, &SerializeOptions::minified()),
+            "<p>a b c</p>"
+        );
+        assert_eq!(
+            serialize(&SerializeOptions::xhtml()),
+            "<!DOCTYPE html><html xmlns=\"http://www.w3.org/1999/xhtml\"><head><title>T</title></head><body><!-- note --><div id=\"main\" class=\"a b\"><p>One &amp; <b>two</b>&#160;!</p><br /><input disabled=\"\" value=\"say &quot;hi&quot;\" /></div><pre>  keep\n  this </pre></body></html>"
+        );
+        let options = SerializeOptions {
+            quote_style: QuoteStyle::Single,
+            escaping: Escaping::Ascii,
+            ..Default::default()
+        };
+        let document = Document::parse("<p title=\"it's\">caf\u{e9}</p>");
+        let paragraph = document.select_first("p").unwrap().unwrap();
+        assert_eq!(
+            document.outer_html_with(paragraph, &options),
+            "<p title='it&#39;s'>caf&#xE9;</p>"
+        );
+    }
+
+    #[test]
+    fn check_frame_is_void() {
+        let document = Document::parse("<frameset><frame src=a.html><frame></frameset>");
+        let frameset = document.select_first("frameset").unwrap().unwrap();
+        assert_eq!(
+            document.outer_html(frameset),
+            "<frameset><frame src=\"a.html\"><frame></frameset>"
+        );
+    }
+}
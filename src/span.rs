@@ -0,0 +1,148 @@
+use std::ops::Index;
+
+/// A point in the source. Offsets are in bytes, lines and columns count
+/// from 1 and columns count characters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// The half-open range of source between two positions
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+/// Where an attribute's name and, unless it is a boolean attribute, its value
+/// sit in the source. A quoted value's span excludes the quotes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AttributeSpan {
+    pub name: Span,
+    pub value: Option<Span>,
+}
+
+/// The spans of an element's attributes in the order they were written,
+/// named as in its [`Attributes`](crate::attributes::Attributes) and looked
+/// up ignoring ASCII case
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AttributeSpans {
+    entries: Vec<(String, AttributeSpan)>,
+}
+
+/// Where a node came from. `span` covers the start tag of an element or the
+/// whole of a text or comment node; `end_tag` is only set when the element
+/// was closed by an explicit end tag.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SourceLocation {
+    pub span: Span,
+    pub end_tag: Option<Span>,
+    pub attributes: AttributeSpans,
+}
+
+impl AttributeSpans {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&AttributeSpan> {
+        self.position(name).map(|index| &self.entries[index].1)
+    }
+
+    /// The names and spans in source order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &AttributeSpan)> {
+        self.entries
+            .iter()
+            .map(|(name, span)| (name.as_str(), span))
+    }
+
+    /// Adds the span of an attribute which is not yet present
+    pub(crate) fn push(&mut self, name: &str, span: AttributeSpan) {
+        self.entries.push((name.to_string(), span));
+    }
+
+    pub(crate) fn contains(&self, name: &str) -> bool {
+        self.position(name).is_some()
+    }
+
+    /// Gives an attribute the spelling of `name`, as
+    /// [`Attributes::adjust_case`](crate::attributes::Attributes) does
+    pub(crate) fn adjust_case(&mut self, name: &str) {
+        if let Some(index) = self.position(name) {
+            self.entries[index].0 = name.to_string();
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|(key, _)| key.eq_ignore_ascii_case(name))
+    }
+}
+
+impl Index<&str> for AttributeSpans {
+    type Output = AttributeSpan;
+
+    /// Panics if the attribute is missing
+    fn index(&self, name: &str) -> &AttributeSpan {
+        match self.get(name) {
+            Some(span) => span,
+            None => panic!("no attribute named {name}"),
+        }
+    }
+}
+
+impl Position {
+    /// The position after moving past `char`
+    pub(crate) fn advance(&mut self, char: char) {
+        self.offset += char.len_utf8();
+        if char == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+    }
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Position {
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+}
+
+impl Span {
+    /// The text this span covers in `source`, which must be the input the
+    /// span was recorded from
+    pub fn slice<'a>(&self, source: &'a str) -> Option<&'a str> {
+        source.get(self.start.offset..self.end.offset)
+    }
+}
+
+impl SourceLocation {
+    pub(crate) fn new(span: Span) -> Self {
+        SourceLocation {
+            span,
+            ..Default::default()
+        }
+    }
+
+    /// Where the node ends: the end of its end tag, or else of its own span
+    pub(crate) fn end(&self) -> Position {
+        self.end_tag.map_or(self.span.end, |end_tag| end_tag.end)
+    }
+}
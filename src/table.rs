@@ -0,0 +1,369 @@
+use crate::{
+    html_elements::HtmlElement,
+    node_tree::{NodeType, TreeNode},
+    whitespace,
+};
+
+/// A `<table>` laid out as a rectangular grid. Every slot a cell covers with
+/// its `colspan` or `rowspan` refers to that one cell.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Table {
+    pub caption: Option<String>,
+    cells: Vec<Cell>,
+    /// Indices into `cells`, `None` for the slots no cell reaches in rows
+    /// shorter than the widest
+    grid: Vec<Vec<Option<usize>>>,
+    header_rows: usize,
+    header_columns: usize,
+}
+
+/// A `<td>` or `<th>` of a [`Table`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Cell {
+    /// The cell's text with whitespace collapsed
+    pub text: String,
+    /// Whether the cell is a `<th>`
+    pub header: bool,
+    /// The row of the top left slot the cell covers
+    pub row: usize,
+    /// The column of the top left slot the cell covers
+    pub column: usize,
+}
+
+type Grid = Vec<Vec<Option<usize>>>;
+
+// Limits from the HTML table processing model
+const MAX_COLSPAN: usize = 1000;
+const MAX_ROWSPAN: usize = 65534;
+
+/// Builds the table for a `<table>` node, given its parts. Rows of nested
+/// tables are left out, though their text is part of the cell they sit in.
+pub(crate) fn build_table<N: TreeNode>(node_type: &NodeType, children: &[N]) -> Option<Table> {
+    if !matches!(node_type, NodeType::Element(HtmlElement::Table)) {
+        return None;
+    }
+    let mut caption = None;
+    let mut cells = vec![];
+    let mut grid = Grid::new();
+    let mut head_rows = 0;
+    for child in children {
+        match child.element() {
+            Some(HtmlElement::Caption) if caption.is_none() => {
+                caption = Some(whitespace::normalize(&child.text_content()));
+            }
+            Some(HtmlElement::Thead) => {
+                add_row_group(&mut cells, &mut grid, &child.children());
+                head_rows = grid.len();
+            }
+            Some(HtmlElement::Tbody | HtmlElement::Tfoot) => {
+                add_row_group(&mut cells, &mut grid, &child.children())
+            }
+            Some(HtmlElement::Tr) => {
+                add_row_group(&mut cells, &mut grid, std::slice::from_ref(child))
+            }
+            _ => {}
+        }
+    }
+    let is_header = |slot: &Option<usize>| slot.is_some_and(|index| cells[index].header);
+    // Without a `<thead>`, leading rows of only `<th>` cells are headers
+    let header_rows = match head_rows {
+        0 => grid
+            .iter()
+            .take_while(|row| {
+                row.iter().any(Option::is_some) && row.iter().flatten().all(|&i| cells[i].header)
+            })
+            .count(),
+        rows => rows,
+    };
+    let width = grid.iter().map(Vec::len).max().unwrap_or(0);
+    let body = &grid[header_rows..];
+    let header_columns = match body.is_empty() {
+        true => 0,
+        false => (0..width)
+            .take_while(|&column| {
+                body.iter()
+                    .all(|row| row.get(column).is_some_and(is_header))
+            })
+            .count(),
+    };
+    for row in &mut grid {
+        row.resize(width, None);
+    }
+    Some(Table {
+        caption,
+        cells,
+        grid,
+        header_rows,
+        header_columns,
+    })
+}
+
+/// Lays out the rows of a row group. A `rowspan` is clipped at the group's
+/// last row, and one of 0 reaches it.
+fn add_row_group<N: TreeNode>(cells: &mut Vec<Cell>, grid: &mut Grid, rows: &[N]) {
+    let rows: Vec<&N> = rows
+        .iter()
+        .filter(|row| row.element() == Some(HtmlElement::Tr))
+        .collect();
+    let start = grid.len();
+    let end = start + rows.len();
+    grid.resize(end, vec![]);
+    for (index, row) in rows.into_iter().enumerate() {
+        let y = start + index;
+        let mut x = 0;
+        for cell in row.children() {
+            let header = match cell.element() {
+                Some(HtmlElement::Th) => true,
+                Some(HtmlElement::Td) => false,
+                _ => continue,
+            };
+            while grid[y].get(x).is_some_and(Option::is_some) {
+                x += 1;
+            }
+            let colspan = span(&cell, "colspan").unwrap_or(1).clamp(1, MAX_COLSPAN);
+            let rowspan = match span(&cell, "rowspan") {
+                Some(0) => MAX_ROWSPAN,
+                Some(rowspan) => rowspan.min(MAX_ROWSPAN),
+                None => 1,
+            }
+            .min(end - y);
+            let id = cells.len();
+            cells.push(Cell {
+                text: whitespace::normalize(&cell.text_content()),
+                header,
+                row: y,
+                column: x,
+            });
+            for row in &mut grid[y..y + rowspan] {
+                if row.len() < x + colspan {
+                    row.resize(x + colspan, None);
+                }
+                // Overlapping cells are a table model error, and the first
+                // one keeps the slot
+                for slot in row[x..x + colspan].iter_mut().filter(|slot| slot.is_none()) {
+                    *slot = Some(id);
+                }
+            }
+            x += colspan;
+        }
+    }
+}
+
+impl Table {
+    /// The number of rows, including header rows
+    pub fn height(&self) -> usize {
+        self.grid.len()
+    }
+
+    /// The number of columns, the length of the longest row
+    pub fn width(&self) -> usize {
+        self.grid.first().map_or(0, Vec::len)
+    }
+
+    /// The cell covering a slot, `None` outside the grid or if no cell
+    /// reaches the slot
+    pub fn cell(&self, row: usize, column: usize) -> Option<&Cell> {
+        let index = (*self.grid.get(row)?.get(column)?)?;
+        Some(&self.cells[index])
+    }
+
+    /// Whether a slot is covered by a `colspan` or `rowspan` of a cell which
+    /// starts in an earlier row or column
+    pub fn spanned(&self, row: usize, column: usize) -> bool {
+        self.cell(row, column)
+            .is_some_and(|cell| (cell.row, cell.column) != (row, column))
+    }
+
+    /// How many leading rows are headers: the rows of the `<thead>`, or
+    /// without one the rows made up only of `<th>` cells
+    pub fn header_rows(&self) -> usize {
+        self.header_rows
+    }
+
+    /// How many leading columns are headers, having a `<th>` in every row
+    /// below the header rows
+    pub fn header_columns(&self) -> usize {
+        self.header_columns
+    }
+
+    /// The text of every row, header rows included
+    pub fn rows(&self) -> Vec<Vec<String>> {
+        self.grid
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|slot| {
+                        slot.map_or_else(String::new, |index| self.cells[index].text.clone())
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// The text of the rows below the header rows
+    pub fn body(&self) -> Vec<Vec<String>> {
+        self.rows().split_off(self.header_rows)
+    }
+
+    /// A name for each column from the header rows, joining the different
+    /// texts of stacked headers with a space. Columns without one are named
+    /// by their index.
+    pub fn headers(&self) -> Vec<String> {
+        (0..self.width())
+            .map(|column| {
+                let mut texts: Vec<&str> = vec![];
+                for row in 0..self.header_rows {
+                    let text = self.cell(row, column).map_or("", |cell| cell.text.as_str());
+                    if !text.is_empty() && texts.last() != Some(&text) {
+                        texts.push(text);
+                    }
+                }
+                match texts.is_empty() {
+                    true => column.to_string(),
+                    false => texts.join(" "),
+                }
+            })
+            .collect()
+    }
+
+    /// Each body row as header and value pairs in column order
+    pub fn records(&self) -> Vec<Vec<(String, String)>> {
+        let headers = self.headers();
+        self.body()
+            .into_iter()
+            .map(|row| headers.iter().cloned().zip(row).collect())
+            .collect()
+    }
+
+    /// Every row as CSV, quoting fields as RFC 4180 describes and ending
+    /// each record with CRLF
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::new();
+        for row in self.rows() {
+            let fields: Vec<String> = row
+                .iter()
+                .map(|field| {
+                    if field.contains(['"', ',', '\r', '\n']) {
+                        format!("\"{}\"", field.replace('"', "\"\""))
+                    } else {
+                        field.clone()
+                    }
+                })
+                .collect();
+            csv.push_str(&fields.join(","));
+            csv.push_str("\r\n");
+        }
+        csv
+    }
+
+    /// The body rows as a JSON array of objects keyed by the headers, or as
+    /// an array of arrays of every row if the table has no header rows
+    pub fn to_json(&self) -> String {
+        let rows: Vec<String> = match self.header_rows {
+            0 => self
+                .rows()
+                .iter()
+                .map(|row| {
+                    let values: Vec<String> = row.iter().map(|value| json_string(value)).collect();
+                    format!("[{}]", values.join(","))
+                })
+                .collect(),
+            _ => self
+                .records()
+                .iter()
+                .map(|record| {
+                    let fields: Vec<String> = record
+                        .iter()
+                        .map(|(key, value)| format!("{}:{}", json_string(key), json_string(value)))
+                        .collect();
+                    format!("{{{}}}", fields.join(","))
+                })
+                .collect(),
+        };
+        format!("[{}]", rows.join(","))
+    }
+}
+
+fn span<N: TreeNode>(cell: &N, name: &str) -> Option<usize> {
+    cell.with_attributes(|attributes| attributes.get_usize(name))
+}
+
+fn json_string(value: &str) -> String {
+    let mut json = String::with_capacity(value.len() + 2);
+    json.push('"');
+    for char in value.chars() {
+        match char {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            char if char < ' ' => json.push_str(&format!("\\u{:04x}", char as u32)),
+            char => json.push(char),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::document::Document;
+
+    const HTML: &str = r#"<table>
+        <caption>Sales</caption>
+        <thead><tr><th rowspan="2">Region</th><th colspan="2">Q1</th></tr>
+        <tr><th>Jan</th><th>Feb</th></tr></thead>
+        <tbody><tr><th>North</th><td>1</td><td rowspan="0">"2, 3"</td></tr>
+        <tr><th>South</th><td>4</td></tr></tbody>
+    </table>"#;
+
+    #[test]
+    fn check_grid_and_headers() {
+        let document = Document::parse(HTML);
+        let table = &document.tables()[0];
+        assert_eq!(table.caption.as_deref(), Some("Sales"));
+        assert_eq!((table.height(), table.width()), (4, 3));
+        assert_eq!((table.header_rows(), table.header_columns()), (2, 1));
+        assert!(table.spanned(1, 0));
+        assert_eq!(table.cell(1, 0).unwrap().row, 0);
+        assert_eq!(table.headers(), vec!["Region", "Q1 Jan", "Q1 Feb"]);
+        assert_eq!(
+            table.body(),
+            vec![
+                vec!["North", "1", "\"2, 3\""],
+                vec!["South", "4", "\"2, 3\""]
+            ]
+        );
+        assert_eq!(
+            table.to_csv(),
+            "Region,Q1,Q1\r\nRegion,Jan,Feb\r\nNorth,1,\"\"\"2, 3\"\"\"\r\nSouth,4,\"\"\"2, 3\"\"\"\r\n"
+        );
+        assert_eq!(
+            table.to_json(),
+            r#"[{"Region":"North","Q1 Jan":"1","Q1 Feb":"\"2, 3\""},{"Region":"South","Q1 Jan":"4","Q1 Feb":"\"2, 3\""}]"#
+        );
+    }
+
+    #[test]
+    fn check_headerless_and_ragged_rows() {
+        let document = Document::parse("<table><tr><td>a<td>b<tr><td colspan=3>c</table>");
+        let table = &document.tables()[0];
+        assert_eq!(table.header_rows(), 0);
+        assert_eq!(table.headers(), vec!["0", "1", "2"]);
+        assert_eq!(table.rows(), vec![vec!["a", "b", ""], vec!["c", "c", "c"]]);
+        assert_eq!(table.to_json(), r#"[["a","b",""],["c","c","c"]]"#);
+        assert!(table.cell(0, 2).is_none());
+    }
+
+    #[test]
+    fn check_huge_spans() {
+        let html = "<table><tr><td rowspan=65534 colspan=1000>x</td></tr><tbody><tr><td>y</table>";
+        let document = Document::parse(html);
+        let table = &document.tables()[0];
+        assert_eq!((table.height(), table.width()), (2, 1000));
+        assert!(table.spanned(0, 999));
+        assert_eq!(table.cell(1, 0).unwrap().text, "y");
+        assert!(table.cell(1, 1).is_none());
+    }
+}
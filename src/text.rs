@@ -0,0 +1,347 @@
+use crate::{
+    attributes::Attributes,
+    html_elements::HtmlElement,
+    node_tree::{NodeType, TreeNode},
+};
+
+/// Controls how a tree is rendered as plain text
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TextOptions {
+    /// Wraps lines at this many characters, except in preformatted text and
+    /// where a single word is longer
+    pub width: Option<usize>,
+    /// Follows each link with a footnote number like `[1]` and lists the
+    /// URLs at the end
+    pub link_footnotes: bool,
+}
+
+/// Renders a node, given its parts, the way a browser lays it out as text:
+/// whitespace collapsed, blocks on lines of their own and paragraphs
+/// separated by a blank line
+pub(crate) fn to_text<N: TreeNode>(
+    node_type: &NodeType,
+    attributes: &Attributes,
+    children: &[N],
+    options: &TextOptions,
+) -> String {
+    let mut renderer = Renderer {
+        options,
+        output: String::new(),
+        column: 0,
+        pending_breaks: 0,
+        pending_space: false,
+        indents: vec![],
+        marker: None,
+        lists: 0,
+        footnotes: vec![],
+    };
+    renderer.node(node_type, attributes, children, false);
+    if !renderer.footnotes.is_empty() {
+        let footnotes = std::mem::take(&mut renderer.footnotes);
+        renderer.indents.clear();
+        renderer.request_breaks(2);
+        for (index, url) in footnotes.iter().enumerate() {
+            renderer.request_breaks(1);
+            renderer.push_word(&format!("[{}] {url}", index + 1));
+        }
+    }
+    renderer.output
+}
+
+struct Renderer<'a> {
+    options: &'a TextOptions,
+    output: String,
+    // Characters on the current line, for wrapping
+    column: usize,
+    // Line breaks owed before the next text, so that empty blocks and the
+    // ends of the output do not leave blank lines
+    pending_breaks: usize,
+    pending_space: bool,
+    // Indentation of list items and block quotes
+    indents: Vec<usize>,
+    // A list marker to write in place of the innermost indent
+    marker: Option<String>,
+    // How many lists the renderer is inside, as nested lists sit directly
+    // under their item
+    lists: usize,
+    footnotes: Vec<String>,
+}
+
+impl Renderer<'_> {
+    fn node<N: TreeNode>(
+        &mut self,
+        node_type: &NodeType,
+        attributes: &Attributes,
+        children: &[N],
+        preformatted: bool,
+    ) {
+        match node_type {
+            NodeType::Text(text) if preformatted => self.push_preformatted(text),
+            NodeType::Text(text) => self.push_text(text),
+            NodeType::Element(element) => self.element(element, attributes, children, preformatted),
+            NodeType::Document => self.children(children, preformatted),
+            NodeType::Comment(_) | NodeType::Doctype(_) => {}
+        }
+    }
+
+    fn children<N: TreeNode>(&mut self, children: &[N], preformatted: bool) {
+        for child in children {
+            child.with_node_type(|node_type| {
+                child.with_attributes(|attributes| {
+                    self.node(node_type, attributes, &child.children(), preformatted)
+                })
+            });
+        }
+    }
+
+    fn element<N: TreeNode>(
+        &mut self,
+        element: &HtmlElement,
+        attributes: &Attributes,
+        children: &[N],
+        preformatted: bool,
+    ) {
+        if element.is_hidden() || attributes.get_bool("hidden") {
+            return;
+        }
+        let gap = match element {
+            HtmlElement::Ul | HtmlElement::Ol if self.lists > 0 => 1,
+            _ => block_gap(element),
+        };
+        self.request_breaks(gap);
+        match element {
+            HtmlElement::Br => {
+                self.pending_space = false;
+                self.new_line();
+            }
+            HtmlElement::Hr => {
+                let width = self
+                    .options
+                    .width
+                    .unwrap_or(40)
+                    .saturating_sub(self.indent());
+                self.push_word(&"-".repeat(width.max(3)));
+            }
+            HtmlElement::Pre | HtmlElement::Textarea => self.children(children, true),
+            HtmlElement::Ul | HtmlElement::Ol => {
+                let mut number = attributes.get_usize("start").unwrap_or(1);
+                self.lists += 1;
+                for child in children {
+                    let is_item = child.with_node_type(|node_type| {
+                        matches!(node_type, NodeType::Element(HtmlElement::Li))
+                    });
+                    if !is_item {
+                        self.children(std::slice::from_ref(child), preformatted);
+                        continue;
+                    }
+                    let marker = match element {
+                        HtmlElement::Ol => format!("{number}. "),
+                        _ => "* ".to_string(),
+                    };
+                    number += 1;
+                    self.request_breaks(1);
+                    self.indents.push(marker.chars().count());
+                    self.marker = Some(marker);
+                    self.children(&child.children(), preformatted);
+                    self.marker = None;
+                    self.indents.pop();
+                    self.request_breaks(1);
+                }
+                self.lists -= 1;
+            }
+            HtmlElement::Blockquote => {
+                self.indents.push(2);
+                self.children(children, preformatted);
+                self.indents.pop();
+            }
+            HtmlElement::Tr => {
+                let mut first = true;
+                for cell in children {
+                    if !first {
+                        self.pending_space = false;
+                        self.push_raw("\t");
+                    }
+                    first = false;
+                    self.children(&cell.children(), preformatted);
+                }
+            }
+            HtmlElement::A => {
+                self.children(children, preformatted);
+                if self.options.link_footnotes
+                    && let Some(url) = attributes.get_url("href")
+                {
+                    let index = match self.footnotes.iter().position(|known| known == url) {
+                        Some(index) => index,
+                        None => {
+                            self.footnotes.push(url.to_string());
+                            self.footnotes.len() - 1
+                        }
+                    };
+                    let pending_space = std::mem::take(&mut self.pending_space);
+                    self.push_word(&format!("[{}]", index + 1));
+                    self.pending_space = pending_space;
+                }
+            }
+            _ => self.children(children, preformatted),
+        }
+        self.request_breaks(gap);
+    }
+
+    /// Adds text with its whitespace collapsed, wrapping if a width is set
+    fn push_text(&mut self, text: &str) {
+        for (index, word) in text.split(|c: char| c.is_ascii_whitespace()).enumerate() {
+            if index > 0 {
+                self.pending_space = true;
+            }
+            if !word.is_empty() {
+                self.push_word(word);
+            }
+        }
+    }
+
+    fn push_preformatted(&mut self, text: &str) {
+        for (index, line) in text.split('\n').enumerate() {
+            if index > 0 {
+                self.new_line();
+            }
+            if !line.is_empty() {
+                self.push_raw(line);
+            }
+        }
+    }
+
+    fn push_word(&mut self, word: &str) {
+        let length = word.chars().count();
+        if let Some(width) = self.options.width
+            && self.pending_space
+            && self.pending_breaks == 0
+            && self.column > self.indent()
+            && self.column + 1 + length > width
+        {
+            self.pending_space = false;
+            self.new_line();
+        }
+        if self.pending_space && self.pending_breaks == 0 && self.column > self.indent() {
+            self.output.push(' ');
+            self.column += 1;
+        }
+        self.pending_space = false;
+        self.push_raw(word);
+    }
+
+    /// Writes text as is, first settling any line breaks owed
+    fn push_raw(&mut self, text: &str) {
+        if self.output.is_empty() {
+            self.pending_breaks = 0;
+            self.start_line();
+        } else if self.pending_breaks > 0 {
+            for _ in 0..self.pending_breaks {
+                self.output.push('\n');
+            }
+            self.pending_breaks = 0;
+            self.start_line();
+        }
+        self.output.push_str(text);
+        self.column += text.chars().count();
+    }
+
+    fn new_line(&mut self) {
+        self.output.push('\n');
+        self.start_line();
+    }
+
+    /// Writes the indentation of a new line, with the list marker in place of
+    /// the innermost indent on an item's first line
+    fn start_line(&mut self) {
+        let mut indent = self.indent();
+        if let Some(marker) = self.marker.take() {
+            indent -= marker.chars().count();
+            self.output.push_str(&" ".repeat(indent));
+            self.output.push_str(&marker);
+            indent += marker.chars().count();
+        } else {
+            self.output.push_str(&" ".repeat(indent));
+        }
+        self.column = indent;
+    }
+
+    fn request_breaks(&mut self, count: usize) {
+        if count > 0 && !self.output.is_empty() {
+            let owed = if self.column > self.indent() || self.pending_breaks > 0 {
+                count
+            } else {
+                // Already at the start of a line
+                count - 1
+            };
+            self.pending_breaks = self.pending_breaks.max(owed);
+            self.pending_space = false;
+        }
+    }
+
+    fn indent(&self) -> usize {
+        self.indents.iter().sum()
+    }
+}
+
+/// How many line breaks separate an element from its neighbours: two for
+/// elements with a margin like paragraphs, one for other blocks and none for
+/// inline elements
+fn block_gap(element: &HtmlElement) -> usize {
+    match element {
+        HtmlElement::P
+        | HtmlElement::Pre
+        | HtmlElement::Blockquote
+        | HtmlElement::Ul
+        | HtmlElement::Ol
+        | HtmlElement::Dl
+        | HtmlElement::Table
+        | HtmlElement::Figure
+        | HtmlElement::Hr => 2,
+        _ if element.is_heading() => 2,
+        HtmlElement::Br => 0,
+        _ if element.is_inline() => 0,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::Document;
+
+    fn render(html: &str, options: &TextOptions) -> String {
+        let document = Document::parse(html);
+        document.to_text(document.root(), options)
+    }
+
+    #[test]
+    fn check_blocks_and_whitespace() {
+        let html = "<h1>Title</h1><p>a</p><p>b\n   c<br>d</p><div>one</div><div>two <span>three</span></div><pre>  x\n    y</pre><script>no</script>";
+        assert_eq!(
+            render(html, &TextOptions::default()),
+            "Title\n\na\n\nb c\nd\n\none\ntwo three\n\n  x\n    y"
+        );
+    }
+
+    #[test]
+    fn check_lists_and_tables() {
+        let html = "<ul><li>one</li><li>two<ol start=\"3\"><li>three</li></ol></li></ul><table><tr><th>a</th><th>b</th></tr><tr><td>1</td><td>2</td></tr></table>";
+        assert_eq!(
+            render(html, &TextOptions::default()),
+            "* one\n* two\n  3. three\n\na\tb\n1\t2"
+        );
+    }
+
+    #[test]
+    fn check_wrapping_and_footnotes() {
+        let html = "<p>The quick brown fox jumps over the <a href=\"/dog\">lazy dog</a>.</p><ul><li>a long item that wraps</li></ul>";
+        let options = TextOptions {
+            width: Some(16),
+            link_footnotes: true,
+        };
+        assert_eq!(
+            render(html, &options),
+            "The quick brown\nfox jumps over\nthe lazy dog[1].\n\n* a long item\n  that wraps\n\n[1] /dog"
+        );
+    }
+}
@@ -1,6 +1,16 @@
-use std::{collections::HashMap, str::Chars};
+use std::{
+    collections::VecDeque,
+    io::{self, Read},
+};
 
-use crate::html_elements::HtmlElement;
+use crate::{
+    attributes::Attributes,
+    diagnostics::{Diagnostic, ErrorCode},
+    doctype::Doctype,
+    entities::{decode_attribute, decode_text},
+    html_elements::HtmlElement,
+    span::{AttributeSpan, AttributeSpans, Position, Span},
+};
 
 pub type TokenStream = Vec<Token>;
 
@@ -10,16 +20,28 @@ pub enum TokenType {
     ClosingTag,
     VoidTag,
     Comment,
+    Doctype,
+    /// A `<![CDATA[...]]>` section, only read as one inside SVG and MathML
+    CData,
     Text,
+    RawText,
     Unknown,
 }
 
-#[derive(PartialEq, Debug)]
+/// The states between the name of a tag and its closing `>`, following the
+/// tag and attribute states of the specification
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum AttrState {
-    CapturingKey,
-    CapturingValue,
-    CapturingWrapper,
-    WhiteSpace,
+    TagName,
+    BeforeAttributeName,
+    AttributeName,
+    AfterAttributeName,
+    BeforeAttributeValue,
+    AttributeValueDoubleQuoted,
+    AttributeValueSingleQuoted,
+    AttributeValueUnquoted,
+    AfterAttributeValueQuoted,
+    SelfClosingStartTag,
 }
 
 #[derive(PartialEq, Debug)]
@@ -30,19 +52,71 @@ pub enum ParsingState {
     CapturingRawText,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Token {
     token_type: TokenType,
     token_value: String,
     token_element: Option<HtmlElement>,
-    properties: HashMap<String, String>,
+    properties: Attributes,
+    self_closing: bool,
+    span: Span,
+    attribute_spans: AttributeSpans,
+    doctype: Option<Doctype>,
 }
 
 #[derive(Debug)]
 pub struct AppState {
     parsing_state: ParsingState,
     current_token: Token,
-    token_stream: TokenStream,
+    token_stream: VecDeque<Token>,
+    raw_text_element: Option<HtmlElement>,
+    // Where the character being processed starts and where the next one will
+    char_start: Position,
+    position: Position,
+    diagnostics: Vec<Diagnostic>,
+    tag_state: AttrState,
+    tag_name: String,
+    attribute: Option<PendingAttribute>,
+    // Whether tree construction is inside SVG or MathML, the only place
+    // `<![CDATA[` starts a CDATA section rather than a bogus comment
+    in_foreign_content: bool,
+}
+
+/// The attribute currently being captured, before its value is decoded
+#[derive(Debug)]
+struct PendingAttribute {
+    name: String,
+    value: String,
+    name_span: Span,
+    value_span: Option<Span>,
+    duplicate: bool,
+}
+
+/// An incremental tokeniser which can be fed input a piece at a time. Input
+/// is only tokenised as the [`Iterator`] implementation asks for the next
+/// token, so tree construction can change how the rest is read; call
+/// [`Tokeniser::finish`] once all input has been fed to flush whatever was
+/// still being captured.
+#[derive(Debug, Default)]
+pub struct Tokeniser {
+    state: AppState,
+    // Input fed but not yet tokenised, from `input_offset` on
+    input: String,
+    input_offset: usize,
+    // Bytes of a UTF-8 sequence split across calls to `feed_bytes`
+    partial_bytes: Vec<u8>,
+    finished: bool,
+    flushed: bool,
+}
+
+/// Tokenises everything read from an [`io::Read`], pulling more input only
+/// when the tokens read so far have been consumed
+#[derive(Debug)]
+pub struct TokenReader<R> {
+    tokeniser: Tokeniser,
+    reader: R,
+    buffer: Vec<u8>,
+    error: Option<io::Error>,
 }
 
 impl AppState {
@@ -53,21 +127,564 @@ impl AppState {
                 token_type: TokenType::Unknown,
                 token_value: String::new(),
                 token_element: None,
-                properties: HashMap::new(),
+                properties: Attributes::new(),
+                self_closing: false,
+                span: Span::default(),
+                attribute_spans: AttributeSpans::default(),
+                doctype: None,
             },
-            token_stream: vec![],
+            token_stream: VecDeque::new(),
+            raw_text_element: None,
+            char_start: Position::default(),
+            position: Position::default(),
+            diagnostics: vec![],
+            tag_state: AttrState::TagName,
+            tag_name: String::new(),
+            attribute: None,
+            in_foreign_content: false,
         }
     }
 
-    fn capturing_tag_transition(&mut self, chars: &mut std::iter::Peekable<Chars>) {
-        if chars.peek() == Some(&'!') {
-            self.current_token.token_type = TokenType::Comment;
-        } else if chars.peek() == Some(&'/') {
-            self.current_token.token_type = TokenType::ClosingTag;
-        } else {
-            self.current_token.token_type = TokenType::OpeningTag;
+    fn process_char(&mut self, char: char) {
+        self.char_start = self.position;
+        self.position.advance(char);
+        if char == '\0' && self.parsing_state != ParsingState::CapturingTag {
+            self.report(ErrorCode::UnexpectedNullCharacter, None);
+        }
+        self.consume(char);
+    }
+
+    fn consume(&mut self, char: char) {
+        match self.parsing_state {
+            ParsingState::DeterminingTokenType => {
+                self.current_token.span.start = self.char_start;
+                if char == '<' {
+                    self.capturing_tag_transition();
+                } else {
+                    self.current_token.token_type = TokenType::Text;
+                    self.parsing_state = ParsingState::CapturingText;
+                }
+                self.current_token.token_value.push(char);
+            }
+            ParsingState::CapturingTag => self.capture_tag_char(char),
+            ParsingState::CapturingText => {
+                if char == '<' {
+                    self.current_token.span.end = self.char_start;
+                    self.push_text_token();
+                    self.current_token.token_value = String::from("<");
+                    self.current_token.span.start = self.char_start;
+                    self.capturing_tag_transition();
+                } else {
+                    self.current_token.token_value.push(char);
+                }
+            }
+            ParsingState::CapturingRawText => self.capture_raw_text_char(char),
+        }
+    }
+
+    fn finish(&mut self) {
+        if self.current_token.token_value.is_empty() {
+            return;
         }
+        self.current_token.span.end = self.position;
+        let token_type = self.current_token.token_type.clone();
+        match token_type {
+            TokenType::Comment => {
+                self.complete_markup_declaration(false);
+                self.token_stream.push_back(self.current_token.clone());
+            }
+            // A `<` or `</` with nothing after it is kept as text
+            TokenType::Unknown | TokenType::ClosingTag
+                if self.current_token.token_value.len() <= 2 =>
+            {
+                self.diagnostics.push(Diagnostic::new(
+                    ErrorCode::EofBeforeTagName,
+                    self.current_token.span,
+                    None,
+                ));
+                self.push_text_token();
+            }
+            // An unfinished tag is dropped
+            TokenType::OpeningTag | TokenType::ClosingTag => {
+                self.diagnostics.push(Diagnostic::new(
+                    ErrorCode::EofInTag,
+                    self.current_token.span,
+                    None,
+                ));
+            }
+            TokenType::Text => self.push_text_token(),
+            _ => self.token_stream.push_back(self.current_token.clone()),
+        }
+        self.current_token.token_value.clear();
+    }
+
+    /// The kind of tag is only known once the character after the `<` arrives
+    fn capturing_tag_transition(&mut self) {
+        self.current_token.token_type = TokenType::Unknown;
         self.parsing_state = ParsingState::CapturingTag;
+        self.tag_state = AttrState::TagName;
+    }
+
+    fn capture_tag_char(&mut self, char: char) {
+        if self.current_token.token_type == TokenType::Unknown {
+            let span = Span {
+                start: self.current_token.span.start,
+                end: self.position,
+            };
+            match char {
+                // Processing instructions are not part of HTML, so `<?` starts a bogus comment
+                '!' | '?' => {
+                    if char == '?' {
+                        self.diagnostics.push(Diagnostic::new(
+                            ErrorCode::UnexpectedQuestionMarkInsteadOfTagName,
+                            span,
+                            None,
+                        ));
+                    }
+                    self.current_token.token_type = TokenType::Comment;
+                }
+                '/' => {
+                    self.current_token.token_type = TokenType::ClosingTag;
+                    self.current_token.token_value.push(char);
+                    return;
+                }
+                _ if char.is_ascii_alphabetic() => {
+                    self.current_token.token_type = TokenType::OpeningTag;
+                }
+                // Anything else means the `<` was just text
+                _ => {
+                    self.diagnostics.push(Diagnostic::new(
+                        ErrorCode::InvalidFirstCharacterOfTagName,
+                        span,
+                        None,
+                    ));
+                    self.current_token.token_type = TokenType::Text;
+                    self.parsing_state = ParsingState::CapturingText;
+                    self.consume(char);
+                    return;
+                }
+            }
+        }
+        self.current_token.token_value.push(char);
+        let emit = if self.current_token.token_type == TokenType::Comment {
+            if char != '>' || !self.markup_declaration_ended() {
+                return;
+            }
+            self.current_token.span.end = self.position;
+            self.complete_markup_declaration(true);
+            true
+        } else {
+            if !self.capture_tag_state(char) {
+                return;
+            }
+            self.current_token.span.end = self.position;
+            self.complete_tag()
+        };
+        if emit {
+            self.token_stream.push_back(self.current_token.clone());
+        }
+        self.parsing_state = ParsingState::DeterminingTokenType;
+
+        if emit
+            && self.current_token.token_type == TokenType::OpeningTag
+            && let Some(element) = &self.current_token.token_element
+            && (element.is_raw_text_element() || element.is_escapable_raw_text_element())
+        {
+            self.raw_text_element = Some(element.clone());
+            self.parsing_state = ParsingState::CapturingRawText;
+        }
+
+        self.current_token.token_value.clear();
+        self.current_token.properties = Attributes::new();
+        self.current_token.attribute_spans.clear();
+        self.current_token.doctype = None;
+        self.current_token.span.start = self.position;
+        self.current_token.token_element = None;
+        self.current_token.self_closing = false;
+        self.current_token.token_type = match &self.raw_text_element {
+            Some(element) if element.is_raw_text_element() => TokenType::RawText,
+            Some(_) => TokenType::Text,
+            None => TokenType::Unknown,
+        };
+        self.tag_name.clear();
+    }
+
+    /// Moves through the tag and attribute states, returning whether `char`
+    /// was the `>` which ends the tag
+    fn capture_tag_state(&mut self, char: char) -> bool {
+        // Some states hand the character on to the next state to be consumed again
+        loop {
+            match self.tag_state {
+                AttrState::TagName
+                    if self.tag_name.is_empty()
+                        && self.current_token.token_type == TokenType::ClosingTag =>
+                {
+                    if char == '>' {
+                        return true;
+                    } else if char.is_ascii_alphabetic() {
+                        self.tag_name.push(char.to_ascii_lowercase());
+                    } else {
+                        // `</` followed by anything else starts a bogus comment
+                        self.report(ErrorCode::InvalidFirstCharacterOfTagName, None);
+                        self.current_token.token_type = TokenType::Comment;
+                    }
+                }
+                AttrState::TagName => match char {
+                    _ if char.is_ascii_whitespace() => {
+                        self.tag_state = AttrState::BeforeAttributeName
+                    }
+                    '/' => self.tag_state = AttrState::SelfClosingStartTag,
+                    '>' => return true,
+                    _ => self.tag_name.push(char.to_ascii_lowercase()),
+                },
+                AttrState::BeforeAttributeName => match char {
+                    _ if char.is_ascii_whitespace() => {}
+                    '/' | '>' => {
+                        self.tag_state = AttrState::AfterAttributeName;
+                        continue;
+                    }
+                    _ => {
+                        if char == '=' {
+                            self.report(ErrorCode::UnexpectedEqualsSignBeforeAttributeName, None);
+                        }
+                        self.start_attribute();
+                        self.tag_state = AttrState::AttributeName;
+                        if char != '=' {
+                            continue;
+                        }
+                        self.push_to_attribute_name(char);
+                    }
+                },
+                AttrState::AttributeName => match char {
+                    _ if char.is_ascii_whitespace() || char == '/' || char == '>' => {
+                        self.end_attribute_name();
+                        self.tag_state = AttrState::AfterAttributeName;
+                        continue;
+                    }
+                    '=' => {
+                        self.end_attribute_name();
+                        self.tag_state = AttrState::BeforeAttributeValue;
+                    }
+                    _ => {
+                        if matches!(char, '"' | '\'' | '<') {
+                            self.report(ErrorCode::UnexpectedCharacterInAttributeName, None);
+                        }
+                        self.push_to_attribute_name(char);
+                    }
+                },
+                AttrState::AfterAttributeName => match char {
+                    _ if char.is_ascii_whitespace() => {}
+                    '/' => self.tag_state = AttrState::SelfClosingStartTag,
+                    '=' => self.tag_state = AttrState::BeforeAttributeValue,
+                    '>' => return true,
+                    _ => {
+                        self.start_attribute();
+                        self.tag_state = AttrState::AttributeName;
+                        continue;
+                    }
+                },
+                AttrState::BeforeAttributeValue => match char {
+                    _ if char.is_ascii_whitespace() => {}
+                    '"' | '\'' => {
+                        self.start_attribute_value(self.position);
+                        self.tag_state = if char == '"' {
+                            AttrState::AttributeValueDoubleQuoted
+                        } else {
+                            AttrState::AttributeValueSingleQuoted
+                        };
+                    }
+                    '>' => {
+                        self.report(ErrorCode::MissingAttributeValue, None);
+                        return true;
+                    }
+                    _ => {
+                        self.start_attribute_value(self.char_start);
+                        self.tag_state = AttrState::AttributeValueUnquoted;
+                        continue;
+                    }
+                },
+                AttrState::AttributeValueDoubleQuoted | AttrState::AttributeValueSingleQuoted => {
+                    let quote = if self.tag_state == AttrState::AttributeValueDoubleQuoted {
+                        '"'
+                    } else {
+                        '\''
+                    };
+                    if char == quote {
+                        self.end_attribute_value();
+                        self.tag_state = AttrState::AfterAttributeValueQuoted;
+                    } else {
+                        self.push_to_attribute_value(char);
+                    }
+                }
+                AttrState::AttributeValueUnquoted => match char {
+                    _ if char.is_ascii_whitespace() => {
+                        self.end_attribute_value();
+                        self.tag_state = AttrState::BeforeAttributeName;
+                    }
+                    '>' => {
+                        self.end_attribute_value();
+                        return true;
+                    }
+                    _ => {
+                        if matches!(char, '"' | '\'' | '<' | '=' | '`') {
+                            self.report(
+                                ErrorCode::UnexpectedCharacterInUnquotedAttributeValue,
+                                None,
+                            );
+                        }
+                        self.push_to_attribute_value(char);
+                    }
+                },
+                AttrState::AfterAttributeValueQuoted => match char {
+                    _ if char.is_ascii_whitespace() => {
+                        self.tag_state = AttrState::BeforeAttributeName
+                    }
+                    '/' => self.tag_state = AttrState::SelfClosingStartTag,
+                    '>' => return true,
+                    _ => {
+                        self.report(ErrorCode::MissingWhitespaceBetweenAttributes, None);
+                        self.tag_state = AttrState::BeforeAttributeName;
+                        continue;
+                    }
+                },
+                AttrState::SelfClosingStartTag => {
+                    if char == '>' {
+                        self.current_token.self_closing = true;
+                        return true;
+                    }
+                    self.report(ErrorCode::UnexpectedSolidusInTag, None);
+                    self.tag_state = AttrState::BeforeAttributeName;
+                    continue;
+                }
+            }
+            return false;
+        }
+    }
+
+    fn start_attribute(&mut self) {
+        self.finish_attribute();
+        self.attribute = Some(PendingAttribute {
+            name: String::new(),
+            value: String::new(),
+            name_span: Span {
+                start: self.char_start,
+                end: self.char_start,
+            },
+            value_span: None,
+            duplicate: false,
+        });
+    }
+
+    fn push_to_attribute_name(&mut self, char: char) {
+        if let Some(attribute) = &mut self.attribute {
+            attribute.name.push(char.to_ascii_lowercase());
+        }
+    }
+
+    /// Duplicates are found as soon as the name ends, though the attribute
+    /// is only dropped once its value has been captured
+    fn end_attribute_name(&mut self) {
+        if let Some(attribute) = &mut self.attribute {
+            attribute.name_span.end = self.char_start;
+            attribute.duplicate = self.current_token.attribute_spans.contains(&attribute.name);
+            if attribute.duplicate {
+                self.diagnostics.push(Diagnostic::new(
+                    ErrorCode::DuplicateAttribute,
+                    attribute.name_span,
+                    Some(&attribute.name),
+                ));
+            }
+        }
+    }
+
+    fn start_attribute_value(&mut self, start: Position) {
+        if let Some(attribute) = &mut self.attribute {
+            attribute.value_span = Some(Span { start, end: start });
+        }
+    }
+
+    fn push_to_attribute_value(&mut self, char: char) {
+        if let Some(attribute) = &mut self.attribute {
+            attribute.value.push(char);
+        }
+    }
+
+    fn end_attribute_value(&mut self) {
+        if let Some(Some(span)) = self.attribute.as_mut().map(|a| a.value_span.as_mut()) {
+            span.end = self.char_start;
+        }
+    }
+
+    /// Adds the attribute just captured to the token, unless an earlier
+    /// attribute had the same name
+    fn finish_attribute(&mut self) {
+        let Some(attribute) = self.attribute.take() else {
+            return;
+        };
+        if attribute.duplicate {
+            return;
+        }
+        let token = &mut self.current_token;
+        let span = AttributeSpan {
+            name: attribute.name_span,
+            value: attribute.value_span,
+        };
+        if token.token_type == TokenType::ClosingTag {
+            self.diagnostics.push(Diagnostic::new(
+                ErrorCode::EndTagWithAttributes,
+                span.name,
+                Some(&attribute.name),
+            ));
+        }
+        token.attribute_spans.push(&attribute.name, span);
+        token
+            .properties
+            .insert(&attribute.name, &decode_attribute(&attribute.value));
+    }
+
+    /// Sets the element of a tag which has just been closed, returning
+    /// whether it should be emitted at all
+    fn complete_tag(&mut self) -> bool {
+        self.finish_attribute();
+        let token = &mut self.current_token;
+        if token.token_type == TokenType::ClosingTag {
+            if self.tag_name.is_empty() {
+                self.diagnostics.push(Diagnostic::new(
+                    ErrorCode::MissingEndTagName,
+                    token.span,
+                    None,
+                ));
+                return false;
+            }
+            if token.self_closing {
+                token.self_closing = false;
+                self.diagnostics.push(Diagnostic::new(
+                    ErrorCode::EndTagWithTrailingSolidus,
+                    token.span,
+                    None,
+                ));
+            }
+        }
+        let element = HtmlElement::from_tag_name(&self.tag_name);
+        if token.token_type == TokenType::OpeningTag && element.is_void_element() {
+            token.token_type = TokenType::VoidTag;
+        }
+        token.token_element = Some(element);
+        true
+    }
+
+    fn capture_raw_text_char(&mut self, char: char) {
+        let Some(element) = self.raw_text_element.clone() else {
+            return;
+        };
+        // Plaintext can never be closed, everything up to the end of the input is text
+        if element != HtmlElement::Plaintext
+            && (char == '>' || char == '/' || char.is_ascii_whitespace())
+        {
+            let end_tag = format!("</{}", element.tag_name());
+            let value = &self.current_token.token_value;
+            let text_length = value.len().saturating_sub(end_tag.len());
+            if value.is_char_boundary(text_length)
+                && value[text_length..].eq_ignore_ascii_case(&end_tag)
+            {
+                self.current_token.token_value.truncate(text_length);
+                // End tag names are ASCII and cannot contain a newline
+                let end_tag_start = Position {
+                    offset: self.char_start.offset - end_tag.len(),
+                    line: self.char_start.line,
+                    column: self.char_start.column - end_tag.len(),
+                };
+                self.current_token.span.end = end_tag_start;
+                if !self.current_token.token_value.is_empty() {
+                    if self.current_token.token_type == TokenType::Text {
+                        self.push_text_token();
+                    } else {
+                        self.token_stream.push_back(self.current_token.clone());
+                    }
+                }
+                self.raw_text_element = None;
+                self.current_token.token_value = end_tag;
+                self.current_token.span.start = end_tag_start;
+                self.current_token.token_type = TokenType::ClosingTag;
+                self.parsing_state = ParsingState::CapturingTag;
+                self.tag_state = AttrState::TagName;
+                self.tag_name = element.tag_name().to_string();
+                self.capture_tag_char(char);
+                return;
+            }
+        }
+        self.current_token.token_value.push(char);
+    }
+
+    /// Whether a `>` ends the comment, CDATA section or doctype captured so
+    /// far. Comments and CDATA sections in foreign content run to their own
+    /// terminators, so a `>` in their text does not end them.
+    fn markup_declaration_ended(&self) -> bool {
+        let value = &self.current_token.token_value;
+        if value.starts_with("<!--") {
+            value.ends_with("-->") || value.ends_with("--!>")
+        } else if value.starts_with("<![CDATA[") && self.in_foreign_content {
+            value.ends_with("]]>")
+        } else {
+            true
+        }
+    }
+
+    /// Works out whether a token starting `<!` or `<?` is a comment, CDATA
+    /// section, doctype or bogus comment once it has ended
+    fn complete_markup_declaration(&mut self, closed: bool) {
+        let token = &mut self.current_token;
+        let value = &token.token_value;
+        let mut errors = vec![];
+        if value.starts_with("<!--") {
+            if !closed {
+                errors.push(ErrorCode::EofInComment);
+            } else if value == "<!-->" || value == "<!--->" {
+                errors.push(ErrorCode::AbruptClosingOfEmptyComment);
+            } else if value.ends_with("--!>") {
+                errors.push(ErrorCode::IncorrectlyClosedComment);
+            }
+        } else if value.starts_with("<![CDATA[") && self.in_foreign_content {
+            token.token_type = TokenType::CData;
+            if !closed {
+                errors.push(ErrorCode::EofInCdata);
+            }
+        } else if value.starts_with("<![CDATA[") {
+            errors.push(ErrorCode::CdataInHtmlContent);
+        } else if value
+            .get(..9)
+            .is_some_and(|start| start.eq_ignore_ascii_case("<!doctype"))
+        {
+            token.token_type = TokenType::Doctype;
+            token.doctype = Some(Doctype::parse(value));
+            if !closed {
+                errors.push(ErrorCode::EofInDoctype);
+            }
+        } else if value.starts_with("<!") {
+            errors.push(ErrorCode::IncorrectlyOpenedComment);
+        }
+        if token.token_type == TokenType::Comment {
+            token.token_value = comment_data(&token.token_value, closed).to_string();
+        }
+        for code in errors {
+            self.diagnostics
+                .push(Diagnostic::new(code, self.current_token.span, None));
+        }
+    }
+
+    /// Records a problem with the character just processed
+    fn report(&mut self, code: ErrorCode, name: Option<&str>) {
+        let span = Span {
+            start: self.char_start,
+            end: self.position,
+        };
+        self.diagnostics.push(Diagnostic::new(code, span, name));
+    }
+
+    fn push_text_token(&mut self) {
+        let mut token = self.current_token.clone();
+        token.token_value = decode_text(&token.token_value);
+        self.token_stream.push_back(token);
     }
 }
 
@@ -90,209 +707,278 @@ impl Token {
         self.token_value.clone()
     }
 
-    pub fn get_properties(&self) -> HashMap<String, String> {
-        self.properties.clone()
+    /// The attributes in the order they were written, without any later
+    /// duplicates
+    pub fn get_properties(&self) -> &Attributes {
+        &self.properties
     }
 
-    fn set_tag_and_properties(&mut self) {
-        let mut tag_content = self.token_value[1..&self.token_value.len() - 1].trim();
-        if tag_content.ends_with('/') {
-            tag_content = &tag_content[..tag_content.len() - 1];
-        }
+    pub fn is_self_closing(&self) -> bool {
+        self.self_closing
+    }
+
+    /// The name and identifiers of a doctype token
+    pub fn get_doctype(&self) -> Option<Doctype> {
+        self.doctype.clone()
+    }
+
+    /// Where the token sits in the source
+    pub fn get_span(&self) -> Span {
+        self.span
+    }
 
-        if tag_content.starts_with('/') {
-            tag_content = &tag_content[1..];
+    /// Where each attribute's name and value sit in the source, in the same
+    /// order as [`get_properties`](Self::get_properties)
+    pub fn get_attribute_spans(&self) -> &AttributeSpans {
+        &self.attribute_spans
+    }
+}
+
+/// The data of a comment captured from `<!--`, `<!`, `</` or `<?`. Bogus
+/// comments hold everything after `<!` or `</`, or from the `?` of `<?`, up
+/// to the `>`. A comment cut off by the end of the input leaves out any
+/// dashes that had started to close it.
+fn comment_data(value: &str, closed: bool) -> &str {
+    if let Some(rest) = value.strip_prefix("<!--") {
+        if !closed {
+            return rest
+                .strip_suffix("--!")
+                .or_else(|| rest.strip_suffix("--"))
+                .or_else(|| rest.strip_suffix('-'))
+                .unwrap_or(rest);
         }
-        match tag_content.find(' ') {
-            Some(index) => {
-                let tag = &tag_content[0..index];
-                let properties = &tag_content[index + 1..];
-                self.token_element = Some(HtmlElement::from_tag_name(tag));
-                self.properties = parse_properties(properties);
-            }
-            None => self.token_element = Some(HtmlElement::from_tag_name(tag_content)),
+        if rest == ">" || rest == "->" {
+            return "";
         }
+        return rest
+            .strip_suffix("-->")
+            .or_else(|| rest.strip_suffix("--!>"))
+            .unwrap_or(rest);
+    }
+    let rest = value
+        .strip_prefix("<!")
+        .or_else(|| value.strip_prefix("</"))
+        .or_else(|| value.strip_prefix('<'))
+        .unwrap_or(value);
+    match closed {
+        true => rest.strip_suffix('>').unwrap_or(rest),
+        false => rest,
+    }
+}
 
-        if self.token_element.as_ref().unwrap().is_void_element() {
-            self.token_type = TokenType::VoidTag;
+impl Tokeniser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wraps a reader, decoding its bytes as UTF-8
+    pub fn from_reader<R: Read>(reader: R) -> TokenReader<R> {
+        TokenReader {
+            tokeniser: Tokeniser::new(),
+            reader,
+            buffer: vec![0; 8192],
+            error: None,
         }
     }
-}
 
-fn parse_properties(properties: &str) -> HashMap<String, String> {
-    let mut properties_map = HashMap::new();
-    let mut wrapper: Option<char> = None;
-    let mut key_buffer = String::new();
-    let mut value_buffer = String::new();
-    let mut state = AttrState::CapturingKey;
-
-    let mut chars = properties.chars().peekable();
-    while let Some(char) = chars.next() {
-        match state {
-            AttrState::CapturingKey => {
-                if char == '=' {
-                    state = AttrState::CapturingWrapper;
-                } else if char.is_whitespace() {
-                    // Continue until we find a non-whitespace character
-                    for c in chars.by_ref() {
-                        if !c.is_whitespace() {
-                            if c == '=' {
-                                state = AttrState::CapturingWrapper;
-                            } else {
-                                properties_map.insert(key_buffer.clone(), "".to_string());
-                                key_buffer.clear();
-                                value_buffer.clear();
-                                state = AttrState::WhiteSpace;
-                            }
+    /// A tokeniser holding the whole of `html`, already finished
+    pub fn with_input(html: &str) -> Self {
+        let mut tokeniser = Tokeniser::new();
+        tokeniser.feed(html);
+        tokeniser.finish();
+        tokeniser
+    }
+
+    /// Feeds the next piece of input, which may end part way through a tag,
+    /// attribute or character reference. Line breaks written as CR or CRLF
+    /// are read as LF, even when a CRLF is split between pieces.
+    pub fn feed(&mut self, chunk: &str) {
+        self.input.drain(..self.input_offset);
+        self.input_offset = 0;
+        self.input.push_str(chunk);
+    }
+
+    /// Tells the tokeniser whether tree construction is in SVG or MathML
+    /// content, where `<![CDATA[` starts a CDATA section. Anywhere else it
+    /// starts a bogus comment which ends at the first `>`. Foreign elements
+    /// such as an SVG `<title>` or `<style>` also hold markup rather than
+    /// raw text, so being told this just after one was read undoes the switch
+    /// to raw text.
+    pub fn set_in_foreign_content(&mut self, in_foreign_content: bool) {
+        let state = &mut self.state;
+        state.in_foreign_content = in_foreign_content;
+        if in_foreign_content
+            && state.parsing_state == ParsingState::CapturingRawText
+            && state.current_token.token_value.is_empty()
+        {
+            state.raw_text_element = None;
+            state.parsing_state = ParsingState::DeterminingTokenType;
+            state.current_token.token_type = TokenType::Unknown;
+        }
+    }
+
+    /// Feeds the next piece of UTF-8 input, which may end part way through a
+    /// multibyte character. Invalid sequences become U+FFFD.
+    pub fn feed_bytes(&mut self, chunk: &[u8]) {
+        self.partial_bytes.extend_from_slice(chunk);
+        let mut bytes = std::mem::take(&mut self.partial_bytes);
+        let mut start = 0;
+        loop {
+            match std::str::from_utf8(&bytes[start..]) {
+                Ok(valid) => {
+                    self.feed(valid);
+                    break;
+                }
+                Err(error) => {
+                    let valid_end = start + error.valid_up_to();
+                    // Safe to unwrap as from_utf8 has just validated this range
+                    self.feed(std::str::from_utf8(&bytes[start..valid_end]).unwrap());
+                    match error.error_len() {
+                        Some(length) => {
+                            self.feed("\u{FFFD}");
+                            start = valid_end + length;
+                        }
+                        None => {
+                            bytes.drain(..valid_end);
+                            self.partial_bytes = bytes;
                             break;
                         }
                     }
-                } else {
-                    key_buffer.push(char);
                 }
             }
-            AttrState::CapturingWrapper => {
-                if char == ' ' {
-                    continue;
-                } else if char == '\'' {
-                    wrapper = Some('\'');
-                } else if char == '"' {
-                    wrapper = Some('"');
-                } else {
-                    wrapper = None;
-                    value_buffer.push(char);
-                }
-                state = AttrState::CapturingValue
+        }
+    }
+
+    /// Marks the end of the input, so any partly captured token is flushed
+    /// once the rest of the input has been read
+    pub fn finish(&mut self) {
+        if self.finished {
+            return;
+        }
+        if !self.partial_bytes.is_empty() {
+            self.partial_bytes.clear();
+            self.feed("\u{FFFD}");
+        }
+        self.finished = true;
+    }
+
+    /// Takes the parse errors found in the input tokenised so far
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.state.diagnostics)
+    }
+
+    /// Whether [`Tokeniser::finish`] has been called
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+impl Iterator for Tokeniser {
+    type Item = Token;
+
+    /// Returns the next complete token, or `None` if more input is needed
+    fn next(&mut self) -> Option<Token> {
+        loop {
+            if let Some(token) = self.state.token_stream.pop_front() {
+                return Some(token);
             }
-            AttrState::CapturingValue => match wrapper {
-                Some(v) => {
-                    if char == v {
-                        properties_map.insert(key_buffer.clone(), value_buffer.clone());
-                        key_buffer.clear();
-                        value_buffer.clear();
-                        state = AttrState::WhiteSpace;
+            let rest = &self.input[self.input_offset..];
+            match rest.chars().next() {
+                // Only the next character tells a CR on its own from the
+                // start of a CRLF, so wait for it
+                Some('\r') if rest.len() == 1 && !self.finished => return None,
+                Some('\r') => {
+                    self.input_offset += 1;
+                    if rest[1..].starts_with('\n') {
+                        // The LF which follows is the line break
+                        self.state.position.offset += 1;
                     } else {
-                        value_buffer.push(char);
+                        self.state.process_char('\n');
                     }
                 }
-                None => {
-                    if char == ' ' {
-                        properties_map.insert(key_buffer.clone(), value_buffer.clone());
-                        key_buffer.clear();
-                        value_buffer.clear();
-                        state = AttrState::WhiteSpace;
-                    } else {
-                        value_buffer.push(char);
-                    }
+                Some(char) => {
+                    self.input_offset += char.len_utf8();
+                    self.state.process_char(char);
                 }
-            },
-            AttrState::WhiteSpace => {
-                if char != ' ' {
-                    state = AttrState::CapturingKey;
-                    key_buffer.push(char)
+                None if self.finished && !self.flushed => {
+                    self.state.finish();
+                    self.flushed = true;
                 }
+                None => return None,
             }
         }
     }
-    if !key_buffer.is_empty() {
-        if !value_buffer.is_empty() {
-            // We were capturing a value
-            properties_map.insert(key_buffer.clone(), value_buffer.clone());
-        } else {
-            // Boolean attribute
-            properties_map.insert(key_buffer.clone(), "".to_string());
-        }
+}
+
+/// Where tree construction reads its tokens from. Sources which tokenise
+/// as they go are told when tree construction enters or leaves foreign
+/// content; tokens read beforehand cannot change.
+pub(crate) trait TokenSource: Iterator<Item = Token> {
+    fn set_in_foreign_content(&mut self, _in_foreign_content: bool) {}
+}
+
+impl TokenSource for Tokeniser {
+    fn set_in_foreign_content(&mut self, in_foreign_content: bool) {
+        Tokeniser::set_in_foreign_content(self, in_foreign_content);
     }
+}
 
-    properties_map
+impl<R: Read> TokenSource for TokenReader<R> {
+    fn set_in_foreign_content(&mut self, in_foreign_content: bool) {
+        self.tokeniser.set_in_foreign_content(in_foreign_content);
+    }
 }
 
-pub fn get_tokens(html: &str) -> TokenStream {
-    let mut app_state = AppState::new();
-    let mut chars = html.chars().peekable();
+impl TokenSource for std::vec::IntoIter<Token> {}
 
-    while let Some(char) = chars.next() {
-        match app_state.parsing_state {
-            ParsingState::DeterminingTokenType => {
-                if char == '<' {
-                    app_state.capturing_tag_transition(&mut chars);
-                } else {
-                    app_state.current_token.token_type = TokenType::Text;
-                    app_state.parsing_state = ParsingState::CapturingText;
-                }
-                app_state.current_token.token_value.push(char);
+impl<T: TokenSource + ?Sized> TokenSource for &mut T {
+    fn set_in_foreign_content(&mut self, in_foreign_content: bool) {
+        (**self).set_in_foreign_content(in_foreign_content);
+    }
+}
+
+impl<R: Read> TokenReader<R> {
+    /// Returns the error which stopped reading early, if there was one
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+}
+
+impl<R: Read> Iterator for TokenReader<R> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        loop {
+            if let Some(token) = self.tokeniser.next() {
+                return Some(token);
             }
-            ParsingState::CapturingTag => {
-                if char == '>' {
-                    app_state.current_token.token_value.push(char);
-                    if app_state
-                        .current_token
-                        .token_value
-                        .to_lowercase()
-                        .starts_with("<script")
-                        || app_state
-                            .current_token
-                            .token_value
-                            .to_lowercase()
-                            .starts_with("<style")
-                    {
-                        app_state.parsing_state = ParsingState::CapturingRawText;
-                    } else {
-                        app_state.parsing_state = ParsingState::DeterminingTokenType;
-                        app_state.current_token.set_tag_and_properties();
-                        app_state.token_stream.push(app_state.current_token.clone());
-                    }
-                    app_state.current_token.token_value.clear();
-                    app_state.current_token.properties.clear();
-                    app_state.current_token.token_type = TokenType::Unknown;
-                } else {
-                    app_state.current_token.token_value.push(char);
-                }
+            if self.tokeniser.is_finished() {
+                return None;
             }
-            ParsingState::CapturingText => {
-                if char == '<' {
-                    if !app_state.current_token.token_value.trim().is_empty() {
-                        app_state.token_stream.push(app_state.current_token.clone());
-                    }
-                    app_state.current_token.token_value = String::from("<");
-                    app_state.capturing_tag_transition(&mut chars);
-                } else {
-                    app_state.current_token.token_value.push(char);
-                }
-            }
-            ParsingState::CapturingRawText => {
-                // For now we will just throw away style or script tags
-                if app_state
-                    .current_token
-                    .token_value
-                    .to_lowercase()
-                    .ends_with("</script")
-                    || app_state
-                        .current_token
-                        .token_value
-                        .to_lowercase()
-                        .ends_with("</style")
-                {
-                    app_state.parsing_state = ParsingState::DeterminingTokenType;
-                    app_state.current_token.token_value.clear();
-                    app_state.current_token.token_type = TokenType::Unknown;
-                } else {
-                    app_state.current_token.token_value.push(char);
+            match self.reader.read(&mut self.buffer) {
+                Ok(0) => self.tokeniser.finish(),
+                Ok(length) => self.tokeniser.feed_bytes(&self.buffer[..length]),
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(error) => {
+                    self.error = Some(error);
+                    self.tokeniser.finish();
                 }
             }
         }
     }
-    if !app_state.current_token.token_value.is_empty() {
-        app_state.token_stream.push(app_state.current_token.clone());
-    }
-    app_state.token_stream
+}
+
+/// Tokenises the whole of `html`. Tokens are read without tree construction,
+/// so `<![CDATA[` is always taken to be in HTML content.
+pub fn get_tokens(html: &str) -> TokenStream {
+    Tokeniser::with_input(html).collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    const BASIC_HTML_DOCUMENT: &'static str = r##"<!DOCTYPE HTML>
+    const BASIC_HTML_DOCUMENT: &str = r##"<!DOCTYPE HTML>
     <html>
     <head>
     <title>A</title>
@@ -323,65 +1009,349 @@ mod tests {
     fn check_first_token() {
         let response = get_tokens(BASIC_HTML_DOCUMENT);
         assert_eq!(response[0].token_value, *"<!DOCTYPE HTML>");
-        assert_eq!(response[0].token_type, TokenType::Comment);
+        assert_eq!(response[0].token_type, TokenType::Doctype);
+        assert_eq!(
+            response[0].get_doctype().unwrap().name.as_deref(),
+            Some("html")
+        );
     }
 
     #[test]
     fn check_second_token() {
         let response = get_tokens(BASIC_HTML_DOCUMENT);
-        assert_eq!(response[1].token_value, *"<html>");
-        assert_eq!(response[1].token_type, TokenType::OpeningTag);
-        assert_eq!(response[1].token_element, Some(HtmlElement::Html));
+        assert_eq!(response[2].token_value, *"<html>");
+        assert_eq!(response[2].token_type, TokenType::OpeningTag);
+        assert_eq!(response[2].token_element, Some(HtmlElement::Html));
     }
 
     #[test]
     fn check_fourth_token() {
         let response = get_tokens(BASIC_HTML_DOCUMENT);
-        assert_eq!(response[3].token_value, *"<title>");
-        assert_eq!(response[3].token_type, TokenType::OpeningTag);
+        assert_eq!(response[6].token_value, *"<title>");
+        assert_eq!(response[6].token_type, TokenType::OpeningTag);
     }
 
     #[test]
     fn check_single_char_text() {
         let response = get_tokens(BASIC_HTML_DOCUMENT);
-        assert_eq!(response[4].token_value, *"A");
-        assert_eq!(response[4].token_type, TokenType::Text);
+        assert_eq!(response[7].token_value, *"A");
+        assert_eq!(response[7].token_type, TokenType::Text);
     }
 
     #[test]
     fn check_self_closing_and_properties() {
         let response = get_tokens(BASIC_HTML_DOCUMENT);
-        assert_eq!(response[8].token_element, Some(HtmlElement::Img));
-        assert_eq!(response[8].token_type, TokenType::VoidTag);
+        assert_eq!(response[22].token_element, Some(HtmlElement::Img));
+        assert_eq!(response[22].token_type, TokenType::VoidTag);
+        assert_eq!(response[22].get_properties().get("src"), Some("random.jpg"));
+        assert_eq!(response[22].get_properties().get("height"), Some("400"));
+
+        assert_eq!(response[22].get_properties().get("width"), Some("300"));
+    }
+
+    #[test]
+    fn check_bool_property() {
+        let response = get_tokens(BASIC_HTML_DOCUMENT);
+        assert!(response[28].get_properties()["required"].is_empty());
+    }
+
+    #[test]
+    fn check_script_and_style_are_kept() {
+        let response = get_tokens(BASIC_HTML_DOCUMENT);
+        assert_eq!(response[10].token_element, Some(HtmlElement::Script));
         assert_eq!(
-            response[8].properties.get("src").unwrap(),
-            &"random.jpg".to_string()
+            response[10].get_properties().get("type"),
+            Some("text/javascript")
         );
+        assert_eq!(response[11].token_type, TokenType::RawText);
         assert_eq!(
-            response[8].properties.get("height").unwrap(),
-            &"400".to_string()
+            response[11].token_value.trim(),
+            r#"console.log("Hello World!");"#
         );
+        assert_eq!(response[12].token_type, TokenType::ClosingTag);
+        assert_eq!(response[12].token_element, Some(HtmlElement::Script));
+        assert_eq!(response[14].token_element, Some(HtmlElement::Style));
+        assert!(
+            response[15]
+                .token_value
+                .contains("background-color: #f0f0f0;")
+        );
+    }
 
+    #[test]
+    fn check_raw_text_is_not_parsed() {
+        let response = get_tokens(
+            r#"<script type="application/ld+json">{"a": "</div> &amp; <b>"}</SCRIPT ><p>after</p>"#,
+        );
+        assert_eq!(response[1].token_type, TokenType::RawText);
+        assert_eq!(response[1].token_value, r#"{"a": "</div> &amp; <b>"}"#);
+        assert_eq!(response[2].token_type, TokenType::ClosingTag);
+        assert_eq!(response[3].token_element, Some(HtmlElement::P));
+    }
+
+    #[test]
+    fn check_rcdata_decodes_references_only() {
+        let response =
+            get_tokens("<textarea><b>bold</b> &amp; more</textarea><title>A &lt; B</title>");
+        assert_eq!(response[1].token_type, TokenType::Text);
+        assert_eq!(response[1].token_value, "<b>bold</b> & more");
+        assert_eq!(response[2].token_element, Some(HtmlElement::Textarea));
+        assert_eq!(response[4].token_value, "A < B");
+    }
+
+    #[test]
+    fn check_plaintext_runs_to_end() {
+        let response = get_tokens("<plaintext><p>not a tag</plaintext>");
+        assert_eq!(response.len(), 2);
+        assert_eq!(response[1].token_type, TokenType::RawText);
+        assert_eq!(response[1].token_value, "<p>not a tag</plaintext>");
+    }
+
+    #[test]
+    fn check_character_references() {
+        let response = get_tokens(
+            r#"<a title="Tom &amp; Jerry" href="?a=1&copy=2">caf&eacute; &#x27;&lt;b&gt;&#39;</a>"#,
+        );
         assert_eq!(
-            response[8].properties.get("width").unwrap(),
-            &"300".to_string()
+            response[0].get_properties().get("title"),
+            Some("Tom & Jerry")
         );
+        assert_eq!(
+            response[0].get_properties().get("href"),
+            Some("?a=1&copy=2")
+        );
+        assert_eq!(response[1].token_type, TokenType::Text);
+        assert_eq!(response[1].token_value, "café '<b>'");
     }
 
     #[test]
-    fn check_bool_property() {
-        let response = get_tokens(BASIC_HTML_DOCUMENT);
-        assert!(response[12].properties.get("required").unwrap().is_empty());
+    fn check_chunked_input_matches_whole_input() {
+        let expected = get_tokens(BASIC_HTML_DOCUMENT);
+        for chunk_size in [1, 2, 3, 7, 64] {
+            let mut tokeniser = Tokeniser::new();
+            let mut tokens = vec![];
+            for chunk in BASIC_HTML_DOCUMENT.as_bytes().chunks(chunk_size) {
+                tokeniser.feed_bytes(chunk);
+                tokens.extend(tokeniser.by_ref());
+            }
+            tokeniser.finish();
+            tokens.extend(tokeniser);
+            assert_eq!(tokens, expected, "chunk size {chunk_size}");
+        }
+    }
+
+    #[test]
+    fn check_multibyte_characters_split_across_chunks() {
+        let html = "<p title=\"caf\u{e9}\">\u{1F600} &eacute;</p>";
+        let mut tokeniser = Tokeniser::new();
+        for byte in html.as_bytes() {
+            tokeniser.feed_bytes(std::slice::from_ref(byte));
+        }
+        tokeniser.finish();
+        let tokens: Vec<Token> = tokeniser.collect();
+
+        assert_eq!(tokens, get_tokens(html));
+        assert_eq!(tokens[1].token_value, "\u{1F600} \u{e9}");
+    }
+
+    #[test]
+    fn check_invalid_utf8_is_replaced() {
+        let mut tokeniser = Tokeniser::new();
+        tokeniser.feed_bytes(b"<p>a\xFFb\xE2\x82");
+        tokeniser.finish();
+        let tokens: Vec<Token> = tokeniser.collect();
+
+        assert_eq!(tokens[1].token_value, "a\u{FFFD}b\u{FFFD}");
+    }
+
+    #[test]
+    fn check_tokens_are_yielded_as_they_complete() {
+        let mut tokeniser = Tokeniser::new();
+        tokeniser.feed("<div cla");
+        assert!(tokeniser.next().is_none());
+        tokeniser.feed("ss=\"a\">te");
+        assert_eq!(
+            tokeniser
+                .next()
+                .unwrap()
+                .get_properties()
+                .get("class")
+                .unwrap(),
+            "a"
+        );
+        assert!(tokeniser.next().is_none());
+        tokeniser.feed("xt<");
+        assert_eq!(tokeniser.next().unwrap().get_text(), "text");
+    }
+
+    #[test]
+    fn check_carriage_returns_become_line_feeds() {
+        let mut tokeniser = Tokeniser::new();
+        tokeniser.feed("<p title='a\r\nb'>one\r");
+        let tag = tokeniser.next().unwrap();
+        assert_eq!(tag.get_properties().get("title"), Some("a\nb"));
+        assert!(tokeniser.next().is_none());
+        tokeniser.feed("\ntwo\rthree\r");
+        tokeniser.finish();
+        let text = tokeniser.next().unwrap();
+        assert_eq!(text.get_text(), "one\ntwo\nthree\n");
+
+        // Spans still point into the source as written
+        let html = "<p title='a\r\nb'>one\r\ntwo\rthree\r";
+        let span = text.get_span();
+        assert_eq!(span.slice(html), Some("one\r\ntwo\rthree\r"));
+        assert_eq!((span.end.line, span.end.column), (5, 1));
+        assert_eq!(get_tokens(html), [tag, text]);
+    }
+
+    #[test]
+    fn check_reader() {
+        let reader = io::Cursor::new(BASIC_HTML_DOCUMENT.as_bytes().to_vec());
+        let mut tokens = Tokeniser::from_reader(reader);
+        let collected: Vec<Token> = tokens.by_ref().collect();
+
+        assert_eq!(collected, get_tokens(BASIC_HTML_DOCUMENT));
+        assert!(tokens.take_error().is_none());
+    }
+
+    #[test]
+    fn check_token_spans() {
+        let html = "<p>caf\u{e9}\n<a href='/x' hidden>link</a><script>a</script>";
+        let tokens = get_tokens(html);
+        let slices: Vec<&str> = tokens
+            .iter()
+            .map(|token| token.get_span().slice(html).unwrap())
+            .collect();
+        assert_eq!(
+            slices,
+            vec![
+                "<p>",
+                "caf\u{e9}\n",
+                "<a href='/x' hidden>",
+                "link",
+                "</a>",
+                "<script>",
+                "a",
+                "</script>"
+            ]
+        );
+
+        let link = tokens[2].get_span();
+        assert_eq!(
+            (link.start.offset, link.start.line, link.start.column),
+            (9, 2, 1)
+        );
+        let attributes = tokens[2].get_attribute_spans();
+        assert_eq!(attributes["href"].name.slice(html), Some("href"));
+        assert_eq!(attributes["href"].value.unwrap().slice(html), Some("/x"));
+        assert_eq!(attributes["href"].value.unwrap().start.column, 10);
+        assert!(attributes["hidden"].value.is_none());
+    }
+
+    #[test]
+    fn check_attribute_states() {
+        let html = "<input\tTYPE=text\nvalue=a\"b disabled= checked title=\"x > y\" id=1 ID=2 />";
+        let tokens = get_tokens(html);
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].get_token_type(), TokenType::VoidTag);
+        assert!(tokens[0].is_self_closing());
+        let attributes: Vec<(&str, &str)> = tokens[0].get_properties().iter().collect();
+        assert_eq!(
+            attributes,
+            vec![
+                ("type", "text"),
+                ("value", "a\"b"),
+                ("disabled", "checked"),
+                ("title", "x > y"),
+                ("id", "1"),
+            ]
+        );
+        let spans = tokens[0].get_attribute_spans();
+        assert_eq!(spans["type"].name.slice(html), Some("TYPE"));
+        assert_eq!(spans["value"].value.unwrap().slice(html), Some("a\"b"));
+        assert_eq!(spans["title"].value.unwrap().slice(html), Some("x > y"));
+        let names: Vec<&str> = spans.iter().map(|(name, _)| name).collect();
+        assert_eq!(names, vec!["type", "value", "disabled", "title", "id"]);
+
+        let mut tokeniser = Tokeniser::with_input("<a id=1 id=2 =x>a < b</p x>");
+        let tokens: Vec<Token> = tokeniser.by_ref().collect();
+        let codes: Vec<&str> = tokeniser
+            .take_diagnostics()
+            .iter()
+            .map(|diagnostic| diagnostic.code.as_str())
+            .collect();
+        assert_eq!(
+            codes,
+            vec![
+                "duplicate-attribute",
+                "unexpected-equals-sign-before-attribute-name",
+                "invalid-first-character-of-tag-name",
+                "end-tag-with-attributes"
+            ]
+        );
+        assert_eq!(tokens[0].get_properties().get("=x"), Some(""));
+        // The `<` starts a new text token, which tree construction merges
+        assert_eq!(tokens[2].get_text(), "< b");
+    }
+
+    #[test]
+    fn check_markup_declarations() {
+        let tokens = get_tokens(
+            "<!-- a > b --><!--><!bogus><?xml version=\"1.0\"?><![CDATA[<p>]]><!doctype html>",
+        );
+        let kinds: Vec<(TokenType, String)> = tokens
+            .iter()
+            .map(|token| (token.get_token_type(), token.get_text()))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (TokenType::Comment, " a > b ".to_string()),
+                (TokenType::Comment, "".to_string()),
+                (TokenType::Comment, "bogus".to_string()),
+                (TokenType::Comment, "?xml version=\"1.0\"?".to_string()),
+                (TokenType::Comment, "[CDATA[<p".to_string()),
+                (TokenType::Text, "]]>".to_string()),
+                (TokenType::Doctype, "<!doctype html>".to_string()),
+            ]
+        );
+        assert!(tokens[0].get_html_element().is_none());
+
+        let mut tokeniser = Tokeniser::with_input("<svg><![CDATA[<p>]]></svg>");
+        assert_eq!(
+            tokeniser.next().unwrap().get_token_type(),
+            TokenType::OpeningTag
+        );
+        tokeniser.set_in_foreign_content(true);
+        let cdata = tokeniser.next().unwrap();
+        assert_eq!(cdata.get_token_type(), TokenType::CData);
+        assert_eq!(cdata.get_text(), "<![CDATA[<p>]]>");
+
+        let mut tokeniser = Tokeniser::with_input("<p><!-- unterminated > still comment");
+        let tokens: Vec<Token> = tokeniser.by_ref().collect();
+        let codes: Vec<ErrorCode> = tokeniser
+            .take_diagnostics()
+            .iter()
+            .map(|diagnostic| diagnostic.code)
+            .collect();
+        assert_eq!(codes, vec![ErrorCode::EofInComment]);
+        let comment = &tokens[1];
+        assert_eq!(comment.get_token_type(), TokenType::Comment);
+        assert_eq!(comment.get_text(), " unterminated > still comment");
+
+        let tokens = get_tokens("<!--a--!></ c><!----><!--b--");
+        let data: Vec<String> = tokens.iter().map(Token::get_text).collect();
+        assert_eq!(data, vec!["a", " c", "", "b"]);
     }
 
     #[test]
     fn check_malformed_url() {
         let response = get_tokens(BASIC_HTML_DOCUMENT);
 
-        println!("{:?}", response[9]);
+        println!("{:?}", response[24]);
         assert_eq!(
-            response[9].properties.get("href").unwrap(),
-            &"i/dont/quote/my/urls.php".to_string()
+            response[24].get_properties().get("href"),
+            Some("i/dont/quote/my/urls.php")
         );
     }
 }
@@ -0,0 +1,2618 @@
+use crate::{
+    attributes::Attributes,
+    diagnostics::{Diagnostic, ErrorCode},
+    doctype::{Doctype, QuirksMode},
+    document::{Document, NodeId},
+    html_elements::HtmlElement,
+    node_tree::{Namespace, NodeType},
+    span::{SourceLocation, Span},
+    tokeniser::{Token, TokenSource, TokenType},
+};
+
+/// The insertion modes of the HTML tree construction stage. "In head
+/// noscript" is not modelled since scripting is treated as enabled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum InsertionMode {
+    Initial,
+    BeforeHtml,
+    BeforeHead,
+    InHead,
+    AfterHead,
+    InBody,
+    Text,
+    InTable,
+    InCaption,
+    InColumnGroup,
+    InTableBody,
+    InRow,
+    InCell,
+    InSelect,
+    InSelectInTable,
+    InTemplate,
+    AfterBody,
+    InFrameset,
+    AfterFrameset,
+    AfterAfterBody,
+}
+
+#[derive(Clone, Debug)]
+enum TreeToken {
+    Start(Tag),
+    End(String),
+    Text(String),
+    Comment(String),
+    Doctype(Doctype),
+}
+
+#[derive(Clone, Debug)]
+struct Tag {
+    name: String,
+    element: HtmlElement,
+    properties: Attributes,
+    self_closing: bool,
+    location: Option<Box<SourceLocation>>,
+}
+
+#[derive(Clone)]
+enum FormattingEntry {
+    Marker,
+    Element(NodeId, Tag),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Scope {
+    Default,
+    ListItem,
+    Button,
+    Table,
+    Select,
+}
+
+/// Where a new node goes: appended to `parent`, or inserted before `before`
+struct InsertionPoint {
+    parent: NodeId,
+    before: Option<NodeId>,
+}
+
+const FORMATTING_ELEMENTS: &[&str] = &[
+    "a", "b", "big", "code", "em", "font", "i", "nobr", "s", "small", "strike", "strong", "tt", "u",
+];
+
+const SPECIAL_ELEMENTS: &[&str] = &[
+    "address",
+    "applet",
+    "area",
+    "article",
+    "aside",
+    "base",
+    "basefont",
+    "bgsound",
+    "blockquote",
+    "body",
+    "br",
+    "button",
+    "caption",
+    "center",
+    "col",
+    "colgroup",
+    "dd",
+    "details",
+    "dir",
+    "div",
+    "dl",
+    "dt",
+    "embed",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "frame",
+    "frameset",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hgroup",
+    "hr",
+    "html",
+    "iframe",
+    "img",
+    "input",
+    "keygen",
+    "li",
+    "link",
+    "listing",
+    "main",
+    "marquee",
+    "menu",
+    "meta",
+    "nav",
+    "noembed",
+    "noframes",
+    "noscript",
+    "object",
+    "ol",
+    "p",
+    "param",
+    "plaintext",
+    "pre",
+    "script",
+    "search",
+    "section",
+    "select",
+    "source",
+    "style",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "template",
+    "textarea",
+    "tfoot",
+    "th",
+    "thead",
+    "title",
+    "tr",
+    "track",
+    "ul",
+    "wbr",
+    "xmp",
+];
+
+const IMPLIED_END_TAGS: &[&str] = &[
+    "dd", "dt", "li", "optgroup", "option", "p", "rb", "rp", "rt", "rtc",
+];
+
+/// Elements whose end tags may be left out, so closing them implicitly is not an error
+const OPTIONAL_END_TAGS: &[&str] = &[
+    "dd", "dt", "li", "optgroup", "option", "p", "rb", "rp", "rt", "rtc", "tbody", "td", "tfoot",
+    "th", "thead", "tr", "body", "html",
+];
+
+const HEADINGS: &[&str] = &["h1", "h2", "h3", "h4", "h5", "h6"];
+
+/// Start tags which break out of SVG or MathML content back into HTML
+const FOREIGN_BREAKOUT: &[&str] = &[
+    "b",
+    "big",
+    "blockquote",
+    "body",
+    "br",
+    "center",
+    "code",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "em",
+    "embed",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "hr",
+    "i",
+    "img",
+    "li",
+    "listing",
+    "menu",
+    "meta",
+    "nobr",
+    "ol",
+    "p",
+    "pre",
+    "ruby",
+    "s",
+    "small",
+    "span",
+    "strong",
+    "strike",
+    "sub",
+    "sup",
+    "table",
+    "tt",
+    "u",
+    "ul",
+    "var",
+];
+
+/// SVG element names with capitals, which the tokeniser lowercased
+const SVG_TAG_NAMES: &[&str] = &[
+    "altGlyph",
+    "altGlyphDef",
+    "altGlyphItem",
+    "animateColor",
+    "animateMotion",
+    "animateTransform",
+    "clipPath",
+    "feBlend",
+    "feColorMatrix",
+    "feComponentTransfer",
+    "feComposite",
+    "feConvolveMatrix",
+    "feDiffuseLighting",
+    "feDisplacementMap",
+    "feDistantLight",
+    "feDropShadow",
+    "feFlood",
+    "feFuncA",
+    "feFuncB",
+    "feFuncG",
+    "feFuncR",
+    "feGaussianBlur",
+    "feImage",
+    "feMerge",
+    "feMergeNode",
+    "feMorphology",
+    "feOffset",
+    "fePointLight",
+    "feSpecularLighting",
+    "feSpotLight",
+    "feTile",
+    "feTurbulence",
+    "foreignObject",
+    "glyphRef",
+    "linearGradient",
+    "radialGradient",
+    "textPath",
+];
+
+/// SVG attribute names with capitals
+const SVG_ATTRIBUTES: &[&str] = &[
+    "attributeName",
+    "attributeType",
+    "baseFrequency",
+    "baseProfile",
+    "calcMode",
+    "clipPathUnits",
+    "diffuseConstant",
+    "edgeMode",
+    "filterUnits",
+    "glyphRef",
+    "gradientTransform",
+    "gradientUnits",
+    "kernelMatrix",
+    "kernelUnitLength",
+    "keyPoints",
+    "keySplines",
+    "keyTimes",
+    "lengthAdjust",
+    "limitingConeAngle",
+    "markerHeight",
+    "markerUnits",
+    "markerWidth",
+    "maskContentUnits",
+    "maskUnits",
+    "numOctaves",
+    "pathLength",
+    "patternContentUnits",
+    "patternTransform",
+    "patternUnits",
+    "pointsAtX",
+    "pointsAtY",
+    "pointsAtZ",
+    "preserveAlpha",
+    "preserveAspectRatio",
+    "primitiveUnits",
+    "refX",
+    "refY",
+    "repeatCount",
+    "repeatDur",
+    "requiredExtensions",
+    "requiredFeatures",
+    "specularConstant",
+    "specularExponent",
+    "spreadMethod",
+    "startOffset",
+    "stdDeviation",
+    "stitchTiles",
+    "surfaceScale",
+    "systemLanguage",
+    "tableValues",
+    "targetX",
+    "targetY",
+    "textLength",
+    "viewBox",
+    "viewTarget",
+    "xChannelSelector",
+    "yChannelSelector",
+    "zoomAndPan",
+];
+
+/// MathML attribute names with capitals
+const MATHML_ATTRIBUTES: &[&str] = &["definitionURL"];
+
+/// Gives SVG and MathML names back the capitals the tokeniser lowercased, so
+/// `viewbox` becomes `viewBox`. Foreign attributes such as `xlink:href` keep
+/// their prefix in the stored name, so they need no adjusting.
+fn adjust_case(element: &mut HtmlElement, attributes: &mut Attributes, namespace: Namespace) {
+    let names = match namespace {
+        Namespace::Html => return,
+        Namespace::Svg => {
+            let tag_name = SVG_TAG_NAMES
+                .iter()
+                .find(|name| name.eq_ignore_ascii_case(element.tag_name()));
+            if let Some(tag_name) = tag_name {
+                *element = HtmlElement::Unknown(tag_name.to_string());
+            }
+            SVG_ATTRIBUTES
+        }
+        Namespace::MathMl => MATHML_ATTRIBUTES,
+    };
+    for name in names {
+        attributes.adjust_case(name);
+    }
+}
+
+fn is_whitespace(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\x0C' | '\r' | ' ')
+}
+
+/// MathML elements whose text content is parsed as HTML
+fn is_mathml_text_integration_point(name: &str) -> bool {
+    matches!(name, "mi" | "mo" | "mn" | "ms" | "mtext")
+}
+
+fn split_whitespace_prefix(text: &str) -> (&str, &str) {
+    let index = text.find(|c| !is_whitespace(c)).unwrap_or(text.len());
+    text.split_at(index)
+}
+
+struct TreeBuilder {
+    document: Document,
+    mode: InsertionMode,
+    original_mode: InsertionMode,
+    open_elements: Vec<NodeId>,
+    active_formatting: Vec<FormattingEntry>,
+    // The element a fragment is parsed for, which stands in for the html
+    // root when resetting the insertion mode
+    context: Option<NodeId>,
+    // The mode to return to inside each open `<template>`, innermost last
+    template_modes: Vec<InsertionMode>,
+    head: Option<NodeId>,
+    form: Option<NodeId>,
+    foster_parenting: bool,
+    frameset_ok: bool,
+    skip_newline: bool,
+    // Span of the token being processed, given to the nodes it creates
+    token_span: Span,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Builds a document tree from a token stream following the tree
+/// construction stage of the HTML specification
+pub(crate) fn build(tokens: impl IntoIterator<IntoIter: TokenSource>) -> Document {
+    build_with_diagnostics(tokens).0
+}
+
+/// Builds a document tree, also returning the tree construction errors found
+pub(crate) fn build_with_diagnostics(
+    tokens: impl IntoIterator<IntoIter: TokenSource>,
+) -> (Document, Vec<Diagnostic>) {
+    let mut builder = TreeBuilder::new();
+    builder.run(tokens);
+    (builder.document, builder.diagnostics)
+}
+
+/// Builds the nodes of a fragment parsed as the contents of a `context`
+/// element, following the HTML fragment parsing algorithm. They end up as the
+/// children of the returned document's `<html>` element.
+pub(crate) fn build_fragment(
+    tokens: impl IntoIterator<IntoIter: TokenSource>,
+    context: &HtmlElement,
+    namespace: Namespace,
+) -> Document {
+    let mut builder = TreeBuilder::new();
+    let context = builder.create_element(&Tag::synthetic(context.tag_name()), namespace);
+    builder.context = Some(context);
+    builder.insert_html(None);
+    if builder.name_of(context) == "template" {
+        builder.template_modes.push(InsertionMode::InTemplate);
+    }
+    builder.reset_insertion_mode();
+    builder.run(tokens);
+    builder.document
+}
+
+impl TreeToken {
+    fn from_token(token: &Token) -> Option<Self> {
+        let tag = |token: &Token| {
+            let element = token.get_html_element()?;
+            Some(Tag {
+                name: element.tag_name().to_ascii_lowercase(),
+                element,
+                properties: token.get_properties().clone(),
+                self_closing: token.is_self_closing(),
+                location: Some(Box::new(SourceLocation {
+                    span: token.get_span(),
+                    end_tag: None,
+                    attributes: token.get_attribute_spans().clone(),
+                })),
+            })
+        };
+        match token.get_token_type() {
+            TokenType::OpeningTag | TokenType::VoidTag => tag(token).map(TreeToken::Start),
+            TokenType::ClosingTag => tag(token).map(|tag| TreeToken::End(tag.name)),
+            TokenType::Text | TokenType::RawText => Some(TreeToken::Text(token.get_text())),
+            TokenType::Comment => Some(TreeToken::Comment(token.get_text())),
+            TokenType::Doctype => token.get_doctype().map(TreeToken::Doctype),
+            TokenType::CData => None,
+            TokenType::Unknown => None,
+        }
+    }
+}
+
+impl TreeBuilder {
+    fn new() -> Self {
+        TreeBuilder {
+            document: Document::new(),
+            mode: InsertionMode::Initial,
+            original_mode: InsertionMode::Initial,
+            open_elements: vec![],
+            active_formatting: vec![],
+            context: None,
+            template_modes: vec![],
+            head: None,
+            form: None,
+            foster_parenting: false,
+            frameset_ok: true,
+            skip_newline: false,
+            token_span: Span::default(),
+            diagnostics: vec![],
+        }
+    }
+
+    fn run(&mut self, tokens: impl IntoIterator<IntoIter: TokenSource>) {
+        let mut tokens = tokens.into_iter();
+        while let Some(token) = tokens.next() {
+            self.process_token(token);
+            tokens.set_in_foreign_content(self.in_foreign_content());
+        }
+        self.finish();
+    }
+
+    fn process_token(&mut self, token: Token) {
+        self.token_span = token.get_span();
+        if token.get_token_type() == TokenType::CData {
+            self.process_cdata(token.get_text());
+            return;
+        }
+        match TreeToken::from_token(&token) {
+            Some(TreeToken::End(name)) => {
+                let open_elements = self.open_elements.clone();
+                self.process(TreeToken::End(name.clone()));
+                self.record_end_tag(&name, &open_elements);
+            }
+            Some(token) => {
+                if let TreeToken::Start(tag) = &token
+                    && tag.self_closing
+                    && !tag.element.is_void_element()
+                    && !matches!(tag.name.as_str(), "svg" | "math")
+                    && !self.use_foreign_rules(&token)
+                {
+                    self.report(
+                        ErrorCode::NonVoidHtmlElementStartTagWithTrailingSolidus,
+                        Some(&tag.name),
+                    );
+                }
+                self.process(token);
+            }
+            None => {}
+        }
+    }
+
+    /// The current node, or the context element while only the root of a
+    /// fragment is open
+    fn adjusted_current_node(&self) -> Option<NodeId> {
+        match (self.context, self.open_elements.len()) {
+            (Some(context), 1) => Some(context),
+            _ => self.current_node(),
+        }
+    }
+
+    /// Whether the adjusted current node is an SVG or MathML element
+    fn in_foreign_content(&self) -> bool {
+        self.adjusted_current_node()
+            .is_some_and(|node| self.in_foreign_namespace(node))
+    }
+
+    fn name_of(&self, node: NodeId) -> &str {
+        self.document[node]
+            .element()
+            .map_or("", HtmlElement::tag_name)
+    }
+
+    fn create_element(&mut self, tag: &Tag, namespace: Namespace) -> NodeId {
+        let mut element = tag.element.clone();
+        let mut properties = tag.properties.clone();
+        adjust_case(&mut element, &mut properties, namespace);
+        let element = self
+            .document
+            .create_node(NodeType::Element(element), properties);
+        let node = self.document.node_mut(element);
+        node.namespace = namespace;
+        node.location = tag.location.as_deref().cloned();
+        if let Some(location) = &mut node.location {
+            for name in node.properties.names() {
+                location.attributes.adjust_case(name);
+            }
+        }
+        element
+    }
+
+    /// Gives the end tag's span to the element it closed, the innermost one
+    /// with a matching name which processing the end tag removed from the stack
+    fn record_end_tag(&mut self, name: &str, open_elements: &[NodeId]) {
+        let closed = open_elements.iter().rposition(|&node| {
+            !self.open_elements.contains(&node) && self.name_of(node).eq_ignore_ascii_case(name)
+        });
+        let Some(index) = closed else {
+            if name != "body" && name != "html" {
+                self.report(ErrorCode::UnexpectedEndTag, Some(name));
+            }
+            return;
+        };
+        for &node in &open_elements[index + 1..] {
+            let node_name = self.name_of(node).to_string();
+            if !self.open_elements.contains(&node)
+                && !OPTIONAL_END_TAGS.contains(&node_name.as_str())
+            {
+                self.report(ErrorCode::EndTagTooEarly, Some(&node_name));
+            }
+        }
+        if let Some(location) = &mut self.document.node_mut(open_elements[index]).location {
+            location.end_tag = Some(self.token_span);
+        }
+    }
+
+    /// Records a problem with the token being processed
+    fn report(&mut self, code: ErrorCode, name: Option<&str>) {
+        self.diagnostics
+            .push(Diagnostic::new(code, self.token_span, name));
+    }
+
+    fn insert_at(&mut self, point: &InsertionPoint, node: NodeId) {
+        match point.before {
+            Some(before) => self.document.insert_before(before, node),
+            None => self.document.append(point.parent, node),
+        }
+    }
+
+    /// Whether the element is in the SVG or MathML namespace
+    fn in_foreign_namespace(&self, node: NodeId) -> bool {
+        self.document[node].namespace() != Namespace::Html
+    }
+
+    /// Whether a foreign element holds HTML content: an SVG `foreignObject`,
+    /// `desc` or `title`, a MathML text element, or an `annotation-xml`
+    /// marked as HTML
+    fn is_integration_point(&self, node: NodeId) -> bool {
+        let name = self.name_of(node);
+        match self.document[node].namespace() {
+            Namespace::Html => false,
+            Namespace::Svg => matches!(name, "foreignObject" | "desc" | "title"),
+            Namespace::MathMl if name == "annotation-xml" => self.document[node]
+                .properties()
+                .get("encoding")
+                .is_some_and(|encoding| {
+                    encoding.eq_ignore_ascii_case("text/html")
+                        || encoding.eq_ignore_ascii_case("application/xhtml+xml")
+                }),
+            Namespace::MathMl => is_mathml_text_integration_point(name),
+        }
+    }
+
+    fn process(&mut self, token: TreeToken) {
+        if self.skip_newline {
+            self.skip_newline = false;
+            if let TreeToken::Text(text) = &token
+                && let Some(rest) = text.strip_prefix('\n')
+            {
+                if !rest.is_empty() {
+                    self.process(TreeToken::Text(rest.to_string()));
+                }
+                return;
+            }
+        }
+
+        if self.use_foreign_rules(&token) {
+            return self.foreign_content(token);
+        }
+        self.process_in_mode(token);
+    }
+
+    /// CDATA sections hold text inside SVG and MathML, but anywhere else they
+    /// are only a bogus comment
+    fn process_cdata(&mut self, source: String) {
+        if self.in_foreign_content() {
+            let text = source.strip_prefix("<![CDATA[").unwrap_or(&source);
+            let text = text.strip_suffix("]]>").unwrap_or(text);
+            self.process(TreeToken::Text(text.to_string()));
+        } else {
+            self.report(ErrorCode::CdataInHtmlContent, None);
+            let data = source.strip_prefix("<!").unwrap_or(&source);
+            let data = data.strip_suffix('>').unwrap_or(data);
+            self.process(TreeToken::Comment(data.to_string()));
+        }
+    }
+
+    fn process_in_mode(&mut self, token: TreeToken) {
+        match self.mode {
+            InsertionMode::Initial => self.initial(token),
+            InsertionMode::BeforeHtml => self.before_html(token),
+            InsertionMode::BeforeHead => self.before_head(token),
+            InsertionMode::InHead => self.in_head(token),
+            InsertionMode::AfterHead => self.after_head(token),
+            InsertionMode::InBody => self.in_body(token),
+            InsertionMode::Text => self.text(token),
+            InsertionMode::InTable => self.in_table(token),
+            InsertionMode::InCaption => self.in_caption(token),
+            InsertionMode::InColumnGroup => self.in_column_group(token),
+            InsertionMode::InTableBody => self.in_table_body(token),
+            InsertionMode::InRow => self.in_row(token),
+            InsertionMode::InCell => self.in_cell(token),
+            InsertionMode::InSelect => self.in_select(token),
+            InsertionMode::InSelectInTable => self.in_select_in_table(token),
+            InsertionMode::InTemplate => self.in_template(token),
+            InsertionMode::AfterBody => self.after_body(token),
+            InsertionMode::InFrameset => self.in_frameset(token),
+            InsertionMode::AfterFrameset => self.after_frameset(token),
+            InsertionMode::AfterAfterBody => self.after_after_body(token),
+        }
+    }
+
+    fn finish(&mut self) {
+        // Templates still open are closed first, leaving the mode outside them
+        while self.template_modes.pop().is_some() {
+            let Some(index) = self
+                .open_elements
+                .iter()
+                .rposition(|&node| self.name_of(node) == "template")
+            else {
+                break;
+            };
+            let unclosed = self.open_elements.split_off(index);
+            self.report_unclosed(unclosed);
+            self.clear_formatting_to_marker();
+            self.reset_insertion_mode();
+        }
+        // End of file: whatever is still open is implicitly closed. If nothing
+        // was ever inserted the document still gets its html, head and body.
+        if matches!(
+            self.mode,
+            InsertionMode::Initial
+                | InsertionMode::BeforeHtml
+                | InsertionMode::BeforeHead
+                | InsertionMode::InHead
+                | InsertionMode::AfterHead
+        ) {
+            self.process(TreeToken::Start(Tag::synthetic("body")));
+        }
+        let unclosed = std::mem::take(&mut self.open_elements);
+        self.report_unclosed(unclosed);
+    }
+
+    fn report_unclosed(&mut self, nodes: Vec<NodeId>) {
+        let end_of_file = Span {
+            start: self.token_span.end,
+            end: self.token_span.end,
+        };
+        for node in nodes {
+            let name = self.name_of(node).to_string();
+            if !OPTIONAL_END_TAGS.contains(&name.as_str()) {
+                let span = self.document[node]
+                    .location()
+                    .map_or(end_of_file, |location| location.span);
+                self.diagnostics.push(Diagnostic::new(
+                    ErrorCode::ExpectedClosingTagButGotEof,
+                    span,
+                    Some(&name),
+                ));
+            }
+        }
+    }
+
+    fn current_node(&self) -> Option<NodeId> {
+        self.open_elements.last().copied()
+    }
+
+    fn current_name(&self) -> &str {
+        self.current_node().map_or("", |node| self.name_of(node))
+    }
+
+    fn current_is(&self, names: &[&str]) -> bool {
+        names.contains(&self.current_name())
+    }
+
+    /// Whether a token goes to the rules for foreign content rather than the
+    /// current insertion mode
+    fn use_foreign_rules(&self, token: &TreeToken) -> bool {
+        let Some(current) = self.adjusted_current_node() else {
+            return false;
+        };
+        let name = self.name_of(current);
+        let namespace = self.document[current].namespace();
+        if namespace == Namespace::Html {
+            return false;
+        }
+        if !self.is_integration_point(current) {
+            // An `annotation-xml` element lets an `<svg>` in without
+            // otherwise holding HTML
+            return !(name == "annotation-xml"
+                && matches!(token, TreeToken::Start(tag) if tag.name == "svg"));
+        }
+        match token {
+            TreeToken::Start(tag) => {
+                namespace == Namespace::MathMl
+                    && is_mathml_text_integration_point(name)
+                    && matches!(tag.name.as_str(), "mglyph" | "malignmark")
+            }
+            TreeToken::Text(_) => false,
+            _ => true,
+        }
+    }
+
+    // Stack of open elements helpers
+
+    fn position_in_stack(&self, node: NodeId) -> Option<usize> {
+        self.open_elements.iter().position(|&open| open == node)
+    }
+
+    fn remove_from_stack(&mut self, node: NodeId) {
+        self.open_elements.retain(|&open| open != node);
+    }
+
+    fn pop_until(&mut self, names: &[&str]) {
+        while let Some(node) = self.open_elements.pop() {
+            if names.contains(&self.name_of(node)) {
+                break;
+            }
+        }
+    }
+
+    fn in_scope_with(&self, names: &[&str], scope: Scope) -> bool {
+        for &node in self.open_elements.iter().rev() {
+            let name = self.name_of(node);
+            let html = !self.in_foreign_namespace(node);
+            if html && names.contains(&name) {
+                return true;
+            }
+            let boundary = match scope {
+                Scope::Select => !html || !matches!(name, "optgroup" | "option"),
+                Scope::Table => html && matches!(name, "html" | "table" | "template"),
+                // Foreign elements which are special hold HTML, which ends scope
+                _ if !html => self.is_special(node),
+                _ => {
+                    matches!(
+                        name,
+                        "applet"
+                            | "caption"
+                            | "html"
+                            | "table"
+                            | "td"
+                            | "th"
+                            | "marquee"
+                            | "object"
+                            | "template"
+                    ) || (scope == Scope::ListItem && matches!(name, "ol" | "ul"))
+                        || (scope == Scope::Button && name == "button")
+                }
+            };
+            if boundary {
+                return false;
+            }
+        }
+        false
+    }
+
+    /// Whether the element is in the special category, which stops searches
+    /// for the element an end tag closes
+    fn is_special(&self, node: NodeId) -> bool {
+        let name = self.name_of(node);
+        match self.document[node].namespace() {
+            Namespace::Html => SPECIAL_ELEMENTS.contains(&name),
+            Namespace::MathMl => is_mathml_text_integration_point(name) || name == "annotation-xml",
+            Namespace::Svg => matches!(name, "foreignObject" | "desc" | "title"),
+        }
+    }
+
+    fn in_scope(&self, name: &str) -> bool {
+        self.in_scope_with(&[name], Scope::Default)
+    }
+
+    fn in_table_scope(&self, name: &str) -> bool {
+        self.in_scope_with(&[name], Scope::Table)
+    }
+
+    fn generate_implied_end_tags(&mut self, except: Option<&str>) {
+        loop {
+            let name = self.current_name();
+            if Some(name) == except || !IMPLIED_END_TAGS.contains(&name) {
+                return;
+            }
+            self.open_elements.pop();
+        }
+    }
+
+    fn close_p_element(&mut self) {
+        self.generate_implied_end_tags(Some("p"));
+        self.pop_until(&["p"]);
+    }
+
+    fn close_p_if_in_button_scope(&mut self) {
+        if self.in_scope_with(&["p"], Scope::Button) {
+            self.close_p_element();
+        }
+    }
+
+    fn clear_stack_back_to(&mut self, names: &[&str]) {
+        while !self.current_is(names) && self.open_elements.len() > 1 {
+            self.open_elements.pop();
+        }
+    }
+
+    fn reset_insertion_mode(&mut self) {
+        for (index, node) in self.open_elements.iter().enumerate().rev() {
+            let last = index == 0;
+            let node = match self.context {
+                Some(context) if last => context,
+                _ => *node,
+            };
+            self.mode = match self.name_of(node) {
+                "select" => {
+                    let in_table = self.open_elements[..index]
+                        .iter()
+                        .any(|ancestor| self.name_of(*ancestor) == "table");
+                    if in_table {
+                        InsertionMode::InSelectInTable
+                    } else {
+                        InsertionMode::InSelect
+                    }
+                }
+                "td" | "th" if !last => InsertionMode::InCell,
+                "tr" => InsertionMode::InRow,
+                "tbody" | "thead" | "tfoot" => InsertionMode::InTableBody,
+                "caption" => InsertionMode::InCaption,
+                "colgroup" => InsertionMode::InColumnGroup,
+                "table" => InsertionMode::InTable,
+                "template" => match self.template_modes.last() {
+                    Some(&mode) => mode,
+                    None => InsertionMode::InTemplate,
+                },
+                "head" if !last => InsertionMode::InHead,
+                "body" => InsertionMode::InBody,
+                "frameset" => InsertionMode::InFrameset,
+                "html" => match self.head {
+                    None => InsertionMode::BeforeHead,
+                    Some(_) => InsertionMode::AfterHead,
+                },
+                _ if last => InsertionMode::InBody,
+                _ => continue,
+            };
+            return;
+        }
+        self.mode = InsertionMode::InBody;
+    }
+
+    // Insertion helpers
+
+    fn appropriate_place(&self, override_target: Option<NodeId>) -> InsertionPoint {
+        let target = override_target
+            .or(self.current_node())
+            .unwrap_or(self.document.root());
+        if self.foster_parenting
+            && matches!(
+                self.name_of(target),
+                "table" | "tbody" | "tfoot" | "thead" | "tr"
+            )
+        {
+            let last_of = |name: &str| {
+                self.open_elements
+                    .iter()
+                    .rposition(|node| self.name_of(*node) == name)
+            };
+            let table = last_of("table");
+            // A template opened inside the table keeps its content to itself
+            if let Some(template) = last_of("template")
+                && table.is_none_or(|table| template > table)
+            {
+                return InsertionPoint {
+                    parent: self.open_elements[template],
+                    before: None,
+                };
+            }
+            if let Some(index) = table {
+                let table = self.open_elements[index];
+                return match self.document[table].parent() {
+                    Some(parent) => InsertionPoint {
+                        parent,
+                        before: Some(table),
+                    },
+                    None => InsertionPoint {
+                        parent: self.open_elements[index - 1],
+                        before: None,
+                    },
+                };
+            }
+            return InsertionPoint {
+                parent: self.open_elements[0],
+                before: None,
+            };
+        }
+        InsertionPoint {
+            parent: target,
+            before: None,
+        }
+    }
+
+    fn insert_element(&mut self, tag: &Tag) -> NodeId {
+        self.insert_foreign_element(tag, Namespace::Html)
+    }
+
+    fn insert_foreign_element(&mut self, tag: &Tag, namespace: Namespace) -> NodeId {
+        let element = self.create_element(tag, namespace);
+        let point = self.appropriate_place(None);
+        self.insert_at(&point, element);
+        self.open_elements.push(element);
+        element
+    }
+
+    /// Inserts an element which is immediately closed again, such as a void element
+    fn insert_empty_element(&mut self, tag: &Tag) {
+        self.insert_element(tag);
+        self.open_elements.pop();
+    }
+
+    fn insert_text(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        let point = self.appropriate_place(None);
+        if point.parent == self.document.root() {
+            return;
+        }
+        let previous = match point.before {
+            Some(before) => self.document[before].previous_sibling(),
+            None => self.document[point.parent].last_child(),
+        };
+        if let Some(previous) = previous {
+            let previous = self.document.node_mut(previous);
+            if let NodeType::Text(existing) = &mut previous.node_type {
+                existing.push_str(text);
+                if let Some(location) = &mut previous.location {
+                    location.span.end = self.token_span.end;
+                }
+                return;
+            }
+        }
+        let node = self
+            .document
+            .create_node(NodeType::Text(text.to_string()), Attributes::new());
+        self.document.node_mut(node).location = Some(SourceLocation::new(self.token_span));
+        self.insert_at(&point, node);
+    }
+
+    fn insert_comment(&mut self, text: String, parent: Option<NodeId>) {
+        let node = self
+            .document
+            .create_node(NodeType::Comment(text), Attributes::new());
+        self.document.node_mut(node).location = Some(SourceLocation::new(self.token_span));
+        match parent {
+            Some(parent) => self.document.append(parent, node),
+            None => {
+                let point = self.appropriate_place(None);
+                self.insert_at(&point, node);
+            }
+        }
+    }
+
+    fn merge_attributes(&mut self, node: NodeId, tag: &Tag) {
+        let node = self.document.node_mut(node);
+        for (key, value) in &tag.properties {
+            if !node.properties.contains(key) {
+                node.properties.insert(key, value);
+            }
+        }
+    }
+
+    // Active formatting elements
+
+    fn push_formatting(&mut self, element: NodeId, tag: &Tag) {
+        // The "Noah's Ark" clause: at most three identical entries after the last marker
+        let mut identical = vec![];
+        for (index, entry) in self.active_formatting.iter().enumerate().rev() {
+            match entry {
+                FormattingEntry::Marker => break,
+                FormattingEntry::Element(_, existing)
+                    if existing.name == tag.name && existing.properties == tag.properties =>
+                {
+                    identical.push(index)
+                }
+                _ => {}
+            }
+        }
+        if identical.len() >= 3 {
+            self.active_formatting
+                .remove(identical[identical.len() - 1]);
+        }
+        self.active_formatting
+            .push(FormattingEntry::Element(element, tag.clone()));
+    }
+
+    fn formatting_position(&self, node: NodeId) -> Option<usize> {
+        self.active_formatting.iter().position(
+            |entry| matches!(entry, FormattingEntry::Element(element, _) if *element == node),
+        )
+    }
+
+    fn clear_formatting_to_marker(&mut self) {
+        while let Some(entry) = self.active_formatting.pop() {
+            if matches!(entry, FormattingEntry::Marker) {
+                break;
+            }
+        }
+    }
+
+    fn reconstruct_formatting(&mut self) {
+        let Some(last) = self.active_formatting.last() else {
+            return;
+        };
+        let open = |entry: &FormattingEntry, builder: &TreeBuilder| match entry {
+            FormattingEntry::Marker => true,
+            FormattingEntry::Element(node, _) => builder.position_in_stack(*node).is_some(),
+        };
+        if open(last, self) {
+            return;
+        }
+        let mut index = self.active_formatting.len() - 1;
+        while index > 0 && !open(&self.active_formatting[index - 1], self) {
+            index -= 1;
+        }
+        for entry_index in index..self.active_formatting.len() {
+            let FormattingEntry::Element(_, tag) = self.active_formatting[entry_index].clone()
+            else {
+                continue;
+            };
+            let element = self.insert_element(&tag);
+            self.active_formatting[entry_index] = FormattingEntry::Element(element, tag);
+        }
+    }
+
+    /// The adoption agency algorithm. Returns true when the end tag should
+    /// instead be handled like any other end tag.
+    fn adoption_agency(&mut self, subject: &str) -> bool {
+        if self.current_name() == subject
+            && let Some(current) = self.current_node()
+            && self.formatting_position(current).is_none()
+        {
+            self.open_elements.pop();
+            return false;
+        }
+
+        for _ in 0..8 {
+            let mut formatting = None;
+            for (index, entry) in self.active_formatting.iter().enumerate().rev() {
+                match entry {
+                    FormattingEntry::Marker => break,
+                    FormattingEntry::Element(node, tag) if tag.name == subject => {
+                        formatting = Some((index, *node, tag.clone()));
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            let Some((formatting_index, formatting_element, formatting_tag)) = formatting else {
+                return true;
+            };
+
+            let Some(stack_index) = self.position_in_stack(formatting_element) else {
+                self.active_formatting.remove(formatting_index);
+                return false;
+            };
+            if !self.in_scope(subject) {
+                return false;
+            }
+
+            let furthest_block = self.open_elements[stack_index + 1..]
+                .iter()
+                .find(|&&node| self.is_special(node))
+                .copied();
+            let Some(furthest_block) = furthest_block else {
+                self.open_elements.truncate(stack_index);
+                self.active_formatting.remove(formatting_index);
+                return false;
+            };
+
+            let common_ancestor = self.open_elements[stack_index - 1];
+            let mut bookmark = formatting_index;
+            let mut node_index = self.position_in_stack(furthest_block).unwrap_or(0);
+            let mut last_node = furthest_block;
+            let mut inner = 0;
+            loop {
+                inner += 1;
+                node_index -= 1;
+                let node = self.open_elements[node_index];
+                if node == formatting_element {
+                    break;
+                }
+                let mut entry = self.formatting_position(node);
+                if inner > 3
+                    && let Some(position) = entry
+                {
+                    self.active_formatting.remove(position);
+                    if position < bookmark {
+                        bookmark -= 1;
+                    }
+                    entry = None;
+                }
+                let Some(entry) = entry else {
+                    self.open_elements.remove(node_index);
+                    continue;
+                };
+                let FormattingEntry::Element(_, tag) = self.active_formatting[entry].clone() else {
+                    continue;
+                };
+                let replacement = self.create_element(&tag, Namespace::Html);
+                self.active_formatting[entry] = FormattingEntry::Element(replacement, tag);
+                self.open_elements[node_index] = replacement;
+                if last_node == furthest_block {
+                    bookmark = entry + 1;
+                }
+                self.document.append(replacement, last_node);
+                last_node = replacement;
+            }
+
+            let point = self.appropriate_place(Some(common_ancestor));
+            self.insert_at(&point, last_node);
+
+            let new_element = self.create_element(&formatting_tag, Namespace::Html);
+            let children: Vec<NodeId> = self.document.children(furthest_block).collect();
+            for child in children {
+                self.document.append(new_element, child);
+            }
+            self.document.append(furthest_block, new_element);
+
+            if let Some(position) = self.formatting_position(formatting_element) {
+                self.active_formatting.remove(position);
+                if position < bookmark {
+                    bookmark -= 1;
+                }
+            }
+            let bookmark = bookmark.min(self.active_formatting.len());
+            self.active_formatting.insert(
+                bookmark,
+                FormattingEntry::Element(new_element, formatting_tag),
+            );
+
+            self.remove_from_stack(formatting_element);
+            let block_index = self.position_in_stack(furthest_block).unwrap_or(0);
+            self.open_elements.insert(block_index + 1, new_element);
+        }
+        false
+    }
+
+    // Insertion modes
+
+    fn initial(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Text(text) => {
+                let (_, rest) = split_whitespace_prefix(&text);
+                if !rest.is_empty() {
+                    self.report(ErrorCode::MissingDoctype, None);
+                    self.document.quirks_mode = QuirksMode::Quirks;
+                    self.mode = InsertionMode::BeforeHtml;
+                    self.process(TreeToken::Text(rest.to_string()));
+                }
+            }
+            TreeToken::Comment(text) => {
+                let document = self.document.root();
+                self.insert_comment(text, Some(document));
+            }
+            TreeToken::Doctype(doctype) => {
+                self.document.quirks_mode = doctype.quirks_mode();
+                let node = self
+                    .document
+                    .create_node(NodeType::Doctype(doctype), Attributes::new());
+                self.document.node_mut(node).location = Some(SourceLocation::new(self.token_span));
+                let document = self.document.root();
+                self.document.append(document, node);
+                self.mode = InsertionMode::BeforeHtml;
+            }
+            token => {
+                self.report(ErrorCode::MissingDoctype, None);
+                self.document.quirks_mode = QuirksMode::Quirks;
+                self.mode = InsertionMode::BeforeHtml;
+                self.process(token);
+            }
+        }
+    }
+
+    fn before_html(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Doctype(_) => {}
+            TreeToken::Comment(text) => {
+                let document = self.document.root();
+                self.insert_comment(text, Some(document));
+            }
+            TreeToken::Text(text) => {
+                let (_, rest) = split_whitespace_prefix(&text);
+                if !rest.is_empty() {
+                    self.insert_html(None);
+                    self.process(TreeToken::Text(rest.to_string()));
+                }
+            }
+            TreeToken::Start(tag) if tag.name == "html" => self.insert_html(Some(&tag)),
+            TreeToken::End(name) if !matches!(name.as_str(), "head" | "body" | "html" | "br") => {}
+            token => {
+                self.insert_html(None);
+                self.process(token);
+            }
+        }
+    }
+
+    fn insert_html(&mut self, tag: Option<&Tag>) {
+        let tag = tag.cloned().unwrap_or_else(|| Tag::synthetic("html"));
+        let html = self.create_element(&tag, Namespace::Html);
+        self.document.append(self.document.root(), html);
+        self.open_elements.push(html);
+        self.mode = InsertionMode::BeforeHead;
+    }
+
+    fn before_head(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Text(text) => {
+                let (_, rest) = split_whitespace_prefix(&text);
+                if !rest.is_empty() {
+                    self.insert_head(None);
+                    self.process(TreeToken::Text(rest.to_string()));
+                }
+            }
+            TreeToken::Comment(text) => self.insert_comment(text, None),
+            TreeToken::Doctype(_) => {}
+            TreeToken::Start(tag) if tag.name == "html" => self.in_body(TreeToken::Start(tag)),
+            TreeToken::Start(tag) if tag.name == "head" => self.insert_head(Some(&tag)),
+            TreeToken::End(name) if !matches!(name.as_str(), "head" | "body" | "html" | "br") => {}
+            token => {
+                self.insert_head(None);
+                self.process(token);
+            }
+        }
+    }
+
+    fn insert_head(&mut self, tag: Option<&Tag>) {
+        let tag = tag.cloned().unwrap_or_else(|| Tag::synthetic("head"));
+        self.head = Some(self.insert_element(&tag));
+        self.mode = InsertionMode::InHead;
+    }
+
+    /// Inserts a raw text or RCDATA element and waits for its end tag
+    fn insert_text_element(&mut self, tag: &Tag) {
+        self.insert_element(tag);
+        self.original_mode = self.mode;
+        self.mode = InsertionMode::Text;
+    }
+
+    fn in_head(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Text(text) => {
+                let (whitespace, rest) = split_whitespace_prefix(&text);
+                self.insert_text(whitespace);
+                if !rest.is_empty() {
+                    self.open_elements.pop();
+                    self.mode = InsertionMode::AfterHead;
+                    self.process(TreeToken::Text(rest.to_string()));
+                }
+            }
+            TreeToken::Comment(text) => self.insert_comment(text, None),
+            TreeToken::Doctype(_) => {}
+            TreeToken::Start(tag) => match tag.name.as_str() {
+                "html" => self.in_body(TreeToken::Start(tag)),
+                "base" | "basefont" | "bgsound" | "link" | "meta" => {
+                    self.insert_empty_element(&tag)
+                }
+                "title" | "noscript" | "noframes" | "style" | "script" => {
+                    self.insert_text_element(&tag)
+                }
+                "template" => {
+                    self.insert_element(&tag);
+                    self.active_formatting.push(FormattingEntry::Marker);
+                    self.frameset_ok = false;
+                    self.mode = InsertionMode::InTemplate;
+                    self.template_modes.push(InsertionMode::InTemplate);
+                }
+                "head" => {}
+                _ => {
+                    self.open_elements.pop();
+                    self.mode = InsertionMode::AfterHead;
+                    self.process(TreeToken::Start(tag));
+                }
+            },
+            TreeToken::End(name) => match name.as_str() {
+                "head" => {
+                    self.open_elements.pop();
+                    self.mode = InsertionMode::AfterHead;
+                }
+                "template"
+                    if self
+                        .open_elements
+                        .iter()
+                        .any(|n| self.name_of(*n) == "template") =>
+                {
+                    self.generate_implied_end_tags(None);
+                    self.pop_until(&["template"]);
+                    self.clear_formatting_to_marker();
+                    self.template_modes.pop();
+                    self.reset_insertion_mode();
+                }
+                "body" | "html" | "br" => {
+                    self.open_elements.pop();
+                    self.mode = InsertionMode::AfterHead;
+                    self.process(TreeToken::End(name));
+                }
+                _ => {}
+            },
+        }
+    }
+
+    fn after_head(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Text(text) => {
+                let (whitespace, rest) = split_whitespace_prefix(&text);
+                self.insert_text(whitespace);
+                if !rest.is_empty() {
+                    self.insert_body(None);
+                    self.process(TreeToken::Text(rest.to_string()));
+                }
+            }
+            TreeToken::Comment(text) => self.insert_comment(text, None),
+            TreeToken::Doctype(_) => {}
+            TreeToken::Start(tag) => match tag.name.as_str() {
+                "html" => self.in_body(TreeToken::Start(tag)),
+                "body" => {
+                    self.insert_body(Some(&tag));
+                    self.frameset_ok = false;
+                }
+                "frameset" => {
+                    self.insert_element(&tag);
+                    self.mode = InsertionMode::InFrameset;
+                }
+                "base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script"
+                | "style" | "template" | "title" => {
+                    // Misplaced head content still belongs in the head
+                    let Some(head) = self.head else {
+                        return;
+                    };
+                    self.open_elements.push(head);
+                    self.in_head(TreeToken::Start(tag));
+                    if self.mode == InsertionMode::Text {
+                        // Keep head on the stack until the text element's end tag
+                        self.original_mode = InsertionMode::AfterHead;
+                        let text_element = self.open_elements.pop();
+                        self.remove_from_stack(head);
+                        self.open_elements.extend(text_element);
+                    } else {
+                        self.remove_from_stack(head);
+                    }
+                }
+                "head" => {}
+                _ => {
+                    self.insert_body(None);
+                    self.process(TreeToken::Start(tag));
+                }
+            },
+            TreeToken::End(name) => match name.as_str() {
+                "body" | "html" | "br" => {
+                    self.insert_body(None);
+                    self.process(TreeToken::End(name));
+                }
+                _ => {}
+            },
+        }
+    }
+
+    fn insert_body(&mut self, tag: Option<&Tag>) {
+        let tag = tag.cloned().unwrap_or_else(|| Tag::synthetic("body"));
+        self.insert_element(&tag);
+        self.mode = InsertionMode::InBody;
+    }
+
+    fn text(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Text(text) => self.insert_text(&text),
+            TreeToken::End(_) => {
+                self.open_elements.pop();
+                self.mode = self.original_mode;
+            }
+            _ => {}
+        }
+    }
+
+    fn in_body(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Text(text) => {
+                self.reconstruct_formatting();
+                self.insert_text(&text);
+                if text.chars().any(|c| !is_whitespace(c)) {
+                    self.frameset_ok = false;
+                }
+            }
+            TreeToken::Comment(text) => self.insert_comment(text, None),
+            TreeToken::Doctype(_) => {}
+            TreeToken::Start(tag) => self.in_body_start_tag(tag),
+            TreeToken::End(name) => self.in_body_end_tag(name),
+        }
+    }
+
+    fn in_body_start_tag(&mut self, mut tag: Tag) {
+        match tag.name.as_str() {
+            "html" => {
+                if let Some(html) = self.open_elements.first().cloned()
+                    && self.template_modes.is_empty()
+                {
+                    self.merge_attributes(html, &tag);
+                }
+            }
+            "base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script" | "style"
+            | "template" | "title" => self.in_head(TreeToken::Start(tag)),
+            "body" => {
+                if let Some(body) = self.open_elements.get(1).cloned()
+                    && self.name_of(body) == "body"
+                    && self.template_modes.is_empty()
+                {
+                    self.frameset_ok = false;
+                    self.merge_attributes(body, &tag);
+                }
+            }
+            "frameset" => {}
+            "address" | "article" | "aside" | "blockquote" | "center" | "details" | "dialog"
+            | "dir" | "div" | "dl" | "fieldset" | "figcaption" | "figure" | "footer" | "header"
+            | "hgroup" | "main" | "menu" | "nav" | "ol" | "p" | "search" | "section"
+            | "summary" | "ul" => {
+                self.close_p_if_in_button_scope();
+                self.insert_element(&tag);
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.close_p_if_in_button_scope();
+                if self.current_is(HEADINGS) {
+                    self.open_elements.pop();
+                }
+                self.insert_element(&tag);
+            }
+            "pre" | "listing" => {
+                self.close_p_if_in_button_scope();
+                self.insert_element(&tag);
+                self.skip_newline = true;
+                self.frameset_ok = false;
+            }
+            "form" => {
+                if self.form.is_none() {
+                    self.close_p_if_in_button_scope();
+                    self.form = Some(self.insert_element(&tag));
+                }
+            }
+            "li" | "dd" | "dt" => {
+                self.frameset_ok = false;
+                let closes: &[&str] = if tag.name == "li" {
+                    &["li"]
+                } else {
+                    &["dd", "dt"]
+                };
+                for node in self.open_elements.clone().iter().rev() {
+                    let name = self.name_of(*node).to_string();
+                    if closes.contains(&name.as_str()) {
+                        self.generate_implied_end_tags(Some(&name));
+                        self.pop_until(&[&name]);
+                        break;
+                    }
+                    if self.is_special(*node) && !matches!(name.as_str(), "address" | "div" | "p") {
+                        break;
+                    }
+                }
+                self.close_p_if_in_button_scope();
+                self.insert_element(&tag);
+            }
+            "plaintext" => {
+                self.close_p_if_in_button_scope();
+                self.insert_text_element(&tag);
+            }
+            "button" => {
+                if self.in_scope("button") {
+                    self.generate_implied_end_tags(None);
+                    self.pop_until(&["button"]);
+                }
+                self.reconstruct_formatting();
+                self.insert_element(&tag);
+                self.frameset_ok = false;
+            }
+            "a" => {
+                let existing = self
+                    .active_formatting
+                    .iter()
+                    .rev()
+                    .take_while(|entry| !matches!(entry, FormattingEntry::Marker))
+                    .find_map(|entry| match entry {
+                        FormattingEntry::Element(node, tag) if tag.name == "a" => Some(*node),
+                        _ => None,
+                    });
+                if let Some(existing) = existing {
+                    self.adoption_agency("a");
+                    if let Some(position) = self.formatting_position(existing) {
+                        self.active_formatting.remove(position);
+                    }
+                    self.remove_from_stack(existing);
+                }
+                self.reconstruct_formatting();
+                let element = self.insert_element(&tag);
+                self.push_formatting(element, &tag);
+            }
+            "b" | "big" | "code" | "em" | "font" | "i" | "s" | "small" | "strike" | "strong"
+            | "tt" | "u" => {
+                self.reconstruct_formatting();
+                let element = self.insert_element(&tag);
+                self.push_formatting(element, &tag);
+            }
+            "nobr" => {
+                self.reconstruct_formatting();
+                if self.in_scope("nobr") {
+                    self.adoption_agency("nobr");
+                    self.reconstruct_formatting();
+                }
+                let element = self.insert_element(&tag);
+                self.push_formatting(element, &tag);
+            }
+            "applet" | "marquee" | "object" => {
+                self.reconstruct_formatting();
+                self.insert_element(&tag);
+                self.active_formatting.push(FormattingEntry::Marker);
+                self.frameset_ok = false;
+            }
+            "table" => {
+                self.close_p_if_in_button_scope();
+                self.insert_element(&tag);
+                self.frameset_ok = false;
+                self.mode = InsertionMode::InTable;
+            }
+            "area" | "br" | "embed" | "img" | "keygen" | "wbr" | "input" => {
+                self.reconstruct_formatting();
+                self.insert_empty_element(&tag);
+                let hidden = tag
+                    .properties
+                    .get("type")
+                    .is_some_and(|t| t.eq_ignore_ascii_case("hidden"));
+                if tag.name != "input" || !hidden {
+                    self.frameset_ok = false;
+                }
+            }
+            "param" | "source" | "track" => self.insert_empty_element(&tag),
+            "hr" => {
+                self.close_p_if_in_button_scope();
+                self.insert_empty_element(&tag);
+                self.frameset_ok = false;
+            }
+            "image" => {
+                tag.name = "img".to_string();
+                tag.element = HtmlElement::Img;
+                self.in_body_start_tag(tag);
+            }
+            "textarea" => {
+                self.insert_text_element(&tag);
+                self.skip_newline = true;
+                self.frameset_ok = false;
+            }
+            "xmp" => {
+                self.close_p_if_in_button_scope();
+                self.reconstruct_formatting();
+                self.frameset_ok = false;
+                self.insert_text_element(&tag);
+            }
+            "iframe" => {
+                self.frameset_ok = false;
+                self.insert_text_element(&tag);
+            }
+            "noembed" | "noscript" => self.insert_text_element(&tag),
+            "select" => {
+                self.reconstruct_formatting();
+                self.insert_element(&tag);
+                self.frameset_ok = false;
+                self.mode = if matches!(
+                    self.mode,
+                    InsertionMode::InTable
+                        | InsertionMode::InCaption
+                        | InsertionMode::InTableBody
+                        | InsertionMode::InRow
+                        | InsertionMode::InCell
+                ) {
+                    InsertionMode::InSelectInTable
+                } else {
+                    InsertionMode::InSelect
+                };
+            }
+            "optgroup" | "option" => {
+                if self.current_name() == "option" {
+                    self.open_elements.pop();
+                }
+                self.reconstruct_formatting();
+                self.insert_element(&tag);
+            }
+            "rb" | "rtc" | "rp" | "rt" => {
+                if self.in_scope("ruby") {
+                    let except = if tag.name == "rp" || tag.name == "rt" {
+                        Some("rtc")
+                    } else {
+                        None
+                    };
+                    self.generate_implied_end_tags(except);
+                }
+                self.insert_element(&tag);
+            }
+            "math" | "svg" => {
+                self.reconstruct_formatting();
+                let namespace = if tag.name == "svg" {
+                    Namespace::Svg
+                } else {
+                    Namespace::MathMl
+                };
+                self.insert_foreign_element(&tag, namespace);
+                if tag.self_closing {
+                    self.open_elements.pop();
+                }
+            }
+            "caption" | "col" | "colgroup" | "frame" | "head" | "tbody" | "td" | "tfoot" | "th"
+            | "thead" | "tr" => {}
+            _ => {
+                self.reconstruct_formatting();
+                self.insert_element(&tag);
+            }
+        }
+    }
+
+    fn in_body_end_tag(&mut self, name: String) {
+        match name.as_str() {
+            "template" => self.in_head(TreeToken::End(name)),
+            "body" => {
+                if self.in_scope("body") {
+                    self.mode = InsertionMode::AfterBody;
+                }
+            }
+            "html" => {
+                if self.in_scope("body") {
+                    self.mode = InsertionMode::AfterBody;
+                    self.process(TreeToken::End(name));
+                }
+            }
+            "address" | "article" | "aside" | "blockquote" | "button" | "center" | "details"
+            | "dialog" | "dir" | "div" | "dl" | "fieldset" | "figcaption" | "figure" | "footer"
+            | "header" | "hgroup" | "listing" | "main" | "menu" | "nav" | "ol" | "pre"
+            | "search" | "section" | "summary" | "ul" => {
+                if self.in_scope(&name) {
+                    self.generate_implied_end_tags(None);
+                    self.pop_until(&[&name]);
+                }
+            }
+            "form" => {
+                let Some(form) = self.form.take() else {
+                    return;
+                };
+                if self.position_in_stack(form).is_some() && self.in_scope("form") {
+                    self.generate_implied_end_tags(None);
+                    self.remove_from_stack(form);
+                }
+            }
+            "p" => {
+                if !self.in_scope_with(&["p"], Scope::Button) {
+                    self.insert_element(&Tag::synthetic("p"));
+                }
+                self.close_p_element();
+            }
+            "li" => {
+                if self.in_scope_with(&["li"], Scope::ListItem) {
+                    self.generate_implied_end_tags(Some("li"));
+                    self.pop_until(&["li"]);
+                }
+            }
+            "dd" | "dt" => {
+                if self.in_scope(&name) {
+                    self.generate_implied_end_tags(Some(&name));
+                    self.pop_until(&[&name]);
+                }
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                if self.in_scope_with(HEADINGS, Scope::Default) {
+                    self.generate_implied_end_tags(None);
+                    self.pop_until(HEADINGS);
+                }
+            }
+            "applet" | "marquee" | "object" => {
+                if self.in_scope(&name) {
+                    self.generate_implied_end_tags(None);
+                    self.pop_until(&[&name]);
+                    self.clear_formatting_to_marker();
+                }
+            }
+            "br" => self.in_body_start_tag(Tag::synthetic("br")),
+            name if FORMATTING_ELEMENTS.contains(&name) => {
+                if self.adoption_agency(name) {
+                    self.any_other_end_tag(name);
+                }
+            }
+            name => self.any_other_end_tag(name),
+        }
+    }
+
+    fn any_other_end_tag(&mut self, name: &str) {
+        for index in (0..self.open_elements.len()).rev() {
+            let node = self.open_elements[index];
+            if self.name_of(node) == name {
+                self.generate_implied_end_tags(Some(name));
+                self.open_elements.truncate(index);
+                return;
+            }
+            if self.is_special(node) {
+                return;
+            }
+        }
+    }
+
+    fn foreign_content(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Text(text) => {
+                self.insert_text(&text);
+                if text.chars().any(|c| !is_whitespace(c)) {
+                    self.frameset_ok = false;
+                }
+            }
+            TreeToken::Comment(text) => self.insert_comment(text, None),
+            TreeToken::Doctype(_) => {}
+            TreeToken::Start(tag) => {
+                let breakout = FOREIGN_BREAKOUT.contains(&tag.name.as_str())
+                    || (tag.name == "font"
+                        && ["color", "face", "size"]
+                            .iter()
+                            .any(|attribute| tag.properties.contains(attribute)));
+                if breakout {
+                    while let Some(current) = self.current_node() {
+                        if !self.in_foreign_namespace(current) || self.is_integration_point(current)
+                        {
+                            break;
+                        }
+                        self.open_elements.pop();
+                    }
+                    self.process(TreeToken::Start(tag));
+                } else {
+                    let namespace = self
+                        .adjusted_current_node()
+                        .map_or(Namespace::Html, |node| self.document[node].namespace());
+                    self.insert_foreign_element(&tag, namespace);
+                    if tag.self_closing {
+                        self.open_elements.pop();
+                    }
+                }
+            }
+            TreeToken::End(name) => {
+                for index in (0..self.open_elements.len()).rev() {
+                    let node = self.open_elements[index];
+                    if !self.in_foreign_namespace(node) {
+                        break;
+                    }
+                    if self.name_of(node).eq_ignore_ascii_case(&name) {
+                        self.open_elements.truncate(index);
+                        return;
+                    }
+                }
+                // Not a foreign element, so the normal insertion mode handles it
+                self.process_in_mode(TreeToken::End(name));
+            }
+        }
+    }
+
+    fn in_table(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Text(text)
+                if self.current_is(&["table", "tbody", "template", "tfoot", "thead", "tr"]) =>
+            {
+                if text.chars().all(is_whitespace) {
+                    self.insert_text(&text);
+                } else {
+                    self.foster_parenting = true;
+                    self.in_body(TreeToken::Text(text));
+                    self.foster_parenting = false;
+                }
+            }
+            TreeToken::Comment(text) => self.insert_comment(text, None),
+            TreeToken::Doctype(_) => {}
+            TreeToken::Start(tag) => match tag.name.as_str() {
+                "caption" => {
+                    self.clear_stack_back_to(&["table", "template", "html"]);
+                    self.active_formatting.push(FormattingEntry::Marker);
+                    self.insert_element(&tag);
+                    self.mode = InsertionMode::InCaption;
+                }
+                "colgroup" => {
+                    self.clear_stack_back_to(&["table", "template", "html"]);
+                    self.insert_element(&tag);
+                    self.mode = InsertionMode::InColumnGroup;
+                }
+                "col" => {
+                    self.clear_stack_back_to(&["table", "template", "html"]);
+                    self.insert_element(&Tag::synthetic("colgroup"));
+                    self.mode = InsertionMode::InColumnGroup;
+                    self.process(TreeToken::Start(tag));
+                }
+                "tbody" | "tfoot" | "thead" => {
+                    self.clear_stack_back_to(&["table", "template", "html"]);
+                    self.insert_element(&tag);
+                    self.mode = InsertionMode::InTableBody;
+                }
+                "td" | "th" | "tr" => {
+                    self.clear_stack_back_to(&["table", "template", "html"]);
+                    self.insert_element(&Tag::synthetic("tbody"));
+                    self.mode = InsertionMode::InTableBody;
+                    self.process(TreeToken::Start(tag));
+                }
+                "table" => {
+                    if self.in_table_scope("table") {
+                        self.pop_until(&["table"]);
+                        self.reset_insertion_mode();
+                        self.process(TreeToken::Start(tag));
+                    }
+                }
+                "style" | "script" | "template" => self.in_head(TreeToken::Start(tag)),
+                "input"
+                    if tag
+                        .properties
+                        .get("type")
+                        .is_some_and(|t| t.eq_ignore_ascii_case("hidden")) =>
+                {
+                    self.insert_empty_element(&tag);
+                }
+                "form" => {
+                    if self.form.is_none() {
+                        self.form = Some(self.insert_element(&tag));
+                        self.open_elements.pop();
+                    }
+                }
+                _ => self.in_table_anything_else(TreeToken::Start(tag)),
+            },
+            TreeToken::End(name) => match name.as_str() {
+                "table" => {
+                    if self.in_table_scope("table") {
+                        self.pop_until(&["table"]);
+                        self.reset_insertion_mode();
+                    }
+                }
+                "body" | "caption" | "col" | "colgroup" | "html" | "tbody" | "td" | "tfoot"
+                | "th" | "thead" | "tr" => {}
+                "template" => self.in_head(TreeToken::End(name)),
+                _ => self.in_table_anything_else(TreeToken::End(name)),
+            },
+            token => self.in_table_anything_else(token),
+        }
+    }
+
+    fn in_table_anything_else(&mut self, token: TreeToken) {
+        self.foster_parenting = true;
+        self.in_body(token);
+        self.foster_parenting = false;
+    }
+
+    fn in_caption(&mut self, token: TreeToken) {
+        let closes_caption = match &token {
+            TreeToken::End(name) => matches!(name.as_str(), "caption" | "table"),
+            TreeToken::Start(tag) => matches!(
+                tag.name.as_str(),
+                "caption" | "col" | "colgroup" | "tbody" | "td" | "tfoot" | "th" | "thead" | "tr"
+            ),
+            _ => false,
+        };
+        if closes_caption {
+            if !self.in_table_scope("caption") {
+                return;
+            }
+            self.generate_implied_end_tags(None);
+            self.pop_until(&["caption"]);
+            self.clear_formatting_to_marker();
+            self.mode = InsertionMode::InTable;
+            if !matches!(&token, TreeToken::End(name) if name == "caption") {
+                self.process(token);
+            }
+            return;
+        }
+        match token {
+            TreeToken::End(name)
+                if matches!(
+                    name.as_str(),
+                    "body"
+                        | "col"
+                        | "colgroup"
+                        | "html"
+                        | "tbody"
+                        | "td"
+                        | "tfoot"
+                        | "th"
+                        | "thead"
+                        | "tr"
+                ) => {}
+            token => self.in_body(token),
+        }
+    }
+
+    fn in_column_group(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Text(text) => {
+                let (whitespace, rest) = split_whitespace_prefix(&text);
+                self.insert_text(whitespace);
+                if !rest.is_empty() {
+                    self.in_column_group_anything_else(TreeToken::Text(rest.to_string()));
+                }
+            }
+            TreeToken::Comment(text) => self.insert_comment(text, None),
+            TreeToken::Doctype(_) => {}
+            TreeToken::Start(tag) if tag.name == "html" => self.in_body(TreeToken::Start(tag)),
+            TreeToken::Start(tag) if tag.name == "col" => self.insert_empty_element(&tag),
+            TreeToken::Start(tag) if tag.name == "template" => self.in_head(TreeToken::Start(tag)),
+            TreeToken::End(name) if name == "template" => self.in_head(TreeToken::End(name)),
+            TreeToken::End(name) if name == "colgroup" => {
+                if self.current_name() == "colgroup" {
+                    self.open_elements.pop();
+                    self.mode = InsertionMode::InTable;
+                }
+            }
+            TreeToken::End(name) if name == "col" => {}
+            token => self.in_column_group_anything_else(token),
+        }
+    }
+
+    fn in_column_group_anything_else(&mut self, token: TreeToken) {
+        if self.current_name() == "colgroup" {
+            self.open_elements.pop();
+            self.mode = InsertionMode::InTable;
+            self.process(token);
+        }
+    }
+
+    fn in_table_body(&mut self, token: TreeToken) {
+        let context = ["tbody", "tfoot", "thead", "template", "html"];
+        match token {
+            TreeToken::Start(tag) if tag.name == "tr" => {
+                self.clear_stack_back_to(&context);
+                self.insert_element(&tag);
+                self.mode = InsertionMode::InRow;
+            }
+            TreeToken::Start(tag) if tag.name == "th" || tag.name == "td" => {
+                self.clear_stack_back_to(&context);
+                self.insert_element(&Tag::synthetic("tr"));
+                self.mode = InsertionMode::InRow;
+                self.process(TreeToken::Start(tag));
+            }
+            TreeToken::End(name) if matches!(name.as_str(), "tbody" | "tfoot" | "thead") => {
+                if self.in_table_scope(&name) {
+                    self.clear_stack_back_to(&context);
+                    self.open_elements.pop();
+                    self.mode = InsertionMode::InTable;
+                }
+            }
+            token @ TreeToken::Start(_) | token @ TreeToken::End(_)
+                if match &token {
+                    TreeToken::Start(tag) => matches!(
+                        tag.name.as_str(),
+                        "caption" | "col" | "colgroup" | "tbody" | "tfoot" | "thead"
+                    ),
+                    TreeToken::End(name) => name == "table",
+                    _ => false,
+                } =>
+            {
+                if self.in_scope_with(&["tbody", "thead", "tfoot"], Scope::Table) {
+                    self.clear_stack_back_to(&context);
+                    self.open_elements.pop();
+                    self.mode = InsertionMode::InTable;
+                    self.process(token);
+                }
+            }
+            TreeToken::End(name)
+                if matches!(
+                    name.as_str(),
+                    "body" | "caption" | "col" | "colgroup" | "html" | "td" | "th" | "tr"
+                ) => {}
+            token => self.in_table(token),
+        }
+    }
+
+    fn close_row(&mut self) -> bool {
+        if !self.in_table_scope("tr") {
+            return false;
+        }
+        self.clear_stack_back_to(&["tr", "template", "html"]);
+        self.open_elements.pop();
+        self.mode = InsertionMode::InTableBody;
+        true
+    }
+
+    fn in_row(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Start(tag) if tag.name == "th" || tag.name == "td" => {
+                self.clear_stack_back_to(&["tr", "template", "html"]);
+                self.insert_element(&tag);
+                self.mode = InsertionMode::InCell;
+                self.active_formatting.push(FormattingEntry::Marker);
+            }
+            TreeToken::End(name) if name == "tr" => {
+                self.close_row();
+            }
+            TreeToken::Start(tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "caption" | "col" | "colgroup" | "tbody" | "tfoot" | "thead" | "tr"
+                ) =>
+            {
+                if self.close_row() {
+                    self.process(TreeToken::Start(tag));
+                }
+            }
+            TreeToken::End(name) if name == "table" => {
+                if self.close_row() {
+                    self.process(TreeToken::End(name));
+                }
+            }
+            TreeToken::End(name) if matches!(name.as_str(), "tbody" | "tfoot" | "thead") => {
+                if self.in_table_scope(&name) && self.close_row() {
+                    self.process(TreeToken::End(name));
+                }
+            }
+            TreeToken::End(name)
+                if matches!(
+                    name.as_str(),
+                    "body" | "caption" | "col" | "colgroup" | "html" | "td" | "th"
+                ) => {}
+            token => self.in_table(token),
+        }
+    }
+
+    fn close_cell(&mut self) {
+        self.generate_implied_end_tags(None);
+        self.pop_until(&["td", "th"]);
+        self.clear_formatting_to_marker();
+        self.mode = InsertionMode::InRow;
+    }
+
+    fn in_cell(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::End(name) if name == "td" || name == "th" => {
+                if self.in_table_scope(&name) {
+                    self.close_cell();
+                }
+            }
+            TreeToken::Start(tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "caption"
+                        | "col"
+                        | "colgroup"
+                        | "tbody"
+                        | "td"
+                        | "tfoot"
+                        | "th"
+                        | "thead"
+                        | "tr"
+                ) =>
+            {
+                if self.in_scope_with(&["td", "th"], Scope::Table) {
+                    self.close_cell();
+                    self.process(TreeToken::Start(tag));
+                }
+            }
+            TreeToken::End(name)
+                if matches!(
+                    name.as_str(),
+                    "body" | "caption" | "col" | "colgroup" | "html"
+                ) => {}
+            TreeToken::End(name)
+                if matches!(name.as_str(), "table" | "tbody" | "tfoot" | "thead" | "tr") =>
+            {
+                if self.in_table_scope(&name) {
+                    self.close_cell();
+                    self.process(TreeToken::End(name));
+                }
+            }
+            token => self.in_body(token),
+        }
+    }
+
+    fn in_select(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Text(text) => self.insert_text(&text),
+            TreeToken::Comment(text) => self.insert_comment(text, None),
+            TreeToken::Doctype(_) => {}
+            TreeToken::Start(tag) => match tag.name.as_str() {
+                "html" => self.in_body(TreeToken::Start(tag)),
+                "option" => {
+                    if self.current_name() == "option" {
+                        self.open_elements.pop();
+                    }
+                    self.insert_element(&tag);
+                }
+                "optgroup" => {
+                    if self.current_name() == "option" {
+                        self.open_elements.pop();
+                    }
+                    if self.current_name() == "optgroup" {
+                        self.open_elements.pop();
+                    }
+                    self.insert_element(&tag);
+                }
+                "hr" => {
+                    if self.current_name() == "option" {
+                        self.open_elements.pop();
+                    }
+                    if self.current_name() == "optgroup" {
+                        self.open_elements.pop();
+                    }
+                    self.insert_empty_element(&tag);
+                }
+                "select" if self.in_scope_with(&["select"], Scope::Select) => {
+                    self.pop_until(&["select"]);
+                    self.reset_insertion_mode();
+                }
+                "input" | "keygen" | "textarea"
+                    if self.in_scope_with(&["select"], Scope::Select) =>
+                {
+                    self.pop_until(&["select"]);
+                    self.reset_insertion_mode();
+                    self.process(TreeToken::Start(tag));
+                }
+                "script" | "template" => self.in_head(TreeToken::Start(tag)),
+                _ => {}
+            },
+            TreeToken::End(name) => match name.as_str() {
+                "optgroup" => {
+                    let length = self.open_elements.len();
+                    if self.current_name() == "option"
+                        && length > 1
+                        && self.name_of(self.open_elements[length - 2]) == "optgroup"
+                    {
+                        self.open_elements.pop();
+                    }
+                    if self.current_name() == "optgroup" {
+                        self.open_elements.pop();
+                    }
+                }
+                "option" if self.current_name() == "option" => {
+                    self.open_elements.pop();
+                }
+                "select" if self.in_scope_with(&["select"], Scope::Select) => {
+                    self.pop_until(&["select"]);
+                    self.reset_insertion_mode();
+                }
+                "template" => self.in_head(TreeToken::End(name)),
+                _ => {}
+            },
+        }
+    }
+
+    fn in_select_in_table(&mut self, token: TreeToken) {
+        const TABLE_TAGS: [&str; 8] = [
+            "caption", "table", "tbody", "tfoot", "thead", "tr", "td", "th",
+        ];
+        match token {
+            TreeToken::Start(tag) if TABLE_TAGS.contains(&tag.name.as_str()) => {
+                self.pop_until(&["select"]);
+                self.reset_insertion_mode();
+                self.process(TreeToken::Start(tag));
+            }
+            TreeToken::End(name) if TABLE_TAGS.contains(&name.as_str()) => {
+                if self.in_table_scope(&name) {
+                    self.pop_until(&["select"]);
+                    self.reset_insertion_mode();
+                    self.process(TreeToken::End(name));
+                }
+            }
+            token => self.in_select(token),
+        }
+    }
+
+    /// Content directly inside a `<template>` picks the mode it would have
+    /// in the element it is meant for
+    fn in_template(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Start(tag) => {
+                let mode = match tag.name.as_str() {
+                    "base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script"
+                    | "style" | "template" | "title" => {
+                        return self.in_head(TreeToken::Start(tag));
+                    }
+                    "caption" | "colgroup" | "tbody" | "tfoot" | "thead" => InsertionMode::InTable,
+                    "col" => InsertionMode::InColumnGroup,
+                    "tr" => InsertionMode::InTableBody,
+                    "td" | "th" => InsertionMode::InRow,
+                    _ => InsertionMode::InBody,
+                };
+                self.template_modes.pop();
+                self.template_modes.push(mode);
+                self.mode = mode;
+                self.process(TreeToken::Start(tag));
+            }
+            TreeToken::End(name) if name == "template" => self.in_head(TreeToken::End(name)),
+            TreeToken::End(_) => {}
+            token => self.in_body(token),
+        }
+    }
+
+    fn after_body(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Text(text) if text.chars().all(is_whitespace) => {
+                self.in_body(TreeToken::Text(text))
+            }
+            TreeToken::Comment(text) => {
+                let html = self.open_elements.first().cloned();
+                self.insert_comment(text, html);
+            }
+            TreeToken::Doctype(_) => {}
+            TreeToken::Start(tag) if tag.name == "html" => self.in_body(TreeToken::Start(tag)),
+            TreeToken::End(name) if name == "html" => self.mode = InsertionMode::AfterAfterBody,
+            token => {
+                self.mode = InsertionMode::InBody;
+                self.process(token);
+            }
+        }
+    }
+
+    fn in_frameset(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Text(text) => {
+                let whitespace: String = text.chars().filter(|c| is_whitespace(*c)).collect();
+                self.insert_text(&whitespace);
+            }
+            TreeToken::Comment(text) => self.insert_comment(text, None),
+            TreeToken::Start(tag) => match tag.name.as_str() {
+                "html" => self.in_body(TreeToken::Start(tag)),
+                "frameset" => {
+                    self.insert_element(&tag);
+                }
+                "frame" => self.insert_empty_element(&tag),
+                "noframes" => self.in_head(TreeToken::Start(tag)),
+                _ => {}
+            },
+            TreeToken::End(name) if name == "frameset" => {
+                if self.current_name() != "html" {
+                    self.open_elements.pop();
+                }
+                if self.current_name() != "frameset" {
+                    self.mode = InsertionMode::AfterFrameset;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn after_frameset(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Text(text) => {
+                let whitespace: String = text.chars().filter(|c| is_whitespace(*c)).collect();
+                self.insert_text(&whitespace);
+            }
+            TreeToken::Comment(text) => self.insert_comment(text, None),
+            TreeToken::Start(tag) if tag.name == "html" => self.in_body(TreeToken::Start(tag)),
+            TreeToken::Start(tag) if tag.name == "noframes" => self.in_head(TreeToken::Start(tag)),
+            _ => {}
+        }
+    }
+
+    fn after_after_body(&mut self, token: TreeToken) {
+        match token {
+            TreeToken::Comment(text) => {
+                let document = self.document.root();
+                self.insert_comment(text, Some(document));
+            }
+            TreeToken::Doctype(_) => {}
+            TreeToken::Text(text) if text.chars().all(is_whitespace) => {
+                self.in_body(TreeToken::Text(text))
+            }
+            TreeToken::Start(tag) if tag.name == "html" => self.in_body(TreeToken::Start(tag)),
+            token => {
+                self.mode = InsertionMode::InBody;
+                self.process(token);
+            }
+        }
+    }
+}
+
+impl Tag {
+    /// A tag the parser inserts itself, like an implied `tbody`
+    fn synthetic(name: &str) -> Self {
+        Tag {
+            name: name.to_string(),
+            element: HtmlElement::from_tag_name(name),
+            properties: Attributes::new(),
+            self_closing: false,
+            location: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokeniser::Tokeniser;
+
+    /// Serialises the element structure of the body, ignoring attributes
+    fn body_html(html: &str) -> String {
+        let document = build(Tokeniser::with_input(html));
+        let body = document.get_elements_by_tag(&HtmlElement::Body);
+        document.inner_html(body[0])
+    }
+
+    fn tree_shape(document: &Document, node: NodeId) -> String {
+        let children: Vec<String> = document
+            .children(node)
+            .map(|child| tree_shape(document, child))
+            .collect();
+        match document[node].node_type() {
+            NodeType::Element(element) => format!("{}({})", element.tag_name(), children.join(",")),
+            NodeType::Text(_) => "#text".to_string(),
+            NodeType::Comment(_) => "#comment".to_string(),
+            NodeType::Doctype(_) => "#doctype".to_string(),
+            NodeType::Document => children.join(","),
+        }
+    }
+
+    #[test]
+    fn check_implied_document_structure() {
+        let document = build(Tokeniser::with_input("<p>Hello"));
+        assert_eq!(
+            tree_shape(&document, document.root()),
+            "html(head(),body(p(#text)))"
+        );
+
+        let document = build(Tokeniser::with_input("<title>T</title><p>x"));
+        assert_eq!(
+            tree_shape(&document, document.root()),
+            "html(head(title(#text)),body(p(#text)))"
+        );
+
+        let document = build(Tokeniser::with_input(""));
+        assert_eq!(
+            tree_shape(&document, document.root()),
+            "html(head(),body())"
+        );
+    }
+
+    #[test]
+    fn check_implied_end_tags() {
+        assert_eq!(body_html("<p>a<p>b"), "<p>a</p><p>b</p>");
+        assert_eq!(
+            body_html("<ul><li>one<li>two</ul>"),
+            "<ul><li>one</li><li>two</li></ul>"
+        );
+        assert_eq!(
+            body_html("<dl><dt>a<dd>b<dt>c</dl>"),
+            "<dl><dt>a</dt><dd>b</dd><dt>c</dt></dl>"
+        );
+        assert_eq!(body_html("<p>a<div>b</div>"), "<p>a</p><div>b</div>");
+    }
+
+    #[test]
+    fn check_stray_end_tags_are_ignored() {
+        assert_eq!(body_html("<div>a</span>b</div></div>c"), "<div>ab</div>c");
+        assert_eq!(body_html("a</p>b"), "a<p></p>b");
+    }
+
+    #[test]
+    fn check_adoption_agency() {
+        assert_eq!(body_html("<b><i>x</b>y</i>"), "<b><i>x</i></b><i>y</i>");
+        assert_eq!(body_html("<b>1<p>2</b>3</p>"), "<b>1</b><p><b>2</b>3</p>");
+        assert_eq!(
+            body_html("<a href=x>1<a href=y>2</a>"),
+            "<a href=\"x\">1</a><a href=\"y\">2</a>"
+        );
+    }
+
+    #[test]
+    fn check_formatting_is_reconstructed() {
+        assert_eq!(
+            body_html("<p><b>bold<p>still bold"),
+            "<p><b>bold</b></p><p><b>still bold</b></p>"
+        );
+    }
+
+    #[test]
+    fn check_templates() {
+        let document = build(Tokeniser::with_input(
+            "<template><p>x</p></template><p>after</p>",
+        ));
+        assert_eq!(
+            tree_shape(&document, document.root()),
+            "html(head(template(p(#text))),body(p(#text)))"
+        );
+
+        let document = build(Tokeniser::with_input(
+            "<template><td>x</td></template><p>after",
+        ));
+        assert_eq!(
+            tree_shape(&document, document.root()),
+            "html(head(template(td(#text))),body(p(#text)))"
+        );
+
+        let document = build(Tokeniser::with_input("<template><tr><td>a</template>b"));
+        assert_eq!(
+            tree_shape(&document, document.root()),
+            "html(head(template(tr(td(#text)))),body(#text))"
+        );
+
+        let document = build(Tokeniser::with_input("<template>x"));
+        assert_eq!(
+            tree_shape(&document, document.root()),
+            "html(head(template(#text)),body())"
+        );
+
+        assert_eq!(
+            body_html("<table><template><b>x</b></template></table>"),
+            "<table><template><b>x</b></template></table>"
+        );
+        assert_eq!(
+            body_html("<b><template><i>x</template>y"),
+            "<b><template><i>x</i></template>y</b>"
+        );
+        assert_eq!(
+            body_html("<div><template><p>unclosed"),
+            "<div><template><p>unclosed</p></template></div>"
+        );
+    }
+
+    #[test]
+    fn check_tables() {
+        assert_eq!(
+            body_html("<table><tr><td>1<td>2</table>"),
+            "<table><tbody><tr><td>1</td><td>2</td></tr></tbody></table>"
+        );
+        assert_eq!(
+            body_html("<table><tr><td>a</td></tr><tr><th>b</table>"),
+            "<table><tbody><tr><td>a</td></tr><tr><th>b</th></tr></tbody></table>"
+        );
+    }
+
+    #[test]
+    fn check_foster_parenting() {
+        assert_eq!(
+            body_html("<table>oops<tr><td>cell</td></tr></table>"),
+            "oops<table><tbody><tr><td>cell</td></tr></tbody></table>"
+        );
+        assert_eq!(
+            body_html("<table><div>moved</div><tr><td>x</table>"),
+            "<div>moved</div><table><tbody><tr><td>x</td></tr></tbody></table>"
+        );
+    }
+
+    #[test]
+    fn check_head_elements_after_body_started() {
+        let document = build(Tokeniser::with_input(
+            "<html><head></head><meta charset=utf-8><p>x",
+        ));
+        assert_eq!(
+            tree_shape(&document, document.root()),
+            "html(head(meta()),body(p(#text)))"
+        );
+    }
+
+    #[test]
+    fn check_select_and_options() {
+        assert_eq!(
+            body_html("<select><option>a<option>b</select>"),
+            "<select><option>a</option><option>b</option></select>"
+        );
+    }
+
+    #[test]
+    fn check_svg_self_closing() {
+        assert_eq!(
+            body_html("<svg><path d=\"M0\"/><circle/></svg><p>after"),
+            "<svg><path d=\"M0\"></path><circle></circle></svg><p>after</p>"
+        );
+    }
+
+    #[test]
+    fn check_end_tag_inside_foreign_content() {
+        assert_eq!(
+            body_html("<div><svg><g></div><p>x"),
+            "<div><svg><g></g></svg></div><p>x</p>"
+        );
+    }
+
+    #[test]
+    fn check_pre_leading_newline() {
+        assert_eq!(body_html("<pre>\nline</pre>"), "<pre>line</pre>");
+    }
+
+    #[test]
+    fn check_doctype_and_cdata() {
+        let document = build(Tokeniser::with_input(
+            "<!DOCTYPE html><!-- a > b --><p><![CDATA[x<y]]></p><svg><![CDATA[x<y]]></svg>",
+        ));
+        assert_eq!(
+            tree_shape(&document, document.root()),
+            "#doctype,#comment,html(head(),body(p(#comment),svg(#text)))"
+        );
+        assert_eq!(document.doctype().unwrap().name.as_deref(), Some("html"));
+        assert_eq!(document.quirks_mode(), QuirksMode::NoQuirks);
+
+        let svg = document.select_first("svg").unwrap().unwrap();
+        assert_eq!(document.inner_text(svg), "x<y");
+        assert_eq!(
+            document.outer_html(document.root()),
+            "<!DOCTYPE html><!-- a > b --><html><head></head><body><p><!--[CDATA[x<y]]--></p><svg>x&lt;y</svg></body></html>"
+        );
+
+        let document = build(Tokeniser::with_input(
+            "<div><![CDATA[ <b>x</b> ]]></div><svg><![CDATA[a]]><foreignObject><![CDATA[b]]></foreignObject></svg>",
+        ));
+        assert_eq!(
+            document.inner_html(document.select_first("body").unwrap().unwrap()),
+            "<div><!--[CDATA[ <b-->x ]]&gt;</div><svg>a<foreignObject>b</foreignObject></svg>"
+        );
+
+        let quirky = build(Tokeniser::with_input("<p>no doctype"));
+        assert!(quirky.doctype().is_none());
+        assert_eq!(quirky.quirks_mode(), QuirksMode::Quirks);
+    }
+
+    #[test]
+    fn check_foreign_content() {
+        assert_eq!(
+            body_html(
+                "<svg VIEWBOX='0 0 1 1'><foreignObject><p>x</p></foreignObject><lineargradient gradientunits=a /></svg>"
+            ),
+            "<svg viewBox=\"0 0 1 1\"><foreignObject><p>x</p></foreignObject><linearGradient gradientUnits=\"a\"></linearGradient></svg>"
+        );
+        assert_eq!(
+            body_html("<math definitionurl=u viewbox=v><mi>x</mi></math>"),
+            "<math definitionURL=\"u\" viewbox=\"v\"><mi>x</mi></math>"
+        );
+
+        let document = build(Tokeniser::with_input(
+            "<svg id=a viewbox=v><desc><b>x</b></desc></svg>",
+        ));
+        let namespace = |selector| {
+            let node = document.select_first(selector).unwrap().unwrap();
+            document[node].namespace()
+        };
+        assert_eq!(namespace("svg"), Namespace::Svg);
+        assert_eq!(namespace("desc"), Namespace::Svg);
+        assert_eq!(namespace("b"), Namespace::Html);
+        let svg = document.select_first("svg").unwrap().unwrap();
+        let spans = &document[svg].location().unwrap().attributes;
+        let names: Vec<&str> = spans.iter().map(|(name, _)| name).collect();
+        assert_eq!(names, vec!["id", "viewBox"]);
+
+        // SVG titles and MathML text elements hold HTML, so an end tag cannot
+        // reach past them
+        assert_eq!(
+            body_html("<p>a<svg><title></p></title></svg>"),
+            "<p>a<svg><title><p></p></title></svg></p>"
+        );
+        assert_eq!(
+            body_html("<p>a<math><mi></p></mi></math>"),
+            "<p>a<math><mi><p></p></mi></math></p>"
+        );
+        assert_eq!(
+            body_html("<svg><style><a>x</a></style><desc><style><a>y</a></style></desc></svg>"),
+            "<svg><style><a>x</a></style><desc><style><a>y</a></style></desc></svg>"
+        );
+        // Only an `annotation-xml` marked as HTML holds HTML
+        let document = build(Tokeniser::with_input(
+            "<math><annotation-xml><a></a></annotation-xml><annotation-xml encoding=TEXT/HTML><a></a></annotation-xml></math>",
+        ));
+        let namespaces: Vec<Namespace> = document
+            .select("a")
+            .unwrap()
+            .into_iter()
+            .map(|node| document[node].namespace())
+            .collect();
+        assert_eq!(namespaces, [Namespace::MathMl, Namespace::Html]);
+    }
+}
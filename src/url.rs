@@ -0,0 +1,204 @@
+/// Resolves a URL reference against an absolute base URL following RFC 3986
+/// section 5.2. Returns `None` if the base has no scheme and the reference is
+/// not absolute itself.
+pub fn resolve(base: &str, reference: &str) -> Option<String> {
+    let reference = Parts::parse(reference);
+    if reference.scheme.is_some() {
+        return Some(
+            Parts {
+                path: remove_dot_segments(&reference.path),
+                ..reference
+            }
+            .to_string(),
+        );
+    }
+    let base = Parts::parse(base);
+    base.scheme?;
+    let target = if reference.authority.is_some() {
+        Parts {
+            scheme: base.scheme,
+            path: remove_dot_segments(&reference.path),
+            ..reference
+        }
+    } else if reference.path.is_empty() {
+        Parts {
+            query: reference.query.or(base.query),
+            fragment: reference.fragment,
+            ..base
+        }
+    } else {
+        let path = if reference.path.starts_with('/') {
+            reference.path
+        } else {
+            merge(&base, &reference.path)
+        };
+        Parts {
+            path: remove_dot_segments(&path),
+            query: reference.query,
+            fragment: reference.fragment,
+            ..base
+        }
+    };
+    Some(target.to_string())
+}
+
+/// Whether a URL has a scheme, as opposed to being relative
+pub fn is_absolute(url: &str) -> bool {
+    Parts::parse(url).scheme.is_some()
+}
+
+/// The components of a URL reference. Absent components are `None`, which is
+/// different from present but empty, as in `http://a/b?`.
+struct Parts<'a> {
+    scheme: Option<&'a str>,
+    authority: Option<&'a str>,
+    path: String,
+    query: Option<&'a str>,
+    fragment: Option<&'a str>,
+}
+
+impl<'a> Parts<'a> {
+    fn parse(url: &'a str) -> Self {
+        let (rest, fragment) = match url.split_once('#') {
+            Some((rest, fragment)) => (rest, Some(fragment)),
+            None => (url, None),
+        };
+        let (rest, query) = match rest.split_once('?') {
+            Some((rest, query)) => (rest, Some(query)),
+            None => (rest, None),
+        };
+        let (scheme, rest) = match rest.split_once(':') {
+            Some((scheme, rest)) if is_scheme(scheme) => (Some(scheme), rest),
+            _ => (None, rest),
+        };
+        let (authority, path) = match rest.strip_prefix("//") {
+            Some(rest) => {
+                let end = rest.find('/').unwrap_or(rest.len());
+                (Some(&rest[..end]), &rest[end..])
+            }
+            None => (None, rest),
+        };
+        Parts {
+            scheme,
+            authority,
+            path: path.to_string(),
+            query,
+            fragment,
+        }
+    }
+}
+
+impl std::fmt::Display for Parts<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(scheme) = self.scheme {
+            write!(f, "{}:", scheme.to_ascii_lowercase())?;
+        }
+        if let Some(authority) = self.authority {
+            write!(f, "//{authority}")?;
+        }
+        f.write_str(&self.path)?;
+        if let Some(query) = self.query {
+            write!(f, "?{query}")?;
+        }
+        if let Some(fragment) = self.fragment {
+            write!(f, "#{fragment}")?;
+        }
+        Ok(())
+    }
+}
+
+fn is_scheme(scheme: &str) -> bool {
+    let mut chars = scheme.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+}
+
+/// Appends a relative path to the base path up to and including its last `/`
+fn merge(base: &Parts, path: &str) -> String {
+    if base.authority.is_some() && base.path.is_empty() {
+        return format!("/{path}");
+    }
+    match base.path.rfind('/') {
+        Some(index) => format!("{}{path}", &base.path[..=index]),
+        None => path.to_string(),
+    }
+}
+
+/// Interprets the `.` and `..` segments of a path
+fn remove_dot_segments(path: &str) -> String {
+    let mut input = path;
+    let mut output = String::with_capacity(path.len());
+    while !input.is_empty() {
+        if let Some(rest) = input
+            .strip_prefix("../")
+            .or_else(|| input.strip_prefix("./"))
+        {
+            input = rest;
+        } else if input.starts_with("/./") {
+            input = &input[2..];
+        } else if input == "/." {
+            input = "/";
+        } else if input.starts_with("/../") || input == "/.." {
+            input = if input == "/.." { "/" } else { &input[3..] };
+            let last = output.rfind('/').unwrap_or(0);
+            output.truncate(last);
+        } else if input == "." || input == ".." {
+            input = "";
+        } else {
+            let start = usize::from(input.starts_with('/'));
+            let end = input[start..].find('/').map_or(input.len(), |i| i + start);
+            output.push_str(&input[..end]);
+            input = &input[end..];
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_rfc_examples() {
+        let base = "http://a/b/c/d;p?q";
+        let examples = [
+            ("g:h", "g:h"),
+            ("g", "http://a/b/c/g"),
+            ("./g", "http://a/b/c/g"),
+            ("g/", "http://a/b/c/g/"),
+            ("/g", "http://a/g"),
+            ("//g", "http://g"),
+            ("?y", "http://a/b/c/d;p?y"),
+            ("g?y", "http://a/b/c/g?y"),
+            ("#s", "http://a/b/c/d;p?q#s"),
+            ("g?y#s", "http://a/b/c/g?y#s"),
+            (";x", "http://a/b/c/;x"),
+            ("", "http://a/b/c/d;p?q"),
+            (".", "http://a/b/c/"),
+            ("..", "http://a/b/"),
+            ("../g", "http://a/b/g"),
+            ("../..", "http://a/"),
+            ("../../../g", "http://a/g"),
+            ("/./g", "http://a/g"),
+            ("g.", "http://a/b/c/g."),
+            ("..g", "http://a/b/c/..g"),
+            ("./../g", "http://a/b/g"),
+            ("g/./h", "http://a/b/c/g/h"),
+            ("g/../h", "http://a/b/c/h"),
+            ("g;x=1/../y", "http://a/b/c/y"),
+            ("g#s/../x", "http://a/b/c/g#s/../x"),
+            ("http:g", "http:g"),
+        ];
+        for (reference, expected) in examples {
+            assert_eq!(
+                resolve(base, reference).as_deref(),
+                Some(expected),
+                "{reference}"
+            );
+        }
+        assert_eq!(resolve("http://a", "b").as_deref(), Some("http://a/b"));
+        assert_eq!(resolve("/relative", "b"), None);
+        assert!(is_absolute("mailto:x@y"));
+        assert!(!is_absolute("/a:b"));
+    }
+}
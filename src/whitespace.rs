@@ -0,0 +1,131 @@
+use crate::{
+    document::{Document, NodeId},
+    node_tree::NodeType,
+};
+
+/// What is done with whitespace in text once a document has been parsed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WhitespacePolicy {
+    /// Keeps all character data as written, so the tree serializes back to
+    /// its source
+    #[default]
+    Preserve,
+    /// Collapses each run of whitespace in text to a single space, except in
+    /// elements such as `<pre>` where it is significant
+    Collapse,
+    /// Removes whitespace-only text which is never rendered: text at the
+    /// start or end of a block or next to one, such as the indentation
+    /// between list items. Whitespace between inline elements and in `<pre>`
+    /// is kept.
+    TrimInsignificant,
+}
+
+/// Applies a policy to every text node in the document
+pub(crate) fn apply(document: &mut Document, policy: WhitespacePolicy) {
+    if policy == WhitespacePolicy::Preserve {
+        return;
+    }
+    // Decided up front so that removing one node does not change whether its
+    // neighbours look significant
+    let text_nodes: Vec<NodeId> = document
+        .descendants(document.root())
+        .filter(|&id| matches!(document[id].node_type, NodeType::Text(_)))
+        .filter(|&id| !is_preformatted(document, id))
+        .filter(|&id| policy == WhitespacePolicy::Collapse || is_insignificant(document, id))
+        .collect();
+    for id in text_nodes {
+        match policy {
+            WhitespacePolicy::Collapse => {
+                if let NodeType::Text(text) = &mut document.node_mut(id).node_type {
+                    *text = collapse(text);
+                }
+            }
+            _ => document.detach(id),
+        }
+    }
+}
+
+fn is_preformatted(document: &Document, id: NodeId) -> bool {
+    document.ancestors(id).any(|ancestor| {
+        document[ancestor]
+            .element()
+            .is_some_and(|element| element.preserves_whitespace())
+    })
+}
+
+/// Whether a text node is only whitespace and sits against the edge of a
+/// block or a block-level sibling. Comments are looked past.
+fn is_insignificant(document: &Document, id: NodeId) -> bool {
+    let NodeType::Text(text) = &document[id].node_type else {
+        return false;
+    };
+    if !text.chars().all(|c| c.is_ascii_whitespace()) {
+        return false;
+    }
+    let in_inline = document[id]
+        .parent()
+        .and_then(|parent| document[parent].element())
+        .is_some_and(|element| element.is_inline());
+    let is_edge = |mut sibling: Option<NodeId>, next: bool| {
+        while let Some(id) = sibling
+            && matches!(document[id].node_type, NodeType::Comment(_))
+        {
+            sibling = match next {
+                true => document[id].next_sibling(),
+                false => document[id].previous_sibling(),
+            };
+        }
+        match sibling {
+            Some(sibling) => document[sibling]
+                .element()
+                .is_some_and(|element| !element.is_inline()),
+            None => !in_inline,
+        }
+    };
+    is_edge(document[id].previous_sibling(), false) || is_edge(document[id].next_sibling(), true)
+}
+
+/// Collapses each run of whitespace to a single space and trims the ends,
+/// for text such as a link's or a table cell's
+pub(crate) fn normalize(text: &str) -> String {
+    text.split_ascii_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn collapse(text: &str) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    for char in text.chars() {
+        if !char.is_ascii_whitespace() {
+            collapsed.push(char);
+        } else if !collapsed.ends_with(' ') {
+            collapsed.push(' ');
+        }
+    }
+    collapsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{document::ParseOptions, html_elements::HtmlElement};
+
+    const HTML: &str =
+        "<ul>\n  <li><b>a</b> <i>b</i></li>\n  <li>c  \n d</li>\n</ul><pre>  x\n</pre>";
+
+    fn parse(whitespace: WhitespacePolicy) -> String {
+        let document = Document::parse_with(HTML, &ParseOptions { whitespace });
+        document.inner_html(document.get_elements_by_tag(&HtmlElement::Body)[0])
+    }
+
+    #[test]
+    fn check_policies() {
+        assert_eq!(parse(WhitespacePolicy::Preserve), HTML);
+        assert_eq!(
+            parse(WhitespacePolicy::Collapse),
+            "<ul> <li><b>a</b> <i>b</i></li> <li>c d</li> </ul><pre>  x\n</pre>"
+        );
+        assert_eq!(
+            parse(WhitespacePolicy::TrimInsignificant),
+            "<ul><li><b>a</b> <i>b</i></li><li>c  \n d</li></ul><pre>  x\n</pre>"
+        );
+    }
+}
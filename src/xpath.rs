@@ -0,0 +1,1679 @@
+use std::{
+    cell::{OnceCell, RefCell},
+    collections::HashMap,
+    fmt,
+    rc::Rc,
+    str::FromStr,
+};
+
+use crate::node_tree::{Node, NodeRef, NodeType};
+
+/// A compiled XPath 1.0 expression
+#[derive(Clone, Debug, PartialEq)]
+pub struct XPath {
+    expr: Expr,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum XPathError {
+    Syntax { position: usize, message: String },
+    UnknownFunction(String),
+    ArgumentCount { function: String, found: usize },
+    NotANodeSet,
+    UnsupportedVariable(String),
+}
+
+/// A member of a node set: either a node in the tree or one of an element's
+/// attributes, which are not nodes in their own right in this crate
+#[derive(Clone, Debug)]
+pub enum XPathNode {
+    Node(NodeRef),
+    Attribute {
+        owner: NodeRef,
+        name: String,
+        value: String,
+    },
+}
+
+/// The result of evaluating an XPath expression
+#[derive(Clone, Debug)]
+pub enum XPathValue {
+    NodeSet(Vec<XPathNode>),
+    String(String),
+    Number(f64),
+    Boolean(bool),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Axis {
+    Child,
+    Descendant,
+    DescendantOrSelf,
+    Parent,
+    Ancestor,
+    AncestorOrSelf,
+    FollowingSibling,
+    PrecedingSibling,
+    Following,
+    Preceding,
+    Attribute,
+    SelfNode,
+    Namespace,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum NodeTest {
+    // `*` for the axis' principal node type
+    Any,
+    Name(String),
+    Node,
+    Text,
+    Comment,
+    ProcessingInstruction,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Step {
+    axis: Axis,
+    test: NodeTest,
+    predicates: Vec<Expr>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BinaryOp {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Union,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum PathStart {
+    Root,
+    Context,
+    Filter(Box<Expr>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Negate(Box<Expr>),
+    Path(PathStart, Vec<Step>),
+    Filter(Box<Expr>, Vec<Expr>),
+    Literal(String),
+    Number(f64),
+    Function(String, Vec<Expr>),
+}
+
+impl fmt::Display for XPathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            XPathError::Syntax { position, message } => {
+                write!(f, "{message} at position {position}")
+            }
+            XPathError::UnknownFunction(name) => write!(f, "unknown function '{name}()'"),
+            XPathError::ArgumentCount { function, found } => {
+                write!(f, "wrong number of arguments ({found}) for '{function}()'")
+            }
+            XPathError::NotANodeSet => write!(f, "expression does not evaluate to a node set"),
+            XPathError::UnsupportedVariable(name) => {
+                write!(f, "variable references are not supported: ${name}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for XPathError {}
+
+impl XPathNode {
+    /// The XPath string-value of the node
+    pub fn string_value(&self) -> String {
+        match self {
+            XPathNode::Node(node) => match &node.borrow().node_type {
+                NodeType::Text(text) | NodeType::Comment(text) => text.clone(),
+                _ => node.borrow().inner_text(),
+            },
+            XPathNode::Attribute { value, .. } => value.clone(),
+        }
+    }
+
+    /// The tree node, or the element owning the attribute
+    pub fn node(&self) -> &NodeRef {
+        match self {
+            XPathNode::Node(node) => node,
+            XPathNode::Attribute { owner, .. } => owner,
+        }
+    }
+
+    fn is_same(&self, other: &XPathNode) -> bool {
+        match (self, other) {
+            (XPathNode::Node(a), XPathNode::Node(b)) => Rc::ptr_eq(a, b),
+            (
+                XPathNode::Attribute {
+                    owner: a, name: n, ..
+                },
+                XPathNode::Attribute {
+                    owner: b, name: m, ..
+                },
+            ) => Rc::ptr_eq(a, b) && n == m,
+            _ => false,
+        }
+    }
+}
+
+impl XPathValue {
+    /// Converts the value with the XPath `string()` function
+    pub fn to_string_value(&self) -> String {
+        match self {
+            XPathValue::NodeSet(nodes) => {
+                nodes.first().map(|n| n.string_value()).unwrap_or_default()
+            }
+            XPathValue::String(s) => s.clone(),
+            XPathValue::Number(n) => number_to_string(*n),
+            XPathValue::Boolean(b) => b.to_string(),
+        }
+    }
+
+    /// Converts the value with the XPath `number()` function
+    pub fn to_number(&self) -> f64 {
+        match self {
+            XPathValue::Number(n) => *n,
+            XPathValue::Boolean(b) => {
+                if *b {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            _ => string_to_number(&self.to_string_value()),
+        }
+    }
+
+    /// Converts the value with the XPath `boolean()` function
+    pub fn to_boolean(&self) -> bool {
+        match self {
+            XPathValue::NodeSet(nodes) => !nodes.is_empty(),
+            XPathValue::String(s) => !s.is_empty(),
+            XPathValue::Number(n) => *n != 0.0 && !n.is_nan(),
+            XPathValue::Boolean(b) => *b,
+        }
+    }
+
+    /// Returns the tree nodes in a node set, skipping attributes
+    pub fn nodes(&self) -> Vec<NodeRef> {
+        match self {
+            XPathValue::NodeSet(nodes) => nodes
+                .iter()
+                .filter_map(|node| match node {
+                    XPathNode::Node(node) => Some(node.clone()),
+                    XPathNode::Attribute { .. } => None,
+                })
+                .collect(),
+            _ => vec![],
+        }
+    }
+}
+
+impl FromStr for XPath {
+    type Err = XPathError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        XPath::parse(s)
+    }
+}
+
+impl XPath {
+    pub fn parse(expression: &str) -> Result<Self, XPathError> {
+        let tokens = tokenise(expression)?;
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+            end: expression.len(),
+        };
+        let expr = parser.parse_or()?;
+        if let Some((token, offset)) = parser.tokens.get(parser.position) {
+            return Err(XPathError::Syntax {
+                position: *offset,
+                message: format!("unexpected {token}"),
+            });
+        }
+        Ok(XPath { expr })
+    }
+
+    /// Evaluates the expression with `context` as the context node
+    pub fn evaluate(&self, context: &NodeRef) -> Result<XPathValue, XPathError> {
+        let order = DocumentOrder::new(root_of(context));
+        let context = Context {
+            node: XPathNode::Node(context.clone()),
+            position: 1,
+            size: 1,
+            order: &order,
+        };
+        evaluate_expr(&self.expr, &context)
+    }
+
+    /// Evaluates the expression and returns the matching tree nodes
+    pub fn select(&self, context: &NodeRef) -> Result<Vec<NodeRef>, XPathError> {
+        match self.evaluate(context)? {
+            value @ XPathValue::NodeSet(_) => Ok(value.nodes()),
+            _ => Err(XPathError::NotANodeSet),
+        }
+    }
+}
+
+/// Parses and evaluates an expression in one go
+pub fn evaluate(expression: &str, context: &NodeRef) -> Result<XPathValue, XPathError> {
+    XPath::parse(expression)?.evaluate(context)
+}
+
+struct Context<'a> {
+    node: XPathNode,
+    position: usize,
+    size: usize,
+    order: &'a DocumentOrder,
+}
+
+/// The pre-order position of every node in the tree being queried, worked
+/// out in one walk the first time a node set is sorted
+struct DocumentOrder {
+    root: NodeRef,
+    positions: OnceCell<HashMap<*const RefCell<Node>, usize>>,
+}
+
+impl DocumentOrder {
+    fn new(root: NodeRef) -> Self {
+        DocumentOrder {
+            root,
+            positions: OnceCell::new(),
+        }
+    }
+
+    /// Sorts nodes into document order and removes duplicates. Attributes
+    /// sort after their owner but before its children.
+    fn sort(&self, nodes: Vec<XPathNode>) -> Vec<XPathNode> {
+        let positions = self.positions.get_or_init(|| {
+            let mut positions = HashMap::new();
+            number_nodes(&self.root, &mut positions);
+            positions
+        });
+        let mut keyed: Vec<((usize, usize), XPathNode)> = nodes
+            .into_iter()
+            .map(|node| {
+                let position = positions
+                    .get(&Rc::as_ptr(node.node()))
+                    .copied()
+                    .unwrap_or(usize::MAX);
+                let attribute = match &node {
+                    XPathNode::Node(_) => 0,
+                    XPathNode::Attribute { owner, name, .. } => {
+                        1 + owner
+                            .borrow()
+                            .properties
+                            .names()
+                            .position(|key| key == name)
+                            .unwrap_or(0)
+                    }
+                };
+                ((position, attribute), node)
+            })
+            .collect();
+        keyed.sort_by_key(|(key, _)| *key);
+        let mut ordered: Vec<XPathNode> = vec![];
+        for (_, node) in keyed {
+            if !ordered.last().is_some_and(|last| last.is_same(&node)) {
+                ordered.push(node);
+            }
+        }
+        ordered
+    }
+}
+
+fn number_nodes(node: &NodeRef, positions: &mut HashMap<*const RefCell<Node>, usize>) {
+    positions.insert(Rc::as_ptr(node), positions.len());
+    for child in &node.borrow().children {
+        number_nodes(child, positions);
+    }
+}
+
+fn evaluate_expr(expr: &Expr, context: &Context) -> Result<XPathValue, XPathError> {
+    match expr {
+        Expr::Literal(s) => Ok(XPathValue::String(s.clone())),
+        Expr::Number(n) => Ok(XPathValue::Number(*n)),
+        Expr::Negate(inner) => Ok(XPathValue::Number(
+            -evaluate_expr(inner, context)?.to_number(),
+        )),
+        Expr::Binary(op, left, right) => evaluate_binary(*op, left, right, context),
+        Expr::Function(name, args) => evaluate_function(name, args, context),
+        Expr::Filter(primary, predicates) => {
+            let XPathValue::NodeSet(mut nodes) = evaluate_expr(primary, context)? else {
+                return Err(XPathError::NotANodeSet);
+            };
+            for predicate in predicates {
+                nodes = apply_predicate(nodes, predicate, context.order)?;
+            }
+            Ok(XPathValue::NodeSet(nodes))
+        }
+        Expr::Path(start, steps) => {
+            let mut nodes = match start {
+                PathStart::Root => vec![XPathNode::Node(root_of(context.node.node()))],
+                PathStart::Context => vec![context.node.clone()],
+                PathStart::Filter(filter) => match evaluate_expr(filter, context)? {
+                    XPathValue::NodeSet(nodes) => nodes,
+                    _ => return Err(XPathError::NotANodeSet),
+                },
+            };
+            for step in steps {
+                let mut next = vec![];
+                for node in &nodes {
+                    let mut selected: Vec<XPathNode> = axis_nodes(step.axis, node)
+                        .into_iter()
+                        .filter(|candidate| node_test(&step.test, step.axis, candidate))
+                        .collect();
+                    for predicate in &step.predicates {
+                        selected = apply_predicate(selected, predicate, context.order)?;
+                    }
+                    next.extend(selected);
+                }
+                nodes = context.order.sort(next);
+            }
+            Ok(XPathValue::NodeSet(nodes))
+        }
+    }
+}
+
+fn apply_predicate(
+    nodes: Vec<XPathNode>,
+    predicate: &Expr,
+    order: &DocumentOrder,
+) -> Result<Vec<XPathNode>, XPathError> {
+    let size = nodes.len();
+    let mut kept = vec![];
+    for (index, node) in nodes.into_iter().enumerate() {
+        let context = Context {
+            node,
+            position: index + 1,
+            size,
+            order,
+        };
+        let keep = match evaluate_expr(predicate, &context)? {
+            XPathValue::Number(n) => n == context.position as f64,
+            value => value.to_boolean(),
+        };
+        if keep {
+            kept.push(context.node);
+        }
+    }
+    Ok(kept)
+}
+
+fn evaluate_binary(
+    op: BinaryOp,
+    left: &Expr,
+    right: &Expr,
+    context: &Context,
+) -> Result<XPathValue, XPathError> {
+    match op {
+        BinaryOp::Or => Ok(XPathValue::Boolean(
+            evaluate_expr(left, context)?.to_boolean()
+                || evaluate_expr(right, context)?.to_boolean(),
+        )),
+        BinaryOp::And => Ok(XPathValue::Boolean(
+            evaluate_expr(left, context)?.to_boolean()
+                && evaluate_expr(right, context)?.to_boolean(),
+        )),
+        BinaryOp::Union => {
+            let (XPathValue::NodeSet(mut a), XPathValue::NodeSet(b)) = (
+                evaluate_expr(left, context)?,
+                evaluate_expr(right, context)?,
+            ) else {
+                return Err(XPathError::NotANodeSet);
+            };
+            a.extend(b);
+            Ok(XPathValue::NodeSet(context.order.sort(a)))
+        }
+        BinaryOp::Add
+        | BinaryOp::Subtract
+        | BinaryOp::Multiply
+        | BinaryOp::Divide
+        | BinaryOp::Modulo => {
+            let a = evaluate_expr(left, context)?.to_number();
+            let b = evaluate_expr(right, context)?.to_number();
+            Ok(XPathValue::Number(match op {
+                BinaryOp::Add => a + b,
+                BinaryOp::Subtract => a - b,
+                BinaryOp::Multiply => a * b,
+                BinaryOp::Divide => a / b,
+                _ => a % b,
+            }))
+        }
+        _ => {
+            let a = evaluate_expr(left, context)?;
+            let b = evaluate_expr(right, context)?;
+            Ok(XPathValue::Boolean(compare(op, &a, &b)))
+        }
+    }
+}
+
+/// Comparison semantics from section 3.4 of the XPath 1.0 specification
+fn compare(op: BinaryOp, left: &XPathValue, right: &XPathValue) -> bool {
+    match (left, right) {
+        // A node set compared with a boolean is first converted to one, so an
+        // empty set equals false()
+        (XPathValue::NodeSet(_), XPathValue::Boolean(_))
+        | (XPathValue::Boolean(_), XPathValue::NodeSet(_)) => compare(
+            op,
+            &XPathValue::Boolean(left.to_boolean()),
+            &XPathValue::Boolean(right.to_boolean()),
+        ),
+        (XPathValue::NodeSet(a), XPathValue::NodeSet(b)) => a.iter().any(|x| {
+            let x = XPathValue::String(x.string_value());
+            b.iter()
+                .any(|y| compare(op, &x, &XPathValue::String(y.string_value())))
+        }),
+        (XPathValue::NodeSet(nodes), other) => nodes
+            .iter()
+            .any(|node| compare(op, &atomise(node, other), other)),
+        (other, XPathValue::NodeSet(nodes)) => nodes
+            .iter()
+            .any(|node| compare(op, other, &atomise(node, other))),
+        _ => match op {
+            BinaryOp::Equal | BinaryOp::NotEqual => {
+                let equal = if matches!(left, XPathValue::Boolean(_))
+                    || matches!(right, XPathValue::Boolean(_))
+                {
+                    left.to_boolean() == right.to_boolean()
+                } else if matches!(left, XPathValue::Number(_))
+                    || matches!(right, XPathValue::Number(_))
+                {
+                    left.to_number() == right.to_number()
+                } else {
+                    left.to_string_value() == right.to_string_value()
+                };
+                equal == (op == BinaryOp::Equal)
+            }
+            _ => {
+                let (a, b) = (left.to_number(), right.to_number());
+                match op {
+                    BinaryOp::Less => a < b,
+                    BinaryOp::LessOrEqual => a <= b,
+                    BinaryOp::Greater => a > b,
+                    _ => a >= b,
+                }
+            }
+        },
+    }
+}
+
+/// Converts a node to the type of the value it is being compared with
+fn atomise(node: &XPathNode, other: &XPathValue) -> XPathValue {
+    match other {
+        XPathValue::Number(_) => XPathValue::Number(string_to_number(&node.string_value())),
+        _ => XPathValue::String(node.string_value()),
+    }
+}
+
+fn evaluate_function(
+    name: &str,
+    args: &[Expr],
+    context: &Context,
+) -> Result<XPathValue, XPathError> {
+    let arg = |index: usize| evaluate_expr(&args[index], context);
+    let string_arg = |index: usize| -> Result<String, XPathError> {
+        match args.get(index) {
+            Some(expr) => Ok(evaluate_expr(expr, context)?.to_string_value()),
+            None => Ok(context.node.string_value()),
+        }
+    };
+    let node_set_arg = |index: usize| -> Result<Vec<XPathNode>, XPathError> {
+        match args.get(index) {
+            Some(expr) => match evaluate_expr(expr, context)? {
+                XPathValue::NodeSet(nodes) => Ok(nodes),
+                _ => Err(XPathError::NotANodeSet),
+            },
+            None => Ok(vec![context.node.clone()]),
+        }
+    };
+
+    let value = match name {
+        "last" => XPathValue::Number(context.size as f64),
+        "position" => XPathValue::Number(context.position as f64),
+        "count" => XPathValue::Number(node_set_arg(0)?.len() as f64),
+        "local-name" | "name" => {
+            XPathValue::String(node_set_arg(0)?.first().map(node_name).unwrap_or_default())
+        }
+        "namespace-uri" => XPathValue::String(String::new()),
+        "id" => {
+            let ids = arg(0)?;
+            let wanted: Vec<String> = match &ids {
+                XPathValue::NodeSet(nodes) => nodes
+                    .iter()
+                    .flat_map(|n| {
+                        n.string_value()
+                            .split_whitespace()
+                            .map(str::to_string)
+                            .collect::<Vec<_>>()
+                    })
+                    .collect(),
+                other => other
+                    .to_string_value()
+                    .split_whitespace()
+                    .map(str::to_string)
+                    .collect(),
+            };
+            let root = XPathNode::Node(root_of(context.node.node()));
+            let found = axis_nodes(Axis::Descendant, &root)
+                .into_iter()
+                .filter(|node| {
+                    node.node()
+                        .borrow()
+                        .properties
+                        .get("id")
+                        .is_some_and(|id| wanted.iter().any(|wanted| wanted == id))
+                })
+                .collect();
+            XPathValue::NodeSet(found)
+        }
+        "string" => XPathValue::String(string_arg(0)?),
+        "concat" => {
+            let mut joined = String::new();
+            for index in 0..args.len() {
+                joined.push_str(&string_arg(index)?);
+            }
+            XPathValue::String(joined)
+        }
+        "starts-with" => XPathValue::Boolean(string_arg(0)?.starts_with(&string_arg(1)?)),
+        "contains" => XPathValue::Boolean(string_arg(0)?.contains(&string_arg(1)?)),
+        "substring-before" => {
+            let (haystack, needle) = (string_arg(0)?, string_arg(1)?);
+            XPathValue::String(
+                haystack
+                    .find(&needle)
+                    .map(|index| haystack[..index].to_string())
+                    .unwrap_or_default(),
+            )
+        }
+        "substring-after" => {
+            let (haystack, needle) = (string_arg(0)?, string_arg(1)?);
+            XPathValue::String(
+                haystack
+                    .find(&needle)
+                    .map(|index| haystack[index + needle.len()..].to_string())
+                    .unwrap_or_default(),
+            )
+        }
+        "substring" => {
+            let value: Vec<char> = string_arg(0)?.chars().collect();
+            let start = round(arg(1)?.to_number());
+            let end = match args.get(2) {
+                Some(_) => start + round(arg(2)?.to_number()),
+                None => f64::INFINITY,
+            };
+            // Characters are 1-indexed and kept when start <= position < end
+            let result = value
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| {
+                    let position = (*index + 1) as f64;
+                    position >= start && position < end
+                })
+                .map(|(_, c)| c)
+                .collect();
+            XPathValue::String(result)
+        }
+        "string-length" => XPathValue::Number(string_arg(0)?.chars().count() as f64),
+        "normalize-space" => XPathValue::String(
+            string_arg(0)?
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" "),
+        ),
+        "translate" => {
+            let value = string_arg(0)?;
+            let from: Vec<char> = string_arg(1)?.chars().collect();
+            let to: Vec<char> = string_arg(2)?.chars().collect();
+            let translated = value
+                .chars()
+                .filter_map(|c| match from.iter().position(|f| *f == c) {
+                    Some(index) => to.get(index).copied(),
+                    None => Some(c),
+                })
+                .collect();
+            XPathValue::String(translated)
+        }
+        "boolean" => XPathValue::Boolean(arg(0)?.to_boolean()),
+        "not" => XPathValue::Boolean(!arg(0)?.to_boolean()),
+        "true" => XPathValue::Boolean(true),
+        "false" => XPathValue::Boolean(false),
+        "lang" => {
+            let wanted = string_arg(0)?.to_lowercase();
+            let mut current = Some(context.node.node().clone());
+            let mut lang = None;
+            while let Some(node) = current {
+                if let Some(value) = node.borrow().properties.get("lang") {
+                    lang = Some(value.to_lowercase());
+                    break;
+                }
+                current = node
+                    .borrow()
+                    .parent_element
+                    .as_ref()
+                    .and_then(|p| p.upgrade());
+            }
+            XPathValue::Boolean(
+                lang.is_some_and(|lang| lang == wanted || lang.starts_with(&format!("{wanted}-"))),
+            )
+        }
+        "number" => XPathValue::Number(match args.first() {
+            Some(_) => arg(0)?.to_number(),
+            None => string_to_number(&context.node.string_value()),
+        }),
+        "sum" => XPathValue::Number(
+            node_set_arg(0)?
+                .iter()
+                .map(|node| string_to_number(&node.string_value()))
+                .sum(),
+        ),
+        "floor" => XPathValue::Number(arg(0)?.to_number().floor()),
+        "ceiling" => XPathValue::Number(arg(0)?.to_number().ceil()),
+        "round" => XPathValue::Number(round(arg(0)?.to_number())),
+        _ => return Err(XPathError::UnknownFunction(name.to_string())),
+    };
+    Ok(value)
+}
+
+/// Rounds halves towards positive infinity as XPath requires
+fn round(n: f64) -> f64 {
+    if n.is_nan() || n.is_infinite() {
+        n
+    } else {
+        (n + 0.5).floor()
+    }
+}
+
+fn function_arity(name: &str) -> Option<(usize, usize)> {
+    Some(match name {
+        "last" | "position" | "true" | "false" => (0, 0),
+        "count" | "id" | "boolean" | "not" | "lang" | "sum" | "floor" | "ceiling" | "round" => {
+            (1, 1)
+        }
+        "local-name" | "name" | "namespace-uri" | "string" | "string-length"
+        | "normalize-space" | "number" => (0, 1),
+        "concat" => (2, usize::MAX),
+        "starts-with" | "contains" | "substring-before" | "substring-after" => (2, 2),
+        "substring" => (2, 3),
+        "translate" => (3, 3),
+        _ => return None,
+    })
+}
+
+fn node_name(node: &XPathNode) -> String {
+    match node {
+        XPathNode::Node(node) => match &node.borrow().node_type {
+            NodeType::Element(element) => element.tag_name().to_string(),
+            _ => String::new(),
+        },
+        XPathNode::Attribute { name, .. } => name.clone(),
+    }
+}
+
+fn string_to_number(value: &str) -> f64 {
+    let trimmed = value.trim();
+    let valid = !trimmed.is_empty()
+        && trimmed
+            .trim_start_matches('-')
+            .chars()
+            .all(|c| c.is_ascii_digit() || c == '.')
+        && trimmed.matches('.').count() <= 1
+        && trimmed.trim_start_matches('-') != ".";
+    if valid {
+        trimmed.parse().unwrap_or(f64::NAN)
+    } else {
+        f64::NAN
+    }
+}
+
+fn number_to_string(n: f64) -> String {
+    if n.is_nan() {
+        "NaN".to_string()
+    } else if n.is_infinite() {
+        if n > 0.0 { "Infinity" } else { "-Infinity" }.to_string()
+    } else if n == 0.0 {
+        "0".to_string()
+    } else {
+        format!("{n}")
+    }
+}
+
+fn root_of(node: &NodeRef) -> NodeRef {
+    let mut current = node.clone();
+    loop {
+        let parent = current
+            .borrow()
+            .parent_element
+            .as_ref()
+            .and_then(|p| p.upgrade());
+        match parent {
+            Some(parent) => current = parent,
+            None => return current,
+        }
+    }
+}
+
+/// A node's attributes in source order, which is the order the attribute
+/// axis visits them
+fn node_attributes(node: &NodeRef) -> Vec<(String, String)> {
+    node.borrow()
+        .properties
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+fn parent_of(node: &XPathNode) -> Option<NodeRef> {
+    match node {
+        XPathNode::Node(node) => node
+            .borrow()
+            .parent_element
+            .as_ref()
+            .and_then(|p| p.upgrade()),
+        XPathNode::Attribute { owner, .. } => Some(owner.clone()),
+    }
+}
+
+fn push_descendants(node: &NodeRef, out: &mut Vec<XPathNode>) {
+    for child in &node.borrow().children {
+        out.push(XPathNode::Node(child.clone()));
+        push_descendants(child, out);
+    }
+}
+
+/// Siblings of a tree node split around it, in document order
+fn siblings(node: &XPathNode) -> (Vec<NodeRef>, Vec<NodeRef>) {
+    let XPathNode::Node(node) = node else {
+        return (vec![], vec![]);
+    };
+    let Some(parent) = node
+        .borrow()
+        .parent_element
+        .as_ref()
+        .and_then(|p| p.upgrade())
+    else {
+        return (vec![], vec![]);
+    };
+    let children = parent.borrow().children.clone();
+    match children.iter().position(|child| Rc::ptr_eq(child, node)) {
+        Some(index) => (children[..index].to_vec(), children[index + 1..].to_vec()),
+        None => (vec![], vec![]),
+    }
+}
+
+/// Nodes along an axis in axis order, so reverse axes start nearest the context node
+fn axis_nodes(axis: Axis, node: &XPathNode) -> Vec<XPathNode> {
+    let mut nodes = vec![];
+    match axis {
+        Axis::SelfNode => nodes.push(node.clone()),
+        Axis::Child => {
+            if let XPathNode::Node(node) = node {
+                nodes.extend(node.borrow().children.iter().cloned().map(XPathNode::Node));
+            }
+        }
+        Axis::Descendant | Axis::DescendantOrSelf => {
+            if axis == Axis::DescendantOrSelf {
+                nodes.push(node.clone());
+            }
+            if let XPathNode::Node(node) = node {
+                push_descendants(node, &mut nodes);
+            }
+        }
+        Axis::Parent => nodes.extend(parent_of(node).map(XPathNode::Node)),
+        Axis::Ancestor | Axis::AncestorOrSelf => {
+            if axis == Axis::AncestorOrSelf {
+                nodes.push(node.clone());
+            }
+            let mut current = parent_of(node);
+            while let Some(parent) = current {
+                current = parent
+                    .borrow()
+                    .parent_element
+                    .as_ref()
+                    .and_then(|p| p.upgrade());
+                nodes.push(XPathNode::Node(parent));
+            }
+        }
+        Axis::FollowingSibling => nodes.extend(siblings(node).1.into_iter().map(XPathNode::Node)),
+        Axis::PrecedingSibling => {
+            nodes.extend(siblings(node).0.into_iter().rev().map(XPathNode::Node))
+        }
+        Axis::Following => {
+            // Attributes are followed by their owner's descendants
+            if let XPathNode::Attribute { owner, .. } = node {
+                push_descendants(owner, &mut nodes);
+            }
+            let mut current = node.clone();
+            loop {
+                for sibling in siblings(&current).1 {
+                    nodes.push(XPathNode::Node(sibling.clone()));
+                    push_descendants(&sibling, &mut nodes);
+                }
+                match parent_of(&current) {
+                    Some(parent) => current = XPathNode::Node(parent),
+                    None => break,
+                }
+            }
+        }
+        Axis::Preceding => {
+            let mut current = node.clone();
+            loop {
+                for sibling in siblings(&current).0.into_iter().rev() {
+                    let mut subtree = vec![XPathNode::Node(sibling.clone())];
+                    push_descendants(&sibling, &mut subtree);
+                    nodes.extend(subtree.into_iter().rev());
+                }
+                match parent_of(&current) {
+                    Some(parent) => current = XPathNode::Node(parent),
+                    None => break,
+                }
+            }
+        }
+        Axis::Attribute => {
+            if let XPathNode::Node(owner) = node {
+                nodes.extend(node_attributes(owner).into_iter().map(|(name, value)| {
+                    XPathNode::Attribute {
+                        owner: owner.clone(),
+                        name,
+                        value,
+                    }
+                }));
+            }
+        }
+        Axis::Namespace => {}
+    }
+    nodes
+}
+
+fn node_test(test: &NodeTest, axis: Axis, node: &XPathNode) -> bool {
+    match (test, node) {
+        (NodeTest::Node, _) => true,
+        (NodeTest::Any, XPathNode::Attribute { .. }) => axis == Axis::Attribute,
+        (NodeTest::Name(wanted), XPathNode::Attribute { name, .. }) => {
+            axis == Axis::Attribute && name.eq_ignore_ascii_case(wanted)
+        }
+        (_, XPathNode::Attribute { .. }) => false,
+        (test, XPathNode::Node(node)) => match (test, &node.borrow().node_type) {
+            (NodeTest::Any, NodeType::Element(_)) => axis != Axis::Attribute,
+            (NodeTest::Name(wanted), NodeType::Element(element)) => {
+                axis != Axis::Attribute && element.tag_name().eq_ignore_ascii_case(wanted)
+            }
+            (NodeTest::Text, NodeType::Text(_)) => true,
+            (NodeTest::Comment, NodeType::Comment(_)) => true,
+            _ => false,
+        },
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Slash,
+    DoubleSlash,
+    Dot,
+    DoubleDot,
+    At,
+    DoubleColon,
+    LeftParen,
+    RightParen,
+    LeftBracket,
+    RightBracket,
+    Comma,
+    Pipe,
+    Plus,
+    Minus,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Star,
+    Name(String),
+    Literal(String),
+    Number(f64),
+    Variable(String),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Name(name) => write!(f, "name '{name}'"),
+            Token::Literal(literal) => write!(f, "literal \"{literal}\""),
+            Token::Number(number) => write!(f, "number {number}"),
+            Token::Variable(name) => write!(f, "variable ${name}"),
+            other => write!(f, "token {other:?}"),
+        }
+    }
+}
+
+fn tokenise(expression: &str) -> Result<Vec<(Token, usize)>, XPathError> {
+    let mut tokens = vec![];
+    let chars: Vec<(usize, char)> = expression.char_indices().collect();
+    let mut index = 0;
+    let at = |i: usize| chars.get(i).map(|(_, c)| *c);
+
+    while index < chars.len() {
+        let (offset, c) = chars[index];
+        let next = at(index + 1);
+        let (token, length) = match c {
+            c if c.is_whitespace() => {
+                index += 1;
+                continue;
+            }
+            '/' if next == Some('/') => (Token::DoubleSlash, 2),
+            '/' => (Token::Slash, 1),
+            '.' if next == Some('.') => (Token::DoubleDot, 2),
+            '.' if !next.is_some_and(|n| n.is_ascii_digit()) => (Token::Dot, 1),
+            '@' => (Token::At, 1),
+            ':' if next == Some(':') => (Token::DoubleColon, 2),
+            '(' => (Token::LeftParen, 1),
+            ')' => (Token::RightParen, 1),
+            '[' => (Token::LeftBracket, 1),
+            ']' => (Token::RightBracket, 1),
+            ',' => (Token::Comma, 1),
+            '|' => (Token::Pipe, 1),
+            '+' => (Token::Plus, 1),
+            '-' => (Token::Minus, 1),
+            '=' => (Token::Equal, 1),
+            '!' if next == Some('=') => (Token::NotEqual, 2),
+            '<' if next == Some('=') => (Token::LessOrEqual, 2),
+            '<' => (Token::Less, 1),
+            '>' if next == Some('=') => (Token::GreaterOrEqual, 2),
+            '>' => (Token::Greater, 1),
+            '*' => (Token::Star, 1),
+            '"' | '\'' => {
+                let mut end = index + 1;
+                while end < chars.len() && chars[end].1 != c {
+                    end += 1;
+                }
+                if end == chars.len() {
+                    return Err(XPathError::Syntax {
+                        position: offset,
+                        message: "unterminated string literal".to_string(),
+                    });
+                }
+                let literal = chars[index + 1..end].iter().map(|(_, c)| c).collect();
+                (Token::Literal(literal), end - index + 1)
+            }
+            c if c.is_ascii_digit() || c == '.' => {
+                let mut end = index;
+                while at(end).is_some_and(|c| c.is_ascii_digit() || c == '.') {
+                    end += 1;
+                }
+                let number: String = chars[index..end].iter().map(|(_, c)| c).collect();
+                let value = number.parse().map_err(|_| XPathError::Syntax {
+                    position: offset,
+                    message: format!("invalid number '{number}'"),
+                })?;
+                (Token::Number(value), end - index)
+            }
+            '$' => {
+                let mut end = index + 1;
+                while at(end).is_some_and(is_name_char) {
+                    end += 1;
+                }
+                let name = chars[index + 1..end].iter().map(|(_, c)| c).collect();
+                (Token::Variable(name), end - index)
+            }
+            c if is_name_start(c) => {
+                let mut end = index;
+                while at(end).is_some_and(is_name_char) {
+                    end += 1;
+                }
+                // QNames keep a single colon, but `::` belongs to the axis
+                if at(end) == Some(':') && at(end + 1) != Some(':') {
+                    if at(end + 1) == Some('*') {
+                        end += 2;
+                    } else if at(end + 1).is_some_and(is_name_start) {
+                        end += 1;
+                        while at(end).is_some_and(is_name_char) {
+                            end += 1;
+                        }
+                    }
+                }
+                let name = chars[index..end].iter().map(|(_, c)| c).collect();
+                (Token::Name(name), end - index)
+            }
+            c => {
+                return Err(XPathError::Syntax {
+                    position: offset,
+                    message: format!("unexpected character '{c}'"),
+                });
+            }
+        };
+        tokens.push((token, offset));
+        index += length;
+    }
+    Ok(tokens)
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-' || c == '.'
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&Token> {
+        self.tokens
+            .get(self.position + offset)
+            .map(|(token, _)| token)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .map(|(token, _)| token.clone());
+        self.position += 1;
+        token
+    }
+
+    fn error(&self, message: &str) -> XPathError {
+        let position = self
+            .tokens
+            .get(self.position)
+            .map(|(_, offset)| *offset)
+            .unwrap_or(self.end);
+        let message = match self.peek() {
+            Some(token) => format!("{message}, found {token}"),
+            None => format!("{message}, found end of expression"),
+        };
+        XPathError::Syntax { position, message }
+    }
+
+    fn expect(&mut self, expected: Token, message: &str) -> Result<(), XPathError> {
+        if self.peek() == Some(&expected) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(self.error(message))
+        }
+    }
+
+    fn peek_operator_name(&self, name: &str) -> bool {
+        matches!(self.peek(), Some(Token::Name(n)) if n == name)
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, XPathError> {
+        let mut expr = self.parse_and()?;
+        while self.peek_operator_name("or") {
+            self.next();
+            expr = Expr::Binary(BinaryOp::Or, Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, XPathError> {
+        let mut expr = self.parse_equality()?;
+        while self.peek_operator_name("and") {
+            self.next();
+            expr = Expr::Binary(
+                BinaryOp::And,
+                Box::new(expr),
+                Box::new(self.parse_equality()?),
+            );
+        }
+        Ok(expr)
+    }
+
+    fn parse_equality(&mut self) -> Result<Expr, XPathError> {
+        let mut expr = self.parse_relational()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Equal) => BinaryOp::Equal,
+                Some(Token::NotEqual) => BinaryOp::NotEqual,
+                _ => return Ok(expr),
+            };
+            self.next();
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.parse_relational()?));
+        }
+    }
+
+    fn parse_relational(&mut self) -> Result<Expr, XPathError> {
+        let mut expr = self.parse_additive()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Less) => BinaryOp::Less,
+                Some(Token::LessOrEqual) => BinaryOp::LessOrEqual,
+                Some(Token::Greater) => BinaryOp::Greater,
+                Some(Token::GreaterOrEqual) => BinaryOp::GreaterOrEqual,
+                _ => return Ok(expr),
+            };
+            self.next();
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.parse_additive()?));
+        }
+    }
+
+    fn parse_additive(&mut self) -> Result<Expr, XPathError> {
+        let mut expr = self.parse_multiplicative()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Plus) => BinaryOp::Add,
+                Some(Token::Minus) => BinaryOp::Subtract,
+                _ => return Ok(expr),
+            };
+            self.next();
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.parse_multiplicative()?));
+        }
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expr, XPathError> {
+        let mut expr = self.parse_unary()?;
+        loop {
+            // After an operand, `*`, `div` and `mod` can only be operators
+            let op = match self.peek() {
+                Some(Token::Star) => BinaryOp::Multiply,
+                Some(Token::Name(name)) if name == "div" => BinaryOp::Divide,
+                Some(Token::Name(name)) if name == "mod" => BinaryOp::Modulo,
+                _ => return Ok(expr),
+            };
+            self.next();
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.parse_unary()?));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, XPathError> {
+        if self.peek() == Some(&Token::Minus) {
+            self.next();
+            return Ok(Expr::Negate(Box::new(self.parse_unary()?)));
+        }
+        self.parse_union()
+    }
+
+    fn parse_union(&mut self) -> Result<Expr, XPathError> {
+        let mut expr = self.parse_path()?;
+        while self.peek() == Some(&Token::Pipe) {
+            self.next();
+            expr = Expr::Binary(
+                BinaryOp::Union,
+                Box::new(expr),
+                Box::new(self.parse_path()?),
+            );
+        }
+        Ok(expr)
+    }
+
+    fn starts_filter_expr(&self) -> bool {
+        match self.peek() {
+            Some(Token::Literal(_) | Token::Number(_) | Token::Variable(_) | Token::LeftParen) => {
+                true
+            }
+            Some(Token::Name(name)) => {
+                self.peek_at(1) == Some(&Token::LeftParen)
+                    && !matches!(
+                        name.as_str(),
+                        "node" | "text" | "comment" | "processing-instruction"
+                    )
+            }
+            _ => false,
+        }
+    }
+
+    fn parse_path(&mut self) -> Result<Expr, XPathError> {
+        if self.starts_filter_expr() {
+            let primary = self.parse_primary()?;
+            let mut predicates = vec![];
+            while self.peek() == Some(&Token::LeftBracket) {
+                predicates.push(self.parse_predicate()?);
+            }
+            let filter = if predicates.is_empty() {
+                primary
+            } else {
+                Expr::Filter(Box::new(primary), predicates)
+            };
+            if !matches!(self.peek(), Some(Token::Slash | Token::DoubleSlash)) {
+                return Ok(filter);
+            }
+            let mut steps = vec![];
+            self.parse_relative_path(&mut steps, true)?;
+            return Ok(Expr::Path(PathStart::Filter(Box::new(filter)), steps));
+        }
+
+        let mut steps = vec![];
+        match self.peek() {
+            Some(Token::Slash) => {
+                self.next();
+                if self.starts_step() {
+                    self.parse_relative_path(&mut steps, false)?;
+                }
+                Ok(Expr::Path(PathStart::Root, steps))
+            }
+            Some(Token::DoubleSlash) => {
+                self.parse_relative_path(&mut steps, true)?;
+                Ok(Expr::Path(PathStart::Root, steps))
+            }
+            _ => {
+                self.parse_relative_path(&mut steps, false)?;
+                Ok(Expr::Path(PathStart::Context, steps))
+            }
+        }
+    }
+
+    fn starts_step(&self) -> bool {
+        matches!(
+            self.peek(),
+            Some(Token::Dot | Token::DoubleDot | Token::At | Token::Star | Token::Name(_))
+        )
+    }
+
+    /// Parses steps separated by `/` or `//`. When `leading_separator` is set
+    /// the path is expected to start with one of the separators.
+    fn parse_relative_path(
+        &mut self,
+        steps: &mut Vec<Step>,
+        leading_separator: bool,
+    ) -> Result<(), XPathError> {
+        let mut expect_separator = leading_separator;
+        loop {
+            if expect_separator {
+                match self.peek() {
+                    Some(Token::Slash) => {
+                        self.next();
+                    }
+                    Some(Token::DoubleSlash) => {
+                        self.next();
+                        steps.push(Step {
+                            axis: Axis::DescendantOrSelf,
+                            test: NodeTest::Node,
+                            predicates: vec![],
+                        });
+                    }
+                    _ => return Ok(()),
+                }
+            }
+            steps.push(self.parse_step()?);
+            expect_separator = true;
+        }
+    }
+
+    fn parse_step(&mut self) -> Result<Step, XPathError> {
+        match self.peek() {
+            Some(Token::Dot) => {
+                self.next();
+                return Ok(Step {
+                    axis: Axis::SelfNode,
+                    test: NodeTest::Node,
+                    predicates: vec![],
+                });
+            }
+            Some(Token::DoubleDot) => {
+                self.next();
+                return Ok(Step {
+                    axis: Axis::Parent,
+                    test: NodeTest::Node,
+                    predicates: vec![],
+                });
+            }
+            _ => {}
+        }
+
+        let axis = if self.peek() == Some(&Token::At) {
+            self.next();
+            Axis::Attribute
+        } else if let (Some(Token::Name(name)), Some(Token::DoubleColon)) =
+            (self.peek(), self.peek_at(1))
+        {
+            let axis = match name.as_str() {
+                "child" => Axis::Child,
+                "descendant" => Axis::Descendant,
+                "descendant-or-self" => Axis::DescendantOrSelf,
+                "parent" => Axis::Parent,
+                "ancestor" => Axis::Ancestor,
+                "ancestor-or-self" => Axis::AncestorOrSelf,
+                "following-sibling" => Axis::FollowingSibling,
+                "preceding-sibling" => Axis::PrecedingSibling,
+                "following" => Axis::Following,
+                "preceding" => Axis::Preceding,
+                "attribute" => Axis::Attribute,
+                "self" => Axis::SelfNode,
+                "namespace" => Axis::Namespace,
+                _ => return Err(self.error("unknown axis")),
+            };
+            self.position += 2;
+            axis
+        } else {
+            Axis::Child
+        };
+
+        let test = match self.next() {
+            Some(Token::Star) => NodeTest::Any,
+            Some(Token::Name(name)) => {
+                if self.peek() == Some(&Token::LeftParen) {
+                    let test = match name.as_str() {
+                        "node" => NodeTest::Node,
+                        "text" => NodeTest::Text,
+                        "comment" => NodeTest::Comment,
+                        "processing-instruction" => NodeTest::ProcessingInstruction,
+                        _ => {
+                            self.position -= 1;
+                            return Err(self.error("expected a node test"));
+                        }
+                    };
+                    self.next();
+                    if test == NodeTest::ProcessingInstruction
+                        && matches!(self.peek(), Some(Token::Literal(_)))
+                    {
+                        self.next();
+                    }
+                    self.expect(Token::RightParen, "expected ')'")?;
+                    test
+                } else if name.ends_with(":*") {
+                    NodeTest::Any
+                } else {
+                    // Namespace prefixes carry no meaning in an HTML tree
+                    let local = name.rsplit(':').next().unwrap_or(&name);
+                    NodeTest::Name(local.to_string())
+                }
+            }
+            _ => {
+                self.position -= 1;
+                return Err(self.error("expected a node test"));
+            }
+        };
+
+        let mut predicates = vec![];
+        while self.peek() == Some(&Token::LeftBracket) {
+            predicates.push(self.parse_predicate()?);
+        }
+        Ok(Step {
+            axis,
+            test,
+            predicates,
+        })
+    }
+
+    fn parse_predicate(&mut self) -> Result<Expr, XPathError> {
+        self.expect(Token::LeftBracket, "expected '['")?;
+        let expr = self.parse_or()?;
+        self.expect(Token::RightBracket, "expected ']'")?;
+        Ok(expr)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, XPathError> {
+        let start = self.position;
+        match self.next() {
+            Some(Token::Literal(literal)) => Ok(Expr::Literal(literal)),
+            Some(Token::Number(number)) => Ok(Expr::Number(number)),
+            Some(Token::Variable(name)) => Err(XPathError::UnsupportedVariable(name)),
+            Some(Token::LeftParen) => {
+                let expr = self.parse_or()?;
+                self.expect(Token::RightParen, "expected ')'")?;
+                Ok(expr)
+            }
+            Some(Token::Name(name)) => {
+                let Some((min, max)) = function_arity(&name) else {
+                    return Err(XPathError::UnknownFunction(name));
+                };
+                self.expect(Token::LeftParen, "expected '('")?;
+                let mut args = vec![];
+                if self.peek() != Some(&Token::RightParen) {
+                    args.push(self.parse_or()?);
+                    while self.peek() == Some(&Token::Comma) {
+                        self.next();
+                        args.push(self.parse_or()?);
+                    }
+                }
+                self.expect(Token::RightParen, "expected ')'")?;
+                if args.len() < min || args.len() > max {
+                    return Err(XPathError::ArgumentCount {
+                        function: name,
+                        found: args.len(),
+                    });
+                }
+                Ok(Expr::Function(name, args))
+            }
+            _ => {
+                self.position = start;
+                Err(self.error("expected an expression"))
+            }
+        }
+    }
+}
+
+impl PartialEq for XPathNode {
+    fn eq(&self, other: &Self) -> bool {
+        self.is_same(other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{node_tree::Node, tokeniser::get_tokens};
+
+    const TEST: &str = r##"<html><body>
+    <div id="main" class="content">
+        <h1>Products</h1>
+        <ul>
+            <li class="item" data-price="10"><a href="/a">  Apple   pie </a></li>
+            <li class="item" data-price="25"><a href="/b">Banana</a></li>
+            <li class="item sale" data-price="5"><a href="/c">Cherry</a></li>
+        </ul>
+        <p>Footer <b>text</b><!-- note --></p>
+    </div>
+    </body></html>"##;
+
+    fn document() -> NodeRef {
+        Node::from_token_stream(get_tokens(TEST))
+    }
+
+    fn strings(value: XPathValue) -> Vec<String> {
+        match value {
+            XPathValue::NodeSet(nodes) => nodes.iter().map(|n| n.string_value()).collect(),
+            other => panic!("expected node set, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn check_location_paths() {
+        let document = document();
+
+        assert_eq!(evaluate("//li", &document).unwrap().nodes().len(), 3);
+        assert_eq!(
+            evaluate("/html/body/div/ul/li", &document)
+                .unwrap()
+                .nodes()
+                .len(),
+            3
+        );
+        assert_eq!(
+            strings(evaluate("//li/a/text()", &document).unwrap()),
+            vec!["  Apple   pie ", "Banana", "Cherry"]
+        );
+        assert_eq!(
+            strings(evaluate("//div/h1", &document).unwrap()),
+            vec!["Products"]
+        );
+        assert_eq!(
+            strings(evaluate("//p/comment()", &document).unwrap()),
+            vec![" note "]
+        );
+        assert_eq!(
+            strings(evaluate("//li[1]/@data-price | //b | //li[1] | //h1", &document).unwrap()),
+            vec!["Products", "  Apple   pie ", "10", "text"]
+        );
+    }
+
+    #[test]
+    fn check_axes() {
+        let document = document();
+
+        assert_eq!(
+            strings(
+                evaluate(
+                    "//a[.='Banana']/parent::li/following-sibling::li",
+                    &document
+                )
+                .unwrap()
+            ),
+            vec!["Cherry"]
+        );
+        assert_eq!(
+            strings(evaluate("//li[3]/preceding-sibling::li[1]", &document).unwrap()),
+            vec!["Banana"]
+        );
+        assert_eq!(
+            evaluate("//b/ancestor::*", &document)
+                .unwrap()
+                .nodes()
+                .len(),
+            4
+        );
+        assert_eq!(
+            strings(evaluate("//li[@class='item sale']/@data-price", &document).unwrap()),
+            vec!["5"]
+        );
+        assert_eq!(
+            evaluate("//ul/descendant::a", &document)
+                .unwrap()
+                .nodes()
+                .len(),
+            3
+        );
+        assert_eq!(
+            strings(evaluate("//h1/following::b", &document).unwrap()),
+            vec!["text"]
+        );
+        assert_eq!(evaluate("//li/..", &document).unwrap().nodes().len(), 1);
+    }
+
+    #[test]
+    fn check_predicates_and_positions() {
+        let document = document();
+
+        assert_eq!(
+            strings(evaluate("//li[2]/a", &document).unwrap()),
+            vec!["Banana"]
+        );
+        assert_eq!(
+            strings(evaluate("//li[last()]/a", &document).unwrap()),
+            vec!["Cherry"]
+        );
+        assert_eq!(
+            strings(evaluate("//li[@data-price > 8]/a", &document).unwrap()).len(),
+            2
+        );
+        assert_eq!(
+            strings(evaluate("(//a)[position() < 3]", &document).unwrap()).len(),
+            2
+        );
+        assert_eq!(
+            strings(evaluate("//li[contains(@class, 'sale')]", &document).unwrap()),
+            vec!["Cherry"]
+        );
+    }
+
+    #[test]
+    fn check_functions() {
+        let document = document();
+
+        let value = |expr: &str| evaluate(expr, &document).unwrap();
+        assert_eq!(value("count(//li)").to_number(), 3.0);
+        assert_eq!(
+            value("normalize-space(//li[1])").to_string_value(),
+            "Apple pie"
+        );
+        assert!(value("starts-with(//li[2]/a/@href, '/b')").to_boolean());
+        assert_eq!(value("sum(//li/@data-price)").to_number(), 40.0);
+        assert_eq!(value("string(//p)").to_string_value(), "Footer text");
+        assert_eq!(value("substring('12345', 2, 3)").to_string_value(), "234");
+        assert_eq!(value("concat('a', 'b', 'c')").to_string_value(), "abc");
+        assert_eq!(
+            value("translate('bar', 'abc', 'ABC')").to_string_value(),
+            "BAr"
+        );
+        assert_eq!(value("name(//*[@id='main'])").to_string_value(), "div");
+        assert_eq!(value("1 + 2 * 3 div 4").to_number(), 2.5);
+        assert_eq!(value("7 mod 3").to_string_value(), "1");
+        assert!(value("not(//table)").to_boolean());
+        assert!(value("//li/@data-price = 25").to_boolean());
+    }
+
+    #[test]
+    fn check_node_sets_compared_with_booleans() {
+        let document = document();
+
+        let value = |expr: &str| evaluate(expr, &document).unwrap().to_boolean();
+        assert!(value("//nothing = false()"));
+        assert!(!value("//nothing = true()"));
+        assert!(value("false() = //nothing"));
+        assert!(value("//nothing != true()"));
+        assert!(value("//li = true()"));
+        assert!(!value("//li != true()"));
+        assert!(value("//nothing < true()"));
+    }
+
+    #[test]
+    fn check_compiled_select() {
+        let document = document();
+        let links: XPath = "//a[@href]".parse().unwrap();
+        let nodes = links.select(&document).unwrap();
+
+        assert_eq!(nodes.len(), 3);
+        assert_eq!(
+            links.select(&nodes[0]).unwrap().len(),
+            3,
+            "absolute paths start from the root"
+        );
+        assert_eq!(
+            XPath::parse("a").unwrap().select(&nodes[0]).unwrap().len(),
+            0
+        );
+        assert!(matches!(
+            XPath::parse("count(//a)").unwrap().select(&document),
+            Err(XPathError::NotANodeSet)
+        ));
+    }
+
+    #[test]
+    fn check_errors() {
+        assert_eq!(
+            XPath::parse("foo(1)").unwrap_err(),
+            XPathError::UnknownFunction("foo".to_string())
+        );
+        assert_eq!(
+            XPath::parse("contains('a')").unwrap_err(),
+            XPathError::ArgumentCount {
+                function: "contains".to_string(),
+                found: 1
+            }
+        );
+        assert!(matches!(
+            XPath::parse("//li[").unwrap_err(),
+            XPathError::Syntax { position: 5, .. }
+        ));
+        assert!(matches!(
+            XPath::parse("bogus::li").unwrap_err(),
+            XPathError::Syntax { position: 0, .. }
+        ));
+    }
+}